@@ -0,0 +1,643 @@
+use std::collections::HashSet;
+
+use rust_stemmers::{Algorithm, Stemmer};
+use unicode_segmentation::UnicodeSegmentation;
+
+use document;
+
+/// Words that carry little meaning on their own and are dropped by the `StopWordsFilter` by default
+pub const ENGLISH_STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it", "no",
+    "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these", "they", "this",
+    "to", "was", "will", "with",
+];
+
+/// Longest term, in characters, the standard Analyzer will keep. Anything longer is almost
+/// certainly not something a person will search for.
+pub const DEFAULT_MAX_TOKEN_LENGTH: usize = 255;
+
+/// Put in front of the stems a `StemmerFilter` emits next to original words, so that stems and
+/// originals are separate terms and an exact match for "bear" doesn't find "bears". No tokenizer
+/// produces it on its own.
+pub const STEM_PREFIX: &'static str = "\u{1}";
+
+/// A Token is a single term produced by an Analyzer, along with its position in the original text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    /// The term as it should be stored in, or looked up from, the index
+    pub term: String,
+    /// Position of the term in the text. Filters that drop Tokens do not renumber the ones that
+    /// remain, so positions can be used to check that terms are adjacent.
+    pub position: u64,
+}
+
+impl Token {
+    /// Creates and returns a new Token
+    ///
+    /// # Arguments
+    ///
+    /// * `term` - The text of the Token
+    /// * `position` - Position of the Token in the text it came from
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Token;
+    /// let token = Token::new("bear", 0);
+    /// ```
+    pub fn new<S: Into<String>>(term: S, position: u64) -> Token {
+        Token {
+            term: term.into(),
+            position: position,
+        }
+    }
+}
+
+/// A CharFilter transforms the raw text before it is split into Tokens
+pub trait CharFilter: Send {
+    /// Returns the filtered text
+    fn filter(&self, text: &str) -> String;
+}
+
+/// A Tokenizer splits text into Tokens
+pub trait Tokenizer: Send {
+    /// Returns the Tokens found in the text, in order
+    fn tokenize(&self, text: &str) -> Vec<Token>;
+}
+
+/// A TokenFilter adds, removes or changes Tokens after the text has been tokenized
+pub trait TokenFilter: Send {
+    /// Returns the filtered Tokens
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token>;
+
+    /// Returns true if this filter reduces words to their stems. These filters are skipped when
+    /// analyzing text for an exact match.
+    fn stems(&self) -> bool {
+        false
+    }
+}
+
+/// Removes HTML tags, replacing each one with a space so the words on either side of it are not
+/// joined together
+pub struct HtmlStripCharFilter;
+
+impl CharFilter for HtmlStripCharFilter {
+    fn filter(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut in_tag = false;
+        for c in text.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => {
+                    in_tag = false;
+                    result.push(' ');
+                }
+                _ if !in_tag => result.push(c),
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+/// Splits text into words following the Unicode word boundary rules. Punctuation and whitespace
+/// are not returned as Tokens.
+pub struct UnicodeWordTokenizer;
+
+impl Tokenizer for UnicodeWordTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        text.unicode_words()
+            .enumerate()
+            .map(|(i, word)| Token::new(word, i as u64))
+            .collect()
+    }
+}
+
+/// Splits text on whitespace only. This is how Saga originally tokenized Documents.
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<Token> {
+        document::split_on_whitespace(text)
+            .into_iter()
+            .map(|(term, offset)| Token::new(term, offset))
+            .collect()
+    }
+}
+
+/// Lowercases every Token
+pub struct LowercaseFilter;
+
+impl TokenFilter for LowercaseFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|token| Token::new(token.term.to_lowercase(), token.position))
+            .collect()
+    }
+}
+
+/// Replaces accented Latin characters with their closest ASCII equivalent, so that "café"
+/// and "cafe" are the same term
+pub struct AsciiFoldingFilter;
+
+impl TokenFilter for AsciiFoldingFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .map(|token| {
+                let mut folded = String::with_capacity(token.term.len());
+                for c in token.term.chars() {
+                    match fold_char(c) {
+                        Some(replacement) => folded.push_str(replacement),
+                        None => folded.push(c),
+                    }
+                }
+                Token::new(folded, token.position)
+            })
+            .collect()
+    }
+}
+
+/// Returns the ASCII replacement for a character, or None if it should be left alone
+fn fold_char(c: char) -> Option<&'static str> {
+    let folded = match c {
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    };
+    Some(folded)
+}
+
+/// Drops Tokens that appear in a list of stop words
+pub struct StopWordsFilter {
+    stop_words: HashSet<String>,
+}
+
+impl StopWordsFilter {
+    /// Creates and returns a StopWordsFilter that drops the given words
+    ///
+    /// # Arguments
+    ///
+    /// * `words` - The words to drop. These are compared against Tokens exactly, so they should
+    /// already be in the form the earlier filters produce (usually lowercase).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::StopWordsFilter;
+    /// let filter = StopWordsFilter::new(&["the", "a"]);
+    /// ```
+    pub fn new(words: &[&str]) -> StopWordsFilter {
+        StopWordsFilter {
+            stop_words: words.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    /// Creates and returns a StopWordsFilter using the `ENGLISH_STOP_WORDS`
+    pub fn english() -> StopWordsFilter {
+        StopWordsFilter::new(ENGLISH_STOP_WORDS)
+    }
+}
+
+impl TokenFilter for StopWordsFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|token| !self.stop_words.contains(&token.term))
+            .collect()
+    }
+}
+
+/// Drops Tokens that are shorter or longer than the given number of characters
+pub struct LengthFilter {
+    min: usize,
+    max: usize,
+}
+
+impl LengthFilter {
+    /// Creates and returns a LengthFilter
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - The fewest characters a Token may have
+    /// * `max` - The most characters a Token may have
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::LengthFilter;
+    /// let filter = LengthFilter::new(2, 40);
+    /// ```
+    pub fn new(min: usize, max: usize) -> LengthFilter {
+        LengthFilter { min: min, max: max }
+    }
+}
+
+impl TokenFilter for LengthFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        tokens
+            .into_iter()
+            .filter(|token| {
+                let length = token.term.chars().count();
+                length >= self.min && length <= self.max
+            })
+            .collect()
+    }
+}
+
+/// Reduces each Token to its stem using the Snowball stemmer for a language, so that "bears"
+/// and "bear" are the same term
+pub struct StemmerFilter {
+    stemmer: Stemmer,
+    keep_original: bool,
+}
+
+impl StemmerFilter {
+    /// Creates and returns a StemmerFilter
+    ///
+    /// # Arguments
+    ///
+    /// * `algorithm` - The Snowball algorithm for the language being stemmed
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate rust_stemmers;
+    /// extern crate inverted_index;
+    /// use inverted_index::analyzer::StemmerFilter;
+    /// let filter = StemmerFilter::new(rust_stemmers::Algorithm::English);
+    /// ```
+    pub fn new(algorithm: Algorithm) -> StemmerFilter {
+        StemmerFilter {
+            stemmer: Stemmer::create(algorithm),
+            keep_original: false,
+        }
+    }
+
+    /// Creates and returns a StemmerFilter for a language given by name, or None if there is no
+    /// stemmer for that language
+    ///
+    /// # Arguments
+    ///
+    /// * `language` - Lowercase English name of the language, such as `english` or `german`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::StemmerFilter;
+    /// let filter = StemmerFilter::for_language("french").unwrap();
+    /// ```
+    pub fn for_language(language: &str) -> Option<StemmerFilter> {
+        let algorithm = match language {
+            "arabic" => Algorithm::Arabic,
+            "danish" => Algorithm::Danish,
+            "dutch" => Algorithm::Dutch,
+            "english" => Algorithm::English,
+            "finnish" => Algorithm::Finnish,
+            "french" => Algorithm::French,
+            "german" => Algorithm::German,
+            "hungarian" => Algorithm::Hungarian,
+            "italian" => Algorithm::Italian,
+            "portuguese" => Algorithm::Portuguese,
+            "romanian" => Algorithm::Romanian,
+            "russian" => Algorithm::Russian,
+            "spanish" => Algorithm::Spanish,
+            "swedish" => Algorithm::Swedish,
+            "tamil" => Algorithm::Tamil,
+            "turkish" => Algorithm::Turkish,
+            _ => return None,
+        };
+        Some(StemmerFilter::new(algorithm))
+    }
+
+    /// Sets whether the original form of a word is kept alongside its stem. When it is, both
+    /// are emitted at the same position, with the stem behind `STEM_PREFIX`, which lets
+    /// exact-match queries find the word as it was written. Meant to be used as part of the
+    /// Builder pattern.
+    pub fn keep_original(mut self, keep_original: bool) -> StemmerFilter {
+        self.keep_original = keep_original;
+        self
+    }
+}
+
+impl TokenFilter for StemmerFilter {
+    fn filter(&self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut results = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            let stemmed = self.stemmer.stem(&token.term).into_owned();
+            if self.keep_original {
+                results.push(Token::new(format!("{}{}", STEM_PREFIX, stemmed), token.position));
+                results.push(token);
+            } else {
+                results.push(Token::new(stemmed, token.position));
+            }
+        }
+        results
+    }
+
+    fn stems(&self) -> bool {
+        true
+    }
+}
+
+/// An Analyzer turns text into the Tokens that get stored in an index. Text is passed through
+/// each CharFilter, split up by the Tokenizer, and then passed through each TokenFilter in the
+/// order they were added. The same Analyzer must be used for Documents and for the text of
+/// queries against them, or the terms will not line up.
+pub struct Analyzer {
+    char_filters: Vec<Box<CharFilter>>,
+    tokenizer: Box<Tokenizer>,
+    token_filters: Vec<Box<TokenFilter>>,
+}
+
+impl Analyzer {
+    /// Creates and returns an Analyzer with no filters
+    ///
+    /// # Arguments
+    ///
+    /// * `tokenizer` - The Tokenizer used to split text
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::*;
+    /// let analyzer = Analyzer::new(UnicodeWordTokenizer)
+    ///     .token_filter(LowercaseFilter)
+    ///     .token_filter(StopWordsFilter::english());
+    /// ```
+    pub fn new<T: Tokenizer + 'static>(tokenizer: T) -> Analyzer {
+        Analyzer {
+            char_filters: vec![],
+            tokenizer: Box::new(tokenizer),
+            token_filters: vec![],
+        }
+    }
+
+    /// Adds a CharFilter to the end of the Analyzer's char filters. Meant to be used as part of the
+    /// Builder pattern.
+    pub fn char_filter<F: CharFilter + 'static>(mut self, filter: F) -> Analyzer {
+        self.char_filters.push(Box::new(filter));
+        self
+    }
+
+    /// Adds a TokenFilter to the end of the Analyzer's token filters. Meant to be used as part of
+    /// the Builder pattern.
+    pub fn token_filter<F: TokenFilter + 'static>(mut self, filter: F) -> Analyzer {
+        self.token_filters.push(Box::new(filter));
+        self
+    }
+
+    /// The default Analyzer. Splits on Unicode word boundaries, lowercases, folds accented
+    /// characters to ASCII and drops absurdly long terms.
+    pub fn standard() -> Analyzer {
+        Analyzer::new(UnicodeWordTokenizer)
+            .token_filter(LowercaseFilter)
+            .token_filter(AsciiFoldingFilter)
+            .token_filter(LengthFilter::new(1, DEFAULT_MAX_TOKEN_LENGTH))
+    }
+
+    /// The standard Analyzer with English stop words removed
+    pub fn stop() -> Analyzer {
+        Analyzer::standard().token_filter(StopWordsFilter::english())
+    }
+
+    /// Splits on whitespace and nothing else, so terms are kept exactly as they were written
+    pub fn whitespace() -> Analyzer {
+        Analyzer::new(WhitespaceTokenizer)
+    }
+
+    /// The standard Analyzer followed by a stemmer for the given language, or None if there is no
+    /// stemmer for it. Original words are kept next to their stems so exact matches still work.
+    ///
+    /// # Arguments
+    ///
+    /// * `language` - Lowercase English name of the language, such as `english` or `german`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// let analyzer = Analyzer::language("english").unwrap();
+    /// ```
+    pub fn language(language: &str) -> Option<Analyzer> {
+        StemmerFilter::for_language(language)
+            .map(|stemmer| Analyzer::standard().token_filter(stemmer.keep_original(true)))
+    }
+
+    /// Looks up one of the built-in Analyzers by name, so an Index can be configured with one
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - One of `standard`, `stop`, `whitespace`, or the name of a language that has a
+    /// stemmer
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// let analyzer = Analyzer::from_name("stop").unwrap();
+    /// ```
+    pub fn from_name(name: &str) -> Option<Analyzer> {
+        match name {
+            "standard" => Some(Analyzer::standard()),
+            "stop" => Some(Analyzer::stop()),
+            "whitespace" => Some(Analyzer::whitespace()),
+            _ => Analyzer::language(name),
+        }
+    }
+
+    /// Runs text through the Analyzer and returns the resulting Tokens
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to analyze
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// let tokens = Analyzer::standard().analyze("A Molar Bear!");
+    /// assert_eq!(tokens[2].term, "bear");
+    /// ```
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        let mut filtered = text.to_owned();
+        for char_filter in &self.char_filters {
+            filtered = char_filter.filter(&filtered);
+        }
+        let mut tokens = self.tokenizer.tokenize(&filtered);
+        for token_filter in &self.token_filters {
+            tokens = token_filter.filter(tokens);
+        }
+        tokens
+    }
+
+    /// Runs query text through the Analyzer. Where stemming filters keep original words next to
+    /// their stems, only the stems are kept, since they are what every form of a word shares.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to analyze
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::{Analyzer, STEM_PREFIX};
+    /// let tokens = Analyzer::language("english").unwrap().analyze_stemmed("bears");
+    /// assert_eq!(tokens[0].term, format!("{}bear", STEM_PREFIX));
+    /// ```
+    pub fn analyze_stemmed(&self, text: &str) -> Vec<Token> {
+        let tokens = self.analyze(text);
+        if tokens.iter().any(|token| token.term.starts_with(STEM_PREFIX)) {
+            tokens.into_iter().filter(|token| token.term.starts_with(STEM_PREFIX)).collect()
+        } else {
+            tokens
+        }
+    }
+
+    /// Runs text through the Analyzer without any stemming filters. Query text that must match a
+    /// word exactly as it was written is analyzed this way, and finds it because stemming filters
+    /// configured to keep originals store both forms.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to analyze
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// let tokens = Analyzer::language("english").unwrap().analyze_exact("bears");
+    /// assert_eq!(tokens[0].term, "bears");
+    /// ```
+    pub fn analyze_exact(&self, text: &str) -> Vec<Token> {
+        let mut filtered = text.to_owned();
+        for char_filter in &self.char_filters {
+            filtered = char_filter.filter(&filtered);
+        }
+        let mut tokens = self.tokenizer.tokenize(&filtered);
+        for token_filter in self.token_filters.iter().filter(|f| !f.stems()) {
+            tokens = token_filter.filter(tokens);
+        }
+        tokens
+    }
+}
+
+impl Default for Analyzer {
+    fn default() -> Analyzer {
+        Analyzer::standard()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(tokens: Vec<Token>) -> Vec<String> {
+        tokens.into_iter().map(|t| t.term).collect()
+    }
+
+    #[test]
+    fn test_standard_analyzer_strips_punctuation_and_case() {
+        let tokens = Analyzer::standard().analyze("Bear, bear. BEAR!");
+        assert_eq!(terms(tokens), vec!["bear", "bear", "bear"]);
+    }
+
+    #[test]
+    fn test_ascii_folding() {
+        let tokens = Analyzer::standard().analyze("Café Straße");
+        assert_eq!(terms(tokens), vec!["cafe", "strasse"]);
+    }
+
+    #[test]
+    fn test_stop_words_keep_positions() {
+        let tokens = Analyzer::stop().analyze("the molar bear");
+        assert_eq!(tokens, vec![Token::new("molar", 1), Token::new("bear", 2)]);
+    }
+
+    #[test]
+    fn test_length_filter() {
+        let analyzer = Analyzer::new(WhitespaceTokenizer).token_filter(LengthFilter::new(2, 4));
+        assert_eq!(terms(analyzer.analyze("a be bear bears")), vec!["be", "bear"]);
+    }
+
+    #[test]
+    fn test_html_strip() {
+        let analyzer = Analyzer::standard().char_filter(HtmlStripCharFilter);
+        assert_eq!(terms(analyzer.analyze("<b>molar</b>bear")), vec!["molar", "bear"]);
+    }
+
+    #[test]
+    fn test_analyzer_from_name() {
+        assert!(Analyzer::from_name("standard").is_some());
+        assert!(Analyzer::from_name("german").is_some());
+        assert!(Analyzer::from_name("klingon").is_none());
+    }
+
+    #[test]
+    fn test_stemmer_filter() {
+        let analyzer = Analyzer::standard().token_filter(StemmerFilter::new(Algorithm::English));
+        assert_eq!(terms(analyzer.analyze("Bears bear")), vec!["bear", "bear"]);
+    }
+
+    #[test]
+    fn test_stemmer_keeps_original() {
+        let analyzer = Analyzer::language("english").unwrap();
+        let stem = format!("{}bear", STEM_PREFIX);
+        assert_eq!(
+            analyzer.analyze("bears bear"),
+            vec![
+                Token::new(stem.clone(), 0),
+                Token::new("bears", 0),
+                Token::new(stem.clone(), 1),
+                Token::new("bear", 1),
+            ]
+        );
+        assert_eq!(terms(analyzer.analyze_stemmed("bears bear")), vec![stem.clone(), stem]);
+        // An exact "bear" is a different term from the stem of "bears"
+        assert_eq!(terms(analyzer.analyze_exact("bear")), vec!["bear"]);
+    }
+}
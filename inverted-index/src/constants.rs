@@ -3,3 +3,6 @@ pub const DEFAULT_INDEX_STORE_WORKERS: u16 = 10;
 
 /// The default data directory when running tests. This is meant to provide a useful default for testing.
 pub const TEST_DEFAULT_DATA_DIRECTORY: &'static str = "/tmp/rustsearch";
+
+/// The field that plain text Documents are indexed under
+pub const DEFAULT_FIELD: &'static str = "message";
@@ -1,20 +1,41 @@
 use std::error::Error;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::str::FromStr;
 
+use serde_json::{self, Value};
+
+use analyzer::Analyzer;
+use constants;
+use index::InvertedIndexError;
+use mapping::{FieldType, Mappings, TypedValue};
+
+/// Number of positions left between the values of an array, so that a phrase can not match across
+/// the end of one value and the start of the next
+pub const ARRAY_POSITION_GAP: u64 = 100;
+
 /// Represents a discrete collection of text that we want to index
 pub struct Document {
     /// Unique numerical identifier for the document
     id: Option<u64>,
     /// The raw text of the Document
     raw: String,
-    /// HashMap that stores a term and a vector of all the locations it is found at in the document
-    locations: HashMap<String, Vec<u64>>,
+    /// Set if the raw text is indexed as plain text even though it may be JSON
+    plain_text: bool,
+    /// The values found in the Document, keyed by field name. Nested JSON objects are flattened
+    /// into dotted paths, and every element of an array is a separate value of the same field.
+    fields: BTreeMap<String, Vec<Value>>,
+    /// Stores, for each field, a term and a vector of all the locations it is found at in that field
+    locations: HashMap<String, HashMap<String, Vec<u64>>>,
+    /// The values of fields mapped as numbers, dates or booleans, which are stored as they are
+    /// rather than as terms
+    values: BTreeMap<String, Vec<TypedValue>>,
 }
 
 impl Document {
-    /// Returns a Document with the given ID and raw content
+    /// Returns a Document with the given ID and raw content, analyzed with the standard `Analyzer`.
+    /// If the content is a JSON object its keys become the Document's fields, otherwise it is
+    /// treated as plain text.
     ///
     /// # Arguments
     ///
@@ -25,19 +46,94 @@ impl Document {
     ///
     /// ```
     /// use inverted_index::document::Document;
-    /// let document = Document::new(0, "This is a test");
+    /// let document = Document::new(0, r#"{"message": "This is a test", "user": {"name": "fletcher"}}"#);
+    /// assert!(document.fields().contains_key("user.name"));
     /// ```
     pub fn new(id: u64, raw: &str) -> Document {
-        let mut document = Document {
+        let document = Document {
+            id: Some(id),
+            raw: raw.to_owned(),
+            plain_text: false,
+            fields: extract_fields(raw),
+            locations: HashMap::new(),
+            values: BTreeMap::new(),
+        };
+        document.analyze(&Analyzer::standard())
+    }
+
+    /// Returns a Document whose content is indexed as plain text in the `message` field, even if
+    /// it happens to be valid JSON. Used when the client has said the content is plain text.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A number that uniquely identifies this Document within the index
+    /// * `raw` - This is the raw content as supplied by the client
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::document::Document;
+    /// let document = Document::plain_text(0, "{}");
+    /// assert!(document.fields().contains_key("message"));
+    /// ```
+    pub fn plain_text(id: u64, raw: &str) -> Document {
+        let document = Document {
             id: Some(id),
             raw: raw.to_owned(),
+            plain_text: true,
+            fields: message_field(raw),
             locations: HashMap::new(),
+            values: BTreeMap::new(),
         };
-        Document::process(&mut document);
-        document
+        document.analyze(&Analyzer::standard())
+    }
+
+    /// Re-analyzes the fields of a Document with the given Analyzer, replacing any terms it
+    /// already had. Meant to be used as part of the Builder pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `analyzer` - The Analyzer the Document's Index is configured with
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// use inverted_index::document::Document;
+    /// let document = Document::new(0, "The molar bear").analyze(&Analyzer::stop());
+    /// ```
+    pub fn analyze(mut self, analyzer: &Analyzer) -> Document {
+        // With no mappings every field is text, which every value can be converted to
+        let _ = Document::process(&mut self, analyzer, &Mappings::new());
+        self
+    }
+
+    /// Re-analyzes the fields of a Document according to the Mappings of its Index. Text fields go
+    /// through the Analyzer, keyword fields are kept whole, and everything else is stored as a
+    /// typed value. Fields that are not mapped are treated as text. Meant to be used as part of
+    /// the Builder pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `analyzer` - The Analyzer the Document's Index is configured with
+    /// * `mappings` - The Mappings of the Document's Index
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// use inverted_index::document::Document;
+    /// use inverted_index::mapping::{FieldType, Mappings};
+    /// let mappings = Mappings::new().field("age", FieldType::Integer);
+    /// let document = Document::new(0, r#"{"age": 3}"#).analyze_mapped(&Analyzer::standard(), &mappings).unwrap();
+    /// ```
+    pub fn analyze_mapped(mut self, analyzer: &Analyzer, mappings: &Mappings) -> Result<Document, InvertedIndexError> {
+        Document::process(&mut self, analyzer, mappings)?;
+        Ok(self)
     }
 
-    /// Sets the raw field of a Document. Meant to be used as part of the Builder pattern.
+    /// Sets the raw field of a Document. Meant to be used as part of the Builder pattern. The terms
+    /// and values of the old content are dropped, so the Document has to be analyzed again.
     ///
     /// # Arguments
     ///
@@ -51,29 +147,141 @@ impl Document {
     /// ```
     pub fn raw<S: Into<String>>(mut self, r: S) -> Document {
         self.raw = r.into();
+        self.plain_text = false;
+        self.fields = extract_fields(&self.raw);
+        self.locations.clear();
+        self.values.clear();
         self
     }
 
-    /// Splits the raw field of a Document into Terms
+    /// Returns the ID of the Document, if it has been assigned one
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Returns the raw content of the Document as it was supplied by the client
+    pub fn content(&self) -> &str {
+        &self.raw
+    }
+
+    /// Returns true if the content is indexed as plain text, as made by `Document::plain_text`
+    pub fn is_plain_text(&self) -> bool {
+        self.plain_text
+    }
+
+    /// Returns the values of each field in the Document
+    pub fn fields(&self) -> &BTreeMap<String, Vec<Value>> {
+        &self.fields
+    }
+
+    /// Returns, for each field, the terms found in it and the offsets at which each one occurs
+    pub fn locations(&self) -> &HashMap<String, HashMap<String, Vec<u64>>> {
+        &self.locations
+    }
+
+    /// Returns the typed values of each field mapped as a number, date or boolean
+    pub fn values(&self) -> &BTreeMap<String, Vec<TypedValue>> {
+        &self.values
+    }
+
+    /// Splits each field of a Document into Terms, or converts it to a typed value, depending on
+    /// how it is mapped
     ///
     /// # Arguments
     ///
     /// * `doc` - Mutable reference to the Document we want to process
+    /// * `analyzer` - The Analyzer used to turn text fields into Terms
+    /// * `mappings` - The Mappings that say how each field is stored
     ///
-    fn process(doc: &mut Document) {
-        let results = split_on_whitespace(&doc.raw);
-        for (term, offset) in results {
-            if !doc.locations.contains_key(&term) {
-                doc.locations.insert(term.clone(), vec![]);
+    fn process(doc: &mut Document, analyzer: &Analyzer, mappings: &Mappings) -> Result<(), InvertedIndexError> {
+        doc.locations.clear();
+        doc.values.clear();
+        for (name, values) in &doc.fields {
+            let field_type = mappings.get(name).unwrap_or(FieldType::Text);
+            let mut terms: HashMap<String, Vec<u64>> = HashMap::new();
+            let mut base = 0;
+            for value in values {
+                let mut next = base;
+                match field_type.coerce(name, value)? {
+                    TypedValue::Text(text) => {
+                        for token in analyzer.analyze(&text) {
+                            let position = base + token.position;
+                            next = position + 1;
+                            terms.entry(token.term).or_insert_with(Vec::new).push(position);
+                        }
+                    }
+                    TypedValue::Keyword(keyword) => {
+                        terms.entry(keyword).or_insert_with(Vec::new).push(base);
+                        next = base + 1;
+                    }
+                    typed => {
+                        doc.values.entry(name.clone()).or_insert_with(Vec::new).push(typed);
+                    }
+                }
+                base = next + ARRAY_POSITION_GAP;
             }
-            match doc.locations.get_mut(&term) {
-                Some(v) => v.push(offset),
-                None => {}
+            if !terms.is_empty() {
+                doc.locations.insert(name.clone(), terms);
             }
         }
+        Ok(())
+    }
+}
+
+/// Returns the text that should be analyzed for a single field value. Strings are used as they
+/// are rather than with the quotes JSON would give them.
+pub fn value_to_text(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        ref other => other.to_string(),
+    }
+}
+
+/// Pulls the fields out of the raw content of a Document. JSON objects have each of their keys
+/// extracted; anything else is put into the `message` field, as the design doc describes.
+fn extract_fields(raw: &str) -> BTreeMap<String, Vec<Value>> {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(ref value) if value.is_object() => {
+            let mut fields = BTreeMap::new();
+            flatten_value("", value, &mut fields);
+            fields
+        }
+        _ => message_field(raw),
     }
 }
 
+/// Puts plain text into the default field
+fn message_field(raw: &str) -> BTreeMap<String, Vec<Value>> {
+    let mut fields = BTreeMap::new();
+    fields.insert(String::from(constants::DEFAULT_FIELD), vec![Value::String(raw.to_owned())]);
+    fields
+}
+
+/// Walks a JSON value, recording each scalar under the dotted path of keys that leads to it.
+/// Arrays contribute every element to the same path, and nulls are skipped.
+fn flatten_value(path: &str, value: &Value, fields: &mut BTreeMap<String, Vec<Value>>) {
+    match *value {
+        Value::Object(ref map) => {
+            for (key, child) in map {
+                let child_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten_value(&child_path, child, fields);
+            }
+        }
+        Value::Array(ref values) => {
+            for child in values {
+                flatten_value(path, child, fields);
+            }
+        }
+        Value::Null => {}
+        _ => {
+            fields.entry(path.to_owned()).or_insert_with(Vec::new).push(value.clone());
+        }
+    }
+}
 
 impl FromStr for Document {
     /// Implements FromStr for Document so that we can easily turn a `str` into a `Document`
@@ -99,7 +307,10 @@ impl FromStr for Document {
         Ok(Document {
             id: None,
             raw: s.to_owned(),
+            plain_text: false,
+            fields: extract_fields(s),
             locations: HashMap::new(),
+            values: BTreeMap::new(),
         })
     }
 }
@@ -186,4 +397,86 @@ mod tests {
         assert_eq!(results.len(), 4);
     }
 
+    #[test]
+    fn test_document_terms_are_analyzed() {
+        let new_document = Document::new(0, "Bear, bear!");
+        assert_eq!(new_document.locations["message"].len(), 1);
+        assert_eq!(new_document.locations["message"]["bear"], vec![0, 1]);
+    }
+
+    #[test]
+    fn test_reanalyze_document() {
+        let new_document = Document::new(0, "The molar bear").analyze(&Analyzer::stop());
+        assert!(!new_document.locations["message"].contains_key("the"));
+        assert_eq!(new_document.locations["message"]["bear"], vec![2]);
+    }
+
+    #[test]
+    fn test_raw_drops_old_terms_and_values() {
+        let mappings = Mappings::new().field("status", FieldType::Integer);
+        let new_document = Document::new(0, r#"{"status": 404, "message": "Not Found"}"#)
+            .analyze_mapped(&Analyzer::standard(), &mappings)
+            .unwrap()
+            .raw("A molar bear");
+        assert!(new_document.locations.is_empty());
+        assert!(new_document.values.is_empty());
+        let new_document = new_document.analyze(&Analyzer::standard());
+        assert!(new_document.locations["message"].contains_key("bear"));
+        assert!(!new_document.locations["message"].contains_key("found"));
+    }
+
+    #[test]
+    fn test_json_document_fields() {
+        let new_document = Document::new(
+            0,
+            r#"{"timestamp": 1513666331, "message": "A molar bear", "user": {"name": "Fletcher", "admin": true}, "tags": ["a", "b"], "missing": null}"#,
+        );
+        let fields: Vec<&String> = new_document.fields.keys().collect();
+        assert_eq!(fields, vec!["message", "tags", "timestamp", "user.admin", "user.name"]);
+        assert_eq!(new_document.fields["tags"].len(), 2);
+        assert_eq!(new_document.locations["user.name"]["fletcher"], vec![0]);
+        assert_eq!(new_document.locations["timestamp"]["1513666331"], vec![0]);
+    }
+
+    #[test]
+    fn test_array_values_are_separated() {
+        let new_document = Document::new(0, r#"{"tags": ["molar bear", "polar bear"]}"#);
+        assert_eq!(new_document.locations["tags"]["bear"], vec![1, 2 + ARRAY_POSITION_GAP + 1]);
+    }
+
+    #[test]
+    fn test_plain_text_falls_back_to_message() {
+        let new_document = Document::new(0, "Who wouldn't want to be a molar bear?");
+        assert_eq!(new_document.fields.len(), 1);
+        assert!(new_document.locations["message"].contains_key("molar"));
+
+        let new_document = Document::plain_text(0, r#"{"name": "bear"}"#);
+        assert_eq!(new_document.fields.keys().collect::<Vec<&String>>(), vec!["message"]);
+    }
+
+    #[test]
+    fn test_analyze_mapped_document() {
+        let mappings = Mappings::new()
+            .field("host", FieldType::Keyword)
+            .field("status", FieldType::Integer)
+            .field("timestamp", FieldType::Date);
+        let new_document = Document::new(
+            0,
+            r#"{"host": "Web-01.example.com", "status": 404, "timestamp": "1970-01-01T00:00:01Z", "message": "Not Found"}"#,
+        ).analyze_mapped(&Analyzer::standard(), &mappings)
+            .unwrap();
+        assert_eq!(new_document.locations["host"]["Web-01.example.com"], vec![0]);
+        assert!(!new_document.locations.contains_key("status"));
+        assert_eq!(new_document.values["status"], vec![TypedValue::Integer(404)]);
+        assert_eq!(new_document.values["timestamp"], vec![TypedValue::Date(1000)]);
+        assert_eq!(new_document.locations["message"]["found"], vec![1]);
+    }
+
+    #[test]
+    fn test_analyze_mapped_document_rejects_bad_value() {
+        let mappings = Mappings::new().field("status", FieldType::Integer);
+        let result = Document::new(0, r#"{"status": "teapot"}"#).analyze_mapped(&Analyzer::standard(), &mappings);
+        assert!(result.is_err());
+    }
+
 }
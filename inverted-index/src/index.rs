@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io;
 use std::error::Error;
 
 use rusqlite;
@@ -56,6 +57,21 @@ impl Error for InvertedIndexError {
 #[derive(Debug)]
 pub struct InvertedIndexError {
     details: String,
+    /// Set when the Index itself failed, such as a write to disk or a segment worker that has
+    /// stopped, rather than the request being at fault
+    internal: bool,
+}
+
+impl From<rusqlite::Error> for InvertedIndexError {
+    fn from(err: rusqlite::Error) -> InvertedIndexError {
+        InvertedIndexError::internal(&err.to_string())
+    }
+}
+
+impl From<InvertedIndexError> for io::Error {
+    fn from(err: InvertedIndexError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err.details)
+    }
 }
 
 impl InvertedIndexError {
@@ -72,7 +88,36 @@ impl InvertedIndexError {
     /// let new_index_error = InvertedIndexError::new("Test error");
     /// ```
     pub fn new(msg: &str) -> InvertedIndexError {
-        InvertedIndexError { details: msg.to_string() }
+        InvertedIndexError {
+            details: msg.to_string(),
+            internal: false,
+        }
+    }
+
+    /// Creates and returns an InvertedIndexError for a failure of the Index itself, such as its
+    /// storage or one of its workers, rather than of what it was asked to do
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - Text of the error message
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use inverted_index::index::*;
+    /// let error = InvertedIndexError::internal("Segment worker is no longer running");
+    /// assert!(error.is_internal());
+    /// ```
+    pub fn internal(msg: &str) -> InvertedIndexError {
+        InvertedIndexError {
+            details: msg.to_string(),
+            internal: true,
+        }
+    }
+
+    /// Returns true if the Index itself failed rather than the request being at fault
+    pub fn is_internal(&self) -> bool {
+        self.internal
     }
 }
 
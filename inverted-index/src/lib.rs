@@ -1,9 +1,17 @@
 extern crate rusqlite;
+extern crate rust_stemmers;
+extern crate serde_json;
+extern crate unicode_segmentation;
 
+pub mod analyzer;
 pub mod constants;
 pub mod document;
 pub mod index;
 pub mod manager;
+pub mod mapping;
+pub mod query;
+pub mod replication;
+pub mod scoring;
 pub mod shard;
 pub mod store;
 pub mod stores;
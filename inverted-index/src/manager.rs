@@ -1,15 +1,25 @@
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::sync::mpsc;
 
 use rusqlite;
 
+use analyzer::{Analyzer, Token};
 use document::Document;
 use constants;
+use index::InvertedIndexError;
+use mapping::Mappings;
+use query::{PreparedQuery, Query, SearchResults};
+use replication::{
+    OpLog, Operation, OperationKind, Replicate, ReplicationReport, Snapshot, WriteConsistency, WriteOutcome,
+    SNAPSHOT_OPLOG_FILENAME,
+};
+use scoring::{rank, Bm25, Scorer, Statistics};
 use shard;
-use stores::sqlite::queries::*;
+use store::IndexStore;
+use stores::sqlite::SQLiteStore;
 
 #[derive(Clone, Debug)]
 /// Enum that represents the various StorageEngines that can be used to store the InvertedIndexes
@@ -34,6 +44,8 @@ pub enum StorageEngine {
 pub struct Manager {
     /// Name of the `InvertedIndex` this Manager is responsible for
     index_name: String,
+    /// Number of the `Shard` within its Index
+    shard: u32,
     /// `Shard` type that this Manager manages
     shard_type: shard::ShardType,
     /// Base `Path` for where this Manager will keep all its data
@@ -46,6 +58,72 @@ pub struct Manager {
     workers: u16,
     /// The backing store for this Manager
     storage_engine: StorageEngine,
+    /// The Analyzer used on every Document written to, and all query text run against, this Index
+    analyzer: Analyzer,
+    /// The type of every field seen in this Index so far. Each segment keeps its own copy on disk.
+    mappings: Mappings,
+    /// BM25 parameters used to score search results
+    similarity: Bm25,
+    /// Log of every write committed to the Shard
+    oplog: OpLog,
+    /// Term of the Shard, as the metadata server last assigned it. Every Operation this copy logs
+    /// as a Primary is in this term.
+    term: u64,
+    /// Where committed writes are handed off to be sent to the Shard's Replicas, if anywhere
+    replication: Option<mpsc::Sender<Replicate>>,
+}
+
+/// Contains everything needed to start a Manager. Fields other than the name and data directory
+/// have defaults, so most callers only set the ones they care about.
+pub struct ManagerConfiguration {
+    /// Name of the `InvertedIndex` the Manager is responsible for
+    pub name: String,
+    /// Number of the Shard within its Index, starting at 0
+    pub shard: u32,
+    /// Root directory that holds all the data for the Manager
+    pub data_directory: PathBuf,
+    /// The storage engine backing the Manager
+    pub storage_engine: StorageEngine,
+    /// The type of `Shard` the Manager manages
+    pub shard_type: shard::ShardType,
+    /// The `Analyzer` the Index was configured with
+    pub analyzer: Analyzer,
+    /// The BM25 parameters the Index scores results with
+    pub similarity: Bm25,
+    /// Where the Manager hands off each committed write to be sent to Replicas. If None, writes
+    /// are only made to this copy of the Shard.
+    pub replication: Option<mpsc::Sender<Replicate>>,
+}
+
+impl ManagerConfiguration {
+    /// Creates and returns a ManagerConfiguration for Shard 0 of an Index, as a Primary stored in
+    /// SQLite, using the standard Analyzer and default BM25 parameters
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Anything that can be converted into a String to be the name of the `InvertedIndex`
+    /// * `data_directory` - Root directory that holds all the data for the Manager
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use std::path::PathBuf;
+    /// use inverted_index::manager::ManagerConfiguration;
+    /// let mut config = ManagerConfiguration::new("test_idx", PathBuf::from("/tmp/saga"));
+    /// config.shard = 2;
+    /// ```
+    pub fn new<S: Into<String>>(name: S, data_directory: PathBuf) -> ManagerConfiguration {
+        ManagerConfiguration {
+            name: name.into(),
+            shard: 0,
+            data_directory: data_directory,
+            storage_engine: StorageEngine::SQLite,
+            shard_type: shard::ShardType::Primary,
+            analyzer: Analyzer::standard(),
+            similarity: Bm25::default(),
+            replication: None,
+        }
+    }
 }
 
 impl Manager {
@@ -53,37 +131,45 @@ impl Manager {
     /// 
     /// # Arguments
     /// 
-    /// * `name` - Anything that can be converted into a String to be the name of the `InvertedIndex`
-    /// * `data_directory` - Root directory that holds all the data for this Manager
+    /// * `config` - A ManagerConfiguration struct
     /// * `chan` - Channel by which the Manager can receive commands
-    /// * `storage_engine` - The storage engine backing this Manager
-    /// * `shard_type` - The type of `Shard` this Manager 
     /// 
     /// # Examples
     /// 
     /// ```rust
     /// use std::path::PathBuf;
-    /// use inverted_index::{shard,constants};
+    /// use inverted_index::constants;
     /// use inverted_index::manager::*;
     /// use std::sync::mpsc;
     /// let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
-    /// let mgr = Manager::new("test_idx", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY), rx, StorageEngine::SQLite, shard::ShardType::Primary);
+    /// let config = ManagerConfiguration::new("test_idx", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY));
+    /// let mgr = Manager::new(config, rx);
     /// ```
-    pub fn new<S: Into<String>>(
-        name: S,
-        data_directory: PathBuf,
+    pub fn new(
+        config: ManagerConfiguration,
         chan: mpsc::Receiver<IndexCommand>,
-        storage_engine: StorageEngine,
-        shard_type: shard::ShardType,
     ) -> Result<thread::JoinHandle<()>, io::Error> {
+        // The log is kept apart from the segments, as every database file among those is one
+        let oplog_directory = config.data_directory.join("indices").join(&config.name).join("oplog");
+        fs::create_dir_all(&oplog_directory)?;
+        let oplog = OpLog::open(&oplog_directory.join(format!("{}.db", config.shard)))?;
+        // Shards start in term 1, until they are told otherwise
+        let term = oplog.last_term().max(1);
         let mut mgr = Manager {
-            index_name: name.into(),
-            data_directory: data_directory,
+            index_name: config.name,
+            shard: config.shard,
+            data_directory: config.data_directory,
             segments: vec![],
             receiver: chan,
             workers: constants::DEFAULT_INDEX_STORE_WORKERS,
-            storage_engine: storage_engine.clone(),
-            shard_type: shard_type,
+            storage_engine: config.storage_engine,
+            shard_type: config.shard_type,
+            analyzer: config.analyzer,
+            mappings: Mappings::new(),
+            similarity: config.similarity,
+            oplog: oplog,
+            term: term,
+            replication: config.replication,
         };
         mgr.create_data_directory()?;
         let mut existing_segments = mgr.list_segments()?;
@@ -91,17 +177,22 @@ impl Manager {
             mgr.initialize_segments();
             existing_segments = mgr.list_segments()?;
         }
-        let join_handle = thread::spawn(move || mgr.run(existing_segments, storage_engine));
-        Ok(join_handle)
-    }
-
-    fn run(&mut self, existing_segments: Vec<PathBuf>, storage_engine: StorageEngine) {
         for p in existing_segments {
+            let mut store = SQLiteStore::open(mgr.index_name.clone(), &p).map_err(InvertedIndexError::from)?;
+            let segment_mappings = store.mappings()?;
+            mgr.mappings.merge(&segment_mappings)?;
+            store.close()?;
+
             let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
-            let worker = IndexWorker::new(p, rx);
-            self.segments.push((tx, worker));
+            let worker = IndexWorker::new(mgr.index_name.clone(), p, rx)?;
+            mgr.segments.push((tx, worker));
         }
+        mgr.redo()?;
+        let join_handle = thread::spawn(move || mgr.run());
+        Ok(join_handle)
+    }
 
+    fn run(&mut self) {
         loop {
             match self.receiver.recv() {
                 Ok(msg) => {
@@ -110,21 +201,126 @@ impl Manager {
                             document,
                             response_channel,
                         } => {
-                            println!("Received IndexCommand");
-                            match response_channel {
-                                Some(ch) => {
-                                    match ch.send(true) {
-                                        Ok(r) => {
-                                            println!("Response sent");
-                                        }
-                                        Err(e) => {
-                                            println!("Error sending response: {}", e);
-                                        }
-                                    }
+                            let operation = OperationKind::index(&document).and_then(|kind| self.log(kind));
+                            match operation {
+                                Ok(operation) => {
+                                    respond(&response_channel, Ok(()));
+                                    self.replicate(operation, WriteConsistency::One, None);
+                                }
+                                Err(e) => respond(&response_channel, Err(e)),
+                            }
+                        }
+                        IndexCommand::GetDocument { id, response_channel } => {
+                            let command = IndexCommand::GetDocument {
+                                id: id,
+                                response_channel: response_channel,
+                            };
+                            if let Err(IndexCommand::GetDocument { response_channel, .. }) = self.forward(id, command) {
+                                let _ = response_channel.send(Err(InvertedIndexError::new(
+                                    "Segment worker is no longer running",
+                                )));
+                            }
+                        }
+                        IndexCommand::DeleteDocument { id, response_channel } => {
+                            match self.log(OperationKind::Delete { id: id }) {
+                                Ok(operation) => {
+                                    let _ = response_channel.send(Ok(operation.is_some()));
+                                    self.replicate(operation, WriteConsistency::One, None);
+                                }
+                                Err(e) => {
+                                    let _ = response_channel.send(Err(e));
                                 }
-                                None => {}
                             }
                         }
+                        IndexCommand::Write {
+                            operation,
+                            consistency,
+                            response_channel,
+                        } => match self.log(operation) {
+                            Ok(operation) => self.replicate(operation, consistency, Some(response_channel)),
+                            Err(e) => {
+                                let _ = response_channel.send(Err(e));
+                            }
+                        },
+                        IndexCommand::ApplyOperation {
+                            operation,
+                            response_channel,
+                        } => {
+                            let result = self.apply(operation);
+                            if let Err(ref e) = result {
+                                println!("Shard {} of {} could not apply an operation: {}", self.shard, self.index_name, e);
+                            }
+                            let _ = response_channel.send(result);
+                        }
+                        IndexCommand::SetTerm { term } => {
+                            self.term = self.term.max(term);
+                        }
+                        IndexCommand::LastSequence { response_channel } => {
+                            let _ = response_channel.send(self.oplog.last_seq());
+                        }
+                        IndexCommand::OperationsSince {
+                            seq,
+                            limit,
+                            max_bytes,
+                            response_channel,
+                        } => {
+                            let _ = response_channel.send(self.oplog.since(seq, limit, max_bytes));
+                        }
+                        IndexCommand::Snapshot {
+                            directory,
+                            response_channel,
+                        } => {
+                            self.snapshot(&directory, response_channel);
+                        }
+                        IndexCommand::Restore {
+                            directory,
+                            response_channel,
+                        } => {
+                            let result = self.restore(&directory);
+                            if let Err(ref e) = result {
+                                println!("Shard {} of {} could not be restored: {}", self.shard, self.index_name, e);
+                            }
+                            let _ = response_channel.send(result);
+                        }
+                        IndexCommand::RemoveMappings { response_channel, .. } => {
+                            let _ = response_channel.send(Err(InvertedIndexError::new(
+                                "Mappings are only removed by their Manager",
+                            )));
+                        }
+                        IndexCommand::RestoreSegment { response_channel, .. } => {
+                            let _ = response_channel.send(Err(InvertedIndexError::new(
+                                "Segments are copied through their Manager",
+                            )));
+                        }
+                        IndexCommand::Search {
+                            query,
+                            from,
+                            size,
+                            response_channel,
+                        } => {
+                            let result = self.search(&query, from, size);
+                            let _ = response_channel.send(result);
+                        }
+                        IndexCommand::SearchSegment { response_channel, .. } => {
+                            let _ = response_channel.send(Err(InvertedIndexError::new(
+                                "Segments are searched through their Manager",
+                            )));
+                        }
+                        IndexCommand::SegmentStatistics { response_channel, .. } => {
+                            let _ = response_channel.send(Err(InvertedIndexError::new(
+                                "Segment statistics are gathered through their Manager",
+                            )));
+                        }
+                        IndexCommand::PutMappings {
+                            mappings,
+                            response_channel,
+                        } => {
+                            let result = self.put_mappings(mappings);
+                            let _ = response_channel.send(result);
+                        }
+                        IndexCommand::GetMappings { response_channel } => {
+                            let _ = response_channel.send(self.mappings.clone());
+                        }
                         IndexCommand::Stats { response_channel } => {
                             match response_channel.send(IndexStats) {
                                 Ok(r) => {}
@@ -137,32 +333,462 @@ impl Manager {
                                 Err(e) => {}
                             }
                         }
+                        IndexCommand::Analyze { text, response_channel } => {
+                            let _ = response_channel.send(self.analyzer.analyze(&text));
+                        }
+                        IndexCommand::Shutdown { response_channel } => {
+                            self.shutdown();
+                            let _ = response_channel.send(());
+                            return;
+                        }
                     }
                 }
                 Err(e) => {
-                    println!("RecvError: {}", e);
+                    // Every Sender has been dropped, so nothing can reach this Manager anymore
+                    println!("Manager for {} shutting down: {}", self.index_name, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Stops every segment worker and waits for them to close their stores
+    fn shutdown(&mut self) {
+        for (tx, worker) in self.segments.drain(..) {
+            // Dropping the only Sender to a worker ends its loop
+            drop(tx);
+            if worker.thread.join().is_err() {
+                println!("A segment worker of {} panicked while shutting down", self.index_name);
+            }
+        }
+    }
+
+    /// Commits a write to this copy of the Shard and adds it to the end of the OpLog. Writes are
+    /// committed one at a time, so the log's order is the order they were made in. Returns None
+    /// if the write changed nothing, which is the case when a delete finds no Document.
+    fn log(&mut self, kind: OperationKind) -> Result<Option<Operation>, InvertedIndexError> {
+        let operation = Operation {
+            seq: self.oplog.last_seq() + 1,
+            term: self.term,
+            prev_term: self.oplog.last_term(),
+            kind: kind,
+        };
+        if !self.write(&operation)? {
+            self.oplog.truncate(operation.seq - 1)?;
+            return Ok(None);
+        }
+        Ok(Some(operation))
+    }
+
+    /// Adds an Operation to the end of the OpLog and then commits it to the segments. It is
+    /// logged first so that a segment never has a write the log is missing. If the commit fails
+    /// the Operation is taken back out of the log, and if the Manager stops before it can commit,
+    /// `redo` commits it when the Manager starts again. Returns false if the write changed
+    /// nothing.
+    fn write(&mut self, operation: &Operation) -> Result<bool, InvertedIndexError> {
+        self.oplog.append(operation)?;
+        match self.commit(&operation.kind) {
+            Ok(changed) => Ok(changed),
+            Err(e) => {
+                if let Err(undo) = self.oplog.truncate(operation.seq - 1) {
+                    println!(
+                        "Could not take operation {} of shard {} of {} back out of the log: {}",
+                        operation.seq, self.shard, self.index_name, undo
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Commits the last write in the OpLog again, in case the Manager stopped between logging it
+    /// and committing it. Writes replace or delete a whole Document, so committing one twice
+    /// changes nothing.
+    fn redo(&mut self) -> Result<(), InvertedIndexError> {
+        let last_seq = self.oplog.last_seq();
+        if last_seq == 0 {
+            return Ok(());
+        }
+        for operation in self.oplog.since(last_seq - 1, 1, usize::MAX)? {
+            self.commit(&operation.kind)?;
+        }
+        Ok(())
+    }
+
+    /// Applies an Operation a Primary sent to this Replica. Operations the Replica already has,
+    /// with the same sequence number and term, are skipped, and one that would leave a gap in the
+    /// OpLog is refused. So is one the Replica has a different Operation in its place for, or one
+    /// that doesn't follow on from the Replica's last Operation's term, as the Replica has then
+    /// taken writes from a Primary that was replaced and has to be recovered from its current one.
+    fn apply(&mut self, operation: Operation) -> Result<(), InvertedIndexError> {
+        let last_seq = self.oplog.last_seq();
+        if operation.seq <= last_seq {
+            let term = self.oplog.term_at(operation.seq)?;
+            if term == Some(operation.term) {
+                return Ok(());
+            }
+            return Err(InvertedIndexError::new(&format!(
+                "Shard {} of {} has operation {} from term {}, not from term {}",
+                self.shard,
+                self.index_name,
+                operation.seq,
+                term.unwrap_or(0),
+                operation.term
+            )));
+        }
+        if operation.seq != last_seq + 1 {
+            return Err(InvertedIndexError::new(&format!(
+                "Shard {} of {} is missing operations {} to {}",
+                self.shard,
+                self.index_name,
+                last_seq + 1,
+                operation.seq - 1
+            )));
+        }
+        if operation.prev_term != self.oplog.last_term() {
+            return Err(InvertedIndexError::new(&format!(
+                "Operation {} follows one from term {}, but shard {} of {} has operation {} from term {}",
+                operation.seq,
+                operation.prev_term,
+                self.shard,
+                self.index_name,
+                last_seq,
+                self.oplog.last_term()
+            )));
+        }
+        self.write(&operation)?;
+        Ok(())
+    }
+
+    /// Copies the Shard into a directory for a new Replica to start from. Read transactions are
+    /// opened on the OpLog and every segment before any later write is committed, so the copy
+    /// holds exactly the Operations up to the last one the OpLog's transaction sees. They are
+    /// copied from another thread, which sends the response, so writes carry on in the meantime.
+    fn snapshot(&self, directory: &Path, response_channel: mpsc::Sender<Result<Snapshot, InvertedIndexError>>) {
+        let (seq, copies) = match self.start_snapshot(directory) {
+            Ok(started) => started,
+            Err(e) => {
+                let _ = response_channel.send(Err(e));
+                return;
+            }
+        };
+        thread::spawn(move || {
+            let mut files = vec![];
+            for (connection, path) in copies {
+                if let Err(e) = connection.backup(rusqlite::DatabaseName::Main, &path, None) {
+                    println!("There was an error copying {}: {}", path.display(), e);
+                    let _ = response_channel.send(Err(InvertedIndexError::from(e)));
+                    return;
                 }
+                if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                    files.push(name.to_owned());
+                }
+            }
+            let _ = response_channel.send(Ok(Snapshot { seq: seq, files: files }));
+        });
+    }
+
+    /// Opens a read transaction on the OpLog and on each segment. Returns the sequence number the
+    /// Snapshot is taken at, and each connection along with the file in the Snapshot's directory
+    /// it is copied to.
+    fn start_snapshot(&self, directory: &Path) -> Result<(u64, Vec<(rusqlite::Connection, PathBuf)>), InvertedIndexError> {
+        fs::create_dir_all(directory)
+            .map_err(|e| InvertedIndexError::internal(&format!("Could not create {}: {}", directory.display(), e)))?;
+        let (oplog, seq) = self.oplog.read_snapshot()?;
+        let mut copies = vec![(oplog, directory.join(SNAPSHOT_OPLOG_FILENAME))];
+        for &(_, ref worker) in &self.segments {
+            let name = match worker.database_path.file_name() {
+                Some(name) => name.to_owned(),
+                None => return Err(InvertedIndexError::internal("Segment has no file name")),
+            };
+            copies.push((SQLiteStore::open_read_snapshot(&worker.database_path)?, directory.join(name)));
+        }
+        Ok((seq, copies))
+    }
+
+    /// Replaces everything in this copy of the Shard with a Snapshot of another copy. Returns the
+    /// sequence number of the last Operation it now has.
+    fn restore(&mut self, directory: &Path) -> Result<u64, InvertedIndexError> {
+        let mut sources = vec![];
+        for &(_, ref worker) in &self.segments {
+            let source = match worker.database_path.file_name() {
+                Some(name) => directory.join(name),
+                None => return Err(InvertedIndexError::internal("Segment has no file name")),
+            };
+            if !source.exists() {
+                return Err(InvertedIndexError::internal(&format!("Snapshot has no copy of segment {}", source.display())));
+            }
+            sources.push(source);
+        }
+
+        let mut mappings = Mappings::new();
+        for (&(ref tx, _), source) in self.segments.iter().zip(sources.iter()) {
+            let (restore_tx, restore_rx) = mpsc::channel();
+            tx.send(IndexCommand::RestoreSegment {
+                path: source.clone(),
+                response_channel: restore_tx,
+            }).map_err(|_| InvertedIndexError::internal("Segment worker is no longer running"))?;
+            restore_rx
+                .recv()
+                .map_err(|_| InvertedIndexError::internal("Segment worker is no longer running"))??;
+            let mut store = SQLiteStore::open(self.index_name.clone(), source)?;
+            mappings.merge(&store.mappings()?)?;
+            store.close()?;
+        }
+        self.mappings = mappings;
+        self.oplog.restore(&directory.join(SNAPSHOT_OPLOG_FILENAME))?;
+        self.term = self.term.max(self.oplog.last_term());
+        Ok(self.oplog.last_seq())
+    }
+
+    /// Writes an Operation to the segment that holds its Document and waits until it is
+    /// committed. Returns false if a delete found no Document to delete.
+    fn commit(&mut self, kind: &OperationKind) -> Result<bool, InvertedIndexError> {
+        let stopped = || InvertedIndexError::internal("Segment worker is no longer running");
+        match *kind {
+            OperationKind::Index {
+                id,
+                ref content,
+                plain_text,
+            } => {
+                let document = if plain_text {
+                    Document::plain_text(id, content)
+                } else {
+                    Document::new(id, content)
+                };
+                let (id, document) = self.prepare_document(document)?;
+                let (response_tx, response_rx) = mpsc::channel();
+                let command = IndexCommand::IndexDocument {
+                    document: document,
+                    response_channel: Some(response_tx),
+                };
+                self.forward(id, command).map_err(|_| stopped())?;
+                response_rx.recv().map_err(|_| stopped())??;
+                Ok(true)
+            }
+            OperationKind::Delete { id } => {
+                let (response_tx, response_rx) = mpsc::channel();
+                let command = IndexCommand::DeleteDocument {
+                    id: id,
+                    response_channel: response_tx,
+                };
+                self.forward(id, command).map_err(|_| stopped())?;
+                response_rx.recv().map_err(|_| stopped())?
+            }
+        }
+    }
+
+    /// Hands a logged write off to be sent to the Shard's Replicas, which answers the client once
+    /// enough of them have it. With nowhere to hand it off to, the client is answered here.
+    fn replicate(
+        &self,
+        operation: Option<Operation>,
+        consistency: WriteConsistency,
+        response_channel: Option<mpsc::Sender<Result<WriteOutcome, InvertedIndexError>>>,
+    ) {
+        let mut outcome = WriteOutcome {
+            seq: 0,
+            found: false,
+            replication: ReplicationReport {
+                consistency: consistency,
+                copies: 1,
+                successful: 1,
+            },
+        };
+        let mut response_channel = response_channel;
+        if let Some(operation) = operation {
+            outcome.seq = operation.seq;
+            outcome.found = true;
+            if let Some(ref replication) = self.replication {
+                let replicate = Replicate {
+                    operation: operation,
+                    consistency: consistency,
+                    response_channel: response_channel,
+                };
+                match replication.send(replicate) {
+                    Ok(()) => return,
+                    Err(mpsc::SendError(replicate)) => {
+                        println!("Writes to shard {} of {} are no longer being replicated", self.shard, self.index_name);
+                        response_channel = replicate.response_channel;
+                    }
+                }
+            }
+        }
+        if let Some(response_channel) = response_channel {
+            let _ = response_channel.send(Ok(outcome));
+        }
+    }
+
+    /// Sends a command about a single Document to the segment that holds it. If the segment's
+    /// worker has stopped, the command is handed back so its sender can be told.
+    fn forward(&self, id: u64, command: IndexCommand) -> Result<(), IndexCommand> {
+        let segment = self.segment_for(id);
+        self.segments[segment]
+            .0
+            .send(command)
+            .map_err(|mpsc::SendError(command)| command)
+    }
+
+    /// Checks a Document against the Index's Mappings, maps any fields that are new, and analyzes
+    /// it. Returns the Document's ID along with the analyzed Document.
+    fn prepare_document(&mut self, document: Document) -> Result<(u64, Document), InvertedIndexError> {
+        let id = match document.id() {
+            Some(id) => id,
+            None => {
+                return Err(InvertedIndexError::new("Document must have an id to be indexed"));
+            }
+        };
+        let new_fields = self.mappings.dynamic_mappings(&document)?;
+        if !new_fields.is_empty() {
+            self.put_mappings(new_fields)?;
+        }
+        let document = document.analyze_mapped(&self.analyzer, &self.mappings)?;
+        Ok((id, document))
+    }
+
+    /// Adds fields to the Index's Mappings and writes them to every segment. Mappings that conflict
+    /// with a field's existing type are rejected and nothing is written. If a segment fails to save
+    /// the new fields, they are taken back out of the segments that did save them, so every
+    /// segment keeps the same Mappings.
+    fn put_mappings(&mut self, mappings: Mappings) -> Result<(), InvertedIndexError> {
+        let mut merged = self.mappings.clone();
+        merged.merge(&mappings)?;
+        // Only the fields that are new are written, so undoing the write can't remove a field the
+        // Index already had
+        let mut added = Mappings::new();
+        for (name, field_type) in mappings.iter() {
+            if self.mappings.get(name).is_none() {
+                added = added.field(name.clone(), field_type.clone());
+            }
+        }
+        if added.is_empty() {
+            return Ok(());
+        }
+
+        let mut responses = vec![];
+        for &(ref tx, _) in &self.segments {
+            let (response_tx, response_rx) = mpsc::channel();
+            let sent = tx.send(IndexCommand::PutMappings {
+                mappings: added.clone(),
+                response_channel: response_tx,
+            });
+            responses.push(sent.ok().map(|_| response_rx));
+        }
+        let mut saved = vec![];
+        let mut failure = None;
+        for (segment, response) in responses.into_iter().enumerate() {
+            match response.map(|response| response.recv()) {
+                Some(Ok(Ok(()))) => saved.push(segment),
+                Some(Ok(Err(e))) => failure = Some(e),
+                Some(Err(_)) | None => failure = Some(InvertedIndexError::internal("Segment worker is no longer running")),
+            }
+        }
+        if let Some(e) = failure {
+            for segment in saved {
+                let (response_tx, response_rx) = mpsc::channel();
+                let removed = self.segments[segment]
+                    .0
+                    .send(IndexCommand::RemoveMappings {
+                        mappings: added.clone(),
+                        response_channel: response_tx,
+                    })
+                    .ok()
+                    .and_then(|_| response_rx.recv().ok());
+                if removed.map_or(true, |result| result.is_err()) {
+                    println!("Segment {} of {} kept mappings that could not be saved everywhere", segment, self.index_name);
+                }
+            }
+            return Err(e);
+        }
+
+        self.mappings = merged;
+        Ok(())
+    }
+
+    /// Prepares a Query with the Index's Analyzer and Mappings and runs it on every segment.
+    /// This takes two rounds: the first gathers the statistics of the Query's terms from every
+    /// segment and adds them up, and the second runs the Query with a Scorer built from those
+    /// totals, so that a Document scores the same no matter which segment it landed in. Each
+    /// segment only sends back its `from + size` best Documents, since no others can make the page.
+    fn search(&self, query: &Query, from: usize, size: usize) -> Result<SearchResults, InvertedIndexError> {
+        let prepared = query.prepare(&self.analyzer, &self.mappings)?;
+        let terms = prepared.terms();
+
+        let mut statistics = Statistics::default();
+        for segment_statistics in self.ask_segments(|response_channel| IndexCommand::SegmentStatistics {
+            terms: terms.clone(),
+            response_channel: response_channel,
+        })? {
+            statistics.merge(&segment_statistics);
+        }
+
+        let scorer = Scorer::new(self.similarity, statistics);
+        let limit = from.saturating_add(size);
+        let mut total = 0;
+        let mut hits = vec![];
+        for segment_results in self.ask_segments(|response_channel| IndexCommand::SearchSegment {
+            query: prepared.clone(),
+            scorer: scorer.clone(),
+            limit: limit,
+            response_channel: response_channel,
+        })? {
+            total += segment_results.total;
+            hits.extend(segment_results.hits);
+        }
+        rank(&mut hits);
+        Ok(SearchResults {
+            total: total,
+            hits: hits.into_iter().skip(from).take(size).collect(),
+        })
+    }
+
+    /// Sends a command to every segment at once and waits for all of their answers
+    fn ask_segments<T, F>(&self, command: F) -> Result<Vec<T>, InvertedIndexError>
+    where
+        F: Fn(mpsc::Sender<Result<T, InvertedIndexError>>) -> IndexCommand,
+    {
+        let mut responses = vec![];
+        for &(ref tx, _) in &self.segments {
+            let (response_tx, response_rx) = mpsc::channel();
+            tx.send(command(response_tx))
+                .map_err(|_| InvertedIndexError::internal("Segment worker is no longer running"))?;
+            responses.push(response_rx);
+        }
+        let mut results = vec![];
+        for response in responses {
+            match response.recv() {
+                Ok(result) => results.push(result?),
+                Err(_) => return Err(InvertedIndexError::internal("Segment worker is no longer running")),
             }
         }
+        Ok(results)
+    }
+
+    /// Picks the segment a Document belongs in. Documents are spread across segments by their
+    /// ID modulo the number of segments, which stays stable across restarts as long as the number
+    /// of segments does not change.
+    fn segment_for(&self, id: u64) -> usize {
+        (id % self.segments.len() as u64) as usize
     }
 
-    /// Lists all the segments of a Shard, which translates to just an enumeration of the files
-    /// in the directory at the time
+    /// Lists all the segments of a Shard, which translates to just an enumeration of the database
+    /// files in the directory at the time. The SQLite WAL and shared memory files that live next to
+    /// each database are skipped, and the result is ordered by segment number so that Documents
+    /// keep mapping to the same segment.
     fn list_segments(&self) -> io::Result<Vec<PathBuf>> {
         let mut results = vec![];
-        let segment_path: PathBuf = [
-            self.data_directory.to_str().unwrap(),
-            "indices",
-            &self.index_name,
-            "segments",
-            &self.shard_type.to_string(),
-        ].iter()
-            .collect();
-        for entry in fs::read_dir(segment_path)? {
+        for entry in fs::read_dir(self.segment_directory())? {
             let entry = entry?;
             let dir = entry.path();
-            results.push(dir);
+            if dir.extension().map_or(false, |ext| ext == "db") {
+                results.push(dir);
+            }
         }
+        results.sort_by_key(|p| {
+            p.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u16>().ok())
+        });
         Ok(results)
     }
 
@@ -171,74 +797,275 @@ impl Manager {
     fn initialize_segments(&self) {
         for num in 0..self.workers {
             let filename = format!("{}.db", num.to_string());
-            let segment_path: PathBuf = [
-                self.data_directory.to_str().unwrap(),
-                "indices",
-                &self.index_name,
-                "segments",
-                &self.shard_type.to_string(),
-                &filename,
-            ].iter()
-                .collect();
+            let segment_path = self.segment_directory().join(&filename);
             match rusqlite::Connection::open(segment_path) {
                 Ok(conn) => {
-                    for query in QUERIES_INITIALIZE_INDEX_DB {
-                        match conn.execute(query, &[]) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                println!(
-                                    "There was an error executing query: {:?}. Error was: {:?}",
-                                    query,
-                                    e
-                                );
-                            }
+                    match SQLiteStore::initialize(&conn) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("There was an error initializing segment {}. Error was: {:?}", filename, e);
                         }
                     }
                 }
-                Err(e) => {}
+                Err(e) => {
+                    println!("There was an error creating segment {}. Error was: {:?}", filename, e);
+                }
             }
         }
     }
 
     /// Convenience function to create the needed data directory
     fn create_data_directory(&self) -> io::Result<()> {
-        let segment_path: PathBuf = [
-            self.data_directory.to_str().unwrap(),
-            "indices",
-            &self.index_name,
-            "segments",
-            &self.shard_type.to_string(),
-        ].iter()
-            .collect();
-        fs::create_dir_all(segment_path)?;
+        fs::create_dir_all(self.segment_directory())?;
         Ok(())
     }
+
+    /// Directory that holds the segment database files of this Manager's Shard
+    fn segment_directory(&self) -> PathBuf {
+        self.data_directory
+            .join("indices")
+            .join(&self.index_name)
+            .join("segments")
+            .join(self.shard_type.to_string())
+            .join(self.shard.to_string())
+    }
 }
 
+/// An IndexWorker owns a single segment of a Shard. It has its own thread and its own store
+/// opened on the segment's database file, and writes the Documents the Manager routes to it.
 pub struct IndexWorker {
     thread: thread::JoinHandle<()>,
     database_path: PathBuf,
 }
 
 impl IndexWorker {
-    pub fn new(path: PathBuf, rx: mpsc::Receiver<IndexCommand>) -> IndexWorker {
-        let thread = thread::spawn(move || loop {
-            let command = rx.recv();
+    /// Opens the store for a segment and starts the thread that services it
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the `InvertedIndex` the segment belongs to
+    /// * `path` - Path to the segment's database file
+    /// * `rx` - Channel on which the worker receives commands from its Manager
+    pub fn new<S: Into<String>>(
+        name: S,
+        path: PathBuf,
+        rx: mpsc::Receiver<IndexCommand>,
+    ) -> Result<IndexWorker, InvertedIndexError> {
+        let mut store = SQLiteStore::open(name, &path)?;
+        let thread = thread::spawn(move || {
+            for command in rx.iter() {
+                match command {
+                    IndexCommand::IndexDocument {
+                        document,
+                        response_channel,
+                    } => {
+                        let result = store.save_document(document);
+                        if let Err(ref e) = result {
+                            println!("There was an error saving a document: {}", e);
+                        }
+                        respond(&response_channel, result);
+                    }
+                    IndexCommand::PutMappings {
+                        mappings,
+                        response_channel,
+                    } => {
+                        let result = store.save_mappings(&mappings);
+                        if let Err(ref e) = result {
+                            println!("There was an error saving mappings: {}", e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::RemoveMappings {
+                        mappings,
+                        response_channel,
+                    } => {
+                        let result = store.delete_mappings(&mappings);
+                        if let Err(ref e) = result {
+                            println!("There was an error removing mappings: {}", e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::GetDocument { id, response_channel } => {
+                        let result = store.document_by_id(id);
+                        if let Err(ref e) = result {
+                            println!("There was an error fetching document {}: {}", id, e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::DeleteDocument { id, response_channel } => {
+                        let result = store.delete_document_by_id(id);
+                        if let Err(ref e) = result {
+                            println!("There was an error deleting document {}: {}", id, e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::SegmentStatistics {
+                        terms,
+                        response_channel,
+                    } => {
+                        let result = store.statistics(&terms);
+                        if let Err(ref e) = result {
+                            println!("There was an error gathering segment statistics: {}", e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::SearchSegment {
+                        query,
+                        scorer,
+                        limit,
+                        response_channel,
+                    } => {
+                        let result = store.search(&query, &scorer, limit);
+                        if let Err(ref e) = result {
+                            println!("There was an error searching a segment: {}", e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    IndexCommand::RestoreSegment { path, response_channel } => {
+                        let result = store.restore(&path);
+                        if let Err(ref e) = result {
+                            println!("There was an error restoring a segment from {}: {}", path.display(), e);
+                        }
+                        let _ = response_channel.send(result);
+                    }
+                    _ => {}
+                }
+            }
+            let _ = store.close();
         });
-        IndexWorker {
+        Ok(IndexWorker {
             thread: thread,
             database_path: path,
+        })
+    }
+}
+
+/// Sends the outcome of a write back to whoever asked for it, if anyone did
+fn respond(
+    response_channel: &Option<mpsc::Sender<Result<(), InvertedIndexError>>>,
+    result: Result<(), InvertedIndexError>,
+) {
+    if let Some(ref ch) = *response_channel {
+        if let Err(e) = ch.send(result) {
+            println!("Error sending response: {}", e);
         }
     }
 }
 
 pub enum IndexCommand {
+    /// Writes a Document to the segment picked by its ID. The response is sent once the write has
+    /// been committed, or carries the error that stopped it. Sent to a Manager, the write is
+    /// also logged and replicated, but without waiting for any Replica.
     IndexDocument {
         document: Document,
-        response_channel: Option<mpsc::Sender<bool>>,
+        response_channel: Option<mpsc::Sender<Result<(), InvertedIndexError>>>,
+    },
+    /// Fetches a Document by ID from the segment that holds it. The response is None if there
+    /// is no such Document.
+    GetDocument {
+        id: u64,
+        response_channel: mpsc::Sender<Result<Option<Document>, InvertedIndexError>>,
+    },
+    /// Deletes a Document by ID from the segment that holds it. The response says whether there
+    /// was a Document to delete.
+    DeleteDocument {
+        id: u64,
+        response_channel: mpsc::Sender<Result<bool, InvertedIndexError>>,
+    },
+    /// Commits and logs a write to a Primary, then answers once as many copies of the Shard have
+    /// it as `consistency` asks for. The response says how many did.
+    Write {
+        operation: OperationKind,
+        consistency: WriteConsistency,
+        response_channel: mpsc::Sender<Result<WriteOutcome, InvertedIndexError>>,
+    },
+    /// Applies a write its Primary logged to a Replica. Operations must arrive in order; one the
+    /// Replica already has is skipped, and one that leaves a gap or conflicts with the Replica's
+    /// OpLog is refused.
+    ApplyOperation {
+        operation: Operation,
+        response_channel: mpsc::Sender<Result<(), InvertedIndexError>>,
+    },
+    /// Tells the Shard which term it is in, so that it logs its writes in that term if it is a
+    /// Primary. Terms only go forward, so an older one is ignored.
+    SetTerm { term: u64 },
+    /// Returns the sequence number of the last Operation the Shard has, or 0 if it has none. The
+    /// metadata server asks each Replica for this to find the most up to date one to promote.
+    LastSequence { response_channel: mpsc::Sender<u64> },
+    /// Returns up to `limit` of the Operations in the Shard's OpLog after `seq`, in order, which
+    /// is how a Replica that fell behind catches up. Fewer are returned once they add up to more
+    /// than `max_bytes` of JSON.
+    OperationsSince {
+        seq: u64,
+        limit: usize,
+        max_bytes: usize,
+        response_channel: mpsc::Sender<Result<Vec<Operation>, InvertedIndexError>>,
+    },
+    /// Copies the Shard into a directory as a `Snapshot` a new Replica can start from
+    Snapshot {
+        directory: PathBuf,
+        response_channel: mpsc::Sender<Result<Snapshot, InvertedIndexError>>,
+    },
+    /// Replaces everything in the Shard with a `Snapshot` in a directory. The response is the
+    /// sequence number of the last Operation the Shard then has.
+    Restore {
+        directory: PathBuf,
+        response_channel: mpsc::Sender<Result<u64, InvertedIndexError>>,
+    },
+    /// Replaces a single segment with its copy in a Snapshot. A Manager sends this to its workers
+    /// while it restores a Snapshot.
+    RestoreSegment {
+        path: PathBuf,
+        response_channel: mpsc::Sender<Result<(), InvertedIndexError>>,
     },
     Stats { response_channel: mpsc::Sender<IndexStats>, },
     Ready { response_channel: mpsc::Sender<bool>, },
+    /// Adds fields to the Index's Mappings. Fails if any of them are already mapped as a different
+    /// type.
+    PutMappings {
+        mappings: Mappings,
+        response_channel: mpsc::Sender<Result<(), InvertedIndexError>>,
+    },
+    /// Removes fields from a single segment's Mappings. A Manager sends this to its workers to
+    /// undo Mappings that could not be saved to every segment.
+    RemoveMappings {
+        mappings: Mappings,
+        response_channel: mpsc::Sender<Result<(), InvertedIndexError>>,
+    },
+    /// Runs a Query against every segment of the Index and returns `size` of the matching
+    /// Documents, skipping the `from` most relevant
+    Search {
+        query: Query,
+        from: usize,
+        size: usize,
+        response_channel: mpsc::Sender<Result<SearchResults, InvertedIndexError>>,
+    },
+    /// Gathers the statistics of a single segment needed to score the given field and term
+    /// pairs. A Manager sends this to its workers while it services a Search.
+    SegmentStatistics {
+        terms: Vec<(String, String)>,
+        response_channel: mpsc::Sender<Result<Statistics, InvertedIndexError>>,
+    },
+    /// Runs an already prepared Query against a single segment, scoring with statistics of the
+    /// whole Shard, and returns the number of matches with the `limit` most relevant of them. A
+    /// Manager sends this to its workers while it services a Search.
+    SearchSegment {
+        query: PreparedQuery,
+        scorer: Scorer,
+        limit: usize,
+        response_channel: mpsc::Sender<Result<SearchResults, InvertedIndexError>>,
+    },
+    /// Returns the Index's current Mappings
+    GetMappings { response_channel: mpsc::Sender<Mappings> },
+    /// Runs text through the Index's Analyzer, which is what query text must go through to line
+    /// up with the terms that were stored
+    Analyze {
+        text: String,
+        response_channel: mpsc::Sender<Vec<Token>>,
+    },
+    /// Stops the Manager and all of its segment workers. The response is sent once every segment
+    /// has been closed, after which nothing else sent to the Manager is answered.
+    Shutdown { response_channel: mpsc::Sender<()> },
 }
 
 pub struct IndexStats;
@@ -246,17 +1073,15 @@ pub struct IndexStats;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mapping::FieldType;
     use std::path::PathBuf;
 
     #[test]
     fn test_create_manager() {
         let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
         match Manager::new(
-            "test_idx",
-            PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY),
+            ManagerConfiguration::new("test_idx", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
             rx,
-            StorageEngine::SQLite,
-            shard::ShardType::Primary,
         ) {
             Ok(join_handle) => {
                 println!("Join handle is: {:?}", join_handle);
@@ -271,4 +1096,437 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn test_index_document() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(
+            ManagerConfiguration::new("test_idx_index_document", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::IndexDocument {
+            document: Document::new(13, "A molar bear"),
+            response_channel: Some(sub_tx),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_ok());
+
+        let segment: PathBuf = [
+            constants::TEST_DEFAULT_DATA_DIRECTORY,
+            "indices",
+            "test_idx_index_document",
+            "segments",
+            "primary",
+            "0",
+            "3.db",
+        ].iter()
+            .collect();
+        let mut store = SQLiteStore::open("test_idx_index_document", &segment).unwrap();
+        assert_eq!(store.document_by_id(13).unwrap().unwrap().content(), "A molar bear");
+    }
+
+    #[test]
+    fn test_conflicting_field_type_is_rejected() {
+        let data_directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        let _ = fs::remove_dir_all(data_directory.join("indices").join("test_idx_mapping_conflict"));
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("test_idx_mapping_conflict", data_directory), rx).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::IndexDocument {
+            document: Document::new(1, r#"{"age": 3, "host": "web01"}"#),
+            response_channel: Some(sub_tx.clone()),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_ok());
+
+        tx.send(IndexCommand::IndexDocument {
+            document: Document::new(2, r#"{"age": "three"}"#),
+            response_channel: Some(sub_tx),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_err());
+        // The refused write is taken back out of the log
+        let (seq_tx, seq_rx) = mpsc::channel();
+        tx.send(IndexCommand::LastSequence { response_channel: seq_tx }).unwrap();
+        assert_eq!(seq_rx.recv().unwrap(), 1);
+
+        let (mappings_tx, mappings_rx) = mpsc::channel();
+        tx.send(IndexCommand::GetMappings { response_channel: mappings_tx }).unwrap();
+        let mappings = mappings_rx.recv().unwrap();
+        assert_eq!(mappings.get("age"), Some(FieldType::Integer));
+        assert_eq!(mappings.get("host"), Some(FieldType::Text));
+    }
+
+    #[test]
+    fn test_put_mappings() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(
+            ManagerConfiguration::new("test_idx_put_mappings", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::PutMappings {
+            mappings: Mappings::new().field("host", FieldType::Keyword),
+            response_channel: sub_tx.clone(),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_ok());
+
+        tx.send(IndexCommand::PutMappings {
+            mappings: Mappings::new().field("host", FieldType::Integer),
+            response_channel: sub_tx,
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_analyze_with_index_analyzer() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        let mut config = ManagerConfiguration::new("test_idx_analyze", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY));
+        config.analyzer = Analyzer::stop();
+        Manager::new(config, rx).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::Analyze {
+            text: String::from("The Molar Bear"),
+            response_channel: sub_tx,
+        }).unwrap();
+        let terms: Vec<String> = sub_rx.recv().unwrap().into_iter().map(|t| t.term).collect();
+        assert_eq!(terms, vec!["molar", "bear"]);
+    }
+
+    #[test]
+    fn test_get_and_delete_document() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(
+            ManagerConfiguration::new("test_idx_get_delete", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::IndexDocument {
+            document: Document::new(7, "A molar bear"),
+            response_channel: Some(sub_tx),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_ok());
+
+        let (get_tx, get_rx) = mpsc::channel();
+        tx.send(IndexCommand::GetDocument {
+            id: 7,
+            response_channel: get_tx.clone(),
+        }).unwrap();
+        assert_eq!(get_rx.recv().unwrap().unwrap().unwrap().content(), "A molar bear");
+
+        let (delete_tx, delete_rx) = mpsc::channel();
+        tx.send(IndexCommand::DeleteDocument {
+            id: 7,
+            response_channel: delete_tx.clone(),
+        }).unwrap();
+        assert!(delete_rx.recv().unwrap().unwrap());
+        tx.send(IndexCommand::DeleteDocument {
+            id: 7,
+            response_channel: delete_tx,
+        }).unwrap();
+        assert!(!delete_rx.recv().unwrap().unwrap());
+
+        tx.send(IndexCommand::GetDocument {
+            id: 7,
+            response_channel: get_tx,
+        }).unwrap();
+        assert!(get_rx.recv().unwrap().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_write_is_logged_and_replicated() {
+        let data_directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        let _ = fs::remove_dir_all(data_directory.join("indices").join("test_idx_replicated"));
+        let (tx, rx) = mpsc::channel();
+        let (replication_tx, replication_rx) = mpsc::channel();
+        let mut config = ManagerConfiguration::new("test_idx_replicated", data_directory);
+        config.replication = Some(replication_tx);
+        Manager::new(config, rx).unwrap();
+
+        let (write_tx, write_rx) = mpsc::channel();
+        tx.send(IndexCommand::Write {
+            operation: OperationKind::Delete { id: 3 },
+            consistency: WriteConsistency::All,
+            response_channel: write_tx.clone(),
+        }).unwrap();
+        // Nothing was deleted, so nothing is logged or replicated
+        let outcome = write_rx.recv().unwrap().unwrap();
+        assert!(!outcome.found && outcome.seq == 0);
+
+        tx.send(IndexCommand::Write {
+            operation: OperationKind::Index {
+                id: 3,
+                content: String::from("A molar bear"),
+                plain_text: false,
+            },
+            consistency: WriteConsistency::All,
+            response_channel: write_tx,
+        }).unwrap();
+        let replicate = replication_rx.recv().unwrap();
+        assert_eq!(replicate.operation.seq, 1);
+        assert_eq!(replicate.consistency, WriteConsistency::All);
+        // The client is answered by whoever sends the write to the Replicas
+        assert!(write_rx.try_recv().is_err());
+        assert!(replicate.response_channel.is_some());
+
+        let (delete_tx, delete_rx) = mpsc::channel();
+        tx.send(IndexCommand::DeleteDocument {
+            id: 3,
+            response_channel: delete_tx,
+        }).unwrap();
+        assert!(delete_rx.recv().unwrap().unwrap());
+        let replicate = replication_rx.recv().unwrap();
+        assert_eq!(replicate.operation, Operation { seq: 2, term: 1, prev_term: 1, kind: OperationKind::Delete { id: 3 } });
+        assert!(replicate.response_channel.is_none());
+
+        // Writes are logged in the term the Shard was last told about, which never goes back
+        tx.send(IndexCommand::SetTerm { term: 3 }).unwrap();
+        tx.send(IndexCommand::SetTerm { term: 2 }).unwrap();
+        let (index_tx, index_rx) = mpsc::channel();
+        tx.send(IndexCommand::IndexDocument {
+            document: Document::new(3, "A polar bear"),
+            response_channel: Some(index_tx),
+        }).unwrap();
+        assert!(index_rx.recv().unwrap().is_ok());
+        let operation = replication_rx.recv().unwrap().operation;
+        assert_eq!((operation.term, operation.prev_term), (3, 1));
+    }
+
+    #[test]
+    fn test_apply_operations() {
+        let data_directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        let _ = fs::remove_dir_all(data_directory.join("indices").join("test_idx_apply"));
+        let (tx, rx) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("test_idx_apply", data_directory), rx).unwrap();
+
+        let apply = |seq: u64, term: u64, prev_term: u64, kind: OperationKind| {
+            let (apply_tx, apply_rx) = mpsc::channel();
+            tx.send(IndexCommand::ApplyOperation {
+                operation: Operation { seq: seq, term: term, prev_term: prev_term, kind: kind },
+                response_channel: apply_tx,
+            }).unwrap();
+            apply_rx.recv().unwrap()
+        };
+        let index = OperationKind::Index {
+            id: 9,
+            content: String::from("A molar bear"),
+            plain_text: false,
+        };
+        assert!(apply(1, 1, 0, index.clone()).is_ok());
+        // Already applied
+        assert!(apply(1, 1, 0, OperationKind::Delete { id: 9 }).is_ok());
+        // A Primary in a later term logged something else as operation 1
+        assert!(apply(1, 2, 0, OperationKind::Delete { id: 9 }).is_err());
+        // Operation 2 never arrived
+        assert!(apply(3, 1, 1, OperationKind::Delete { id: 9 }).is_err());
+        assert!(apply(2, 2, 1, OperationKind::Delete { id: 10 }).is_ok());
+        // The Primary that logged this had a different operation 2
+        assert!(apply(3, 3, 1, OperationKind::Delete { id: 9 }).is_err());
+
+        let (get_tx, get_rx) = mpsc::channel();
+        tx.send(IndexCommand::GetDocument {
+            id: 9,
+            response_channel: get_tx,
+        }).unwrap();
+        assert!(get_rx.recv().unwrap().unwrap().is_some());
+
+        let (seq_tx, seq_rx) = mpsc::channel();
+        tx.send(IndexCommand::LastSequence { response_channel: seq_tx }).unwrap();
+        assert_eq!(seq_rx.recv().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_logged_write_is_redone() {
+        let data_directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        let index_directory = data_directory.join("indices").join("test_idx_redo");
+        let _ = fs::remove_dir_all(&index_directory);
+        fs::create_dir_all(index_directory.join("oplog")).unwrap();
+        // The Manager stopped after logging the write but before committing it
+        let mut oplog = OpLog::open(&index_directory.join("oplog").join("0.db")).unwrap();
+        oplog.append(&Operation {
+            seq: 1,
+            term: 1,
+            prev_term: 0,
+            kind: OperationKind::Index {
+                id: 4,
+                content: String::from("A molar bear"),
+                plain_text: false,
+            },
+        }).unwrap();
+        drop(oplog);
+
+        let (tx, rx) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("test_idx_redo", data_directory), rx).unwrap();
+        let (get_tx, get_rx) = mpsc::channel();
+        tx.send(IndexCommand::GetDocument {
+            id: 4,
+            response_channel: get_tx,
+        }).unwrap();
+        assert!(get_rx.recv().unwrap().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let data_directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        let snapshot_directory = data_directory.join("snapshots").join("test_idx_snapshot");
+        for name in &["test_idx_snapshot", "test_idx_restore"] {
+            let _ = fs::remove_dir_all(data_directory.join("indices").join(name));
+        }
+        let _ = fs::remove_dir_all(&snapshot_directory);
+        let (primary_tx, primary_rx) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("test_idx_snapshot", data_directory.clone()), primary_rx).unwrap();
+        let (replica_tx, replica_rx) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("test_idx_restore", data_directory), replica_rx).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        for (id, raw) in vec![(1, "A molar bear"), (12, "A polar bear")] {
+            primary_tx.send(IndexCommand::IndexDocument {
+                document: Document::new(id, raw),
+                response_channel: Some(sub_tx.clone()),
+            }).unwrap();
+            assert!(sub_rx.recv().unwrap().is_ok());
+        }
+
+        let (snapshot_tx, snapshot_rx) = mpsc::channel();
+        primary_tx.send(IndexCommand::Snapshot {
+            directory: snapshot_directory.clone(),
+            response_channel: snapshot_tx,
+        }).unwrap();
+        let snapshot = snapshot_rx.recv().unwrap().unwrap();
+        assert_eq!(snapshot.seq, 2);
+        assert_eq!(snapshot.files.len(), constants::DEFAULT_INDEX_STORE_WORKERS as usize + 1);
+
+        // Written after the Snapshot, so only found by replaying the OpLog
+        primary_tx.send(IndexCommand::IndexDocument {
+            document: Document::new(23, "A molar walrus"),
+            response_channel: Some(sub_tx.clone()),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_ok());
+
+        let (restore_tx, restore_rx) = mpsc::channel();
+        replica_tx.send(IndexCommand::Restore {
+            directory: snapshot_directory,
+            response_channel: restore_tx,
+        }).unwrap();
+        assert_eq!(restore_rx.recv().unwrap().unwrap(), 2);
+
+        let (since_tx, since_rx) = mpsc::channel();
+        primary_tx.send(IndexCommand::OperationsSince {
+            seq: 2,
+            limit: 10,
+            max_bytes: usize::MAX,
+            response_channel: since_tx,
+        }).unwrap();
+        let operations = since_rx.recv().unwrap().unwrap();
+        assert_eq!(operations.len(), 1);
+        for operation in operations {
+            let (apply_tx, apply_rx) = mpsc::channel();
+            replica_tx.send(IndexCommand::ApplyOperation {
+                operation: operation,
+                response_channel: apply_tx,
+            }).unwrap();
+            assert!(apply_rx.recv().unwrap().is_ok());
+        }
+
+        for id in vec![1, 12, 23] {
+            let (get_tx, get_rx) = mpsc::channel();
+            replica_tx.send(IndexCommand::GetDocument {
+                id: id,
+                response_channel: get_tx,
+            }).unwrap();
+            assert!(get_rx.recv().unwrap().unwrap().is_some());
+        }
+        let (mappings_tx, mappings_rx) = mpsc::channel();
+        replica_tx.send(IndexCommand::GetMappings { response_channel: mappings_tx }).unwrap();
+        assert!(mappings_rx.recv().unwrap().get("message").is_some());
+    }
+
+    #[test]
+    fn test_search_across_segments() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(
+            ManagerConfiguration::new("test_idx_search", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        for (id, raw) in vec![(1, "A molar bear"), (12, "A polar bear"), (23, "A molar walrus")] {
+            tx.send(IndexCommand::IndexDocument {
+                document: Document::new(id, raw),
+                response_channel: Some(sub_tx.clone()),
+            }).unwrap();
+            assert!(sub_rx.recv().unwrap().is_ok());
+        }
+
+        let (search_tx, search_rx) = mpsc::channel();
+        tx.send(IndexCommand::Search {
+            query: Query::Bool {
+                must: vec![Query::matching("message", "Molar")],
+                should: vec![],
+                must_not: vec![Query::phrase("message", "molar walrus")],
+            },
+            from: 0,
+            size: 10,
+            response_channel: search_tx.clone(),
+        }).unwrap();
+        let hits = search_rx.recv().unwrap().unwrap().hits;
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![1]);
+
+        tx.send(IndexCommand::Search {
+            query: Query::matching("message", "bear"),
+            from: 1,
+            size: 10,
+            response_channel: search_tx.clone(),
+        }).unwrap();
+        let results = search_rx.recv().unwrap().unwrap();
+        assert_eq!(results.total, 2);
+        assert_eq!(results.hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![12]);
+
+        tx.send(IndexCommand::Search {
+            query: Query::matching("message", "bear"),
+            from: 0,
+            size: 10,
+            response_channel: search_tx,
+        }).unwrap();
+        let hits = search_rx.recv().unwrap().unwrap().hits;
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![1, 12]);
+        assert_eq!(hits[1].source, "A polar bear");
+        // Both were scored with the same statistics even though they live in different segments
+        assert_eq!(hits[0].score, hits[1].score);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        let join_handle = Manager::new(
+            ManagerConfiguration::new("test_idx_shutdown", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::Shutdown { response_channel: sub_tx }).unwrap();
+        assert!(sub_rx.recv().is_ok());
+        assert!(join_handle.join().is_ok());
+        let (ready_tx, _ready_rx) = mpsc::channel();
+        assert!(tx.send(IndexCommand::Ready { response_channel: ready_tx }).is_err());
+    }
+
+    #[test]
+    fn test_index_document_without_id() {
+        let (tx, rx): (mpsc::Sender<IndexCommand>, mpsc::Receiver<IndexCommand>) = mpsc::channel();
+        Manager::new(
+            ManagerConfiguration::new("test_idx_index_document_without_id", PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY)),
+            rx,
+        ).unwrap();
+
+        let (sub_tx, sub_rx) = mpsc::channel();
+        tx.send(IndexCommand::IndexDocument {
+            document: "A molar bear".parse().unwrap(),
+            response_channel: Some(sub_tx),
+        }).unwrap();
+        assert!(sub_rx.recv().unwrap().is_err());
+    }
 }
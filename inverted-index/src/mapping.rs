@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use document::Document;
+use index::InvertedIndexError;
+
+/// FieldType says how the values of a field are stored and searched
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldType {
+    /// Stored as a single term exactly as it was written, for things like IDs, tags and hostnames
+    Keyword,
+    /// Run through the Index's Analyzer and stored as terms
+    Text,
+    /// Stored as a 64-bit signed integer
+    Integer,
+    /// Stored as a 64-bit float
+    Float,
+    /// Stored as milliseconds since the Unix epoch
+    Date,
+    /// Stored as 0 or 1
+    Boolean,
+}
+
+impl fmt::Display for FieldType {
+    /// Implements Display for FieldType. This is also the name stored in the `mappings` table.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FieldType::Keyword => write!(f, "keyword"),
+            FieldType::Text => write!(f, "text"),
+            FieldType::Integer => write!(f, "integer"),
+            FieldType::Float => write!(f, "float"),
+            FieldType::Date => write!(f, "date"),
+            FieldType::Boolean => write!(f, "boolean"),
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    /// Implements FromStr for FieldType so that types stored in the `mappings` table, or sent by a
+    /// client, can be turned back into a FieldType
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::mapping::FieldType;
+    /// let field_type: FieldType = "keyword".parse().unwrap();
+    /// ```
+    type Err = InvertedIndexError;
+    fn from_str(s: &str) -> Result<FieldType, Self::Err> {
+        match s {
+            "keyword" => Ok(FieldType::Keyword),
+            "text" => Ok(FieldType::Text),
+            "integer" => Ok(FieldType::Integer),
+            "float" => Ok(FieldType::Float),
+            "date" => Ok(FieldType::Date),
+            "boolean" => Ok(FieldType::Boolean),
+            _ => Err(InvertedIndexError::new(&format!("Unknown field type: {}", s))),
+        }
+    }
+}
+
+/// A single field value after it has been converted to the type its field is mapped as
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedValue {
+    Keyword(String),
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    /// Milliseconds since the Unix epoch
+    Date(i64),
+    Boolean(bool),
+}
+
+impl FieldType {
+    /// Works out the type a field should be mapped as from a value seen in a Document. Whole
+    /// numbers become integers, strings that look like ISO-8601 dates become dates, and all other
+    /// strings become text.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - A scalar value taken from a Document
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate serde_json;
+    /// extern crate inverted_index;
+    /// use inverted_index::mapping::FieldType;
+    /// assert_eq!(FieldType::infer(&serde_json::Value::from(42)), FieldType::Integer);
+    /// ```
+    pub fn infer(value: &Value) -> FieldType {
+        match *value {
+            Value::Bool(_) => FieldType::Boolean,
+            Value::Number(ref n) if n.is_i64() || n.is_u64() => FieldType::Integer,
+            Value::Number(_) => FieldType::Float,
+            Value::String(ref s) if parse_date(s).is_some() => FieldType::Date,
+            _ => FieldType::Text,
+        }
+    }
+
+    /// Converts a value from a Document to this type. Numeric strings are accepted for numeric
+    /// types and integers are accepted for dates, but anything that can not be represented
+    /// without losing its meaning is rejected.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - Name of the field the value belongs to, used in the error message
+    /// * `value` - A scalar value taken from a Document
+    pub fn coerce(&self, field: &str, value: &Value) -> Result<TypedValue, InvertedIndexError> {
+        let coerced = match (*self, value) {
+            (FieldType::Keyword, &Value::String(ref s)) => Some(TypedValue::Keyword(s.clone())),
+            (FieldType::Keyword, v) => Some(TypedValue::Keyword(v.to_string())),
+            (FieldType::Text, &Value::String(ref s)) => Some(TypedValue::Text(s.clone())),
+            (FieldType::Text, v) => Some(TypedValue::Text(v.to_string())),
+            (FieldType::Integer, &Value::Number(ref n)) => n.as_i64().map(TypedValue::Integer),
+            (FieldType::Integer, &Value::String(ref s)) => s.trim().parse().ok().map(TypedValue::Integer),
+            (FieldType::Float, &Value::Number(ref n)) => n.as_f64().map(TypedValue::Float),
+            (FieldType::Float, &Value::String(ref s)) => s.trim().parse().ok().map(TypedValue::Float),
+            (FieldType::Date, &Value::Number(ref n)) => n.as_i64().map(TypedValue::Date),
+            (FieldType::Date, &Value::String(ref s)) => parse_date(s).map(TypedValue::Date),
+            (FieldType::Boolean, &Value::Bool(b)) => Some(TypedValue::Boolean(b)),
+            (FieldType::Boolean, &Value::String(ref s)) => match s.as_str() {
+                "true" => Some(TypedValue::Boolean(true)),
+                "false" => Some(TypedValue::Boolean(false)),
+                _ => None,
+            },
+            _ => None,
+        };
+        coerced.ok_or_else(|| {
+            InvertedIndexError::new(&format!(
+                "Field '{}' is mapped as {} but {} is not a valid {}",
+                field, self, value, self
+            ))
+        })
+    }
+}
+
+/// Mappings record the type of every field in an Index. They are either given explicitly when
+/// the Index is set up, or inferred from the first Document that contains a field.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mappings {
+    fields: BTreeMap<String, FieldType>,
+}
+
+impl Mappings {
+    /// Creates and returns an empty set of Mappings
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::mapping::{FieldType, Mappings};
+    /// let mappings = Mappings::new().field("timestamp", FieldType::Date);
+    /// ```
+    pub fn new() -> Mappings {
+        Mappings {
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a field to the Mappings. Meant to be used as part of the Builder pattern.
+    pub fn field<S: Into<String>>(mut self, name: S, field_type: FieldType) -> Mappings {
+        self.fields.insert(name.into(), field_type);
+        self
+    }
+
+    /// Returns the type a field is mapped as, if it has been mapped
+    pub fn get(&self, name: &str) -> Option<FieldType> {
+        self.fields.get(name).cloned()
+    }
+
+    /// Returns an iterator over every mapped field and its type
+    pub fn iter(&self) -> btree_map::Iter<String, FieldType> {
+        self.fields.iter()
+    }
+
+    /// Returns true if no fields are mapped
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Adds every field from another set of Mappings. A field that is already mapped as a
+    /// different type is a conflict, and nothing is merged if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The Mappings to add
+    pub fn merge(&mut self, other: &Mappings) -> Result<(), InvertedIndexError> {
+        for (name, field_type) in other.iter() {
+            match self.get(name) {
+                Some(existing) if existing != *field_type => {
+                    return Err(InvertedIndexError::new(&format!(
+                        "Field '{}' is already mapped as {} and can not be changed to {}",
+                        name, existing, field_type
+                    )));
+                }
+                _ => {}
+            }
+        }
+        for (name, field_type) in other.iter() {
+            self.fields.insert(name.clone(), *field_type);
+        }
+        Ok(())
+    }
+
+    /// Checks every value in a Document against these Mappings, and works out the types of the
+    /// fields that are not mapped yet. Returns the Mappings for just the new fields, or an error
+    /// naming the first value that does not fit the type of its field.
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - The Document about to be indexed
+    pub fn dynamic_mappings(&self, document: &Document) -> Result<Mappings, InvertedIndexError> {
+        let mut new_fields = Mappings::new();
+        for (name, values) in document.fields() {
+            let field_type = match self.get(name) {
+                Some(field_type) => field_type,
+                None => {
+                    let field_type = infer_field_type(values);
+                    new_fields.fields.insert(name.clone(), field_type);
+                    field_type
+                }
+            };
+            for value in values {
+                field_type.coerce(name, value)?;
+            }
+        }
+        Ok(new_fields)
+    }
+}
+
+/// Works out the type of a new field from all of its values. A mix of integers and floats is
+/// mapped as float; any other mix is mapped from the first value, and the rest are then checked
+/// against it.
+fn infer_field_type(values: &[Value]) -> FieldType {
+    let mut types = values.iter().map(FieldType::infer);
+    let first = types.next().unwrap_or(FieldType::Text);
+    types.fold(first, |acc, next| match (acc, next) {
+        (FieldType::Integer, FieldType::Float) | (FieldType::Float, FieldType::Integer) => FieldType::Float,
+        _ => acc,
+    })
+}
+
+/// Parses an ISO-8601 date, such as `2017-12-19` or `2017-12-19T06:52:11.250+01:00`, into
+/// milliseconds since the Unix epoch. Times without an offset are taken to be UTC.
+///
+/// # Arguments
+///
+/// * `s` - The text to parse
+///
+/// # Example
+///
+/// ```
+/// use inverted_index::mapping::parse_date;
+/// assert_eq!(parse_date("1970-01-02"), Some(86_400_000));
+/// assert_eq!(parse_date("molar bear"), None);
+/// ```
+pub fn parse_date(s: &str) -> Option<i64> {
+    let bytes = s.as_bytes();
+    if !s.is_ascii() || bytes.len() < 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let year = parse_digits(&s[0..4])?;
+    let month = parse_digits(&s[5..7])?;
+    let day = parse_digits(&s[8..10])?;
+    if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * 86_400_000;
+
+    let rest = &s[10..];
+    if rest.is_empty() {
+        return Some(millis);
+    }
+    let time = rest.as_bytes();
+    if (time[0] != b'T' && time[0] != b' ') || time.len() < 9 || time[3] != b':' || time[6] != b':' {
+        return None;
+    }
+    let hour = parse_digits(&rest[1..3])?;
+    let minute = parse_digits(&rest[4..6])?;
+    let second = parse_digits(&rest[7..9])?;
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    millis += ((hour * 60 + minute) * 60 + second) * 1000;
+
+    let mut rest = &rest[9..];
+    if rest.starts_with('.') {
+        let digits = rest[1..].bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        let fraction = format!("{:0<3}", &rest[1..1 + digits.min(3)]);
+        millis += parse_digits(&fraction)?;
+        rest = &rest[1 + digits..];
+    }
+
+    match rest {
+        "" | "Z" => Some(millis),
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = rest[1..].replace(":", "");
+            if offset.len() != 4 {
+                return None;
+            }
+            let offset_hours = parse_digits(&offset[0..2])?;
+            let offset_minutes = parse_digits(&offset[2..4])?;
+            Some(millis - sign * (offset_hours * 60 + offset_minutes) * 60_000)
+        }
+    }
+}
+
+/// Parses a string made up only of ASCII digits
+fn parse_digits(s: &str) -> Option<i64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Number of days between the Unix epoch and a date in the proleptic Gregorian calendar. This is
+/// Howard Hinnant's `days_from_civil` algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_field_types() {
+        assert_eq!(FieldType::infer(&Value::from(1)), FieldType::Integer);
+        assert_eq!(FieldType::infer(&Value::from(1.5)), FieldType::Float);
+        assert_eq!(FieldType::infer(&Value::from(true)), FieldType::Boolean);
+        assert_eq!(FieldType::infer(&Value::from("2017-12-19T06:52:11Z")), FieldType::Date);
+        assert_eq!(FieldType::infer(&Value::from("molar bear")), FieldType::Text);
+        assert_eq!(infer_field_type(&[Value::from(1), Value::from(1.5)]), FieldType::Float);
+    }
+
+    #[test]
+    fn test_field_type_round_trip() {
+        for field_type in &[
+            FieldType::Keyword,
+            FieldType::Text,
+            FieldType::Integer,
+            FieldType::Float,
+            FieldType::Date,
+            FieldType::Boolean,
+        ] {
+            assert_eq!(field_type.to_string().parse::<FieldType>().unwrap(), *field_type);
+        }
+        assert!("klingon".parse::<FieldType>().is_err());
+    }
+
+    #[test]
+    fn test_coerce() {
+        assert_eq!(FieldType::Integer.coerce("age", &Value::from("42")).unwrap(), TypedValue::Integer(42));
+        assert_eq!(FieldType::Float.coerce("age", &Value::from(42)).unwrap(), TypedValue::Float(42.0));
+        assert_eq!(FieldType::Keyword.coerce("id", &Value::from(7)).unwrap(), TypedValue::Keyword("7".into()));
+        assert!(FieldType::Integer.coerce("age", &Value::from("old")).is_err());
+        assert!(FieldType::Boolean.coerce("admin", &Value::from(1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2017-12-19T06:52:11Z"), Some(1_513_666_331_000));
+        assert_eq!(parse_date("2017-12-19T07:52:11.5+01:00"), Some(1_513_666_331_500));
+        assert_eq!(parse_date("1969-12-31T23:59:59Z"), Some(-1000));
+        assert_eq!(parse_date("2017-02-29"), None);
+        assert_eq!(parse_date("2017-12-19T25:00:00Z"), None);
+        assert_eq!(parse_date("2017-12-19 and then some"), None);
+    }
+
+    #[test]
+    fn test_dynamic_mappings() {
+        let mappings = Mappings::new().field("age", FieldType::Integer);
+        let document = Document::new(0, r#"{"age": 3, "name": "bear", "born": "2015-04-01"}"#);
+        let new_fields = mappings.dynamic_mappings(&document).unwrap();
+        assert_eq!(new_fields, Mappings::new().field("born", FieldType::Date).field("name", FieldType::Text));
+
+        let document = Document::new(0, r#"{"age": "three"}"#);
+        assert!(mappings.dynamic_mappings(&document).is_err());
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let mut mappings = Mappings::new().field("age", FieldType::Integer);
+        assert!(mappings.merge(&Mappings::new().field("age", FieldType::Text)).is_err());
+        assert!(mappings.merge(&Mappings::new().field("name", FieldType::Text)).is_ok());
+        assert_eq!(mappings.get("name"), Some(FieldType::Text));
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use serde_json::Value;
+
+use analyzer::{Analyzer, Token};
+use index::InvertedIndexError;
+use mapping::{FieldType, Mappings, TypedValue};
+
+/// Operator decides whether a Match query needs all of its terms or just one of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    /// Every term must be in the field
+    And,
+    /// Any one of the terms must be in the field
+    Or,
+}
+
+/// A Query describes which Documents a search should return. Queries are written in terms of
+/// what a client sends; before they are run they are prepared against the Index's Analyzer and
+/// Mappings, which turns text into the terms that are actually stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// Matches every Document
+    MatchAll,
+    /// Matches Documents where a field holds exactly this value. Text fields are not stemmed, so
+    /// this finds a word as it was written.
+    Term { field: String, value: Value },
+    /// Matches Documents where a field holds any of these values exactly
+    Terms { field: String, values: Vec<Value> },
+    /// Analyzes the text and matches Documents with any, or all, of the resulting terms in a field
+    Match {
+        field: String,
+        text: String,
+        operator: Operator,
+    },
+    /// Analyzes the text and matches Documents where the resulting terms appear in a field in the
+    /// same order and next to each other
+    Phrase { field: String, text: String },
+    /// Matches Documents where a field falls between two values. Numbers and dates are compared
+    /// as such and keywords alphabetically. Text fields can't be ranged over, since they are split
+    /// into terms. Either end may be left open, but not both.
+    Range {
+        field: String,
+        gt: Option<Value>,
+        gte: Option<Value>,
+        lt: Option<Value>,
+        lte: Option<Value>,
+    },
+    /// Combines other Queries. A Document must match every `must` Query and none of the
+    /// `must_not` Queries. If there are no `must` Queries it has to match at least one `should`
+    /// Query.
+    Bool {
+        must: Vec<Query>,
+        should: Vec<Query>,
+        must_not: Vec<Query>,
+    },
+}
+
+/// A Query after its text has been analyzed and its values converted to the types of their
+/// fields. This is what each segment runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreparedQuery {
+    /// Matches every Document
+    MatchAll,
+    /// Matches no Documents
+    MatchNone,
+    /// Matches Documents with any of the terms in the field
+    Terms { field: String, terms: Vec<String> },
+    /// Matches Documents with the terms in the field at the given positions relative to each
+    /// other. Each position lists the terms that may appear there, since a stemmer that keeps
+    /// originals puts more than one term at the same position.
+    Phrase {
+        field: String,
+        positions: Vec<(u64, Vec<String>)>,
+    },
+    /// Matches Documents where a typed field holds the value
+    Value { field: String, value: TypedValue },
+    /// Matches Documents where a field falls between the bounds. Keyword and Text bounds are
+    /// compared against terms, anything else against typed values.
+    Range {
+        field: String,
+        lower: Bound<TypedValue>,
+        upper: Bound<TypedValue>,
+    },
+    /// Combines other PreparedQueries the same way `Query::Bool` does
+    Bool {
+        must: Vec<PreparedQuery>,
+        should: Vec<PreparedQuery>,
+        must_not: Vec<PreparedQuery>,
+    },
+}
+
+/// A Document returned by a search
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// ID of the Document
+    pub id: u64,
+    /// How relevant the Document is to the Query, higher being better
+    pub score: f64,
+    /// The raw content of the Document as it was indexed
+    pub source: String,
+    /// Whether the content was indexed as plain text, even if it happens to be valid JSON
+    pub plain_text: bool,
+}
+
+/// One page of the Documents that matched a search
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResults {
+    /// Number of Documents that matched, including those not on this page
+    pub total: usize,
+    /// The matching Documents on this page, most relevant first
+    pub hits: Vec<Hit>,
+}
+
+impl Query {
+    /// Creates and returns a Match query that needs any one of the terms
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::query::Query;
+    /// let query = Query::matching("message", "molar bear");
+    /// ```
+    pub fn matching<S: Into<String>, T: Into<String>>(field: S, text: T) -> Query {
+        Query::Match {
+            field: field.into(),
+            text: text.into(),
+            operator: Operator::Or,
+        }
+    }
+
+    /// Creates and returns a Phrase query
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::query::Query;
+    /// let query = Query::phrase("message", "molar bear");
+    /// ```
+    pub fn phrase<S: Into<String>, T: Into<String>>(field: S, text: T) -> Query {
+        Query::Phrase {
+            field: field.into(),
+            text: text.into(),
+        }
+    }
+
+    /// Creates and returns a Term query
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::query::Query;
+    /// let query = Query::term("host", "web01");
+    /// ```
+    pub fn term<S: Into<String>, V: Into<Value>>(field: S, value: V) -> Query {
+        Query::Term {
+            field: field.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Prepares the Query to be run against the segments of an Index
+    ///
+    /// # Arguments
+    ///
+    /// * `analyzer` - The Analyzer the Index was configured with
+    /// * `mappings` - The Mappings of the Index, which decide how each field is searched
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::analyzer::Analyzer;
+    /// use inverted_index::mapping::Mappings;
+    /// use inverted_index::query::Query;
+    /// let prepared = Query::matching("message", "Molar Bears").prepare(&Analyzer::standard(), &Mappings::new());
+    /// ```
+    pub fn prepare(&self, analyzer: &Analyzer, mappings: &Mappings) -> Result<PreparedQuery, InvertedIndexError> {
+        let field_type = |field: &str| mappings.get(field).unwrap_or(FieldType::Text);
+        match *self {
+            Query::MatchAll => Ok(PreparedQuery::MatchAll),
+            Query::Term { ref field, ref value } => match field_type(field).coerce(field, value)? {
+                TypedValue::Text(text) => Ok(phrase(field, analyzer.analyze_exact(&text))),
+                TypedValue::Keyword(keyword) => Ok(PreparedQuery::Terms {
+                    field: field.clone(),
+                    terms: vec![keyword],
+                }),
+                typed => Ok(PreparedQuery::Value {
+                    field: field.clone(),
+                    value: typed,
+                }),
+            },
+            Query::Terms { ref field, ref values } => {
+                let mut should = vec![];
+                for value in values {
+                    should.push(Query::Term {
+                        field: field.clone(),
+                        value: value.clone(),
+                    }.prepare(analyzer, mappings)?);
+                }
+                Ok(PreparedQuery::Bool {
+                    must: vec![],
+                    should: should,
+                    must_not: vec![],
+                })
+            }
+            Query::Match {
+                ref field,
+                ref text,
+                operator,
+            } => match field_type(field) {
+                FieldType::Text => {
+                    let mut terms: Vec<String> = analyzer.analyze_stemmed(text).into_iter().map(|t| t.term).collect();
+                    terms.sort();
+                    terms.dedup();
+                    if terms.is_empty() {
+                        return Ok(PreparedQuery::MatchNone);
+                    }
+                    match operator {
+                        Operator::Or => Ok(PreparedQuery::Terms {
+                            field: field.clone(),
+                            terms: terms,
+                        }),
+                        Operator::And => Ok(PreparedQuery::Bool {
+                            must: terms
+                                .into_iter()
+                                .map(|term| PreparedQuery::Terms {
+                                    field: field.clone(),
+                                    terms: vec![term],
+                                })
+                                .collect(),
+                            should: vec![],
+                            must_not: vec![],
+                        }),
+                    }
+                }
+                _ => Query::term(field.clone(), text.clone()).prepare(analyzer, mappings),
+            },
+            Query::Phrase { ref field, ref text } => match field_type(field) {
+                FieldType::Text => Ok(phrase(field, analyzer.analyze_stemmed(text))),
+                _ => Query::term(field.clone(), text.clone()).prepare(analyzer, mappings),
+            },
+            Query::Range {
+                ref field,
+                ref gt,
+                ref gte,
+                ref lt,
+                ref lte,
+            } => {
+                let field_type = field_type(field);
+                // Text is stored as analyzed terms, which don't keep the order of the values they
+                // came from
+                if field_type == FieldType::Text {
+                    return Err(InvertedIndexError::new(&format!(
+                        "Range on '{}' needs a field that is not text, such as a keyword",
+                        field
+                    )));
+                }
+                let bound = |exclusive: &Option<Value>, inclusive: &Option<Value>| match (exclusive, inclusive) {
+                    (&Some(_), &Some(_)) => Err(InvertedIndexError::new(&format!(
+                        "Range on '{}' can only have one bound on each side",
+                        field
+                    ))),
+                    (&Some(ref value), &None) => Ok(Bound::Excluded(field_type.coerce(field, value)?)),
+                    (&None, &Some(ref value)) => Ok(Bound::Included(field_type.coerce(field, value)?)),
+                    (&None, &None) => Ok(Bound::Unbounded),
+                };
+                let lower = bound(gt, gte)?;
+                let upper = bound(lt, lte)?;
+                if lower == Bound::Unbounded && upper == Bound::Unbounded {
+                    return Err(InvertedIndexError::new(&format!("Range on '{}' needs at least one bound", field)));
+                }
+                Ok(PreparedQuery::Range {
+                    field: field.clone(),
+                    lower: lower,
+                    upper: upper,
+                })
+            }
+            Query::Bool {
+                ref must,
+                ref should,
+                ref must_not,
+            } => {
+                let prepare_all = |queries: &Vec<Query>| -> Result<Vec<PreparedQuery>, InvertedIndexError> {
+                    queries.iter().map(|q| q.prepare(analyzer, mappings)).collect()
+                };
+                Ok(PreparedQuery::Bool {
+                    must: prepare_all(must)?,
+                    should: prepare_all(should)?,
+                    must_not: prepare_all(must_not)?,
+                })
+            }
+        }
+    }
+}
+
+impl PreparedQuery {
+    /// Lists the field and term pairs whose statistics are needed to score this query. Terms
+    /// under `must_not` are left out because they never add to a score.
+    pub fn terms(&self) -> Vec<(String, String)> {
+        let mut pairs = vec![];
+        self.collect_terms(&mut pairs);
+        pairs.sort();
+        pairs.dedup();
+        pairs
+    }
+
+    fn collect_terms(&self, pairs: &mut Vec<(String, String)>) {
+        match *self {
+            PreparedQuery::Terms { ref field, ref terms } => {
+                pairs.extend(terms.iter().map(|term| (field.clone(), term.clone())));
+            }
+            PreparedQuery::Phrase {
+                ref field,
+                ref positions,
+            } => {
+                for &(_, ref terms) in positions {
+                    pairs.extend(terms.iter().map(|term| (field.clone(), term.clone())));
+                }
+            }
+            PreparedQuery::Bool {
+                ref must, ref should, ..
+            } => {
+                for query in must.iter().chain(should.iter()) {
+                    query.collect_terms(pairs);
+                }
+            }
+            PreparedQuery::MatchAll
+            | PreparedQuery::MatchNone
+            | PreparedQuery::Value { .. }
+            | PreparedQuery::Range { .. } => {}
+        }
+    }
+}
+
+/// Builds a Phrase from analyzed Tokens, with positions made relative to the first Token. A
+/// single position is just a lookup of its terms.
+fn phrase(field: &str, tokens: Vec<Token>) -> PreparedQuery {
+    let first = match tokens.iter().map(|t| t.position).min() {
+        Some(first) => first,
+        None => return PreparedQuery::MatchNone,
+    };
+    let mut positions: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for token in tokens {
+        positions.entry(token.position - first).or_insert_with(Vec::new).push(token.term);
+    }
+    if positions.len() == 1 {
+        let (_, terms) = positions.into_iter().next().unwrap();
+        return PreparedQuery::Terms {
+            field: field.to_owned(),
+            terms: terms,
+        };
+    }
+    PreparedQuery::Phrase {
+        field: field.to_owned(),
+        positions: positions.into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(field: &str, terms: &[&str]) -> PreparedQuery {
+        PreparedQuery::Terms {
+            field: field.to_owned(),
+            terms: terms.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_prepare_match() {
+        let prepared = Query::matching("message", "Molar bear, molar!")
+            .prepare(&Analyzer::standard(), &Mappings::new())
+            .unwrap();
+        assert_eq!(prepared, terms("message", &["bear", "molar"]));
+    }
+
+    #[test]
+    fn test_prepare_match_and() {
+        let query = Query::Match {
+            field: "message".into(),
+            text: "molar bear".into(),
+            operator: Operator::And,
+        };
+        let prepared = query.prepare(&Analyzer::standard(), &Mappings::new()).unwrap();
+        assert_eq!(
+            prepared,
+            PreparedQuery::Bool {
+                must: vec![terms("message", &["bear"]), terms("message", &["molar"])],
+                should: vec![],
+                must_not: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_prepare_phrase_keeps_gaps() {
+        let prepared = Query::phrase("message", "molar the bear")
+            .prepare(&Analyzer::stop(), &Mappings::new())
+            .unwrap();
+        assert_eq!(
+            prepared,
+            PreparedQuery::Phrase {
+                field: "message".into(),
+                positions: vec![(0, vec!["molar".into()]), (2, vec!["bear".into()])],
+            }
+        );
+    }
+
+    #[test]
+    fn test_prepare_term_uses_field_type() {
+        let mappings = Mappings::new()
+            .field("status", FieldType::Integer)
+            .field("host", FieldType::Keyword);
+        let analyzer = Analyzer::language("english").unwrap();
+        assert_eq!(
+            Query::term("status", 404).prepare(&analyzer, &mappings).unwrap(),
+            PreparedQuery::Value {
+                field: "status".into(),
+                value: TypedValue::Integer(404),
+            }
+        );
+        assert_eq!(
+            Query::term("host", "Web01").prepare(&analyzer, &mappings).unwrap(),
+            terms("host", &["Web01"])
+        );
+        assert_eq!(
+            Query::term("message", "Bears").prepare(&analyzer, &mappings).unwrap(),
+            terms("message", &["bears"])
+        );
+        // Matching any form of the word looks for the stem that every form shares
+        let stem = format!("{}bear", ::analyzer::STEM_PREFIX);
+        assert_eq!(
+            Query::matching("message", "Bears").prepare(&analyzer, &mappings).unwrap(),
+            terms("message", &[&stem])
+        );
+        assert!(Query::term("status", "teapot").prepare(&analyzer, &mappings).is_err());
+    }
+
+    #[test]
+    fn test_prepare_range() {
+        let mappings = Mappings::new().field("timestamp", FieldType::Date);
+        let query = Query::Range {
+            field: "timestamp".into(),
+            gt: None,
+            gte: Some(Value::from("2018-09-01")),
+            lt: Some(Value::from(1538352000000i64)),
+            lte: None,
+        };
+        assert_eq!(
+            query.prepare(&Analyzer::standard(), &mappings).unwrap(),
+            PreparedQuery::Range {
+                field: "timestamp".into(),
+                lower: Bound::Included(TypedValue::Date(1535760000000)),
+                upper: Bound::Excluded(TypedValue::Date(1538352000000)),
+            }
+        );
+        let open = Query::Range {
+            field: "timestamp".into(),
+            gt: None,
+            gte: None,
+            lt: None,
+            lte: None,
+        };
+        assert!(open.prepare(&Analyzer::standard(), &mappings).is_err());
+        let both = Query::Range {
+            field: "timestamp".into(),
+            gt: Some(Value::from(1)),
+            gte: Some(Value::from(2)),
+            lt: None,
+            lte: None,
+        };
+        assert!(both.prepare(&Analyzer::standard(), &mappings).is_err());
+        let text = Query::Range {
+            field: "message".into(),
+            gt: None,
+            gte: Some(Value::from("Bear")),
+            lt: None,
+            lte: None,
+        };
+        assert!(text.prepare(&Analyzer::standard(), &mappings).is_err());
+    }
+
+    #[test]
+    fn test_scored_terms() {
+        let query = Query::Bool {
+            must: vec![Query::phrase("message", "molar bear")],
+            should: vec![Query::matching("message", "bear ice")],
+            must_not: vec![Query::matching("message", "walrus")],
+        };
+        let prepared = query.prepare(&Analyzer::standard(), &Mappings::new()).unwrap();
+        let pairs = prepared.terms();
+        let pairs: Vec<(&str, &str)> = pairs.iter().map(|&(ref f, ref t)| (f.as_str(), t.as_str())).collect();
+        assert_eq!(pairs, vec![("message", "bear"), ("message", "ice"), ("message", "molar")]);
+    }
+
+    #[test]
+    fn test_prepare_empty_match() {
+        let prepared = Query::matching("message", "the")
+            .prepare(&Analyzer::stop(), &Mappings::new())
+            .unwrap();
+        assert_eq!(prepared, PreparedQuery::MatchNone);
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+
+use rusqlite;
+use serde_json::{self, Value};
+
+use document::Document;
+use index::InvertedIndexError;
+use stores::sqlite::queries::*;
+use stores::sqlite::SQLiteStore;
+
+/// A write to a Shard, in the form it is logged and sent to Replicas
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperationKind {
+    /// Indexes a Document, replacing any with the same ID
+    Index {
+        id: u64,
+        /// The raw content of the Document as it was supplied by the client
+        content: String,
+        /// Set if the content is indexed as plain text even though it may be JSON
+        plain_text: bool,
+    },
+    /// Deletes the Document with the ID
+    Delete { id: u64 },
+}
+
+impl OperationKind {
+    /// Returns the kind of Operation that indexes a Document
+    ///
+    /// # Arguments
+    ///
+    /// * `document` - The Document, which must have an ID
+    pub fn index(document: &Document) -> Result<OperationKind, InvertedIndexError> {
+        match document.id() {
+            Some(id) => Ok(OperationKind::Index {
+                id: id,
+                content: document.content().to_owned(),
+                plain_text: document.is_plain_text(),
+            }),
+            None => Err(InvertedIndexError::new("Document must have an id to be indexed")),
+        }
+    }
+
+    /// Returns the ID of the Document the Operation writes
+    pub fn id(&self) -> u64 {
+        match *self {
+            OperationKind::Index { id, .. } | OperationKind::Delete { id } => id,
+        }
+    }
+}
+
+/// A write to a Shard along with its place in the Shard's `OpLog`. Operations are numbered from
+/// 1 with no gaps, and a Replica applies them in that order. Two copies of a Shard hold the same
+/// Operation only if both its sequence number and its term match, and a Replica only adds one to
+/// its OpLog if its last Operation is in `prev_term`, so that it never builds on Operations its
+/// Primary does not have.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    /// Sequence number of the Operation within its Shard
+    pub seq: u64,
+    /// Term of the Primary that logged the Operation. A Primary in a later term may have logged a
+    /// different Operation with the same sequence number.
+    pub term: u64,
+    /// Term of the Operation before this one in the Primary's OpLog, or 0 if there is none
+    pub prev_term: u64,
+    /// What the Operation does
+    pub kind: OperationKind,
+}
+
+impl Operation {
+    /// Serializes the Operation into a JSON object, the form it is logged and sent in
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::replication::{Operation, OperationKind};
+    /// let operation = Operation { seq: 1, term: 1, prev_term: 0, kind: OperationKind::Delete { id: 42 } };
+    /// assert_eq!(Operation::from_json(&operation.to_json()).unwrap(), operation);
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut object = serde_json::Map::new();
+        object.insert(String::from("seq"), Value::from(self.seq));
+        object.insert(String::from("term"), Value::from(self.term));
+        object.insert(String::from("prev_term"), Value::from(self.prev_term));
+        match self.kind {
+            OperationKind::Index {
+                id,
+                ref content,
+                plain_text,
+            } => {
+                object.insert(String::from("op"), Value::from("index"));
+                object.insert(String::from("id"), Value::from(id));
+                object.insert(String::from("content"), Value::from(content.clone()));
+                object.insert(String::from("plain_text"), Value::from(plain_text));
+            }
+            OperationKind::Delete { id } => {
+                object.insert(String::from("op"), Value::from("delete"));
+                object.insert(String::from("id"), Value::from(id));
+            }
+        }
+        Value::Object(object).to_string()
+    }
+
+    /// Reads an Operation serialized by `to_json`
+    pub fn from_json(json: &str) -> Result<Operation, InvertedIndexError> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| InvertedIndexError::new(&format!("Operation is not valid JSON: {}", e)))?;
+        let seq = value["seq"].as_u64();
+        // Operations logged before terms were recorded have none
+        let term = value["term"].as_u64().unwrap_or(0);
+        let prev_term = value["prev_term"].as_u64().unwrap_or(0);
+        let id = value["id"].as_u64();
+        let kind = match (value["op"].as_str(), id) {
+            (Some("index"), Some(id)) => match value["content"].as_str() {
+                Some(content) => OperationKind::Index {
+                    id: id,
+                    content: content.to_owned(),
+                    plain_text: value["plain_text"].as_bool().unwrap_or(false),
+                },
+                None => return Err(InvertedIndexError::new("Index operation has no content")),
+            },
+            (Some("delete"), Some(id)) => OperationKind::Delete { id: id },
+            _ => return Err(InvertedIndexError::new(&format!("Unknown operation: {}", json))),
+        };
+        match seq {
+            Some(seq) => Ok(Operation {
+                seq: seq,
+                term: term,
+                prev_term: prev_term,
+                kind: kind,
+            }),
+            None => Err(InvertedIndexError::new("Operation has no sequence number")),
+        }
+    }
+}
+
+/// How many copies of a Shard must have a write before the client is told it succeeded. The
+/// Primary always counts as one of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteConsistency {
+    /// Just the Primary
+    One,
+    /// More than half of the copies the Index is configured with
+    Quorum,
+    /// Every copy the Index is configured with
+    All,
+}
+
+impl WriteConsistency {
+    /// Returns the number of copies that must have a write
+    ///
+    /// # Arguments
+    ///
+    /// * `copies` - Number of copies of the Shard the Index is configured with, Primary included
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::replication::WriteConsistency;
+    /// assert_eq!(WriteConsistency::Quorum.required(3), 2);
+    /// ```
+    pub fn required(&self, copies: usize) -> usize {
+        match *self {
+            WriteConsistency::One => 1,
+            WriteConsistency::Quorum => copies / 2 + 1,
+            WriteConsistency::All => copies.max(1),
+        }
+    }
+
+    /// Returns the name clients use for the WriteConsistency
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            WriteConsistency::One => "one",
+            WriteConsistency::Quorum => "quorum",
+            WriteConsistency::All => "all",
+        }
+    }
+}
+
+impl FromStr for WriteConsistency {
+    type Err = InvertedIndexError;
+    fn from_str(s: &str) -> Result<WriteConsistency, Self::Err> {
+        match s {
+            "one" => Ok(WriteConsistency::One),
+            "quorum" => Ok(WriteConsistency::Quorum),
+            "all" => Ok(WriteConsistency::All),
+            _ => Err(InvertedIndexError::new(&format!(
+                "Unknown write consistency {}, expected one, quorum or all",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for WriteConsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// How many copies of a Shard had a write when the client was answered
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplicationReport {
+    /// The WriteConsistency the write asked for
+    pub consistency: WriteConsistency,
+    /// Number of copies of the Shard the Index is configured with, Primary included
+    pub copies: usize,
+    /// Number of copies that had the write, Primary included
+    pub successful: usize,
+}
+
+impl ReplicationReport {
+    /// Returns true if enough copies had the write to satisfy its WriteConsistency
+    pub fn is_met(&self) -> bool {
+        self.successful >= self.consistency.required(self.copies)
+    }
+}
+
+/// What a client is told about a write to a Primary
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WriteOutcome {
+    /// Sequence number of the write's Operation, or 0 if nothing was written
+    pub seq: u64,
+    /// False if a delete found no Document to delete
+    pub found: bool,
+    /// How many copies of the Shard had the write
+    pub replication: ReplicationReport,
+}
+
+/// A committed write a Primary's `Manager` hands off to be sent to its Replicas. Whoever
+/// receives it answers the client, once enough Replicas have the write.
+pub struct Replicate {
+    /// The write as it was logged
+    pub operation: Operation,
+    /// How many copies must have the write before the client is answered
+    pub consistency: WriteConsistency,
+    /// Where to send the WriteOutcome, if anyone is waiting for it
+    pub response_channel: Option<mpsc::Sender<Result<WriteOutcome, InvertedIndexError>>>,
+}
+
+/// The log of every write committed to a Shard, kept in its own SQLite database next to the
+/// Shard's segments. A Primary reads it to catch Replicas up, and a Replica's tells how far
+/// behind its Primary it is.
+pub struct OpLog {
+    connection: rusqlite::Connection,
+    /// Path to the database file the log is kept in
+    path: PathBuf,
+    /// Sequence number of the last Operation in the log, or 0 if it is empty
+    last_seq: u64,
+    /// Term of the last Operation in the log, or 0 if it is empty
+    last_term: u64,
+}
+
+impl OpLog {
+    /// Opens the OpLog kept in a database file, creating it if it does not exist
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the database file
+    pub fn open(path: &Path) -> Result<OpLog, InvertedIndexError> {
+        let connection = rusqlite::Connection::open(path)?;
+        // Snapshots read the log through their own connections while it is appended to
+        connection.execute_batch(PRAGMA_WAL)?;
+        connection.execute(QUERY_CREATE_OPERATIONS_TABLE, &[])?;
+        SQLiteStore::migrate_table(&connection, "operations", OPERATION_TABLE_MIGRATIONS)?;
+        let mut oplog = OpLog {
+            connection: connection,
+            path: path.to_path_buf(),
+            last_seq: 0,
+            last_term: 0,
+        };
+        oplog.read_last()?;
+        Ok(oplog)
+    }
+
+    /// Reads the sequence number and term of the last Operation in the log from its database
+    fn read_last(&mut self) -> Result<(), InvertedIndexError> {
+        let last_seq: i64 = self.connection.query_row(QUERY_LAST_OPERATION_SEQ, &[], |row| row.get(0))?;
+        let last_term: i64 = self.connection.query_row(QUERY_LAST_OPERATION_TERM, &[], |row| row.get(0))?;
+        self.last_seq = last_seq as u64;
+        self.last_term = last_term as u64;
+        Ok(())
+    }
+
+    /// Returns the sequence number of the last Operation in the log, or 0 if it is empty
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Returns the term of the last Operation in the log, or 0 if it is empty
+    pub fn last_term(&self) -> u64 {
+        self.last_term
+    }
+
+    /// Returns the term of the Operation in the log with the given sequence number, or None if
+    /// the log has no such Operation
+    pub fn term_at(&self, seq: u64) -> Result<Option<u64>, InvertedIndexError> {
+        let term = self.connection.query_row(QUERY_OPERATION_TERM, &[&(seq as i64)], |row| {
+            let term: i64 = row.get(0);
+            term as u64
+        });
+        match term {
+            Ok(term) => Ok(Some(term)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(InvertedIndexError::from(e)),
+        }
+    }
+
+    /// Adds an Operation to the end of the log. It must be numbered right after the last one.
+    pub fn append(&mut self, operation: &Operation) -> Result<(), InvertedIndexError> {
+        if operation.seq != self.last_seq + 1 {
+            return Err(InvertedIndexError::new(&format!(
+                "Operation {} does not follow operation {}",
+                operation.seq, self.last_seq
+            )));
+        }
+        self.connection.execute(
+            QUERY_INSERT_OPERATION,
+            &[&(operation.seq as i64), &operation.to_json(), &(operation.term as i64)],
+        )?;
+        self.last_seq = operation.seq;
+        self.last_term = operation.term;
+        Ok(())
+    }
+
+    /// Removes every Operation in the log after the given sequence number
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - Sequence number of the last Operation to keep
+    pub fn truncate(&mut self, seq: u64) -> Result<(), InvertedIndexError> {
+        if seq >= self.last_seq {
+            return Ok(());
+        }
+        self.connection.execute(QUERY_DELETE_OPERATIONS_AFTER, &[&(seq as i64)])?;
+        self.read_last()
+    }
+
+    /// Returns the Operations in the log after the given sequence number, in order. The first
+    /// one is always returned, even if it alone is over `max_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seq` - Sequence number of the last Operation the caller already has
+    /// * `limit` - Most Operations to return
+    /// * `max_bytes` - Most bytes of JSON the Operations returned may add up to
+    pub fn since(&self, seq: u64, limit: usize, max_bytes: usize) -> Result<Vec<Operation>, InvertedIndexError> {
+        let mut stmt = self.connection.prepare(QUERY_OPERATIONS_SINCE)?;
+        let rows = stmt.query_map(&[&(seq as i64), &(limit as i64)], |row| {
+            let json: String = row.get(0);
+            json
+        })?;
+        let mut operations = vec![];
+        let mut bytes = 0;
+        for row in rows {
+            let json = row?;
+            bytes += json.len();
+            if bytes > max_bytes && !operations.is_empty() {
+                break;
+            }
+            operations.push(Operation::from_json(&json)?);
+        }
+        Ok(operations)
+    }
+
+    /// Opens a read transaction on the log that goes on seeing it as it is now, however much is
+    /// appended afterwards. Returns it along with the sequence number of the last Operation it
+    /// sees, so the log can be copied up to that Operation without holding up writes.
+    pub fn read_snapshot(&self) -> Result<(rusqlite::Connection, u64), InvertedIndexError> {
+        let connection = SQLiteStore::open_read_snapshot(&self.path)?;
+        let last_seq: i64 = connection.query_row(QUERY_LAST_OPERATION_SEQ, &[], |row| row.get(0))?;
+        Ok((connection, last_seq as u64))
+    }
+
+    /// Replaces the log with a copy of another OpLog's database file
+    pub fn restore(&mut self, path: &Path) -> Result<(), InvertedIndexError> {
+        self.connection.restore(rusqlite::DatabaseName::Main, path, None)?;
+        // The copy may come from a version that did not record terms
+        SQLiteStore::migrate_table(&self.connection, "operations", OPERATION_TABLE_MIGRATIONS)?;
+        self.read_last()
+    }
+}
+
+/// Name of the copy of the OpLog within a `Snapshot`
+pub const SNAPSHOT_OPLOG_FILENAME: &'static str = "oplog.db";
+
+/// A copy of a Shard made to bring a new Replica up to date. It is a directory holding a copy of
+/// every segment, named like the segment it came from, and of the OpLog.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Sequence number of the last Operation in the copy
+    pub seq: u64,
+    /// Names of the files in the Snapshot's directory
+    pub files: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use constants;
+
+    #[test]
+    fn test_operation_json() {
+        let document = Document::plain_text(7, r#"{"not": "json"}"#);
+        let operation = Operation {
+            seq: 3,
+            term: 2,
+            prev_term: 1,
+            kind: OperationKind::index(&document).unwrap(),
+        };
+        assert_eq!(Operation::from_json(&operation.to_json()).unwrap(), operation);
+        assert_eq!(Operation::from_json(r#"{"seq": 1, "op": "delete", "id": 1}"#).unwrap().term, 0);
+        assert!(Operation::from_json(r#"{"seq": 1, "op": "index", "id": 1}"#).is_err());
+        assert!(Operation::from_json(r#"{"op": "delete", "id": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_write_consistency() {
+        assert_eq!("all".parse::<WriteConsistency>().unwrap(), WriteConsistency::All);
+        assert!("most".parse::<WriteConsistency>().is_err());
+        assert_eq!(WriteConsistency::One.required(3), 1);
+        assert_eq!(WriteConsistency::Quorum.required(1), 1);
+        assert_eq!(WriteConsistency::Quorum.required(4), 3);
+        assert_eq!(WriteConsistency::All.required(3), 3);
+        let report = ReplicationReport {
+            consistency: WriteConsistency::Quorum,
+            copies: 3,
+            successful: 1,
+        };
+        assert!(!report.is_met());
+    }
+
+    #[test]
+    fn test_oplog() {
+        let directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test_oplog.db");
+        let _ = fs::remove_file(&path);
+
+        let mut oplog = OpLog::open(&path).unwrap();
+        assert_eq!(oplog.last_seq(), 0);
+        for seq in 1..4 {
+            oplog.append(&Operation {
+                seq: seq,
+                term: if seq < 3 { 1 } else { 2 },
+                prev_term: if seq < 3 { seq - 1 } else { 1 },
+                kind: OperationKind::Delete { id: seq },
+            }).unwrap();
+        }
+        // Gaps are refused
+        assert!(oplog.append(&Operation { seq: 5, term: 2, prev_term: 2, kind: OperationKind::Delete { id: 5 } }).is_err());
+        assert_eq!((oplog.term_at(2).unwrap(), oplog.term_at(3).unwrap(), oplog.term_at(4).unwrap()), (Some(1), Some(2), None));
+        let since: Vec<u64> = oplog.since(1, 10, usize::MAX).unwrap().iter().map(|operation| operation.seq).collect();
+        assert_eq!(since, vec![2, 3]);
+        assert_eq!(oplog.since(0, 1, usize::MAX).unwrap().len(), 1);
+        // A batch stops short of the byte limit, but always has at least one Operation
+        assert_eq!(oplog.since(0, 10, 1).unwrap().len(), 1);
+        let first = Operation { seq: 1, term: 1, prev_term: 0, kind: OperationKind::Delete { id: 1 } };
+        assert_eq!(oplog.since(0, 10, first.to_json().len() * 2).unwrap().len(), 2);
+
+        let mut oplog = OpLog::open(&path).unwrap();
+        assert_eq!((oplog.last_seq(), oplog.last_term()), (3, 2));
+        oplog.truncate(2).unwrap();
+        assert_eq!((oplog.last_seq(), oplog.last_term()), (2, 1));
+        assert!(oplog.since(2, 10, usize::MAX).unwrap().is_empty());
+        oplog.append(&Operation { seq: 3, term: 2, prev_term: 1, kind: OperationKind::Delete { id: 3 } }).unwrap();
+
+        // Operations appended after a read snapshot is taken are not in copies made from it
+        let (reader, seq) = oplog.read_snapshot().unwrap();
+        assert_eq!(seq, 3);
+        oplog.append(&Operation { seq: 4, term: 2, prev_term: 2, kind: OperationKind::Delete { id: 4 } }).unwrap();
+        let copy_path = directory.join("test_oplog_copy.db");
+        let _ = fs::remove_file(&copy_path);
+        reader.backup(rusqlite::DatabaseName::Main, &copy_path, None).unwrap();
+        let restore_path = directory.join("test_oplog_restore.db");
+        let _ = fs::remove_file(&restore_path);
+        let mut restored = OpLog::open(&restore_path).unwrap();
+        restored.restore(&copy_path).unwrap();
+        assert_eq!(restored.last_seq(), 3);
+    }
+
+    #[test]
+    fn test_migrate_oplog() {
+        let directory = PathBuf::from(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test_oplog_migrate.db");
+        let _ = fs::remove_file(&path);
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            connection.execute("CREATE TABLE operations (seq INTEGER PRIMARY KEY NOT NULL, operation TEXT NOT NULL)", &[]).unwrap();
+            connection.execute(r#"INSERT INTO operations VALUES (1, '{"seq": 1, "op": "delete", "id": 1}')"#, &[]).unwrap();
+        }
+        let mut oplog = OpLog::open(&path).unwrap();
+        assert_eq!((oplog.last_seq(), oplog.last_term()), (1, 0));
+        oplog.append(&Operation { seq: 2, term: 1, prev_term: 0, kind: OperationKind::Delete { id: 2 } }).unwrap();
+        assert_eq!(oplog.term_at(2).unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use query::Hit;
+
+/// Default BM25 term frequency saturation
+pub const DEFAULT_K1: f64 = 1.2;
+/// Default BM25 document length normalization
+pub const DEFAULT_B: f64 = 0.75;
+
+/// Parameters of the BM25 ranking function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bm25 {
+    /// How quickly repeats of a term stop adding to the score. 0 ignores term frequency entirely.
+    pub k1: f64,
+    /// How much a long field is penalized against the average length, from 0 (not at all) to 1
+    pub b: f64,
+}
+
+impl Bm25 {
+    /// Creates and returns a new Bm25
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use inverted_index::scoring::Bm25;
+    /// let bm25 = Bm25::new(1.2, 0.75);
+    /// ```
+    pub fn new(k1: f64, b: f64) -> Bm25 {
+        Bm25 { k1: k1, b: b }
+    }
+
+    /// Inverse document frequency of a term that `frequency` of `documents` Documents contain
+    pub fn idf(&self, documents: u64, frequency: u64) -> f64 {
+        let documents = documents as f64;
+        let frequency = frequency as f64;
+        (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln()
+    }
+
+    /// Scores a term that occurs `frequency` times in a field `length` terms long
+    pub fn score(&self, idf: f64, frequency: f64, length: f64, average_length: f64) -> f64 {
+        let normalized = if average_length > 0.0 {
+            1.0 - self.b + self.b * length / average_length
+        } else {
+            1.0
+        };
+        idf * frequency * (self.k1 + 1.0) / (frequency + self.k1 * normalized)
+    }
+}
+
+impl Default for Bm25 {
+    fn default() -> Bm25 {
+        Bm25::new(DEFAULT_K1, DEFAULT_B)
+    }
+}
+
+/// Length totals of one field
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FieldStatistics {
+    /// Number of Documents that have the field
+    pub documents: u64,
+    /// Sum of the lengths of the field across those Documents
+    pub total_length: u64,
+}
+
+/// Collection statistics that BM25 needs. Each segment gathers its own and the Manager adds them
+/// up, so every segment scores with numbers for the whole Shard rather than just its own slice.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Statistics {
+    /// Number of Documents
+    pub documents: u64,
+    /// Length totals by field name
+    pub fields: BTreeMap<String, FieldStatistics>,
+    /// Number of Documents containing each term, keyed by field name and term
+    pub document_frequencies: BTreeMap<(String, String), u64>,
+}
+
+impl Statistics {
+    /// Adds the statistics of another segment to these
+    pub fn merge(&mut self, other: &Statistics) {
+        self.documents += other.documents;
+        for (field, stats) in &other.fields {
+            let entry = self.fields.entry(field.clone()).or_insert_with(FieldStatistics::default);
+            entry.documents += stats.documents;
+            entry.total_length += stats.total_length;
+        }
+        for (key, frequency) in &other.document_frequencies {
+            *self.document_frequencies.entry(key.clone()).or_insert(0) += *frequency;
+        }
+    }
+
+    /// Average length of a field across the Documents that have it
+    pub fn average_length(&self, field: &str) -> f64 {
+        match self.fields.get(field) {
+            Some(stats) if stats.documents > 0 => stats.total_length as f64 / stats.documents as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// Number of Documents with a term in a field
+    pub fn document_frequency(&self, field: &str, term: &str) -> u64 {
+        self.document_frequencies
+            .get(&(field.to_owned(), term.to_owned()))
+            .cloned()
+            .unwrap_or(0)
+    }
+}
+
+/// Scores matches with BM25 using statistics gathered before the search
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scorer {
+    similarity: Bm25,
+    statistics: Statistics,
+}
+
+impl Scorer {
+    /// Creates and returns a new Scorer
+    ///
+    /// # Arguments
+    ///
+    /// * `similarity` - BM25 parameters of the Index
+    /// * `statistics` - Statistics of every segment the search runs on, merged together
+    pub fn new(similarity: Bm25, statistics: Statistics) -> Scorer {
+        Scorer {
+            similarity: similarity,
+            statistics: statistics,
+        }
+    }
+
+    /// Inverse document frequency of a term in a field
+    pub fn idf(&self, field: &str, term: &str) -> f64 {
+        self.similarity.idf(
+            self.statistics.documents,
+            self.statistics.document_frequency(field, term),
+        )
+    }
+
+    /// Scores a match of weight `idf` found `frequency` times in a field `length` terms long
+    pub fn score(&self, field: &str, idf: f64, frequency: u64, length: u64) -> f64 {
+        self.similarity.score(
+            idf,
+            frequency as f64,
+            length as f64,
+            self.statistics.average_length(field),
+        )
+    }
+}
+
+/// Orders Hits by score, best first, with ties going to the lower Document ID
+pub fn rank(hits: &mut Vec<Hit>) {
+    hits.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then(a.id.cmp(&b.id))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rarer_terms_weigh_more() {
+        let bm25 = Bm25::default();
+        assert!(bm25.idf(100, 1) > bm25.idf(100, 50));
+        assert!(bm25.idf(100, 100) > 0.0);
+    }
+
+    #[test]
+    fn test_term_frequency_saturates() {
+        let bm25 = Bm25::default();
+        let one = bm25.score(1.0, 1.0, 10.0, 10.0);
+        let two = bm25.score(1.0, 2.0, 10.0, 10.0);
+        let twenty = bm25.score(1.0, 20.0, 10.0, 10.0);
+        assert!(two > one);
+        assert!(twenty < bm25.k1 + 1.0);
+        assert!(bm25.score(1.0, 1.0, 5.0, 10.0) > bm25.score(1.0, 1.0, 20.0, 10.0));
+        assert_eq!(Bm25::new(1.2, 0.0).score(1.0, 1.0, 5.0, 10.0), Bm25::new(1.2, 0.0).score(1.0, 1.0, 20.0, 10.0));
+    }
+
+    #[test]
+    fn test_merge_statistics() {
+        let mut first = Statistics::default();
+        first.documents = 2;
+        first.fields.insert("message".into(), FieldStatistics { documents: 2, total_length: 6 });
+        first.document_frequencies.insert(("message".into(), "bear".into()), 1);
+        let mut second = first.clone();
+        second.documents = 1;
+        second.fields.insert("message".into(), FieldStatistics { documents: 1, total_length: 6 });
+        first.merge(&second);
+        assert_eq!(first.documents, 3);
+        assert_eq!(first.average_length("message"), 4.0);
+        assert_eq!(first.document_frequency("message", "bear"), 2);
+        assert_eq!(first.document_frequency("message", "walrus"), 0);
+    }
+
+    #[test]
+    fn test_rank() {
+        let hit = |id, score| Hit {
+            id: id,
+            score: score,
+            source: String::new(),
+            plain_text: false,
+        };
+        let mut hits = vec![hit(3, 0.5), hit(2, 1.5), hit(1, 0.5)];
+        rank(&mut hits);
+        assert_eq!(hits.iter().map(|h| h.id).collect::<Vec<u64>>(), vec![2, 1, 3]);
+    }
+}
@@ -68,6 +68,26 @@ impl Shard {
     }
 }
 
+/// Picks which of an Index's Primary Shards a Document belongs in. The ID is mixed before taking
+/// the remainder because each Shard spreads its Documents across segments by ID modulo the number
+/// of segments; routing on the plain remainder too would leave most segments of a Shard empty.
+///
+/// # Arguments
+///
+/// * `id` - ID of the Document
+/// * `shards` - Number of Primary Shards the Index has
+///
+/// # Example
+///
+/// ```rust
+/// use inverted_index::shard::shard_for;
+/// assert!(shard_for(42, 3) < 3);
+/// ```
+pub fn shard_for(id: u64, shards: u32) -> u32 {
+    let mixed = id.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    ((mixed >> 32) % u64::from(shards)) as u32
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,4 +97,14 @@ mod tests {
         let s = Shard::new("TestShard", ShardType::Primary);
         assert_eq!(s.index, "TestShard");
     }
+
+    #[test]
+    fn test_shard_for() {
+        let mut counts = [0; 3];
+        for id in 0..3000 {
+            counts[shard_for(id, 3) as usize] += 1;
+        }
+        assert!(counts.iter().all(|count| *count > 800));
+        assert_eq!(shard_for(7, 1), 0);
+    }
 }
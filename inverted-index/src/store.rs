@@ -4,6 +4,9 @@ use rusqlite;
 
 use document::Document;
 use index::InvertedIndexError;
+use mapping::Mappings;
+use query::{PreparedQuery, SearchResults};
+use scoring::{Scorer, Statistics};
 
 /// IndexStore is something that can store and retrieve Documents
 pub trait IndexStore: Sized {
@@ -13,8 +16,26 @@ pub trait IndexStore: Sized {
     fn close(&mut self) -> Result<(), InvertedIndexError>;
     /// Saves a Document to the IndexStore
     fn save_document(&mut self, document: Document) -> Result<(), InvertedIndexError>;
-    /// Deletes a Document from the IndexStore
-    fn delete_document_by_id(&mut self, id: u64) -> Result<(), InvertedIndexError>;
-    /// Retrieves a Document by id
-    fn document_by_id(&mut self, id: u64) -> Result<Document, InvertedIndexError>;
+    /// Deletes a Document from the IndexStore, returning whether there was one to delete
+    fn delete_document_by_id(&mut self, id: u64) -> Result<bool, InvertedIndexError>;
+    /// Retrieves a Document by id, or None if there is no such Document
+    fn document_by_id(&mut self, id: u64) -> Result<Option<Document>, InvertedIndexError>;
+    /// Retrieves the field Mappings stored in the IndexStore
+    fn mappings(&mut self) -> Result<Mappings, InvertedIndexError>;
+    /// Saves field Mappings to the IndexStore, keeping any it already has
+    fn save_mappings(&mut self, mappings: &Mappings) -> Result<(), InvertedIndexError>;
+    /// Removes field Mappings from the IndexStore
+    fn delete_mappings(&mut self, mappings: &Mappings) -> Result<(), InvertedIndexError>;
+    /// Runs a PreparedQuery and returns how many Documents matched along with the `limit` most
+    /// relevant of them, most relevant first
+    fn search(
+        &mut self,
+        query: &PreparedQuery,
+        scorer: &Scorer,
+        limit: usize,
+    ) -> Result<SearchResults, InvertedIndexError>;
+    /// Gathers the statistics needed to score the given field and term pairs
+    fn statistics(&mut self, terms: &[(String, String)]) -> Result<Statistics, InvertedIndexError>;
+    /// Replaces everything in the IndexStore with a copy of another IndexStore's database file
+    fn restore(&mut self, path: &Path) -> Result<(), InvertedIndexError>;
 }
@@ -1,12 +1,19 @@
 pub mod queries;
 
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
 use std::path::Path;
 
 use rusqlite;
+use rusqlite::types::Value as SqlValue;
 
-use document::Document;
+use document::{value_to_text, Document};
 use index::InvertedIndexError;
+use mapping::{FieldType, Mappings, TypedValue};
+use query::{Hit, PreparedQuery, SearchResults};
+use scoring::{rank, FieldStatistics, Scorer, Statistics};
 use store::IndexStore;
+use self::queries::*;
 
 pub struct SQLiteStore {
     index_name: String,
@@ -14,13 +21,317 @@ pub struct SQLiteStore {
     connection: rusqlite::Connection,
 }
 
-impl SQLiteStore {}
+impl SQLiteStore {
+    /// Creates the tables and indices a segment needs if they do not already exist. Every query
+    /// is idempotent, so this is safe to run each time a segment is opened.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the SQLite database backing the segment
+    pub fn initialize(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        for query in QUERIES_INITIALIZE_INDEX_DB {
+            // execute_batch is used because the PRAGMAs return rows, which execute rejects
+            conn.execute_batch(query)?;
+        }
+        SQLiteStore::migrate_table(conn, "documents", DOCUMENT_TABLE_MIGRATIONS)
+    }
+
+    /// Adds any columns a table is missing because the segment was made by an older version
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the SQLite database backing the segment
+    /// * `table` - Name of the table
+    /// * `migrations` - Each column the table should have, with the query that adds it
+    pub fn migrate_table(conn: &rusqlite::Connection, table: &str, migrations: &[(&str, &str)]) -> Result<(), rusqlite::Error> {
+        let columns: Vec<String> = {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let rows = stmt.query_map(&[], |row| row.get(1))?;
+            let mut columns = vec![];
+            for column in rows {
+                columns.push(column?);
+            }
+            columns
+        };
+        for &(column, query) in migrations {
+            if !columns.iter().any(|existing| existing == column) {
+                conn.execute(query, &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens another connection to a database file and starts a read transaction on it, so that
+    /// it keeps seeing the database as it is now while writes carry on through other connections.
+    /// The database has to be in WAL mode, or those writes wait until the connection is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the database file
+    pub fn open_read_snapshot(path: &Path) -> Result<rusqlite::Connection, rusqlite::Error> {
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch(QUERY_BEGIN)?;
+        // The transaction only settles on what it sees once it first reads
+        conn.query_row(QUERY_COUNT_SCHEMA, &[], |row| {
+            let _: i64 = row.get(0);
+        })?;
+        Ok(conn)
+    }
+
+    /// Finds the Documents in this segment that match a PreparedQuery, along with their scores
+    fn matching(&self, query: &PreparedQuery, scorer: &Scorer) -> Result<BTreeMap<u64, f64>, InvertedIndexError> {
+        match *query {
+            PreparedQuery::MatchAll => Ok(constant_score(self.ids(QUERY_ALL_DOCUMENT_IDS, &[])?, 1.0)),
+            PreparedQuery::MatchNone => Ok(BTreeMap::new()),
+            PreparedQuery::Terms { ref field, ref terms } => {
+                let mut scores = BTreeMap::new();
+                for term in terms {
+                    let idf = scorer.idf(field, term);
+                    for (id, frequency) in self.term_frequencies(field, term)? {
+                        let score = scorer.score(field, idf, frequency, self.field_length(field, id)?);
+                        *scores.entry(id).or_insert(0.0) += score;
+                    }
+                }
+                Ok(scores)
+            }
+            PreparedQuery::Phrase {
+                ref field,
+                ref positions,
+            } => {
+                // A phrase weighs as much as its terms put together. Where a position has more
+                // than one term, the rarest of them counts.
+                let idf: f64 = positions
+                    .iter()
+                    .map(|&(_, ref terms)| terms.iter().map(|term| scorer.idf(field, term)).fold(0.0, f64::max))
+                    .sum();
+                let mut scores = BTreeMap::new();
+                for (id, frequency) in self.phrase_frequencies(field, positions)? {
+                    scores.insert(id, scorer.score(field, idf, frequency, self.field_length(field, id)?));
+                }
+                Ok(scores)
+            }
+            PreparedQuery::Value { ref field, ref value } => Ok(constant_score(
+                self.ids(QUERY_DOCUMENTS_WITH_VALUE, &[field, &sql_value(value)])?,
+                1.0,
+            )),
+            PreparedQuery::Range {
+                ref field,
+                ref lower,
+                ref upper,
+            } => self.range_ids(field, lower, upper).map(|ids| constant_score(ids, 1.0)),
+            PreparedQuery::Bool {
+                ref must,
+                ref should,
+                ref must_not,
+            } => {
+                let mut scores: Option<BTreeMap<u64, f64>> = None;
+                for query in must {
+                    let matched = self.matching(query, scorer)?;
+                    scores = Some(match scores {
+                        Some(scores) => scores
+                            .into_iter()
+                            .filter_map(|(id, score)| matched.get(&id).map(|extra| (id, score + extra)))
+                            .collect(),
+                        None => matched,
+                    });
+                }
+                let mut should_scores = BTreeMap::new();
+                for query in should {
+                    for (id, score) in self.matching(query, scorer)? {
+                        *should_scores.entry(id).or_insert(0.0) += score;
+                    }
+                }
+                let mut scores = match scores {
+                    // should only adds to the scores of Documents that match everything in must
+                    Some(mut scores) => {
+                        for (id, score) in scores.iter_mut() {
+                            if let Some(extra) = should_scores.get(id) {
+                                *score += *extra;
+                            }
+                        }
+                        scores
+                    }
+                    None if !should.is_empty() => should_scores,
+                    // With nothing but must_not, everything left matches and none of it is more
+                    // relevant than the rest
+                    None => constant_score(self.ids(QUERY_ALL_DOCUMENT_IDS, &[])?, 0.0),
+                };
+                for query in must_not {
+                    if scores.is_empty() {
+                        break;
+                    }
+                    for id in self.matching(query, scorer)?.keys() {
+                        scores.remove(id);
+                    }
+                }
+                Ok(scores)
+            }
+        }
+    }
+
+    /// Finds the Documents where a field falls between two bounds. Keyword bounds are compared
+    /// with the field's terms and anything else with its typed values. Text fields can't be
+    /// ranged over, so they never get here.
+    fn range_ids(
+        &self,
+        field: &str,
+        lower: &Bound<TypedValue>,
+        upper: &Bound<TypedValue>,
+    ) -> Result<BTreeSet<u64>, InvertedIndexError> {
+        let textual = [lower, upper].iter().any(|bound| match **bound {
+            Bound::Included(TypedValue::Keyword(_)) | Bound::Excluded(TypedValue::Keyword(_)) => true,
+            _ => false,
+        });
+        let (mut query, column) = if textual {
+            (String::from(QUERY_PARTIAL_RANGE), "term")
+        } else {
+            (String::from(QUERY_PARTIAL_VALUE_RANGE), "value")
+        };
+        let mut values = vec![SqlValue::Text(field.to_owned())];
+        for &(bound, inclusive, exclusive) in &[(lower, ">=", ">"), (upper, "<=", "<")] {
+            let (value, operator) = match *bound {
+                Bound::Included(ref value) => (value, inclusive),
+                Bound::Excluded(ref value) => (value, exclusive),
+                Bound::Unbounded => continue,
+            };
+            values.push(sql_value(value));
+            query.push_str(&format!("AND {} {} ?{} ", column, operator, values.len()));
+        }
+        let params: Vec<&rusqlite::types::ToSql> = values.iter().map(|v| v as &rusqlite::types::ToSql).collect();
+        self.ids(&query, &params)
+    }
+
+    /// Counts how many times the terms of a phrase sit at the given distances from its first
+    /// term in each Document, using the offsets stored with each occurrence
+    fn phrase_frequencies(
+        &self,
+        field: &str,
+        positions: &[(u64, Vec<String>)],
+    ) -> Result<HashMap<u64, u64>, InvertedIndexError> {
+        // Start offsets of the phrase in each Document that are still possible
+        let mut starts: Option<HashMap<u64, HashSet<u64>>> = None;
+        for &(position, ref terms) in positions {
+            let offsets = self.offsets(field, terms)?;
+            let remaining = match starts {
+                None => offsets
+                    .into_iter()
+                    .map(|(id, offsets)| (id, offsets.into_iter().filter(|o| *o >= position).map(|o| o - position).collect()))
+                    .collect(),
+                Some(starts) => starts
+                    .into_iter()
+                    .filter_map(|(id, starts)| {
+                        let offsets = offsets.get(&id)?;
+                        let starts: HashSet<u64> = starts.into_iter().filter(|s| offsets.contains(&(s + position))).collect();
+                        if starts.is_empty() {
+                            None
+                        } else {
+                            Some((id, starts))
+                        }
+                    })
+                    .collect(),
+            };
+            starts = Some(remaining);
+        }
+        Ok(starts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, starts)| (id, starts.len() as u64))
+            .collect())
+    }
+
+    /// Retrieves the offsets of any of the terms in a field, grouped by Document
+    fn offsets(&self, field: &str, terms: &[String]) -> Result<HashMap<u64, HashSet<u64>>, InvertedIndexError> {
+        let mut stmt = self.connection.prepare_cached(QUERY_OFFSETS_OF_TERM_IN_FIELD)?;
+        let mut offsets: HashMap<u64, HashSet<u64>> = HashMap::new();
+        for term in terms {
+            let rows = stmt.query_map(&[&field, term], |row| {
+                let document: String = row.get(0);
+                let offset: i64 = row.get(1);
+                (document, offset)
+            })?;
+            for row in rows {
+                let (document, offset) = row?;
+                offsets
+                    .entry(parse_id(&document)?)
+                    .or_insert_with(HashSet::new)
+                    .insert(offset as u64);
+            }
+        }
+        Ok(offsets)
+    }
+
+    /// Counts how many times a term occurs in a field of each Document that has it
+    fn term_frequencies(&self, field: &str, term: &str) -> Result<Vec<(u64, u64)>, InvertedIndexError> {
+        let mut stmt = self.connection.prepare_cached(QUERY_TERM_FREQUENCIES_IN_FIELD)?;
+        let rows = stmt.query_map(&[&field, &term], |row| {
+            let document: String = row.get(0);
+            let frequency: i64 = row.get(1);
+            (document, frequency)
+        })?;
+        let mut frequencies = vec![];
+        for row in rows {
+            let (document, frequency) = row?;
+            frequencies.push((parse_id(&document)?, frequency as u64));
+        }
+        Ok(frequencies)
+    }
+
+    /// Retrieves the length of a field in a Document, which is 0 if the Document does not have it
+    fn field_length(&self, field: &str, id: u64) -> Result<u64, InvertedIndexError> {
+        let result = self.connection.query_row(QUERY_FIELD_LENGTH, &[&field, &id.to_string()], |row| {
+            let length: i64 = row.get(0);
+            length
+        });
+        match result {
+            Ok(length) => Ok(length as u64),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(InvertedIndexError::from(e)),
+        }
+    }
+
+    /// Runs a query whose first column is a Document ID and collects the IDs
+    fn ids(&self, query: &str, params: &[&rusqlite::types::ToSql]) -> Result<BTreeSet<u64>, InvertedIndexError> {
+        let mut stmt = self.connection.prepare_cached(query)?;
+        let rows = stmt.query_map(params, |row| {
+            let id: String = row.get(0);
+            id
+        })?;
+        let mut ids = BTreeSet::new();
+        for row in rows {
+            ids.insert(parse_id(&row?)?);
+        }
+        Ok(ids)
+    }
+}
+
+/// Gives every Document the same score
+fn constant_score(ids: BTreeSet<u64>, score: f64) -> BTreeMap<u64, f64> {
+    ids.into_iter().map(|id| (id, score)).collect()
+}
+
+/// Document IDs are stored as text, so they are parsed back into numbers when read
+fn parse_id(id: &str) -> Result<u64, InvertedIndexError> {
+    id.parse::<u64>()
+        .map_err(|_| InvertedIndexError::new(&format!("Invalid document id {}", id)))
+}
+
+/// Converts a TypedValue into the value stored for it in field_values. Integers, dates and
+/// booleans are stored as integers so that they compare numerically.
+fn sql_value(value: &TypedValue) -> SqlValue {
+    match *value {
+        TypedValue::Integer(i) | TypedValue::Date(i) => SqlValue::Integer(i),
+        TypedValue::Float(f) => SqlValue::Real(f),
+        TypedValue::Boolean(b) => SqlValue::Integer(b as i64),
+        TypedValue::Keyword(ref s) | TypedValue::Text(ref s) => SqlValue::Text(s.clone()),
+    }
+}
 
 impl IndexStore for SQLiteStore {
     /// Opens an IndexStore or creates a new one
     fn open<S: Into<String>>(name: S, path: &Path) -> Result<Self, rusqlite::Error> {
         match rusqlite::Connection::open(path) {
             Ok(conn) => {
+                SQLiteStore::initialize(&conn)?;
                 Ok(SQLiteStore {
                     index_name: name.into(),
                     data_path: String::from(path.to_str().unwrap()),
@@ -38,26 +349,222 @@ impl IndexStore for SQLiteStore {
         Ok(())
     }
 
-    /// Saves a Document to the IndexStore
+    /// Replaces the segment with a copy of another segment's database file
+    fn restore(&mut self, path: &Path) -> Result<(), InvertedIndexError> {
+        self.connection.restore(rusqlite::DatabaseName::Main, path, None)?;
+        Ok(())
+    }
+
+    /// Saves a Document to the IndexStore. The Document, its fields, its terms and their
+    /// occurrences are written in a single transaction, so a failure part way through leaves
+    /// nothing behind.
+    /// Saving a Document with an ID that already exists replaces the old one.
     fn save_document(&mut self, document: Document) -> Result<(), InvertedIndexError> {
-        Err(InvertedIndexError::new("Not Yet Implemented"))
+        let id = match document.id() {
+            Some(id) => id.to_string(),
+            None => {
+                return Err(InvertedIndexError::new("Document must have an id to be saved"));
+            }
+        };
+
+        let tx = self.connection.transaction()?;
+        tx.execute(QUERY_DELETE_OCCURRENCES_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELDS_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELD_VALUES_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELD_LENGTHS_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_INSERT_DOCUMENT, &[&id, &document.content(), &document.is_plain_text()])?;
+        {
+            let mut insert_field = tx.prepare(QUERY_INSERT_FIELD)?;
+            for (field, values) in document.fields() {
+                for value in values {
+                    insert_field.execute(&[field, &id, &value_to_text(value)])?;
+                }
+            }
+
+            let mut insert_value = tx.prepare(QUERY_INSERT_FIELD_VALUE)?;
+            for (field, values) in document.values() {
+                for value in values {
+                    match *value {
+                        TypedValue::Keyword(_) | TypedValue::Text(_) => {}
+                        _ => {
+                            insert_value.execute(&[field, &id, &sql_value(value)])?;
+                        }
+                    }
+                }
+            }
+
+            let mut insert_term = tx.prepare(QUERY_INSERT_TERM)?;
+            let mut insert_occurrence = tx.prepare(QUERY_INSERT_OCCURRENCE)?;
+            let mut insert_length = tx.prepare(QUERY_INSERT_FIELD_LENGTH)?;
+            for (field, terms) in document.locations() {
+                // Terms that share a position, like a stem and its original, only count once
+                let positions: HashSet<&u64> = terms.values().flat_map(|offsets| offsets.iter()).collect();
+                insert_length.execute(&[field, &id, &(positions.len() as i64)])?;
+                for (term, offsets) in terms {
+                    insert_term.execute(&[term])?;
+                    for offset in offsets {
+                        insert_occurrence.execute(&[term, &id, field, &(*offset as i64)])?;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Deletes a Document from the IndexStore, returning whether there was one to delete
+    fn delete_document_by_id(&mut self, id: u64) -> Result<bool, InvertedIndexError> {
+        let id = id.to_string();
+        let tx = self.connection.transaction()?;
+        tx.execute(QUERY_DELETE_OCCURRENCES_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELDS_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELD_VALUES_BY_DOCUMENT_ID, &[&id])?;
+        tx.execute(QUERY_DELETE_FIELD_LENGTHS_BY_DOCUMENT_ID, &[&id])?;
+        let deleted = tx.execute(QUERY_DELETE_DOCUMENT_BY_ID, &[&id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    /// Retrieves a Document by id, or None if there is no such Document
+    fn document_by_id(&mut self, id: u64) -> Result<Option<Document>, InvertedIndexError> {
+        let result = self.connection.query_row(QUERY_DOCUMENT_BY_ID, &[&id.to_string()], |row| {
+            let content: String = row.get(1);
+            let plain_text: bool = row.get(2);
+            (content, plain_text)
+        });
+        match result {
+            // Plain text that happens to be valid JSON must not come back as fields
+            Ok((content, true)) => Ok(Some(Document::plain_text(id, &content))),
+            Ok((content, false)) => Ok(Some(Document::new(id, &content))),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(InvertedIndexError::from(e)),
+        }
+    }
+
+    /// Retrieves the field Mappings stored in the IndexStore
+    fn mappings(&mut self) -> Result<Mappings, InvertedIndexError> {
+        let mut stmt = self.connection.prepare(QUERY_ALL_MAPPINGS)?;
+        let rows = stmt.query_map(&[], |row| {
+            let name: String = row.get(0);
+            let field_type: String = row.get(1);
+            (name, field_type)
+        })?;
+        let mut mappings = Mappings::new();
+        for row in rows {
+            let (name, field_type) = row?;
+            mappings = mappings.field(name, field_type.parse::<FieldType>()?);
+        }
+        Ok(mappings)
+    }
+
+    /// Saves field Mappings to the IndexStore, keeping any it already has
+    fn save_mappings(&mut self, mappings: &Mappings) -> Result<(), InvertedIndexError> {
+        let tx = self.connection.transaction()?;
+        {
+            let mut insert_mapping = tx.prepare(QUERY_INSERT_MAPPING)?;
+            for (name, field_type) in mappings.iter() {
+                insert_mapping.execute(&[name, &field_type.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
     }
 
-    /// Deletes a Document from the IndexStore
-    fn delete_document_by_id(&mut self, id: u64) -> Result<(), InvertedIndexError> {
-        Err(InvertedIndexError::new("Not Yet Implemented"))
+    /// Removes field Mappings from the IndexStore
+    fn delete_mappings(&mut self, mappings: &Mappings) -> Result<(), InvertedIndexError> {
+        let tx = self.connection.transaction()?;
+        {
+            let mut delete_mapping = tx.prepare(QUERY_DELETE_MAPPING)?;
+            for (name, field_type) in mappings.iter() {
+                delete_mapping.execute(&[name, &field_type.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
     }
 
-    /// Retrieves a Document by id
-    fn document_by_id(&mut self, id: u64) -> Result<Document, InvertedIndexError> {
-        Err(InvertedIndexError::new("Not Yet Implemented"))
+    /// Runs a PreparedQuery and returns how many Documents matched along with the `limit` most
+    /// relevant of them. Matches are ranked by score alone, so the source is only loaded for the
+    /// Documents that make the cut.
+    fn search(
+        &mut self,
+        query: &PreparedQuery,
+        scorer: &Scorer,
+        limit: usize,
+    ) -> Result<SearchResults, InvertedIndexError> {
+        let scores = self.matching(query, scorer)?;
+        let total = scores.len();
+        let mut hits: Vec<Hit> = scores
+            .into_iter()
+            .map(|(id, score)| Hit {
+                id: id,
+                score: score,
+                source: String::new(),
+                plain_text: false,
+            })
+            .collect();
+        rank(&mut hits);
+        hits.truncate(limit);
+        for hit in &mut hits {
+            let (source, plain_text): (String, bool) = self
+                .connection
+                .query_row(QUERY_DOCUMENT_BY_ID, &[&hit.id.to_string()], |row| (row.get(1), row.get(2)))?;
+            hit.source = source;
+            hit.plain_text = plain_text;
+        }
+        Ok(SearchResults {
+            total: total,
+            hits: hits,
+        })
+    }
+
+    /// Gathers the statistics needed to score the given field and term pairs
+    fn statistics(&mut self, terms: &[(String, String)]) -> Result<Statistics, InvertedIndexError> {
+        let mut statistics = Statistics::default();
+        let documents: i64 = self.connection.query_row(QUERY_COUNT_DOCUMENTS, &[], |row| row.get(0))?;
+        statistics.documents = documents as u64;
+        for &(ref field, ref term) in terms {
+            if !statistics.fields.contains_key(field) {
+                let (documents, total_length): (i64, i64) = self
+                    .connection
+                    .query_row(QUERY_FIELD_STATISTICS, &[field], |row| (row.get(0), row.get(1)))?;
+                statistics.fields.insert(
+                    field.clone(),
+                    FieldStatistics {
+                        documents: documents as u64,
+                        total_length: total_length as u64,
+                    },
+                );
+            }
+            let frequency: i64 = self
+                .connection
+                .query_row(QUERY_DOCUMENT_FREQUENCY, &[field, term], |row| row.get(0))?;
+            statistics
+                .document_frequencies
+                .insert((field.clone(), term.clone()), frequency as u64);
+        }
+        Ok(statistics)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+    use analyzer::Analyzer;
+    use constants;
+    use query::Query;
+    use scoring::Bm25;
+    use serde_json::Value;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn test_store_path(name: &str) -> PathBuf {
+        let directory = Path::new(constants::TEST_DEFAULT_DATA_DIRECTORY);
+        fs::create_dir_all(directory).unwrap();
+        let path = directory.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
 
     #[test]
     fn test_open_sqlite_store() {
@@ -65,4 +572,275 @@ mod tests {
         let c = SQLiteStore::open("test", p);
         assert_eq!(c.is_err(), false);
     }
+
+    #[test]
+    fn test_save_and_fetch_document() {
+        let p = test_store_path("test_save_and_fetch.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store.save_document(Document::new(1, "A molar bear")).unwrap();
+        let document = store.document_by_id(1).unwrap().unwrap();
+        assert_eq!(document.id(), Some(1));
+        assert_eq!(document.content(), "A molar bear");
+        assert_eq!(document.locations()["message"].len(), 3);
+    }
+
+    #[test]
+    fn test_save_json_document_fields() {
+        let p = test_store_path("test_save_json_fields.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store
+            .save_document(Document::new(1, r#"{"message": "A molar bear", "user": {"name": "fletcher"}}"#))
+            .unwrap();
+        let field: String = store
+            .connection
+            .query_row(QUERY_DOCUMENTS_WITH_TERM_IN_FIELD, &[&"user.name", &"fletcher"], |row| row.get(0))
+            .unwrap();
+        assert_eq!(field, "1");
+        let fields: i64 = store
+            .connection
+            .query_row("SELECT COUNT(*) FROM fields WHERE document = '1'", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(fields, 2);
+    }
+
+    #[test]
+    fn test_save_replaces_document() {
+        let p = test_store_path("test_save_replaces.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store.save_document(Document::new(1, "A molar bear")).unwrap();
+        store.save_document(Document::new(1, "A polar bear")).unwrap();
+        let count: i64 = store
+            .connection
+            .query_row("SELECT COUNT(*) FROM occurrences WHERE document = '1'", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(store.document_by_id(1).unwrap().unwrap().content(), "A polar bear");
+    }
+
+    #[test]
+    fn test_delete_document() {
+        let p = test_store_path("test_delete.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store.save_document(Document::new(1, "A molar bear")).unwrap();
+        assert!(store.delete_document_by_id(1).unwrap());
+        assert!(store.document_by_id(1).unwrap().is_none());
+        assert!(!store.delete_document_by_id(1).unwrap());
+    }
+
+    #[test]
+    fn test_fetch_plain_text_document() {
+        let p = test_store_path("test_fetch_plain_text.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store.save_document(Document::plain_text(1, r#"{"name": "bear"}"#)).unwrap();
+        let document = store.document_by_id(1).unwrap().unwrap();
+        assert!(document.is_plain_text());
+        assert!(document.locations().contains_key("message"));
+    }
+
+    #[test]
+    fn test_migrate_documents_table() {
+        let p = test_store_path("test_migrate_documents.db");
+        {
+            let conn = rusqlite::Connection::open(&p).unwrap();
+            conn.execute("CREATE TABLE documents (id TEXT PRIMARY KEY, content TEXT)", &[]).unwrap();
+            conn.execute("INSERT INTO documents (id, content) VALUES ('1', 'A molar bear')", &[]).unwrap();
+        }
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        assert!(!store.document_by_id(1).unwrap().unwrap().is_plain_text());
+    }
+
+    #[test]
+    fn test_save_typed_values() {
+        let p = test_store_path("test_save_typed_values.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        let mappings = Mappings::new()
+            .field("status", FieldType::Integer)
+            .field("took", FieldType::Float);
+        let document = Document::new(1, r#"{"status": 404, "took": 1.5}"#)
+            .analyze_mapped(&Analyzer::standard(), &mappings)
+            .unwrap();
+        store.save_document(document).unwrap();
+        let status: String = store
+            .connection
+            .query_row("SELECT typeof(value) FROM field_values WHERE name = 'status'", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(status, "integer");
+        let took: f64 = store
+            .connection
+            .query_row("SELECT value FROM field_values WHERE name = 'took'", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(took, 1.5);
+    }
+
+    #[test]
+    fn test_save_and_load_mappings() {
+        let p = test_store_path("test_mappings.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        let mappings = Mappings::new()
+            .field("host", FieldType::Keyword)
+            .field("timestamp", FieldType::Date);
+        store.save_mappings(&mappings).unwrap();
+        store.save_mappings(&mappings).unwrap();
+        assert_eq!(store.mappings().unwrap(), mappings);
+
+        store.delete_mappings(&Mappings::new().field("timestamp", FieldType::Date)).unwrap();
+        assert_eq!(store.mappings().unwrap(), Mappings::new().field("host", FieldType::Keyword));
+    }
+
+    #[test]
+    fn test_save_document_without_id() {
+        let p = test_store_path("test_save_without_id.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        let document: Document = "A molar bear".parse().unwrap();
+        assert!(store.save_document(document).is_err());
+    }
+
+    fn search_hits(store: &mut SQLiteStore, query: Query, mappings: &Mappings) -> Vec<Hit> {
+        let prepared = query.prepare(&Analyzer::standard(), mappings).unwrap();
+        let statistics = store.statistics(&prepared.terms()).unwrap();
+        store
+            .search(&prepared, &Scorer::new(Bm25::default(), statistics), usize::MAX)
+            .unwrap()
+            .hits
+    }
+
+    fn search_ids(store: &mut SQLiteStore, query: Query, mappings: &Mappings) -> Vec<u64> {
+        let mut ids: Vec<u64> = search_hits(store, query, mappings).into_iter().map(|hit| hit.id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn search_store(name: &str) -> SQLiteStore {
+        let p = test_store_path(name);
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        store.save_document(Document::new(1, "A molar bear ate a polar bear")).unwrap();
+        store.save_document(Document::new(2, "The bear is molar")).unwrap();
+        store.save_document(Document::new(3, "Polar ice")).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_search_terms() {
+        let mut store = search_store("test_search_terms.db");
+        let mappings = Mappings::new();
+        assert_eq!(search_ids(&mut store, Query::matching("message", "molar"), &mappings), vec![1, 2]);
+        assert_eq!(search_ids(&mut store, Query::matching("message", "molar ice"), &mappings), vec![1, 2, 3]);
+        assert_eq!(search_ids(&mut store, Query::matching("message", "walrus"), &mappings), Vec::<u64>::new());
+        let results = store.search(&PreparedQuery::MatchAll, &Scorer::default(), usize::MAX).unwrap();
+        assert_eq!(results.total, 3);
+        assert_eq!(results.hits[2].source, "Polar ice");
+        assert_eq!(results.hits[2].score, 1.0);
+        let results = store.search(&PreparedQuery::MatchAll, &Scorer::default(), 2).unwrap();
+        assert_eq!(results.total, 3);
+        assert_eq!(results.hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![1, 2]);
+        assert_eq!(results.hits[1].source, "The bear is molar");
+    }
+
+    #[test]
+    fn test_search_phrase() {
+        let mut store = search_store("test_search_phrase.db");
+        let mappings = Mappings::new();
+        assert_eq!(search_ids(&mut store, Query::phrase("message", "molar bear"), &mappings), vec![1]);
+        assert_eq!(search_ids(&mut store, Query::phrase("message", "bear molar"), &mappings), Vec::<u64>::new());
+        assert_eq!(search_ids(&mut store, Query::phrase("message", "is molar"), &mappings), vec![2]);
+    }
+
+    #[test]
+    fn test_search_bool() {
+        let mut store = search_store("test_search_bool.db");
+        let mappings = Mappings::new();
+        let query = Query::Bool {
+            must: vec![Query::matching("message", "bear")],
+            should: vec![],
+            must_not: vec![Query::matching("message", "polar")],
+        };
+        assert_eq!(search_ids(&mut store, query, &mappings), vec![2]);
+        let query = Query::Bool {
+            must: vec![],
+            should: vec![Query::matching("message", "ice"), Query::phrase("message", "bear is")],
+            must_not: vec![],
+        };
+        assert_eq!(search_ids(&mut store, query, &mappings), vec![2, 3]);
+        let query = Query::Bool {
+            must: vec![],
+            should: vec![],
+            must_not: vec![Query::matching("message", "molar")],
+        };
+        assert_eq!(search_ids(&mut store, query, &mappings), vec![3]);
+    }
+
+    #[test]
+    fn test_search_typed_value() {
+        let p = test_store_path("test_search_typed_value.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        let mappings = Mappings::new().field("status", FieldType::Integer);
+        for (id, raw) in vec![(1, r#"{"status": 200}"#), (2, r#"{"status": 404}"#)] {
+            let document = Document::new(id, raw)
+                .analyze_mapped(&Analyzer::standard(), &mappings)
+                .unwrap();
+            store.save_document(document).unwrap();
+        }
+        assert_eq!(search_ids(&mut store, Query::term("status", 404), &mappings), vec![2]);
+        assert_eq!(search_ids(&mut store, Query::term("status", "200"), &mappings), vec![1]);
+    }
+
+    #[test]
+    fn test_search_range() {
+        let p = test_store_path("test_search_range.db");
+        let mut store = SQLiteStore::open("test", &p).unwrap();
+        let mappings = Mappings::new()
+            .field("status", FieldType::Integer)
+            .field("host", FieldType::Keyword);
+        for (id, raw) in vec![
+            (1, r#"{"status": 200, "host": "web01"}"#),
+            (2, r#"{"status": 404, "host": "web02"}"#),
+            (3, r#"{"status": 500, "host": "web03"}"#),
+        ] {
+            let document = Document::new(id, raw)
+                .analyze_mapped(&Analyzer::standard(), &mappings)
+                .unwrap();
+            store.save_document(document).unwrap();
+        }
+        let range = |field: &str, gt: Option<Value>, lte: Option<Value>| Query::Range {
+            field: field.into(),
+            gt: gt,
+            gte: None,
+            lt: None,
+            lte: lte,
+        };
+        assert_eq!(
+            search_ids(&mut store, range("status", Some(Value::from(200)), Some(Value::from(500))), &mappings),
+            vec![2, 3]
+        );
+        assert_eq!(search_ids(&mut store, range("status", None, Some(Value::from(404))), &mappings), vec![1, 2]);
+        assert_eq!(search_ids(&mut store, range("host", Some(Value::from("web01")), None), &mappings), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut store = search_store("test_statistics.db");
+        let statistics = store
+            .statistics(&[("message".to_owned(), "bear".to_owned()), ("message".to_owned(), "ice".to_owned())])
+            .unwrap();
+        assert_eq!(statistics.documents, 3);
+        assert_eq!(statistics.fields["message"].documents, 3);
+        assert_eq!(statistics.fields["message"].total_length, 13);
+        assert_eq!(statistics.document_frequency("message", "bear"), 2);
+        assert_eq!(statistics.document_frequency("message", "ice"), 1);
+    }
+
+    #[test]
+    fn test_search_ranks_by_relevance() {
+        let mut store = search_store("test_search_ranks.db");
+        let mappings = Mappings::new();
+        // Document 1 has bear twice
+        let hits = search_hits(&mut store, Query::matching("message", "bear"), &mappings);
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![1, 2]);
+        assert!(hits[0].score > hits[1].score);
+        // Document 1 has both terms, and Document 3 beats Document 2 because polar is the rarer
+        // term and Document 3 is shorter
+        let hits = search_hits(&mut store, Query::matching("message", "polar bear"), &mappings);
+        assert_eq!(hits.iter().map(|hit| hit.id).collect::<Vec<u64>>(), vec![1, 3, 2]);
+        assert!(hits.iter().all(|hit| hit.score > 0.0));
+    }
 }
@@ -1,5 +1,7 @@
 pub const PRAGMA_WAL: &'static str = "PRAGMA journal_mode=WAL";
 pub const PRAGMA_SYNCHRONOUS_OFF: &'static str = "PRAGMA synchronous=OFF";
+pub const QUERY_BEGIN: &'static str = "BEGIN";
+pub const QUERY_COUNT_SCHEMA: &'static str = "SELECT COUNT(*) FROM sqlite_master";
 
 /// Node and shard related queries
 
@@ -9,27 +11,35 @@ pub const QUERY_CREATE_NODES_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS n
         ip              TEXT NOT NULL,
         port            INTEGER NOT NULL,
         is_master       INTEGER DEFAULT 0,
-        is_data         INTEGER DEFAULT 1
+        is_data         INTEGER DEFAULT 1,
+        last_heard      INTEGER NOT NULL DEFAULT 0
     )";
 
 /// Query to create the Indexes table
 pub const QUERY_CREATE_INDEXES_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS indexes (
-        name            TEXT PRIMARY KEY NOT NULL
+        name            TEXT PRIMARY KEY NOT NULL,
+        primary_shards  INTEGER NOT NULL DEFAULT 1,
+        replica_shards  INTEGER NOT NULL DEFAULT 0,
+        state           TEXT NOT NULL DEFAULT 'open'
     )";
 
-/// Query to create the Shards table
+/// Query to create the Shards table. A Shard's term goes up every time a Replica is promoted to
+/// take its place.
 pub const QUERY_CREATE_SHARDS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS shards (
         id           TEXT PRIMARY KEY NOT NULL,
         index_name   TEXT NOT NULL,
+        number       INTEGER NOT NULL,
         node         TEXT NOT NULL,
+        term         INTEGER NOT NULL DEFAULT 1,
         FOREIGN KEY(node) REFERENCES nodes(id),
         FOREIGN KEY(index_name) REFERENCES indexes(name)
     )";
 
+/// Query to create the Replicas table. A replica's node is NULL until it has been assigned one.
 pub const QUERY_CREATE_REPLICAS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS replicas (
         id           TEXT PRIMARY KEY NOT NULL,
         index_name   TEXT NOT NULL,
-        node         TEXT NOT NULL,
+        node         TEXT,
         shard        TEXT NOT NULL,
         FOREIGN KEY(node) REFERENCES nodes(id),
         FOREIGN KEY(index_name) REFERENCES indexes(name),
@@ -51,21 +61,32 @@ pub const QUERY_CREATE_METRICS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS
 pub const QUERY_INSERT_CONFIG_ID: &'static str = "INSERT OR REPLACE INTO configuration (id) VALUES (?1)";
 pub const QUERY_GET_CONFIG_ID: &'static str = "SELECT id FROM configuration";
 pub const QUERY_INSERT_NODE: &'static str = "INSERT OR REPLACE INTO nodes (id, ip, port, is_master, is_data) VALUES (?1, ?2, ?3, ?4, ?5)";
-pub const QUERY_INSERT_SHARD: &'static str = "INSERT OR REPLACE INTO shards (id, index_name, node) VALUES (?1, ?2, ?3)";
+pub const QUERY_INSERT_SHARD: &'static str = "INSERT OR REPLACE INTO shards (id, index_name, number, node) VALUES (?1, ?2, ?3, ?4)";
 pub const QUERY_INSERT_REPLICA: &'static str = "INSERT OR REPLACE INTO replicas (id, index_name, node, shard) VALUES (?1, ?2, ?3, ?4)";
-pub const QUERY_INSERT_INDEX: &'static str = "INSERT INTO indexes (name) VALUES (?1)";
+pub const QUERY_INSERT_INDEX: &'static str = "INSERT INTO indexes (name, primary_shards, replica_shards, state) VALUES (?1, ?2, ?3, ?4)";
 pub const QUERY_INDEX_EXISTS: &'static str = "SELECT name FROM indexes WHERE name = ?1";
 pub const QUERY_DELETE_NODE: &'static str = "DELETE FROM nodes WHERE id = ?1";
 pub const QUERY_DELETE_ALL_NODES: &'static str = "DROP TABLE IF EXISTS nodes";
-pub const QUERY_DELETE_INDEX: &'static str = "DELETE FROM indexes WHERE id = ?1";
+pub const QUERY_DELETE_INDEX: &'static str = "DELETE FROM indexes WHERE name = ?1";
+pub const QUERY_DELETE_SHARDS_BY_INDEX: &'static str = "DELETE FROM shards WHERE index_name = ?1";
+pub const QUERY_DELETE_REPLICAS_BY_INDEX: &'static str = "DELETE FROM replicas WHERE index_name = ?1";
+pub const QUERY_SET_INDEX_STATE: &'static str = "UPDATE indexes SET state = ?2 WHERE name = ?1";
 pub const QUERY_ALL_NODES: &'static str = "SELECT id, ip, port, last_heard FROM nodes";
 pub const QUERY_FIND_NODE_BY: &'static str = "SELECT id, ip, port, last_heard FROM nodes WHERE {} = :value";
 pub const QUERY_FIND_NODE_BY_IP_PORT: &'static str = "SELECT id, ip, port, last_heard FROM nodes WHERE ip = :ip AND port = :port";
 pub const QUERY_DELETE_NODE_BY_IP_PORT: &'static str = "DELETE FROM nodes WHERE ip = :ip AND port = :port";
 pub const QUERY_GET_NODE_ID: &'static str = "SELECT id FROM nodes WHERE ip = ?1 AND port = ?2";
-pub const QUERY_ALL_INDEXES: &'static str = "SELECT name FROM indexes";
-pub const QUERY_GET_SHARDS_BY_INDEX: &'static str = "SELECT id, node FROM shards WHERE index_name = ?1";
+pub const QUERY_ALL_INDEXES: &'static str = "SELECT name, primary_shards, replica_shards, state FROM indexes ORDER BY name";
+pub const QUERY_GET_INDEX: &'static str = "SELECT name, primary_shards, replica_shards, state FROM indexes WHERE name = ?1";
+pub const QUERY_GET_SHARDS_BY_INDEX: &'static str = "SELECT id, number, node FROM shards WHERE index_name = ?1 ORDER BY number";
+pub const QUERY_GET_SHARD_NUMBERS_BY_INDEX_AND_NODE: &'static str = "SELECT number FROM shards WHERE index_name = ?1 AND node = ?2 ORDER BY number";
 pub const QUERY_GET_REPLICAS_BY_SHARD: &'static str = "SELECT id, index_name, node FROM replicas WHERE shard = ?1";
+pub const QUERY_GET_REPLICA_NUMBERS_BY_INDEX_AND_NODE: &'static str = "SELECT shards.number FROM replicas JOIN shards ON replicas.shard = shards.id WHERE replicas.index_name = ?1 AND replicas.node = ?2 ORDER BY shards.number";
+pub const QUERY_GET_UNASSIGNED_REPLICAS: &'static str = "SELECT id, shard FROM replicas WHERE node IS NULL ORDER BY id";
+pub const QUERY_GET_SHARD_HOLDERS: &'static str = "SELECT node FROM shards WHERE id = ?1 UNION ALL SELECT node FROM replicas WHERE shard = ?1 AND node IS NOT NULL";
+pub const QUERY_GET_NODES_BY_INDEX: &'static str = "SELECT node FROM shards WHERE index_name = ?1 UNION SELECT node FROM replicas WHERE index_name = ?1 AND node IS NOT NULL";
+pub const QUERY_GET_SHARD_LOAD: &'static str = "SELECT node, COUNT(*) FROM (SELECT node FROM shards UNION ALL SELECT node FROM replicas WHERE node IS NOT NULL) GROUP BY node";
+pub const QUERY_ASSIGN_REPLICA: &'static str = "UPDATE replicas SET node = ?2 WHERE id = ?1";
 
 // Index related queries
 pub const QUERY_CREATE_METADATA_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS metadata (
@@ -81,9 +102,16 @@ pub const QUERY_CREATE_TERMS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS t
 
 pub const QUERY_CREATE_DOCUMENTS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS documents (
         id TEXT PRIMARY KEY,
-        content TEXT
+        content TEXT,
+        plain_text INTEGER NOT NULL DEFAULT 0
     )";
 
+/// Columns added to the documents table after it was first created, with the query that adds each
+/// to a segment made before it existed
+pub const DOCUMENT_TABLE_MIGRATIONS: &'static [(&'static str, &'static str)] = &[
+    ("plain_text", "ALTER TABLE documents ADD COLUMN plain_text INTEGER NOT NULL DEFAULT 0"),
+];
+
 pub const QUERY_CREATE_FIELDS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS fields (
         name     TEXT,
         document TEXT,
@@ -100,6 +128,17 @@ pub const QUERY_CREATE_MAPPINGS_INDEX: &'static str = "CREATE UNIQUE INDEX IF NO
 
 pub const QUERY_CREATE_FIELDS_INDEX: &'static str = "CREATE INDEX IF NOT EXISTS field_index ON fields (name, document)";
 
+/// Values of fields mapped as numbers, dates or booleans. The value column has no declared type so
+/// that SQLite keeps integers and reals as they are and compares them numerically.
+pub const QUERY_CREATE_FIELD_VALUES_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS field_values (
+        name     TEXT,
+        document TEXT,
+        value,
+        FOREIGN KEY(document) REFERENCES documents(id)
+    )";
+
+pub const QUERY_CREATE_FIELD_VALUES_INDEX: &'static str = "CREATE INDEX IF NOT EXISTS field_value_index ON field_values (name, value)";
+
 pub const QUERY_CREATE_OCCURRENCES_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS occurrences (
         term TEXT,
         document TEXT,
@@ -109,7 +148,21 @@ pub const QUERY_CREATE_OCCURRENCES_TABLE: &'static str = "CREATE TABLE IF NOT EX
         FOREIGN KEY(document) REFERENCES documents(id)
     )";
 
-pub const QUERIES_INITIALIZE_INDEX_DB: &[&str; 9] = &[
+/// Phrase and term lookups go through field and term; deleting a Document goes through document
+pub const QUERY_CREATE_OCCURRENCES_INDEX: &'static str = "CREATE INDEX IF NOT EXISTS occurrence_index ON occurrences (field, term, document)";
+pub const QUERY_CREATE_OCCURRENCES_DOCUMENT_INDEX: &'static str = "CREATE INDEX IF NOT EXISTS occurrence_document_index ON occurrences (document)";
+
+/// Number of positions each field of a Document takes up, which BM25 uses to normalize scores
+pub const QUERY_CREATE_FIELD_LENGTHS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS field_lengths (
+        name     TEXT,
+        document TEXT,
+        length   INTEGER NOT NULL,
+        FOREIGN KEY(document) REFERENCES documents(id)
+    )";
+
+pub const QUERY_CREATE_FIELD_LENGTHS_INDEX: &'static str = "CREATE INDEX IF NOT EXISTS field_length_index ON field_lengths (name, document)";
+
+pub const QUERIES_INITIALIZE_INDEX_DB: &[&str; 15] = &[
     PRAGMA_WAL,
     PRAGMA_SYNCHRONOUS_OFF,
     QUERY_CREATE_TERMS_TABLE,
@@ -119,27 +172,65 @@ pub const QUERIES_INITIALIZE_INDEX_DB: &[&str; 9] = &[
     QUERY_CREATE_MAPPINGS_INDEX,
     QUERY_CREATE_FIELDS_INDEX,
     QUERY_CREATE_OCCURRENCES_TABLE,
+    QUERY_CREATE_OCCURRENCES_INDEX,
+    QUERY_CREATE_OCCURRENCES_DOCUMENT_INDEX,
+    QUERY_CREATE_FIELD_VALUES_TABLE,
+    QUERY_CREATE_FIELD_VALUES_INDEX,
+    QUERY_CREATE_FIELD_LENGTHS_TABLE,
+    QUERY_CREATE_FIELD_LENGTHS_INDEX,
 ];
 
 pub const QUERY_INSERT_FIELD: &'static str = "INSERT OR IGNORE INTO fields(name, document, content) VALUES (?1, ?2, ?3)";
 pub const QUERY_INSERT_TERM: &'static str = "INSERT OR IGNORE INTO terms (term) VALUES (?1)";
 pub const QUERY_INSERT_OCCURRENCE: &'static str = "INSERT INTO occurrences (term, document, field, offset) VALUES (?1, ?2, ?3, ?4)";
-pub const QUERY_INSERT_DOCUMENT: &'static str = "INSERT INTO documents (id, content) VALUES (?1, ?2)";
+pub const QUERY_INSERT_DOCUMENT: &'static str = "INSERT OR REPLACE INTO documents (id, content, plain_text) VALUES (?1, ?2, ?3)";
 pub const QUERY_INSERT_MAPPING: &'static str = "INSERT OR IGNORE INTO mappings (name, type) VALUES (?1, ?2)";
+pub const QUERY_ALL_MAPPINGS: &'static str = "SELECT name, type FROM mappings";
+pub const QUERY_DELETE_MAPPING: &'static str = "DELETE FROM mappings WHERE name = ?1 AND type = ?2";
+pub const QUERY_INSERT_FIELD_VALUE: &'static str = "INSERT INTO field_values (name, document, value) VALUES (?1, ?2, ?3)";
+pub const QUERY_INSERT_FIELD_LENGTH: &'static str = "INSERT INTO field_lengths (name, document, length) VALUES (?1, ?2, ?3)";
 pub const QUERY_INIT_METADATA: &'static str = "INSERT OR IGNORE INTO metadata (name, keep_raw) VALUES (?1, ?2)";
 pub const QUERY_ALL_TERMS: &'static str = "SELECT term FROM terms";
 pub const QUERY_OCCURRENCES_FOR_TERM: &'static str = "SELECT document, field, offset FROM occurrences WHERE term = ?1";
 pub const QUERY_COUNT_TERMS: &'static str = "SELECT COUNT(*) FROM terms";
-pub const QUERY_DOCUMENT_BY_ID: &'static str = "SELECT id, content FROM documents WHERE id = ?1";
+pub const QUERY_DOCUMENT_BY_ID: &'static str = "SELECT id, content, plain_text FROM documents WHERE id = ?1";
 pub const QUERY_DELETE_DOCUMENT_BY_ID: &'static str = "DELETE FROM documents WHERE id = ?1";
 pub const QUERY_DELETE_OCCURRENCES_BY_DOCUMENT_ID: &'static str = "DELETE FROM occurrences WHERE document = ?1";
+pub const QUERY_DELETE_FIELDS_BY_DOCUMENT_ID: &'static str = "DELETE FROM fields WHERE document = ?1";
+pub const QUERY_DELETE_FIELD_VALUES_BY_DOCUMENT_ID: &'static str = "DELETE FROM field_values WHERE document = ?1";
+pub const QUERY_DELETE_FIELD_LENGTHS_BY_DOCUMENT_ID: &'static str = "DELETE FROM field_lengths WHERE document = ?1";
 pub const QUERY_DOCUMENTS_WITH_TERM_IN_FIELD: &'static str = "SELECT document FROM occurrences WHERE field = ?1 AND term = ?2";
 pub const QUERY_DOCUMENTS_WITH_TERM: &'static str = "SELECT document FROM occurrences WHERE field = ?1";
+pub const QUERY_OFFSETS_OF_TERM_IN_FIELD: &'static str = "SELECT document, offset FROM occurrences WHERE field = ?1 AND term = ?2";
+pub const QUERY_DOCUMENTS_WITH_VALUE: &'static str = "SELECT document FROM field_values WHERE name = ?1 AND value = ?2";
+pub const QUERY_ALL_DOCUMENT_IDS: &'static str = "SELECT id FROM documents";
+pub const QUERY_TERM_FREQUENCIES_IN_FIELD: &'static str = "SELECT document, COUNT(*) FROM occurrences WHERE field = ?1 AND term = ?2 GROUP BY document";
+pub const QUERY_FIELD_LENGTH: &'static str = "SELECT length FROM field_lengths WHERE name = ?1 AND document = ?2";
+pub const QUERY_FIELD_STATISTICS: &'static str = "SELECT COUNT(*), COALESCE(SUM(length), 0) FROM field_lengths WHERE name = ?1";
+pub const QUERY_DOCUMENT_FREQUENCY: &'static str = "SELECT COUNT(DISTINCT document) FROM occurrences WHERE field = ?1 AND term = ?2";
+pub const QUERY_COUNT_DOCUMENTS: &'static str = "SELECT COUNT(*) FROM documents";
 pub const QUERY_TERM_IN_DOCUMENTS: &'static str = "SELECT document FROM occurrences WHERE term = ?1 LIMIT 1";
 
 // Partial queries, used for dynamically building up longer queries
 pub const QUERY_PARTIAL_RANGE: &'static str = "SELECT document FROM occurrences WHERE field = ?1 ";
+pub const QUERY_PARTIAL_VALUE_RANGE: &'static str = "SELECT document FROM field_values WHERE name = ?1 ";
 
 // Metrics queries
 pub const QUERY_INSERT_METRIC: &'static str = "INSERT INTO metrics (component, value, timestamp) VALUES (?1, ?2, ?3)";
 pub const QUERY_JOB_AVG_COMPLETION_TIME: &'static str = "SELECT AVG(value) FROM metrics WHERE component = 'job_completion_time'";
+
+// Operation log queries
+pub const QUERY_CREATE_OPERATIONS_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS operations (
+        seq          INTEGER PRIMARY KEY NOT NULL,
+        operation    TEXT NOT NULL,
+        term         INTEGER NOT NULL DEFAULT 0
+    )";
+pub const OPERATION_TABLE_MIGRATIONS: &'static [(&'static str, &'static str)] = &[
+    ("term", "ALTER TABLE operations ADD COLUMN term INTEGER NOT NULL DEFAULT 0"),
+];
+pub const QUERY_INSERT_OPERATION: &'static str = "INSERT INTO operations (seq, operation, term) VALUES (?1, ?2, ?3)";
+pub const QUERY_LAST_OPERATION_SEQ: &'static str = "SELECT COALESCE(MAX(seq), 0) FROM operations";
+pub const QUERY_LAST_OPERATION_TERM: &'static str = "SELECT COALESCE((SELECT term FROM operations ORDER BY seq DESC LIMIT 1), 0)";
+pub const QUERY_OPERATION_TERM: &'static str = "SELECT term FROM operations WHERE seq = ?1";
+pub const QUERY_OPERATIONS_SINCE: &'static str = "SELECT operation FROM operations WHERE seq > ?1 ORDER BY seq LIMIT ?2";
+pub const QUERY_DELETE_OPERATIONS_AFTER: &'static str = "DELETE FROM operations WHERE seq > ?1";
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+
+use db::{IndexDefinition, NodeState, NodeStatus};
+use replication::ReplicaTarget;
+
+/// Where a Primary Shard and each of its Replicas are placed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShardPlacement {
+    /// Number of the Primary Shard
+    pub number: u32,
+    /// Name of the Node the Primary Shard is placed on
+    pub primary: String,
+    /// Name of the Node each Replica is placed on, or None if there was nowhere to put it
+    pub replicas: Vec<Option<String>>,
+}
+
+/// The copies of an Index's Shards that a Node should be running. The metadata server sends
+/// these to a Node whenever what is placed on it changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexAssignment {
+    /// Definition of the Index
+    pub index: IndexDefinition,
+    /// Numbers of the Primary Shards placed on the Node
+    pub primaries: Vec<u32>,
+    /// Numbers of the Primary Shards the Node holds a Replica of
+    pub replicas: Vec<u32>,
+    /// Where the Replicas of each Primary Shard on the Node are, by Shard number
+    #[serde(default)]
+    pub replica_targets: BTreeMap<u32, Vec<ReplicaTarget>>,
+    /// Term of each Shard the Node holds a copy of, by Shard number. A Replica refuses writes
+    /// from a Primary whose term is older than this.
+    #[serde(default)]
+    pub terms: BTreeMap<u32, u64>,
+    /// Address of the Node holding the Primary of each Shard the Node holds a Replica of, as
+    /// `host:port`, by Shard number. A new Replica recovers from there.
+    #[serde(default)]
+    pub primary_addresses: BTreeMap<u32, String>,
+    /// Set only when the metadata server tells the Nodes that held the Index that it was deleted,
+    /// so they remove its data
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// Decides which Nodes Shards are placed on. Each new copy of a Shard goes to the alive Node
+/// holding the fewest copies so far, and a Replica never goes on the same host as its Primary or
+/// another Replica of the same Shard.
+pub struct Allocator {
+    /// Every registered Node, including those that can't take Shards, so their hosts are known
+    nodes: Vec<NodeStatus>,
+    /// Number of Shard copies placed on each Node, by name
+    load: HashMap<String, usize>,
+}
+
+impl Allocator {
+    /// Creates and returns an Allocator
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - Every registered Node. Only alive ones are given Shards.
+    /// * `load` - Number of Shard copies already placed on each Node, by name
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use rpc::allocator::Allocator;
+    /// let mut allocator = Allocator::new(vec![], HashMap::new());
+    /// assert_eq!(allocator.place_primary(), None);
+    /// ```
+    pub fn new(nodes: Vec<NodeStatus>, load: HashMap<String, usize>) -> Allocator {
+        Allocator {
+            nodes: nodes,
+            load: load,
+        }
+    }
+
+    /// Picks the Node for a Primary Shard. Returns None if no Node is alive.
+    pub fn place_primary(&mut self) -> Option<String> {
+        self.least_loaded(|_| true)
+    }
+
+    /// Picks the Node for a Replica. Returns None if every alive Node shares a host with one of
+    /// the `holders`.
+    ///
+    /// # Arguments
+    ///
+    /// * `holders` - Names of the Nodes that already hold a copy of the Shard, Primary included
+    pub fn place_replica(&mut self, holders: &[String]) -> Option<String> {
+        let hosts: Vec<String> = self.nodes
+            .iter()
+            .filter(|node| holders.contains(&node.name))
+            .map(|node| node.host.clone())
+            .collect();
+        self.least_loaded(|node| !holders.contains(&node.name) && !hosts.contains(&node.host))
+    }
+
+    /// Places every Shard of a new Index. Returns None if there is no Node to put the Primary
+    /// Shards on. Replicas that can't be placed are left for when more Nodes join.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Definition of the Index
+    pub fn place_index(&mut self, index: &IndexDefinition) -> Option<Vec<ShardPlacement>> {
+        let mut placements = vec![];
+        for number in 0..index.primary_shards {
+            let primary = self.place_primary()?;
+            let mut holders = vec![primary.clone()];
+            let mut replicas = vec![];
+            for _ in 0..index.replica_shards {
+                let replica = self.place_replica(&holders);
+                if let Some(ref node) = replica {
+                    holders.push(node.clone());
+                }
+                replicas.push(replica);
+            }
+            placements.push(ShardPlacement {
+                number: number,
+                primary: primary,
+                replicas: replicas,
+            });
+        }
+        Some(placements)
+    }
+
+    /// Picks the alive Node with the fewest copies among those `eligible` accepts, breaking ties
+    /// by name so placement is repeatable, and counts the new copy against it
+    fn least_loaded<F: Fn(&NodeStatus) -> bool>(&mut self, eligible: F) -> Option<String> {
+        let chosen = {
+            let load = &self.load;
+            self.nodes
+                .iter()
+                .filter(|node| node.state == NodeState::Alive && eligible(node))
+                .min_by_key(|node| (load.get(&node.name).cloned().unwrap_or(0), node.name.clone()))
+                .map(|node| node.name.clone())
+        };
+        if let Some(ref name) = chosen {
+            *self.load.entry(name.clone()).or_insert(0) += 1;
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::IndexState;
+
+    fn node(name: &str, host: &str, state: NodeState) -> NodeStatus {
+        NodeStatus {
+            name: name.to_owned(),
+            host: host.to_owned(),
+            port: 3001,
+            last_heard: 0,
+            state: state,
+        }
+    }
+
+    fn index(primary_shards: u32, replica_shards: u32) -> IndexDefinition {
+        IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: primary_shards,
+            replica_shards: replica_shards,
+            state: IndexState::Open,
+        }
+    }
+
+    #[test]
+    fn test_place_index() {
+        let nodes = vec![
+            node("node01", "10.0.0.1", NodeState::Alive),
+            node("node02", "10.0.0.2", NodeState::Alive),
+            node("node03", "10.0.0.3", NodeState::Alive),
+            node("node04", "10.0.0.4", NodeState::Dead),
+        ];
+        let mut load = HashMap::new();
+        load.insert(String::from("node01"), 1);
+        let mut allocator = Allocator::new(nodes, load);
+
+        let placements = allocator.place_index(&index(2, 1)).unwrap();
+        assert_eq!(placements[0].primary, "node02");
+        assert_eq!(placements[0].replicas, vec![Some(String::from("node03"))]);
+        assert_eq!(placements[1].primary, "node01");
+        assert_eq!(placements[1].replicas, vec![Some(String::from("node02"))]);
+    }
+
+    #[test]
+    fn test_replicas_avoid_primary_host() {
+        let nodes = vec![
+            node("node01", "10.0.0.1", NodeState::Alive),
+            node("node02", "10.0.0.1", NodeState::Alive),
+            node("node03", "10.0.0.2", NodeState::Alive),
+        ];
+        let mut allocator = Allocator::new(nodes, HashMap::new());
+
+        let placements = allocator.place_index(&index(1, 2)).unwrap();
+        assert_eq!(placements[0].primary, "node01");
+        // node02 shares a host with the Primary, and there is no third host for the second Replica
+        assert_eq!(placements[0].replicas, vec![Some(String::from("node03")), None]);
+    }
+
+    #[test]
+    fn test_no_nodes() {
+        let mut allocator = Allocator::new(vec![node("node01", "10.0.0.1", NodeState::Suspect)], HashMap::new());
+        assert_eq!(allocator.place_index(&index(1, 0)), None);
+        // An Index with no Shards needs no Nodes
+        assert_eq!(allocator.place_index(&index(0, 0)), Some(vec![]));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use codec::{self, CodecError};
+use messages::Message;
+
+/// How long a request waits for its reply by default
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests waiting for a reply, by the ID of the request
+type Pending = Arc<Mutex<HashMap<Uuid, mpsc::Sender<Message>>>>;
+
+/// Sends `Message`s to another Node over a single connection and matches up the replies. Any
+/// number of threads can have requests in flight at once, and clones share the connection.
+#[derive(Clone)]
+pub struct RpcClient {
+    connection: Arc<Connection>,
+    /// How long a request waits for its reply
+    timeout: Duration,
+}
+
+/// The shared half of an RpcClient. The connection is shut down once every clone is dropped,
+/// which also stops the thread reading replies.
+struct Connection {
+    writer: Mutex<TcpStream>,
+    pending: Pending,
+    closed: Arc<AtomicBool>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Ok(writer) = self.writer.lock() {
+            let _ = writer.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl RpcClient {
+    /// Connects to another Node's RPC server and returns an RpcClient for it
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - Address of the RPC server, as `host:port`
+    /// * `timeout` - How long connecting, and then each request, waits before giving up
+    pub fn connect(address: &str, timeout: Duration) -> Result<RpcClient, RpcError> {
+        let writer = connect_any(address, timeout)?;
+        let reader = writer.try_clone()?;
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let reader_pending = pending.clone();
+        let reader_closed = closed.clone();
+        thread::spawn(move || {
+            read_replies(reader, reader_pending, reader_closed);
+        });
+
+        Ok(RpcClient {
+            connection: Arc::new(Connection {
+                writer: Mutex::new(writer),
+                pending: pending,
+                closed: closed,
+            }),
+            timeout: timeout,
+        })
+    }
+
+    /// Sends a `Message` and waits for the reply to it
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The request
+    pub fn request(&self, message: Message) -> Result<Message, RpcError> {
+        let id = message.message_id;
+        let (reply_tx, reply_rx) = mpsc::channel();
+        self.connection.pending.lock()?.insert(id, reply_tx);
+        // Checked after the request is registered, so a connection that closes in between
+        // either shows up here or drops the channel
+        if self.is_closed() {
+            self.forget(&id);
+            return Err(RpcError::new(ErrorKind::Closed, "Connection is closed"));
+        }
+        if let Err(e) = self.send(&message) {
+            self.forget(&id);
+            return Err(e);
+        }
+
+        match reply_rx.recv_timeout(self.timeout) {
+            Ok(reply) => Ok(reply),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.forget(&id);
+                Err(RpcError::new(
+                    ErrorKind::Timeout,
+                    &format!("No reply to {:?} within {:?}", message.message_type, self.timeout),
+                ))
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(RpcError::new(ErrorKind::Closed, "Connection closed before the reply arrived"))
+            },
+        }
+    }
+
+    /// Sends a `Message` that does not get a reply
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The Message to send
+    pub fn send(&self, message: &Message) -> Result<(), RpcError> {
+        let mut writer = self.connection.writer.lock()?;
+        codec::write_message(&mut *writer, message).map_err(|e| {
+            self.connection.closed.store(true, Ordering::SeqCst);
+            RpcError::from(e)
+        })
+    }
+
+    /// Returns true once the connection has failed or the other Node has closed it. A closed
+    /// RpcClient stays closed, so a new one has to be connected.
+    pub fn is_closed(&self) -> bool {
+        self.connection.closed.load(Ordering::SeqCst)
+    }
+
+    fn forget(&self, id: &Uuid) {
+        if let Ok(mut pending) = self.connection.pending.lock() {
+            pending.remove(id);
+        }
+    }
+}
+
+/// Connects to the first of the addresses a `host:port` resolves to that answers within the
+/// timeout, so that a Node which is down can't hold up the caller for the OS connect timeout
+fn connect_any(address: &str, timeout: Duration) -> Result<TcpStream, RpcError> {
+    let mut last_error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => RpcError::from(e),
+        None => RpcError::new(ErrorKind::Io, &format!("{} did not resolve to any address", address)),
+    })
+}
+
+/// Reads replies off a connection and hands each one to the request waiting for it, until the
+/// connection closes
+fn read_replies(mut reader: TcpStream, pending: Pending, closed: Arc<AtomicBool>) {
+    loop {
+        let reply = match codec::read_message(&mut reader) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => break,
+            Err(e) => {
+                println!("There was an error reading a reply, closing the connection: {}", e);
+                break;
+            },
+        };
+        let waiting = match (reply.reply_to, pending.lock()) {
+            (Some(id), Ok(mut pending)) => pending.remove(&id),
+            _ => None,
+        };
+        match waiting {
+            Some(reply_tx) => {
+                let _ = reply_tx.send(reply);
+            },
+            None => {
+                println!("Dropping a reply to a request that is no longer waiting: {:?}", reply.reply_to);
+            },
+        }
+    }
+
+    closed.store(true, Ordering::SeqCst);
+    // Dropping the channels wakes every request still waiting
+    if let Ok(mut pending) = pending.lock() {
+        pending.clear();
+    }
+}
+
+/// What went wrong with a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    /// No reply arrived in time
+    Timeout,
+    /// The connection is closed
+    Closed,
+    /// The request could not be written or the reply could not be read
+    Io,
+}
+
+/// Custom error type returned when a request to another Node fails
+#[derive(Debug)]
+pub struct RpcError {
+    kind: ErrorKind,
+    details: String,
+}
+
+impl RpcError {
+    /// Creates and returns a new RpcError
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - What went wrong
+    /// * `msg` - The error message we want to include in the RpcError
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::client::{ErrorKind, RpcError};
+    /// let _rpc_error = RpcError::new(ErrorKind::Timeout, "No reply!");
+    /// ```
+    pub fn new(kind: ErrorKind, msg: &str) -> RpcError {
+        RpcError {
+            kind: kind,
+            details: msg.to_string(),
+        }
+    }
+
+    /// Returns what went wrong
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for RpcError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<::std::io::Error> for RpcError {
+    fn from(err: ::std::io::Error) -> RpcError {
+        RpcError::new(ErrorKind::Io, &err.to_string())
+    }
+}
+
+impl From<CodecError> for RpcError {
+    fn from(err: CodecError) -> RpcError {
+        RpcError::new(ErrorKind::Io, &err.to_string())
+    }
+}
+
+impl<T> From<::std::sync::PoisonError<T>> for RpcError {
+    fn from(err: ::std::sync::PoisonError<T>) -> RpcError {
+        RpcError::new(ErrorKind::Io, &err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use messages::MessageType;
+
+    /// Starts a server that reads `requests` Messages and then answers them in reverse order
+    fn reversing_server(requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = vec![];
+            for _ in 0..requests {
+                match codec::read_message(&mut stream) {
+                    Ok(Some(request)) => received.push(request),
+                    _ => return,
+                }
+            }
+            for request in received.into_iter().rev() {
+                let reply = Message::new(request.message_type.clone())
+                    .args(request.args.clone())
+                    .reply_to(request.message_id);
+                codec::write_message(&mut stream, &reply).unwrap();
+            }
+            // Hold the connection open until the client goes away
+            let _ = codec::read_message(&mut stream);
+        });
+        address
+    }
+
+    #[test]
+    fn test_concurrent_requests() {
+        let client = RpcClient::connect(&reversing_server(2), DEFAULT_RPC_TIMEOUT).unwrap();
+        let other = client.clone();
+        let first = thread::spawn(move || {
+            other.request(Message::new(MessageType::LIST_NODES).arg(String::from("first"))).unwrap()
+        });
+        let second = client.request(Message::new(MessageType::LIST_NODES).arg(String::from("second"))).unwrap();
+        assert_eq!(second.args, vec!["second"]);
+        assert_eq!(first.join().unwrap().args, vec!["first"]);
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let client = RpcClient::connect(&reversing_server(2), Duration::from_millis(100)).unwrap();
+        let error = client.request(Message::new(MessageType::LIST_NODES)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Timeout);
+        assert!(client.connection.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_connection_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = codec::read_message(&mut stream);
+        });
+        let client = RpcClient::connect(&address, DEFAULT_RPC_TIMEOUT).unwrap();
+        let error = client.request(Message::new(MessageType::LIST_NODES)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Closed);
+        assert!(client.is_closed());
+    }
+}
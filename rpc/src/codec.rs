@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde_json;
+
+use messages::Message;
+
+/// Number of bytes in the length prefix of a frame
+pub const FRAME_HEADER_SIZE: usize = 4;
+/// Largest payload a frame may carry, in bytes. Frames claiming to be larger are refused rather
+/// than read, so a bad length can't make a Node allocate without bound.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Writes a payload as a single frame. A frame is a 4 byte, big endian length followed by that
+/// many bytes, so the reader always knows where one `Message` ends and the next begins no matter
+/// how TCP splits or joins them.
+///
+/// # Arguments
+///
+/// * `writer` - Where the frame is written, usually a `TcpStream`
+/// * `payload` - Bytes to send. Must be at most `MAX_FRAME_SIZE` long.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), CodecError> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(CodecError::new(&format!(
+            "Frame of {} bytes is larger than the limit of {} bytes",
+            payload.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+    let length = payload.len() as u32;
+    let header = [(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8];
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the payload of the next frame. Returns None if the reader was closed cleanly between
+/// frames.
+///
+/// # Arguments
+///
+/// * `reader` - Where the frame is read from, usually a `TcpStream`
+/// * `max_frame_size` - Largest payload accepted, in bytes
+pub fn read_frame<R: Read>(reader: &mut R, max_frame_size: usize) -> Result<Option<Vec<u8>>, CodecError> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    let mut read = 0;
+    while read < FRAME_HEADER_SIZE {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(CodecError::new("Connection closed in the middle of a frame header")),
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CodecError::from(e)),
+        }
+    }
+
+    let length = header.iter().fold(0usize, |length, byte| (length << 8) | *byte as usize);
+    if length > max_frame_size {
+        return Err(CodecError::new(&format!(
+            "Frame of {} bytes is larger than the limit of {} bytes",
+            length,
+            max_frame_size
+        )));
+    }
+    let mut payload = vec![0u8; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Serializes a `Message` and writes it as a single frame
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> Result<(), CodecError> {
+    write_frame(writer, &message.to_vec()?)
+}
+
+/// Reads the next frame and deserializes the `Message` in it. Returns None if the reader was
+/// closed cleanly between frames.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Option<Message>, CodecError> {
+    match read_frame(reader, MAX_FRAME_SIZE)? {
+        Some(payload) => Ok(Some(serde_json::from_slice(&payload)?)),
+        None => Ok(None),
+    }
+}
+
+/// Custom error type returned when a frame can't be read or written
+#[derive(Debug)]
+pub struct CodecError {
+    details: String,
+}
+
+impl CodecError {
+    /// Creates and returns a new CodecError
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The error message we want to include in the CodecError
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::codec::CodecError;
+    /// let _codec_error = CodecError::new("Frame is too large!");
+    /// ```
+    pub fn new(msg: &str) -> CodecError {
+        CodecError { details: msg.to_string() }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CodecError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(err: io::Error) -> CodecError {
+        CodecError::new(&err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CodecError {
+    fn from(err: serde_json::Error) -> CodecError {
+        CodecError::new(&err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use messages::MessageType;
+
+    #[test]
+    fn test_frames() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, b"first").unwrap();
+        write_frame(&mut buffer, b"").unwrap();
+        write_frame(&mut buffer, b"second").unwrap();
+        assert_eq!(&buffer[0..4], &[0, 0, 0, 5]);
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader, MAX_FRAME_SIZE).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_frame(&mut reader, MAX_FRAME_SIZE).unwrap(), Some(vec![]));
+        assert_eq!(read_frame(&mut reader, MAX_FRAME_SIZE).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(read_frame(&mut reader, MAX_FRAME_SIZE).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bad_frames() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, &[1u8; 32]).unwrap();
+        assert!(read_frame(&mut Cursor::new(buffer.clone()), 16).is_err());
+        buffer.truncate(20);
+        assert!(read_frame(&mut Cursor::new(buffer.clone()), MAX_FRAME_SIZE).is_err());
+        buffer.truncate(2);
+        assert!(read_frame(&mut Cursor::new(buffer), MAX_FRAME_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_messages() {
+        let large = "x".repeat(100 * 1024);
+        let mut buffer = vec![];
+        write_message(&mut buffer, &Message::new(MessageType::HEARTBEAT)).unwrap();
+        write_message(&mut buffer, &Message::new(MessageType::REGISTER).arg(large.clone())).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_message(&mut reader).unwrap().unwrap().message_type, MessageType::HEARTBEAT);
+        let message = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(message.message_type, MessageType::REGISTER);
+        assert_eq!(message.args, vec![large]);
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+}
@@ -1,4 +1,20 @@
+use rusqlite;
 use rusqlite::Connection;
+use rusqlite::types::Null;
+
+use std::collections::{BTreeMap, HashMap};
+
+use inverted_index::stores::sqlite::queries::{
+    QUERY_ALL_INDEXES, QUERY_ASSIGN_REPLICA, QUERY_CREATE_INDEXES_TABLE, QUERY_CREATE_REPLICAS_TABLE,
+    QUERY_CREATE_SHARDS_TABLE, QUERY_DELETE_INDEX, QUERY_DELETE_REPLICAS_BY_INDEX, QUERY_DELETE_SHARDS_BY_INDEX,
+    QUERY_GET_INDEX, QUERY_GET_NODES_BY_INDEX, QUERY_GET_REPLICA_NUMBERS_BY_INDEX_AND_NODE, QUERY_GET_SHARD_HOLDERS,
+    QUERY_GET_SHARD_LOAD, QUERY_GET_SHARD_NUMBERS_BY_INDEX_AND_NODE, QUERY_GET_UNASSIGNED_REPLICAS,
+    QUERY_INDEX_EXISTS, QUERY_INSERT_INDEX, QUERY_INSERT_REPLICA, QUERY_INSERT_SHARD, QUERY_SET_INDEX_STATE,
+};
+
+use allocator::{IndexAssignment, ShardPlacement};
+use heartbeat::HeartbeatConfiguration;
+use replication::ReplicaTarget;
 
 /// Query to create the Cluster table
 pub const QUERY_CREATE_CLUSTER_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS cluster (
@@ -9,12 +25,180 @@ pub const QUERY_CREATE_CLUSTER_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS
 pub const QUERY_CREATE_NODE_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS nodes (
         name            TEXT PRIMARY KEY NOT NULL,
         host            TEXT NOT NULL,
-        port            INT NOT NULL
+        port            INT NOT NULL,
+        last_heard      INTEGER NOT NULL DEFAULT 0,
+        state           TEXT NOT NULL DEFAULT 'alive'
+    )";
+
+/// Columns added to the nodes table after it was first created, with the query that adds each to
+/// a metadata database made before it existed
+const NODE_TABLE_MIGRATIONS: &'static [(&'static str, &'static str)] = &[
+    ("last_heard", "ALTER TABLE nodes ADD COLUMN last_heard INTEGER NOT NULL DEFAULT 0"),
+    ("state", "ALTER TABLE nodes ADD COLUMN state TEXT NOT NULL DEFAULT 'alive'"),
+];
+
+/// Columns added to the indexes table after it was first created
+const INDEX_TABLE_MIGRATIONS: &'static [(&'static str, &'static str)] = &[
+    ("primary_shards", "ALTER TABLE indexes ADD COLUMN primary_shards INTEGER NOT NULL DEFAULT 1"),
+    ("replica_shards", "ALTER TABLE indexes ADD COLUMN replica_shards INTEGER NOT NULL DEFAULT 0"),
+    ("state", "ALTER TABLE indexes ADD COLUMN state TEXT NOT NULL DEFAULT 'open'"),
+];
+
+/// Columns added to the shards table after it was first created
+const SHARD_TABLE_MIGRATIONS: &'static [(&'static str, &'static str)] = &[
+    ("term", "ALTER TABLE shards ADD COLUMN term INTEGER NOT NULL DEFAULT 1"),
+];
+
+pub const QUERY_LIST_NODES: &'static str = "SELECT name, host, port, last_heard, state FROM nodes ORDER BY name";
+
+/// Registers a Node, replacing what was recorded about it before so a Node that comes back on a
+/// different host or port can be found again
+pub const QUERY_REGISTER_NODE: &'static str = "INSERT OR REPLACE INTO nodes (name, host, port, last_heard, state) VALUES (?1, ?2, ?3, ?4, 'alive')";
+
+pub const QUERY_GET_NODE: &'static str = "SELECT name, host, port, last_heard, state FROM nodes WHERE name = ?1";
+
+pub const QUERY_GET_NODE_STATE: &'static str = "SELECT state FROM nodes WHERE name = ?1";
+
+pub const QUERY_RECORD_HEARTBEAT: &'static str = "UPDATE nodes SET last_heard = ?2 WHERE name = ?1 AND state != 'left'";
+
+/// Query to count every Node that hasn't left as heard from at the same time
+pub const QUERY_RESET_HEARTBEATS: &'static str = "UPDATE nodes SET last_heard = ?1 WHERE state != 'left'";
+
+pub const QUERY_SET_NODE_STATE: &'static str = "UPDATE nodes SET state = ?2 WHERE name = ?1";
+
+pub const QUERY_GET_SHARD_IDS_BY_NODE: &'static str = "SELECT id FROM shards WHERE node = ?1 ORDER BY id";
+
+pub const QUERY_GET_SHARDS_BY_NODE: &'static str = "SELECT id, index_name, number FROM shards WHERE node = ?1 ORDER BY id";
+
+pub const QUERY_GET_SHARD_TERM: &'static str = "SELECT term FROM shards WHERE id = ?1";
+
+/// Query to move a Primary Shard to the Node holding one of its Replicas, starting a new term
+pub const QUERY_PROMOTE_REPLICA: &'static str = "UPDATE shards SET node = ?2, term = term + 1 WHERE id = ?1";
+
+pub const QUERY_RELEASE_REPLICA_BY_SHARD_AND_NODE: &'static str = "UPDATE replicas SET node = NULL WHERE shard = ?1 AND node = ?2";
+
+pub const QUERY_RELEASE_REPLICAS_BY_NODE: &'static str = "UPDATE replicas SET node = NULL WHERE node = ?1";
+/// Query to find the Nodes holding the Replicas of a Primary Shard, with their addresses
+pub const QUERY_GET_REPLICA_TARGETS: &'static str = "SELECT nodes.name, nodes.host, nodes.port FROM replicas JOIN nodes ON replicas.node = nodes.name WHERE replicas.shard = ?1 ORDER BY replicas.id";
+/// Query to find the address of the Node a Primary Shard is placed on
+pub const QUERY_GET_PRIMARY_ADDRESS: &'static str = "SELECT nodes.host, nodes.port FROM shards JOIN nodes ON shards.node = nodes.name WHERE shards.id = ?1";
+/// Query to find the Nodes holding the Primaries of the Shards a Node holds Replicas of
+pub const QUERY_GET_PRIMARY_NODES_BY_REPLICA_NODE: &'static str = "SELECT DISTINCT shards.node FROM replicas JOIN shards ON replicas.shard = shards.id WHERE replicas.node = ?1";
+
+/// Query to create the table recording how far through the Raft log the metadata database is
+pub const QUERY_CREATE_APPLIED_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS raft_applied (
+        id              INTEGER PRIMARY KEY NOT NULL,
+        idx             INTEGER NOT NULL
     )";
 
-pub const QUERY_LIST_NODES: &'static str = "SELECT name FROM nodes";
+pub const QUERY_GET_APPLIED_INDEX: &'static str = "SELECT idx FROM raft_applied WHERE id = 0";
+
+pub const QUERY_SET_APPLIED_INDEX: &'static str = "INSERT OR REPLACE INTO raft_applied (id, idx) VALUES (0, ?1)";
+
+/// Whether the metadata server has heard from a Node recently
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeState {
+    /// The Node's heartbeats are arriving
+    Alive,
+    /// The Node has missed heartbeats for longer than the suspect timeout
+    Suspect,
+    /// The Node has missed heartbeats for longer than the dead timeout
+    Dead,
+    /// The Node deregistered when it shut down. Its Primary Shards stay placed on it, flagged by
+    /// this state, until they are moved or it registers again.
+    Left,
+}
+
+impl NodeState {
+    /// Returns how the state is stored in the metadata database
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            NodeState::Alive => "alive",
+            NodeState::Suspect => "suspect",
+            NodeState::Dead => "dead",
+            NodeState::Left => "left",
+        }
+    }
+
+    /// Reads a state as stored in the metadata database. Anything unexpected is treated as
+    /// suspect, so it is checked again on the next sweep.
+    fn from_db(state: &str) -> NodeState {
+        match state {
+            "alive" => NodeState::Alive,
+            "dead" => NodeState::Dead,
+            "left" => NodeState::Left,
+            _ => NodeState::Suspect,
+        }
+    }
+}
+
+/// A Node that has registered with the metadata server, and when it was last heard from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    /// Name of the Node
+    pub name: String,
+    /// Address of the Node's RPC server
+    pub host: String,
+    /// Port of the Node's RPC server
+    pub port: u16,
+    /// When the Node's last heartbeat arrived, in milliseconds since the epoch
+    pub last_heard: u64,
+    /// Whether the Node's heartbeats are arriving
+    pub state: NodeState,
+}
 
-pub const QUERY_REGISTER_NODE: &'static str = "INSERT OR IGNORE INTO nodes VALUES (?, ?, ?)";
+/// Whether an Index's Shards are running
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexState {
+    /// The Index can be read from and written to
+    Open,
+    /// The Index's Shards are stopped but its data is kept
+    Closed,
+}
+
+impl IndexState {
+    /// Returns how the state is stored in the metadata database
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            IndexState::Open => "open",
+            IndexState::Closed => "closed",
+        }
+    }
+}
+
+/// How an Index was defined when it was created, and whether it is open
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexDefinition {
+    /// Name of the Index
+    pub name: String,
+    /// Number of Primary Shards the Index's Documents are spread across
+    pub primary_shards: u32,
+    /// Number of Replicas each Primary Shard has
+    pub replica_shards: u32,
+    /// Whether the Index's Shards are running
+    pub state: IndexState,
+}
+
+/// A change to the Cluster's metadata. The metadata Nodes agree on the order of these through
+/// Raft, and each applies them to its own metadata database so any of them can take over as
+/// leader. Everything a command needs is part of it, so applying it gives the same result on
+/// every Node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MetadataCommand {
+    /// Records a Node that has joined the Cluster, heard from at `now`
+    RegisterNode { name: String, host: String, port: u16, now: u64 },
+    SetNodeState { name: String, state: NodeState },
+    DeregisterNode { name: String },
+    /// Records a new Index along with where the leader placed its Shards
+    CreateIndex { index: IndexDefinition, placements: Vec<ShardPlacement> },
+    SetIndexState { name: String, state: IndexState },
+    DeleteIndex { name: String },
+    AssignReplica { replica_id: String, node: String },
+    PromoteReplica { shard_id: String, node: String },
+    ReleaseReplicas { node: String },
+}
 
 pub struct MetadataDB;
 
@@ -37,12 +221,42 @@ impl MetadataDB {
             },
             Err(e) => {
                 println!("There was an error creating the node table: {:?}", e);
+                return;
             },
         }
+        if let Err(e) = MetadataDB::migrate_table(conn, "nodes", NODE_TABLE_MIGRATIONS) {
+            println!("There was an error updating the node table: {:?}", e);
+        }
+    }
+
+    /// Adds any columns a table is missing because it was created by an older version
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `table` - Name of the table
+    /// * `migrations` - Each column the table should have, with the query that adds it
+    fn migrate_table(conn: &Connection, table: &str, migrations: &[(&str, &str)]) -> Result<(), rusqlite::Error> {
+        let columns: Vec<String> = {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let rows = stmt.query_map(&[], |row| row.get(1))?;
+            let mut columns = vec![];
+            for column in rows {
+                columns.push(column?);
+            }
+            columns
+        };
+        for &(column, query) in migrations {
+            if !columns.iter().any(|existing| existing == column) {
+                conn.execute(query, &[])?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn register_node(conn: &Connection, name: &str, hostname: &str, port: u16) -> bool {
-        match conn.execute(QUERY_REGISTER_NODE, &[&name, &hostname, &port]) {
+    /// Records a Node that has joined the Cluster. It counts as heard from at `now`.
+    pub fn register_node(conn: &Connection, name: &str, hostname: &str, port: u16, now: u64) -> bool {
+        match conn.execute(QUERY_REGISTER_NODE, &[&name, &hostname, &port, &(now as i64)]) {
             Ok(c) => {
                 true
             },
@@ -53,18 +267,866 @@ impl MetadataDB {
         }
     }
 
-    pub fn list_nodes(conn: &Connection) -> Vec<String> {
+    /// Lists every registered Node, ordered by name
+    pub fn list_nodes(conn: &Connection) -> Result<Vec<NodeStatus>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_LIST_NODES)?;
+        let rows = stmt.query_map(&[], node_from_row)?;
         let mut results = vec![];
-        let mut stmt = conn.prepare(QUERY_LIST_NODES).unwrap();
+        for node in rows {
+            results.push(node?);
+        }
+        Ok(results)
+    }
 
-        let node_iter = stmt.query_map(&[], |row| {
-            row.get(0)
-        }).unwrap();
+    /// Returns a registered Node, or None if no Node with the name has registered
+    pub fn get_node(conn: &Connection, name: &str) -> Result<Option<NodeStatus>, rusqlite::Error> {
+        match conn.query_row(QUERY_GET_NODE, &[&name], node_from_row) {
+            Ok(node) => Ok(Some(node)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 
-        for node in node_iter {
-            results.push(node.unwrap());
+    /// Records when a heartbeat arrived from a Node that hasn't left. Returns the state the Node
+    /// is in, which the heartbeat doesn't change, or None if no Node with the name has
+    /// registered.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `name` - Name of the Node the heartbeat came from
+    /// * `now` - When the heartbeat arrived, in milliseconds since the epoch
+    pub fn record_heartbeat(conn: &Connection, name: &str, now: u64) -> Result<Option<NodeState>, rusqlite::Error> {
+        let previous = match conn.query_row(QUERY_GET_NODE_STATE, &[&name], |row| {
+            let state: String = row.get(0);
+            NodeState::from_db(&state)
+        }) {
+            Ok(state) => state,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        conn.execute(QUERY_RECORD_HEARTBEAT, &[&name, &(now as i64)])?;
+        Ok(Some(previous))
+    }
+
+    /// Works out which Nodes have gone quiet for too long and records their new state. Returns
+    /// the Nodes whose state changed, with the state they are in now.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `config` - How long Nodes may go without a heartbeat
+    /// * `now` - The current time, in milliseconds since the epoch
+    pub fn update_node_states(conn: &mut Connection, config: &HeartbeatConfiguration, now: u64) -> Result<Vec<NodeStatus>, rusqlite::Error> {
+        let tx = conn.transaction()?;
+        let changed = MetadataDB::node_state_changes(&tx, config, now)?;
+        for node in &changed {
+            tx.execute(QUERY_SET_NODE_STATE, &[&node.name, &node.state.as_str()])?;
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Works out which Nodes should change state given when they were last heard from, without
+    /// recording it. Returns those Nodes, with the state they should be in.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `config` - How long Nodes may go without a heartbeat
+    /// * `now` - The current time, in milliseconds since the epoch
+    pub fn node_state_changes(conn: &Connection, config: &HeartbeatConfiguration, now: u64) -> Result<Vec<NodeStatus>, rusqlite::Error> {
+        let mut changed = vec![];
+        for mut node in MetadataDB::list_nodes(conn)? {
+            // Nodes that left said so, so there is nothing to wait for
+            if node.state == NodeState::Left {
+                continue;
+            }
+            // Nodes only come back to life when their heartbeats are handled
+            let state = config.state_for(node.last_heard, now);
+            if state != node.state && (node.state == NodeState::Alive || state == NodeState::Dead) {
+                node.state = state;
+                changed.push(node);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Counts every Node that hasn't left as heard from at `now`. Heartbeats only go to the
+    /// metadata leader, so a new leader gives every Node a full timeout to find it.
+    pub fn reset_heartbeats(conn: &Connection, now: u64) -> Result<(), rusqlite::Error> {
+        conn.execute(QUERY_RESET_HEARTBEATS, &[&(now as i64)])?;
+        Ok(())
+    }
+
+    /// Records that a Node has left the Cluster. Its Replicas are released so they can be placed
+    /// elsewhere, and its Primary Shards are flagged by the Node's state. Returns the IDs of those
+    /// Primary Shards, or None if no Node with the name has registered.
+    ///
+    /// It is one of the changes a `MetadataCommand` makes, so it runs in the transaction the
+    /// command is applied in.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `name` - Name of the Node that is leaving
+    pub fn deregister_node(conn: &Connection, name: &str) -> Result<Option<Vec<String>>, rusqlite::Error> {
+        if conn.execute(QUERY_SET_NODE_STATE, &[&name, &NodeState::Left.as_str()])? == 0 {
+            return Ok(None);
+        }
+        conn.execute(QUERY_RELEASE_REPLICAS_BY_NODE, &[&name])?;
+        let shards = {
+            let mut stmt = conn.prepare(QUERY_GET_SHARD_IDS_BY_NODE)?;
+            let rows = stmt.query_map(&[&name], |row| row.get(0))?;
+            let mut shards = vec![];
+            for shard in rows {
+                shards.push(shard?);
+            }
+            shards
+        };
+        Ok(Some(shards))
+    }
+
+    /// Creates the tables that record Indices and where their Shards live
+    pub fn create_index_tables(conn: &Connection) {
+        for query in &[QUERY_CREATE_INDEXES_TABLE, QUERY_CREATE_SHARDS_TABLE, QUERY_CREATE_REPLICAS_TABLE] {
+            if let Err(e) = conn.execute(query, &[]) {
+                println!("There was an error creating the index tables: {:?}", e);
+                return;
+            }
+        }
+        if let Err(e) = MetadataDB::migrate_table(conn, "indexes", INDEX_TABLE_MIGRATIONS) {
+            println!("There was an error updating the index table: {:?}", e);
+            return;
+        }
+        if let Err(e) = MetadataDB::migrate_table(conn, "shards", SHARD_TABLE_MIGRATIONS) {
+            println!("There was an error updating the shard table: {:?}", e);
+            return;
+        }
+        println!("Index tables created!");
+    }
+
+    /// Returns true if an Index with the name has been created
+    pub fn index_exists(conn: &Connection, name: &str) -> Result<bool, rusqlite::Error> {
+        match conn.query_row(QUERY_INDEX_EXISTS, &[&name], |_| ()) {
+            Ok(()) => Ok(true),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records a new Index along with where its Shards are placed. Replicas without a Node are
+    /// recorded as unassigned.
+    ///
+    /// It is one of the changes a `MetadataCommand` makes, so it runs in the transaction the
+    /// command is applied in.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `index` - Definition of the Index
+    /// * `placements` - Where each Primary Shard and its Replicas are placed, as decided by an
+    ///   `Allocator`
+    pub fn create_index(conn: &Connection, index: &IndexDefinition, placements: &[ShardPlacement]) -> Result<(), rusqlite::Error> {
+        conn.execute(
+            QUERY_INSERT_INDEX,
+            &[
+                &index.name,
+                &i64::from(index.primary_shards),
+                &i64::from(index.replica_shards),
+                &index.state.as_str(),
+            ],
+        )?;
+        for placement in placements {
+            let shard_id = format!("{}_{}", index.name, placement.number);
+            conn.execute(QUERY_INSERT_SHARD, &[&shard_id, &index.name, &i64::from(placement.number), &placement.primary])?;
+            for (replica, node) in placement.replicas.iter().enumerate() {
+                let replica_id = format!("{}_r{}", shard_id, replica);
+                match *node {
+                    Some(ref node) => conn.execute(QUERY_INSERT_REPLICA, &[&replica_id, &index.name, node, &shard_id])?,
+                    None => conn.execute(QUERY_INSERT_REPLICA, &[&replica_id, &index.name, &Null, &shard_id])?,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists every Index that has been created, ordered by name
+    pub fn list_indices(conn: &Connection) -> Result<Vec<IndexDefinition>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_ALL_INDEXES)?;
+        let rows = stmt.query_map(&[], index_from_row)?;
+        let mut results = vec![];
+        for index in rows {
+            results.push(index?);
+        }
+        Ok(results)
+    }
+
+    /// Returns an Index's definition, or None if there is no such Index
+    pub fn get_index(conn: &Connection, name: &str) -> Result<Option<IndexDefinition>, rusqlite::Error> {
+        match conn.query_row(QUERY_GET_INDEX, &[&name], index_from_row) {
+            Ok(index) => Ok(Some(index)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens or closes an Index. Returns false if there is no such Index.
+    pub fn set_index_state(conn: &Connection, name: &str, state: IndexState) -> Result<bool, rusqlite::Error> {
+        let changed = conn.execute(QUERY_SET_INDEX_STATE, &[&name, &state.as_str()])?;
+        Ok(changed > 0)
+    }
+
+    /// Removes an Index along with the records of its Shards and Replicas. Returns false if there
+    /// is no such Index.
+    ///
+    /// It is one of the changes a `MetadataCommand` makes, so it runs in the transaction the
+    /// command is applied in.
+    pub fn delete_index(conn: &Connection, name: &str) -> Result<bool, rusqlite::Error> {
+        conn.execute(QUERY_DELETE_REPLICAS_BY_INDEX, &[&name])?;
+        conn.execute(QUERY_DELETE_SHARDS_BY_INDEX, &[&name])?;
+        let deleted = conn.execute(QUERY_DELETE_INDEX, &[&name])?;
+        Ok(deleted > 0)
+    }
+
+    /// Returns the numbers of an Index's Primary Shards that are placed on a Node
+    pub fn shards_on_node(conn: &Connection, index: &str, node: &str) -> Result<Vec<u32>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_SHARD_NUMBERS_BY_INDEX_AND_NODE)?;
+        let rows = stmt.query_map(&[&index, &node], |row| {
+            let number: i64 = row.get(0);
+            number as u32
+        })?;
+        let mut results = vec![];
+        for number in rows {
+            results.push(number?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the number of Shard copies, Primaries and Replicas alike, placed on each Node
+    pub fn shard_load(conn: &Connection) -> Result<HashMap<String, usize>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_SHARD_LOAD)?;
+        let rows = stmt.query_map(&[], |row| {
+            let count: i64 = row.get(1);
+            (row.get(0), count as usize)
+        })?;
+        let mut load = HashMap::new();
+        for row in rows {
+            let (node, count): (String, usize) = row?;
+            load.insert(node, count);
+        }
+        Ok(load)
+    }
+
+    /// Returns the IDs of the Replicas that are not placed on any Node, along with the ID of the
+    /// Primary Shard each is a copy of
+    pub fn unassigned_replicas(conn: &Connection) -> Result<Vec<(String, String)>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_UNASSIGNED_REPLICAS)?;
+        let rows = stmt.query_map(&[], |row| (row.get(0), row.get(1)))?;
+        let mut results = vec![];
+        for row in rows {
+            results.push(row?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the names of the Nodes holding a copy of a Shard, starting with its Primary
+    pub fn shard_holders(conn: &Connection, shard_id: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_SHARD_HOLDERS)?;
+        let rows = stmt.query_map(&[&shard_id], |row| row.get(0))?;
+        let mut results = vec![];
+        for node in rows {
+            results.push(node?);
+        }
+        Ok(results)
+    }
+
+    /// Places a Replica on a Node
+    pub fn assign_replica(conn: &Connection, replica_id: &str, node: &str) -> Result<(), rusqlite::Error> {
+        conn.execute(QUERY_ASSIGN_REPLICA, &[&replica_id, &node])?;
+        Ok(())
+    }
+
+    /// Returns the numbers of the Primary Shards of an Index that a Node holds a Replica of
+    pub fn replicas_on_node(conn: &Connection, index: &str, node: &str) -> Result<Vec<u32>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_REPLICA_NUMBERS_BY_INDEX_AND_NODE)?;
+        let rows = stmt.query_map(&[&index, &node], |row| {
+            let number: i64 = row.get(0);
+            number as u32
+        })?;
+        let mut results = vec![];
+        for number in rows {
+            results.push(number?);
         }
-        results
+        Ok(results)
+    }
+
+    /// Returns the names of the Nodes holding any copy of an Index's Shards
+    pub fn nodes_with_index(conn: &Connection, index: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_NODES_BY_INDEX)?;
+        let rows = stmt.query_map(&[&index], |row| row.get(0))?;
+        let mut results = vec![];
+        for node in rows {
+            results.push(node?);
+        }
+        Ok(results)
+    }
+
+    /// Returns where the Replicas of a Primary Shard that have been placed are
+    pub fn replica_targets(conn: &Connection, shard_id: &str) -> Result<Vec<ReplicaTarget>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_REPLICA_TARGETS)?;
+        let rows = stmt.query_map(&[&shard_id], |row| {
+            let name: String = row.get(0);
+            let host: String = row.get(1);
+            let port: i64 = row.get(2);
+            ReplicaTarget {
+                node: name,
+                address: format!("{}:{}", host, port),
+            }
+        })?;
+        let mut results = vec![];
+        for target in rows {
+            results.push(target?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the address of the Node a Primary Shard is placed on, as `host:port`, or None if
+    /// there is no such Shard
+    pub fn primary_address(conn: &Connection, shard_id: &str) -> Result<Option<String>, rusqlite::Error> {
+        let address = conn.query_row(QUERY_GET_PRIMARY_ADDRESS, &[&shard_id], |row| {
+            let host: String = row.get(0);
+            let port: i64 = row.get(1);
+            format!("{}:{}", host, port)
+        });
+        match address {
+            Ok(address) => Ok(Some(address)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the names of the Nodes holding the Primaries of the Shards a Node holds Replicas
+    /// of, which need to know when those Replicas move
+    pub fn primaries_replicated_to(conn: &Connection, node: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_PRIMARY_NODES_BY_REPLICA_NODE)?;
+        let rows = stmt.query_map(&[&node], |row| row.get(0))?;
+        let mut results = vec![];
+        for node in rows {
+            results.push(node?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the Primary Shards placed on a Node, each as its ID, the name of its Index and its
+    /// number
+    pub fn primaries_on_node(conn: &Connection, node: &str) -> Result<Vec<(String, String, u32)>, rusqlite::Error> {
+        let mut stmt = conn.prepare(QUERY_GET_SHARDS_BY_NODE)?;
+        let rows = stmt.query_map(&[&node], |row| {
+            let number: i64 = row.get(2);
+            (row.get(0), row.get(1), number as u32)
+        })?;
+        let mut results = vec![];
+        for row in rows {
+            results.push(row?);
+        }
+        Ok(results)
+    }
+
+    /// Returns a Shard's term, which starts at 1 and goes up every time a Replica is promoted to
+    /// take its place
+    pub fn shard_term(conn: &Connection, shard_id: &str) -> Result<u64, rusqlite::Error> {
+        let term: i64 = conn.query_row(QUERY_GET_SHARD_TERM, &[&shard_id], |row| row.get(0))?;
+        Ok(term as u64)
+    }
+
+    /// Makes the Replica of a Shard on a Node its Primary and starts a new term, so writes from
+    /// the old Primary are refused. The Replica's place is freed to be filled on another Node.
+    /// Returns the Shard's new term.
+    ///
+    /// It is one of the changes a `MetadataCommand` makes, so it runs in the transaction the
+    /// command is applied in.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `shard_id` - ID of the Primary Shard
+    /// * `node` - Name of the Node holding the Replica to promote
+    pub fn promote_replica(conn: &Connection, shard_id: &str, node: &str) -> Result<u64, rusqlite::Error> {
+        if conn.execute(QUERY_RELEASE_REPLICA_BY_SHARD_AND_NODE, &[&shard_id, &node])? == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        conn.execute(QUERY_PROMOTE_REPLICA, &[&shard_id, &node])?;
+        let term = MetadataDB::shard_term(conn, shard_id)?;
+        Ok(term)
+    }
+
+    /// Frees every Replica placed on a Node so they can be placed elsewhere
+    pub fn release_replicas(conn: &Connection, node: &str) -> Result<(), rusqlite::Error> {
+        conn.execute(QUERY_RELEASE_REPLICAS_BY_NODE, &[&node])?;
+        Ok(())
+    }
+
+    /// Makes a change to the metadata in a transaction of its own. The metadata Nodes each apply
+    /// the same commands in the same order.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `command` - The change to make
+    pub fn apply(conn: &mut Connection, command: &MetadataCommand) -> Result<(), rusqlite::Error> {
+        let tx = conn.transaction()?;
+        MetadataDB::make_change(&tx, command)?;
+        tx.commit()
+    }
+
+    /// Applies an entry of the Raft log and records it as the last one applied, in one
+    /// transaction so that a crash never leaves one done without the other.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - Connection to the metadata database
+    /// * `index` - Index of the entry in the Raft log
+    /// * `command` - The change the entry makes, or None if it makes none
+    pub fn apply_entry(conn: &mut Connection, index: u64, command: Option<&MetadataCommand>) -> Result<(), rusqlite::Error> {
+        let applied = {
+            let tx = conn.transaction()?;
+            let applied = match command {
+                Some(command) => MetadataDB::make_change(&tx, command),
+                None => Ok(()),
+            };
+            if applied.is_ok() {
+                MetadataDB::set_applied_index(&tx, index)?;
+                return tx.commit();
+            }
+            applied
+        };
+        // The failed command was rolled back with the transaction. It would fail the same way
+        // again, so the entry is recorded as applied without it.
+        MetadataDB::set_applied_index(conn, index)?;
+        applied
+    }
+
+    /// Makes the change a command describes, within a transaction opened by `apply` or
+    /// `apply_entry`
+    fn make_change(conn: &Connection, command: &MetadataCommand) -> Result<(), rusqlite::Error> {
+        match *command {
+            MetadataCommand::RegisterNode { ref name, ref host, port, now } => {
+                conn.execute(QUERY_REGISTER_NODE, &[name, host, &port, &(now as i64)])?;
+            },
+            MetadataCommand::SetNodeState { ref name, state } => {
+                conn.execute(QUERY_SET_NODE_STATE, &[name, &state.as_str()])?;
+            },
+            MetadataCommand::DeregisterNode { ref name } => {
+                MetadataDB::deregister_node(conn, name)?;
+            },
+            MetadataCommand::CreateIndex { ref index, ref placements } => {
+                MetadataDB::create_index(conn, index, placements)?;
+            },
+            MetadataCommand::SetIndexState { ref name, state } => {
+                MetadataDB::set_index_state(conn, name, state)?;
+            },
+            MetadataCommand::DeleteIndex { ref name } => {
+                MetadataDB::delete_index(conn, name)?;
+            },
+            MetadataCommand::AssignReplica { ref replica_id, ref node } => {
+                MetadataDB::assign_replica(conn, replica_id, node)?;
+            },
+            MetadataCommand::PromoteReplica { ref shard_id, ref node } => {
+                MetadataDB::promote_replica(conn, shard_id, node)?;
+            },
+            MetadataCommand::ReleaseReplicas { ref node } => {
+                MetadataDB::release_replicas(conn, node)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Creates the table recording how far through the Raft log the metadata database is
+    pub fn create_applied_table(conn: &Connection) {
+        if let Err(e) = conn.execute(QUERY_CREATE_APPLIED_TABLE, &[]) {
+            println!("There was an error creating the applied index table: {:?}", e);
+        }
+    }
+
+    /// Returns the index of the last Raft log entry applied to the metadata database, or 0 if
+    /// none has been
+    pub fn applied_index(conn: &Connection) -> Result<u64, rusqlite::Error> {
+        let index = conn.query_row(QUERY_GET_APPLIED_INDEX, &[], |row| {
+            let index: i64 = row.get(0);
+            index as u64
+        });
+        match index {
+            Ok(index) => Ok(index),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records the index of the last Raft log entry applied to the metadata database
+    pub fn set_applied_index(conn: &Connection, index: u64) -> Result<(), rusqlite::Error> {
+        conn.execute(QUERY_SET_APPLIED_INDEX, &[&(index as i64)])?;
+        Ok(())
+    }
+
+    /// Returns the copies of Shards of open Indices that a Node should be running. Every other
+    /// Index is listed too, with no copies, so the Node stops any Shards of it that it runs.
+    pub fn assignments_for_node(conn: &Connection, node: &str) -> Result<Vec<IndexAssignment>, rusqlite::Error> {
+        let mut assignments = vec![];
+        for index in MetadataDB::list_indices(conn)? {
+            let (primaries, replicas) = if index.state == IndexState::Open {
+                (
+                    MetadataDB::shards_on_node(conn, &index.name, node)?,
+                    MetadataDB::replicas_on_node(conn, &index.name, node)?,
+                )
+            } else {
+                (vec![], vec![])
+            };
+            let mut replica_targets = BTreeMap::new();
+            for &number in &primaries {
+                let targets = MetadataDB::replica_targets(conn, &format!("{}_{}", index.name, number))?;
+                replica_targets.insert(number, targets);
+            }
+            let mut terms = BTreeMap::new();
+            for &number in primaries.iter().chain(replicas.iter()) {
+                terms.insert(number, MetadataDB::shard_term(conn, &format!("{}_{}", index.name, number))?);
+            }
+            let mut primary_addresses = BTreeMap::new();
+            for &number in &replicas {
+                if let Some(address) = MetadataDB::primary_address(conn, &format!("{}_{}", index.name, number))? {
+                    primary_addresses.insert(number, address);
+                }
+            }
+            assignments.push(IndexAssignment {
+                index: index,
+                primaries: primaries,
+                replicas: replicas,
+                replica_targets: replica_targets,
+                terms: terms,
+                primary_addresses: primary_addresses,
+                deleted: false,
+            });
+        }
+        Ok(assignments)
+    }
+}
+
+/// Reads a Node from a row of `name, host, port, last_heard, state`
+fn node_from_row(row: &rusqlite::Row) -> NodeStatus {
+    let port: i64 = row.get(2);
+    let last_heard: i64 = row.get(3);
+    let state: String = row.get(4);
+    NodeStatus {
+        name: row.get(0),
+        host: row.get(1),
+        port: port as u16,
+        last_heard: last_heard as u64,
+        state: NodeState::from_db(&state),
+    }
+}
+
+/// Reads an Index's definition from a row of `name, primary_shards, replica_shards, state`
+fn index_from_row(row: &rusqlite::Row) -> IndexDefinition {
+    let primary_shards: i64 = row.get(1);
+    let replica_shards: i64 = row.get(2);
+    let state: String = row.get(3);
+    IndexDefinition {
+        name: row.get(0),
+        primary_shards: primary_shards as u32,
+        replica_shards: replica_shards as u32,
+        // Anything unexpected is treated as closed rather than starting Shards nobody asked for
+        state: if state == IndexState::Open.as_str() {
+            IndexState::Open
+        } else {
+            IndexState::Closed
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Places every Primary Shard of an Index on one Node and leaves its Replicas unassigned
+    fn place_on(index: &IndexDefinition, node: &str) -> Vec<ShardPlacement> {
+        (0..index.primary_shards)
+            .map(|number| ShardPlacement {
+                number: number,
+                primary: node.to_owned(),
+                replicas: vec![None; index.replica_shards as usize],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_create_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_index_tables(&conn);
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 3,
+            replica_shards: 1,
+            state: IndexState::Open,
+        };
+        assert!(!MetadataDB::index_exists(&conn, "logs").unwrap());
+        MetadataDB::create_index(&conn, &index, &place_on(&index, "node01")).unwrap();
+        assert!(MetadataDB::index_exists(&conn, "logs").unwrap());
+        assert!(MetadataDB::create_index(&conn, &index, &place_on(&index, "node01")).is_err());
+
+        assert_eq!(MetadataDB::list_indices(&conn).unwrap(), vec![index]);
+        assert_eq!(MetadataDB::shards_on_node(&conn, "logs", "node01").unwrap(), vec![0, 1, 2]);
+        assert!(MetadataDB::shards_on_node(&conn, "logs", "node02").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_lifecycle() {
+        let conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_index_tables(&conn);
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 2,
+            replica_shards: 0,
+            state: IndexState::Open,
+        };
+        MetadataDB::create_index(&conn, &index, &place_on(&index, "node01")).unwrap();
+
+        assert!(MetadataDB::set_index_state(&conn, "logs", IndexState::Closed).unwrap());
+        assert_eq!(MetadataDB::get_index(&conn, "logs").unwrap().unwrap().state, IndexState::Closed);
+        assert!(!MetadataDB::set_index_state(&conn, "metrics", IndexState::Closed).unwrap());
+
+        assert!(MetadataDB::delete_index(&conn, "logs").unwrap());
+        assert!(!MetadataDB::delete_index(&conn, "logs").unwrap());
+        assert_eq!(MetadataDB::get_index(&conn, "logs").unwrap(), None);
+        assert!(MetadataDB::shards_on_node(&conn, "logs", "node01").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_node_states() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&conn);
+        let config = HeartbeatConfiguration::default();
+        assert!(MetadataDB::register_node(&conn, "node01", "127.0.0.1", 3001, 1_000));
+        assert!(MetadataDB::register_node(&conn, "node02", "127.0.0.1", 3002, 1_000));
+        assert_eq!(MetadataDB::record_heartbeat(&conn, "node03", 1_000).unwrap(), None);
+
+        // node02 keeps sending heartbeats while node01 goes quiet
+        MetadataDB::record_heartbeat(&conn, "node02", 5_000).unwrap();
+        let changed = MetadataDB::update_node_states(&mut conn, &config, 7_000).unwrap();
+        assert_eq!(changed.len(), 1);
+        assert_eq!((changed[0].name.as_str(), changed[0].state), ("node01", NodeState::Suspect));
+        assert!(MetadataDB::update_node_states(&mut conn, &config, 7_500).unwrap().is_empty());
+
+        MetadataDB::record_heartbeat(&conn, "node02", 15_000).unwrap();
+        let changed = MetadataDB::update_node_states(&mut conn, &config, 16_000).unwrap();
+        assert_eq!((changed[0].name.as_str(), changed[0].state), ("node01", NodeState::Dead));
+
+        // A heartbeat is only recorded, and the leader decides whether the Node is alive again
+        assert_eq!(MetadataDB::record_heartbeat(&conn, "node01", 17_000).unwrap(), Some(NodeState::Dead));
+        assert!(MetadataDB::node_state_changes(&conn, &config, 17_500).unwrap().is_empty());
+        let command = MetadataCommand::SetNodeState { name: String::from("node01"), state: NodeState::Alive };
+        MetadataDB::apply(&mut conn, &command).unwrap();
+        let nodes = MetadataDB::list_nodes(&conn).unwrap();
+        assert_eq!(nodes.iter().map(|node| node.state).collect::<Vec<_>>(), vec![NodeState::Alive, NodeState::Alive]);
+        assert_eq!(nodes[0].last_heard, 17_000);
+
+        // A new leader gives every Node a full timeout
+        MetadataDB::reset_heartbeats(&conn, 40_000).unwrap();
+        assert!(MetadataDB::node_state_changes(&conn, &config, 41_000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_apply_commands() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&conn);
+        MetadataDB::create_index_tables(&conn);
+        MetadataDB::create_applied_table(&conn);
+        assert_eq!(MetadataDB::applied_index(&conn).unwrap(), 0);
+
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 1,
+            replica_shards: 1,
+            state: IndexState::Open,
+        };
+        let commands = vec![
+            MetadataCommand::RegisterNode { name: String::from("node01"), host: String::from("10.0.0.1"), port: 3001, now: 1_000 },
+            MetadataCommand::RegisterNode { name: String::from("node02"), host: String::from("10.0.0.2"), port: 3001, now: 1_000 },
+            MetadataCommand::CreateIndex { index: index.clone(), placements: place_on(&index, "node01") },
+            MetadataCommand::AssignReplica { replica_id: String::from("logs_0_r0"), node: String::from("node02") },
+            MetadataCommand::SetNodeState { name: String::from("node01"), state: NodeState::Dead },
+            MetadataCommand::PromoteReplica { shard_id: String::from("logs_0"), node: String::from("node02") },
+            MetadataCommand::SetIndexState { name: String::from("logs"), state: IndexState::Closed },
+        ];
+        for (number, command) in commands.iter().enumerate() {
+            // Commands travel through the Raft log as JSON
+            let command: MetadataCommand = ::serde_json::from_str(&::serde_json::to_string(command).unwrap()).unwrap();
+            MetadataDB::apply_entry(&mut conn, number as u64 + 1, Some(&command)).unwrap();
+        }
+        assert_eq!(MetadataDB::applied_index(&conn).unwrap(), 7);
+        assert_eq!(MetadataDB::get_node(&conn, "node01").unwrap().unwrap().state, NodeState::Dead);
+        assert_eq!(MetadataDB::shards_on_node(&conn, "logs", "node02").unwrap(), vec![0]);
+        assert_eq!(MetadataDB::shard_term(&conn, "logs_0").unwrap(), 2);
+        assert_eq!(MetadataDB::get_index(&conn, "logs").unwrap().unwrap().state, IndexState::Closed);
+
+        // A command that can't be applied fails the same way on every Node
+        let again = MetadataCommand::CreateIndex { index: index.clone(), placements: place_on(&index, "node01") };
+        assert!(MetadataDB::apply_entry(&mut conn, 8, Some(&again)).is_err());
+        assert_eq!(MetadataDB::applied_index(&conn).unwrap(), 8);
+        MetadataDB::apply_entry(&mut conn, 9, None).unwrap();
+        assert_eq!(MetadataDB::applied_index(&conn).unwrap(), 9);
+
+        // An entry is only applied along with the record of it being applied
+        conn.execute("DROP TABLE raft_applied", &[]).unwrap();
+        let open = MetadataCommand::SetIndexState { name: String::from("logs"), state: IndexState::Open };
+        assert!(MetadataDB::apply_entry(&mut conn, 10, Some(&open)).is_err());
+        assert_eq!(MetadataDB::get_index(&conn, "logs").unwrap().unwrap().state, IndexState::Closed);
+        MetadataDB::create_applied_table(&conn);
+        assert!(MetadataDB::apply(&mut conn, &again).is_err());
+        MetadataDB::apply(&mut conn, &MetadataCommand::DeregisterNode { name: String::from("node02") }).unwrap();
+        MetadataDB::apply(&mut conn, &MetadataCommand::DeleteIndex { name: String::from("logs") }).unwrap();
+        assert_eq!(MetadataDB::get_node(&conn, "node02").unwrap().unwrap().state, NodeState::Left);
+        assert!(MetadataDB::list_indices(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shard_assignments() {
+        let conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&conn);
+        MetadataDB::create_index_tables(&conn);
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 2,
+            replica_shards: 1,
+            state: IndexState::Open,
+        };
+        let mut placements = place_on(&index, "node01");
+        placements[1].replicas[0] = Some(String::from("node02"));
+        MetadataDB::create_index(&conn, &index, &placements).unwrap();
+
+        assert_eq!(MetadataDB::unassigned_replicas(&conn).unwrap(), vec![(String::from("logs_0_r0"), String::from("logs_0"))]);
+        assert_eq!(MetadataDB::shard_holders(&conn, "logs_1").unwrap(), vec!["node01", "node02"]);
+        MetadataDB::assign_replica(&conn, "logs_0_r0", "node03").unwrap();
+        assert!(MetadataDB::unassigned_replicas(&conn).unwrap().is_empty());
+
+        let load = MetadataDB::shard_load(&conn).unwrap();
+        assert_eq!((load["node01"], load["node02"], load["node03"]), (2, 1, 1));
+        let mut nodes = MetadataDB::nodes_with_index(&conn, "logs").unwrap();
+        nodes.sort();
+        assert_eq!(nodes, vec!["node01", "node02", "node03"]);
+
+        let assignments = MetadataDB::assignments_for_node(&conn, "node02").unwrap();
+        assert_eq!(assignments.len(), 1);
+        assert_eq!((assignments[0].primaries.clone(), assignments[0].replicas.clone()), (vec![], vec![1]));
+        // A Primary is told where its Replicas are, once their Nodes have registered
+        MetadataDB::register_node(&conn, "node02", "10.0.0.2", 3001, 1_000);
+        let assignments = MetadataDB::assignments_for_node(&conn, "node01").unwrap();
+        assert_eq!(assignments[0].replica_targets[&0], vec![]);
+        assert_eq!(
+            assignments[0].replica_targets[&1],
+            vec![ReplicaTarget { node: String::from("node02"), address: String::from("10.0.0.2:3001") }]
+        );
+        assert_eq!(MetadataDB::primaries_replicated_to(&conn, "node03").unwrap(), vec!["node01"]);
+        // A Replica is told where its Primary is, so it can recover from it
+        MetadataDB::register_node(&conn, "node01", "10.0.0.1", 3001, 1_000);
+        let assignments = MetadataDB::assignments_for_node(&conn, "node02").unwrap();
+        assert_eq!(assignments[0].primary_addresses[&1], "10.0.0.1:3001");
+        MetadataDB::set_index_state(&conn, "logs", IndexState::Closed).unwrap();
+        let assignments = MetadataDB::assignments_for_node(&conn, "node02").unwrap();
+        assert_eq!(assignments.len(), 1);
+        assert!(assignments[0].primaries.is_empty() && assignments[0].replicas.is_empty());
+    }
+
+    #[test]
+    fn test_deregister_node() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&conn);
+        MetadataDB::create_index_tables(&conn);
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 2,
+            replica_shards: 0,
+            state: IndexState::Open,
+        };
+        MetadataDB::create_index(&conn, &index, &place_on(&index, "node01")).unwrap();
+        MetadataDB::register_node(&conn, "node01", "127.0.0.1", 3001, 1_000);
+
+        assert_eq!(MetadataDB::deregister_node(&conn, "node02").unwrap(), None);
+        assert_eq!(
+            MetadataDB::deregister_node(&conn, "node01").unwrap(),
+            Some(vec![String::from("logs_0"), String::from("logs_1")])
+        );
+        // A Node that left stays left, however long it is quiet and whatever arrives late from it
+        let config = HeartbeatConfiguration::default();
+        assert!(MetadataDB::update_node_states(&mut conn, &config, 60_000).unwrap().is_empty());
+        assert_eq!(MetadataDB::record_heartbeat(&conn, "node01", 60_000).unwrap(), Some(NodeState::Left));
+        assert_eq!(MetadataDB::list_nodes(&conn).unwrap()[0].state, NodeState::Left);
+
+        // Registering again brings it back, on its new address
+        MetadataDB::register_node(&conn, "node01", "10.0.0.2", 4001, 61_000);
+        let nodes = MetadataDB::list_nodes(&conn).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!((nodes[0].host.as_str(), nodes[0].port, nodes[0].state), ("10.0.0.2", 4001, NodeState::Alive));
+    }
+
+    #[test]
+    fn test_promote_replica() {
+        let conn = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&conn);
+        MetadataDB::create_index_tables(&conn);
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 2,
+            replica_shards: 2,
+            state: IndexState::Open,
+        };
+        let mut placements = place_on(&index, "node01");
+        placements[0].replicas = vec![Some(String::from("node02")), Some(String::from("node03"))];
+        MetadataDB::create_index(&conn, &index, &placements).unwrap();
+        assert_eq!(MetadataDB::shard_term(&conn, "logs_0").unwrap(), 1);
+        assert_eq!(
+            MetadataDB::primaries_on_node(&conn, "node01").unwrap(),
+            vec![(String::from("logs_0"), String::from("logs"), 0), (String::from("logs_1"), String::from("logs"), 1)]
+        );
+
+        // node02 holds no Replica of Shard 1
+        assert!(MetadataDB::promote_replica(&conn, "logs_1", "node02").is_err());
+        assert_eq!(MetadataDB::promote_replica(&conn, "logs_0", "node02").unwrap(), 2);
+        assert_eq!(MetadataDB::shards_on_node(&conn, "logs", "node02").unwrap(), vec![0]);
+        assert_eq!(MetadataDB::shard_holders(&conn, "logs_0").unwrap(), vec!["node02", "node03"]);
+        assert_eq!(MetadataDB::unassigned_replicas(&conn).unwrap().len(), 3);
+
+        MetadataDB::release_replicas(&conn, "node03").unwrap();
+        assert_eq!(MetadataDB::shard_holders(&conn, "logs_0").unwrap(), vec!["node02"]);
+        let assignments = MetadataDB::assignments_for_node(&conn, "node02").unwrap();
+        assert_eq!(assignments[0].terms[&0], 2);
+    }
+
+    #[test]
+    fn test_migrate_shard_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE shards (id TEXT PRIMARY KEY NOT NULL, index_name TEXT NOT NULL, number INTEGER NOT NULL, node TEXT NOT NULL)", &[]).unwrap();
+        conn.execute("INSERT INTO shards VALUES ('logs_0', 'logs', 0, 'node01')", &[]).unwrap();
+        MetadataDB::create_index_tables(&conn);
+        assert_eq!(MetadataDB::shard_term(&conn, "logs_0").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_migrate_index_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE indexes (name TEXT PRIMARY KEY NOT NULL, primary_shards INTEGER NOT NULL DEFAULT 1, replica_shards INTEGER NOT NULL DEFAULT 0)", &[]).unwrap();
+        conn.execute("INSERT INTO indexes VALUES ('logs', 2, 1)", &[]).unwrap();
+        MetadataDB::create_index_tables(&conn);
+        let index = MetadataDB::get_index(&conn, "logs").unwrap().unwrap();
+        assert_eq!((index.primary_shards, index.state), (2, IndexState::Open));
+    }
 
+    #[test]
+    fn test_migrate_node_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE nodes (name TEXT PRIMARY KEY NOT NULL, host TEXT NOT NULL, port INT NOT NULL)", &[]).unwrap();
+        conn.execute("INSERT INTO nodes VALUES ('node01', '127.0.0.1', 3001)", &[]).unwrap();
+        MetadataDB::create_node_table(&conn);
+        let nodes = MetadataDB::list_nodes(&conn).unwrap();
+        assert_eq!((nodes[0].last_heard, nodes[0].state), (0, NodeState::Alive));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a Node waits by default for writes in progress to finish before it leaves the Cluster
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps count of the writes in progress on a Node, so that when it leaves the Cluster it can
+/// stop taking new ones and wait for the rest to finish before its Shards are stopped
+pub struct WriteGate {
+    state: Mutex<GateState>,
+    finished: Condvar,
+}
+
+struct GateState {
+    /// Number of writes that have started and not yet finished
+    in_flight: usize,
+    /// Set once the Node starts leaving, after which no new writes are let through
+    draining: bool,
+}
+
+impl WriteGate {
+    /// Creates and returns a WriteGate that lets writes through
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use rpc::drain::WriteGate;
+    /// let gate = Arc::new(WriteGate::new());
+    /// let _write = WriteGate::begin(&gate).unwrap();
+    /// ```
+    pub fn new() -> WriteGate {
+        WriteGate {
+            state: Mutex::new(GateState {
+                in_flight: 0,
+                draining: false,
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    /// Starts a write. The write counts as in progress until the returned WriteGuard is dropped.
+    /// Returns None if the Node is leaving and takes no more writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `gate` - The Node's WriteGate
+    pub fn begin(gate: &Arc<WriteGate>) -> Option<WriteGuard> {
+        let mut state = match gate.state.lock() {
+            Ok(state) => state,
+            Err(_) => return None,
+        };
+        if state.draining {
+            return None;
+        }
+        state.in_flight += 1;
+        Some(WriteGuard { gate: gate.clone() })
+    }
+
+    /// Stops letting new writes through and waits for those in progress to finish. Returns false
+    /// if some were still going when the timeout ran out.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Longest time to wait for writes in progress
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        state.draining = true;
+        while state.in_flight > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = match self.finished.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(_) => return false,
+            };
+        }
+        true
+    }
+
+    /// Returns true once the Node has started leaving and takes no more writes
+    pub fn is_draining(&self) -> bool {
+        self.state.lock().map(|state| state.draining).unwrap_or(true)
+    }
+}
+
+/// A write in progress. Dropping it marks the write as finished.
+pub struct WriteGuard {
+    gate: Arc<WriteGate>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.gate.state.lock() {
+            state.in_flight -= 1;
+            if state.in_flight == 0 {
+                self.gate.finished.notify_all();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_drain() {
+        let gate = Arc::new(WriteGate::new());
+        let write = WriteGate::begin(&gate).unwrap();
+        let finishing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(write);
+        });
+        assert!(gate.drain(Duration::from_secs(5)));
+        assert!(gate.is_draining());
+        assert!(WriteGate::begin(&gate).is_none());
+        finishing.join().unwrap();
+    }
+
+    #[test]
+    fn test_drain_timeout() {
+        let gate = Arc::new(WriteGate::new());
+        let _write = WriteGate::begin(&gate).unwrap();
+        assert!(!gate.drain(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,136 @@
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use client::{RpcClient, DEFAULT_RPC_TIMEOUT};
+use db::NodeState;
+use messages::{Message, MessageType};
+use raft;
+
+/// How often a Node sends a heartbeat to the metadata leader by default
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the metadata server waits for a heartbeat by default before a Node is suspect
+pub const DEFAULT_SUSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the metadata server waits for a heartbeat by default before a Node is dead
+pub const DEFAULT_DEAD_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often heartbeats are sent, and how long the metadata server waits for one before it
+/// stops trusting a Node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeartbeatConfiguration {
+    /// How often a Node sends a heartbeat. The metadata leader checks on Nodes this often.
+    pub interval: Duration,
+    /// Time without a heartbeat after which a Node is suspect
+    pub suspect_timeout: Duration,
+    /// Time without a heartbeat after which a Node is dead. Should be longer than
+    /// `suspect_timeout`.
+    pub dead_timeout: Duration,
+}
+
+impl Default for HeartbeatConfiguration {
+    fn default() -> HeartbeatConfiguration {
+        HeartbeatConfiguration {
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            suspect_timeout: DEFAULT_SUSPECT_TIMEOUT,
+            dead_timeout: DEFAULT_DEAD_TIMEOUT,
+        }
+    }
+}
+
+impl HeartbeatConfiguration {
+    /// Returns the state of a Node given when it was last heard from
+    ///
+    /// # Arguments
+    ///
+    /// * `last_heard` - When the Node's last heartbeat arrived, in milliseconds since the epoch
+    /// * `now` - The current time, in milliseconds since the epoch
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::db::NodeState;
+    /// use rpc::heartbeat::HeartbeatConfiguration;
+    /// let config = HeartbeatConfiguration::default();
+    /// assert_eq!(config.state_for(1000, 2000), NodeState::Alive);
+    /// ```
+    pub fn state_for(&self, last_heard: u64, now: u64) -> NodeState {
+        let silence = now.saturating_sub(last_heard);
+        if silence >= millis(self.dead_timeout) {
+            NodeState::Dead
+        } else if silence >= millis(self.suspect_timeout) {
+            NodeState::Suspect
+        } else {
+            NodeState::Alive
+        }
+    }
+}
+
+/// Returns the current time in milliseconds since the epoch, which is how heartbeats are recorded
+pub fn now_millis() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => millis(elapsed),
+        Err(_) => 0,
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+/// Sends a heartbeat to the metadata leader every `interval`, forever. Each heartbeat carries
+/// the Node's address, so a leader that doesn't know the Node yet can register it. If the leader
+/// can't be reached the heartbeat is skipped, and the leader is looked for again next time.
+///
+/// # Arguments
+///
+/// * `name` - Name of the Node sending the heartbeats
+/// * `host` - Address of the Node's RPC server
+/// * `port` - Port of the Node's RPC server
+/// * `seeds` - Addresses of the metadata Nodes, as `host:port`, which are asked who the leader is
+/// * `interval` - Time between heartbeats
+pub fn send_heartbeats(name: String, host: String, port: u16, seeds: Vec<String>, interval: Duration) {
+    let mut client: Option<RpcClient> = None;
+    loop {
+        thread::sleep(interval);
+        if client.as_ref().map_or(true, |client| client.is_closed()) {
+            let leader = match raft::find_leader(&seeds) {
+                Some(leader) => leader,
+                None => {
+                    println!("Could not find the metadata leader to send a heartbeat to");
+                    continue;
+                },
+            };
+            client = match RpcClient::connect(&leader, DEFAULT_RPC_TIMEOUT) {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    println!("Could not connect to the metadata leader to send a heartbeat: {}", e);
+                    continue;
+                },
+            };
+        }
+        if let Some(ref client) = client {
+            let message = Message::new(MessageType::HEARTBEAT).args(vec![name.clone(), host.clone(), port.to_string()]);
+            if let Err(e) = client.send(&message) {
+                println!("There was an error sending a heartbeat: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_for() {
+        let config = HeartbeatConfiguration {
+            interval: Duration::from_millis(100),
+            suspect_timeout: Duration::from_millis(500),
+            dead_timeout: Duration::from_millis(2000),
+        };
+        assert_eq!(config.state_for(10_000, 10_499), NodeState::Alive);
+        assert_eq!(config.state_for(10_000, 10_500), NodeState::Suspect);
+        assert_eq!(config.state_for(10_000, 12_000), NodeState::Dead);
+        // A clock that went backwards doesn't make a Node look dead
+        assert_eq!(config.state_for(10_000, 9_000), NodeState::Alive);
+    }
+}
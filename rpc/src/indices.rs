@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
+
+use inverted_index::manager::{IndexCommand, Manager, ManagerConfiguration};
+use inverted_index::shard::shard_for;
+
+use allocator::IndexAssignment;
+use recovery;
+use replication::{self, ReplicaSet};
+
+/// The Shards of an Index that are running on this Node
+#[derive(Clone, Debug)]
+pub struct LocalIndex {
+    /// Number of Primary Shards the Index has across the whole Cluster
+    primary_shards: u32,
+    /// Channels to the `Manager` of each Shard on this Node, by Shard number
+    shards: BTreeMap<u32, mpsc::Sender<IndexCommand>>,
+    /// Numbers of the Shards on this Node that are Replicas rather than Primaries
+    replicas: BTreeSet<u32>,
+    /// Where the Replicas of each Shard on this Node are, shared with the thread that sends
+    /// them its writes. Only a Primary's has any.
+    replica_sets: BTreeMap<u32, Arc<Mutex<ReplicaSet>>>,
+    /// Address of the Node holding the Primary of each Replica on this Node, as `host:port`
+    primary_addresses: BTreeMap<u32, String>,
+    /// Numbers of the Replicas on this Node that are catching up with their Primary
+    recovering: Arc<Mutex<BTreeSet<u32>>>,
+}
+
+impl LocalIndex {
+    /// Number of Primary Shards the Index has across the whole Cluster
+    pub fn primary_shards(&self) -> u32 {
+        self.primary_shards
+    }
+
+    /// Returns the number of the Shard a Document belongs in, along with the channel to that
+    /// Shard's `Manager` if its Primary runs on this Node. Only Primaries take writes, so a
+    /// Replica here doesn't count.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - ID of the Document
+    pub fn shard_for(&self, id: u64) -> (u32, Option<mpsc::Sender<IndexCommand>>) {
+        let shard = shard_for(id, self.primary_shards);
+        if self.replicas.contains(&shard) {
+            return (shard, None);
+        }
+        (shard, self.shards.get(&shard).cloned())
+    }
+
+    /// Returns true if the copy of a Shard on this Node is a Replica
+    pub fn is_replica(&self, shard: u32) -> bool {
+        self.replicas.contains(&shard)
+    }
+
+    /// Returns the channel to the `Manager` of a Shard whose Replica runs on this Node, which is
+    /// where its Primary's writes are applied
+    pub fn replica(&self, shard: u32) -> Option<mpsc::Sender<IndexCommand>> {
+        if !self.is_replica(shard) {
+            return None;
+        }
+        self.shards.get(&shard).cloned()
+    }
+
+    /// Returns the channel to the `Manager` of a Shard on this Node, whether it is a Primary or a
+    /// Replica
+    pub fn shard(&self, shard: u32) -> Option<mpsc::Sender<IndexCommand>> {
+        self.shards.get(&shard).cloned()
+    }
+
+    /// Returns the term of a Shard on this Node, as the metadata server last assigned it
+    pub fn term(&self, shard: u32) -> Option<u64> {
+        let replica_set = self.replica_sets.get(&shard)?;
+        let term = replica_set.lock().map(|replica_set| replica_set.term).unwrap_or(0);
+        Some(term)
+    }
+
+    /// Returns true while a Replica on this Node is catching up with its Primary
+    pub fn is_recovering(&self, shard: u32) -> bool {
+        self.recovering.lock().map(|recovering| recovering.contains(&shard)).unwrap_or(false)
+    }
+
+    /// Returns the channels to every Shard's `Manager` on this Node, ordered by Shard number
+    pub fn shards(&self) -> Vec<mpsc::Sender<IndexCommand>> {
+        self.shards.values().cloned().collect()
+    }
+}
+
+/// Starts the `Manager`s of the Shards placed on this Node and keeps track of how to reach them
+pub struct IndexRegistry {
+    /// Root directory under which every Manager keeps its data
+    data_directory: PathBuf,
+    /// Indices with Shards on this Node, by name
+    indices: HashMap<String, LocalIndex>,
+}
+
+impl IndexRegistry {
+    /// Creates and returns an empty IndexRegistry
+    ///
+    /// # Arguments
+    ///
+    /// * `data_directory` - Root directory under which every Manager keeps its data
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::path::PathBuf;
+    /// use rpc::indices::IndexRegistry;
+    /// let registry = IndexRegistry::new(PathBuf::from("/tmp/saga"));
+    /// ```
+    pub fn new(data_directory: PathBuf) -> IndexRegistry {
+        IndexRegistry {
+            data_directory: data_directory,
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Starts a `Manager` for each of an Index's Shards placed on this Node. Shards that are
+    /// already running are left alone.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the Index
+    /// * `primary_shards` - Number of Primary Shards the Index has across the whole Cluster
+    /// * `shards` - Numbers of the Shards placed on this Node
+    pub fn open(&mut self, name: &str, primary_shards: u32, shards: &[u32]) -> io::Result<()> {
+        let data_directory = self.data_directory.clone();
+        let index = self.indices.entry(name.to_owned()).or_insert_with(|| LocalIndex {
+            primary_shards: primary_shards,
+            shards: BTreeMap::new(),
+            replicas: BTreeSet::new(),
+            replica_sets: BTreeMap::new(),
+            primary_addresses: BTreeMap::new(),
+            recovering: Arc::new(Mutex::new(BTreeSet::new())),
+        });
+        for &shard in shards {
+            if index.shards.contains_key(&shard) {
+                continue;
+            }
+            // Every Shard can send its writes to Replicas, so a Replica that is promoted to
+            // Primary doesn't need to be restarted
+            let replica_set = Arc::new(Mutex::new(ReplicaSet::default()));
+            let (tx, rx) = mpsc::channel();
+            let mut config = ManagerConfiguration::new(name, data_directory.clone());
+            config.shard = shard;
+            config.replication = Some(replication::start_replicator(name, shard, replica_set.clone()));
+            Manager::new(config, rx)?;
+            index.shards.insert(shard, tx);
+            index.replica_sets.insert(shard, replica_set);
+        }
+        Ok(())
+    }
+
+    /// Directory that snapshots of Shards are made in and copied to while Replicas recover
+    pub fn recovery_directory(&self) -> PathBuf {
+        self.data_directory.join("recovery")
+    }
+
+    /// Starts bringing a Replica on this Node up to date with its Primary, unless it already is
+    /// being or its Primary is unknown. Returns true if recovery was started.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the Index
+    /// * `shard` - Number of the Shard
+    pub fn recover(&self, name: &str, shard: u32) -> bool {
+        let index = match self.indices.get(name) {
+            Some(index) => index,
+            None => return false,
+        };
+        match (index.replica(shard), index.primary_addresses.get(&shard)) {
+            (Some(shard_tx), Some(address)) => recovery::start_recovery(
+                name,
+                shard,
+                address,
+                shard_tx,
+                self.recovery_directory(),
+                index.recovering.clone(),
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns the Shards of an Index running on this Node, if it has any
+    pub fn get(&self, name: &str) -> Option<&LocalIndex> {
+        self.indices.get(name)
+    }
+
+    /// Stops the `Manager` of each of an Index's Shards on this Node and waits until they have
+    /// closed their segments. Returns false if the Index had no Shards running here.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the Index
+    pub fn close(&mut self, name: &str) -> bool {
+        let index = match self.indices.remove(name) {
+            Some(index) => index,
+            None => return false,
+        };
+        stop_shards(name, index.shards.into_iter().collect());
+        true
+    }
+
+    /// Brings the Shards running on this Node in line with what the metadata server has placed
+    /// on it. Copies that are no longer placed here are stopped, though their data is kept, and
+    /// so are all the Shards of Indices that are closed or not in `assignments` at all. Only an
+    /// Index marked as deleted has its data removed. Replicas that are new here, or whose
+    /// Primary has moved or changed term, recover from their Primary.
+    ///
+    /// # Arguments
+    ///
+    /// * `assignments` - Indices in the Cluster, with the Shards placed on this Node
+    pub fn apply(&mut self, assignments: &[IndexAssignment]) -> io::Result<()> {
+        let unassigned: Vec<String> = self.indices
+            .keys()
+            .filter(|name| !assignments.iter().any(|assignment| &assignment.index.name == *name))
+            .cloned()
+            .collect();
+        for name in unassigned {
+            self.close(&name);
+        }
+
+        for assignment in assignments {
+            let name = &assignment.index.name;
+            if assignment.deleted {
+                println!("Index {} was deleted, removing its data", name);
+                self.delete(name)?;
+                continue;
+            }
+            if assignment.primaries.is_empty() && assignment.replicas.is_empty() {
+                self.close(name);
+                continue;
+            }
+            let mut previous_addresses = BTreeMap::new();
+            let mut previous_terms = BTreeMap::new();
+            if let Some(index) = self.indices.get_mut(name) {
+                previous_addresses = index.primary_addresses.clone();
+                for shard in index.shards.keys() {
+                    if let Some(term) = index.term(*shard) {
+                        previous_terms.insert(*shard, term);
+                    }
+                }
+                let moved: Vec<u32> = index.shards
+                    .keys()
+                    .filter(|shard| !assignment.primaries.contains(shard) && !assignment.replicas.contains(shard))
+                    .cloned()
+                    .collect();
+                let mut stopping = vec![];
+                for shard in moved {
+                    index.replica_sets.remove(&shard);
+                    if let Some(tx) = index.shards.remove(&shard) {
+                        stopping.push((shard, tx));
+                    }
+                }
+                stop_shards(name, stopping);
+            }
+            self.open(name, assignment.index.primary_shards, &assignment.primaries)?;
+            self.open(name, assignment.index.primary_shards, &assignment.replicas)?;
+            if let Some(index) = self.indices.get_mut(name) {
+                index.replicas = assignment.replicas.iter().cloned().collect();
+                for (shard, replica_set) in &index.replica_sets {
+                    let targets = match assignment.replica_targets.get(shard) {
+                        Some(targets) if !index.replicas.contains(shard) => targets.clone(),
+                        _ => vec![],
+                    };
+                    if let Ok(mut replica_set) = replica_set.lock() {
+                        replica_set.copies = assignment.index.replica_shards as usize + 1;
+                        replica_set.targets = targets;
+                        if let Some(&term) = assignment.terms.get(shard) {
+                            replica_set.term = term;
+                        }
+                    }
+                    if let (Some(&term), Some(shard_tx)) = (assignment.terms.get(shard), index.shards.get(shard)) {
+                        let _ = shard_tx.send(IndexCommand::SetTerm { term: term });
+                    }
+                }
+                index.primary_addresses = assignment.primary_addresses.clone();
+            }
+            // A Replica checks its OpLog against its Primary's whenever the Primary moves or a new
+            // one takes over, in case it took writes the new Primary never had
+            for (&shard, address) in &assignment.primary_addresses {
+                let term_changed = match (previous_terms.get(&shard), assignment.terms.get(&shard)) {
+                    (Some(previous), Some(term)) => previous != term,
+                    _ => false,
+                };
+                if previous_addresses.get(&shard) != Some(address) || term_changed {
+                    self.recover(name, shard);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops the Shards of every Index on this Node, waiting until they have closed their
+    /// segments
+    pub fn close_all(&mut self) {
+        let names: Vec<String> = self.indices.keys().cloned().collect();
+        for name in names {
+            self.close(&name);
+        }
+    }
+
+    /// Stops an Index's Shards on this Node and removes all of its data from disk
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the Index
+    pub fn delete(&mut self, name: &str) -> io::Result<()> {
+        self.close(name);
+        let index_directory = self.data_directory.join("indices").join(name);
+        if index_directory.exists() {
+            fs::remove_dir_all(index_directory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shuts down the `Manager` of each Shard and waits until they have closed their segments
+fn stop_shards(name: &str, shards: Vec<(u32, mpsc::Sender<IndexCommand>)>) {
+    let mut stopping = vec![];
+    for (shard, tx) in shards {
+        let (response_tx, response_rx) = mpsc::channel();
+        if tx.send(IndexCommand::Shutdown { response_channel: response_tx }).is_ok() {
+            stopping.push((shard, response_rx));
+        }
+    }
+    for (shard, response_rx) in stopping {
+        if response_rx.recv().is_err() {
+            println!("Shard {} of index {} stopped before it could be shut down", shard, name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_open_index() {
+        let mut registry = IndexRegistry::new(PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4())));
+        assert!(registry.get("logs").is_none());
+        registry.open("logs", 3, &[0, 2]).unwrap();
+        registry.open("logs", 3, &[2]).unwrap();
+
+        let index = registry.get("logs").unwrap();
+        assert_eq!(index.primary_shards(), 3);
+        assert_eq!(index.shards().len(), 2);
+        for tx in index.shards() {
+            let (ready_tx, ready_rx) = mpsc::channel();
+            tx.send(IndexCommand::Ready { response_channel: ready_tx }).unwrap();
+            assert!(ready_rx.recv().unwrap());
+        }
+        let (shard, tx) = index.shard_for(5);
+        assert_eq!(tx.is_some(), shard != 1);
+    }
+
+    #[test]
+    fn test_apply_assignments() {
+        use db::{IndexDefinition, IndexState};
+        use replication::ReplicaTarget;
+
+        let data_directory = PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4()));
+        let mut registry = IndexRegistry::new(data_directory.clone());
+        registry.open("metrics", 1, &[0]).unwrap();
+        registry.open("events", 1, &[0]).unwrap();
+        registry.open("traces", 1, &[0]).unwrap();
+        registry.close("traces");
+        let events = IndexAssignment {
+            index: IndexDefinition {
+                name: String::from("events"),
+                primary_shards: 1,
+                replica_shards: 0,
+                state: IndexState::Closed,
+            },
+            primaries: vec![],
+            replicas: vec![],
+            replica_targets: BTreeMap::new(),
+            terms: BTreeMap::new(),
+            primary_addresses: BTreeMap::new(),
+            deleted: false,
+        };
+        let logs = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 3,
+            replica_shards: 1,
+            state: IndexState::Open,
+        };
+        let mut assignment = IndexAssignment {
+            index: logs,
+            primaries: vec![0],
+            replicas: vec![1],
+            replica_targets: BTreeMap::new(),
+            terms: BTreeMap::new(),
+            primary_addresses: BTreeMap::new(),
+            deleted: false,
+        };
+        assignment.replica_targets.insert(0, vec![ReplicaTarget {
+            node: String::from("node02"),
+            address: String::from("10.0.0.2:3001"),
+        }]);
+        let mut traces = events.clone();
+        traces.index.name = String::from("traces");
+        traces.deleted = true;
+        registry.apply(&[assignment.clone(), events.clone(), traces]).unwrap();
+        // Closed and unlisted Indices are stopped but keep their data, while one marked as
+        // deleted loses it whether it was running or not
+        assert!(registry.get("events").is_none());
+        assert!(data_directory.join("indices").join("events").exists());
+        assert!(registry.get("metrics").is_none());
+        assert!(data_directory.join("indices").join("metrics").exists());
+        assert!(!data_directory.join("indices").join("traces").exists());
+        let index = registry.get("logs").unwrap().clone();
+        assert_eq!(index.shards().len(), 2);
+        assert!(index.is_replica(1) && !index.is_replica(0));
+        assert!(index.replica(1).is_some() && index.replica(0).is_none());
+        assert_eq!(index.replica_sets[&0].lock().unwrap().targets.len(), 1);
+        assert_eq!(index.replica_sets[&0].lock().unwrap().copies, 2);
+        assert!(index.replica_sets[&1].lock().unwrap().targets.is_empty());
+        assert_eq!(index.term(1), Some(1));
+        // Writes only go to a Primary
+        let id = (0..).find(|id| shard_for(*id, 3) == 1).unwrap();
+        assert!(index.shard_for(id).1.is_none());
+
+        // The Replica is promoted and Shard 0 moves away
+        assignment.primaries = vec![1];
+        assignment.replicas = vec![];
+        assignment.terms.insert(1, 2);
+        registry.apply(&[assignment, events]).unwrap();
+        assert!(data_directory.join("indices").join("events").exists());
+        let index = registry.get("logs").unwrap();
+        assert_eq!(index.shards().len(), 1);
+        assert!(index.shard_for(id).1.is_some());
+        assert_eq!((index.term(1), index.term(0)), (Some(2), None));
+    }
+
+    #[test]
+    fn test_close_and_delete_index() {
+        let data_directory = PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4()));
+        let mut registry = IndexRegistry::new(data_directory.clone());
+        registry.open("logs", 2, &[0, 1]).unwrap();
+        let shards = registry.get("logs").unwrap().shards();
+
+        assert!(registry.close("logs"));
+        assert!(!registry.close("logs"));
+        assert!(registry.get("logs").is_none());
+        let (ready_tx, _ready_rx) = mpsc::channel();
+        assert!(shards[0].send(IndexCommand::Ready { response_channel: ready_tx }).is_err());
+
+        registry.open("logs", 2, &[0, 1]).unwrap();
+        registry.open("metrics", 1, &[0]).unwrap();
+        registry.close_all();
+        assert!(registry.get("logs").is_none() && registry.get("metrics").is_none());
+
+        registry.open("logs", 2, &[0, 1]).unwrap();
+        assert!(data_directory.join("indices").join("logs").exists());
+        registry.delete("logs").unwrap();
+        assert!(!data_directory.join("indices").join("logs").exists());
+        assert!(registry.get("logs").is_none());
+    }
+}
@@ -1,15 +1,35 @@
+extern crate inverted_index;
 extern crate rusqlite;
 extern crate uuid;
 extern crate serde;
 extern crate serde_json;
+extern crate base64;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod allocator;
+pub mod client;
+pub mod codec;
+pub mod drain;
+pub mod heartbeat;
 pub mod messages;
 pub mod node;
+pub mod raft;
 pub mod db;
+pub mod indices;
+pub mod recovery;
+pub mod replication;
 
 use std::sync::{Arc,Mutex,mpsc};
+use std::time::Duration;
+
+use inverted_index::replication::WriteConsistency;
+
+use drain::WriteGate;
+use indices::IndexRegistry;
+
+/// How long a web request waits by default before the client gets a 504
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// Cluster represents a collection of Nodes
 pub struct Cluster {
@@ -29,12 +49,25 @@ impl Cluster {
 /// Struct that holds Channels to various entities. 
 pub struct Switchboard {
     pub node_tx: Arc<Mutex<mpsc::Sender<messages::Message>>>,
+    /// The Indices with Shards on this Node
+    pub indices: IndexRegistry,
+    /// Writes in progress on this Node, which it waits for before leaving the Cluster
+    pub writes: Arc<WriteGate>,
+    /// How many copies of a Shard must have a write before the client is answered, unless the
+    /// client asks for something else
+    pub write_consistency: WriteConsistency,
+    /// How long a web request waits on this Node and its Shards before the client gets a 504
+    pub request_timeout: Duration,
 }
 
 impl Switchboard {
-    pub fn new(node_tx: Arc<Mutex<mpsc::Sender<messages::Message>>>) -> Switchboard {
+    pub fn new(node_tx: Arc<Mutex<mpsc::Sender<messages::Message>>>, indices: IndexRegistry) -> Switchboard {
         Switchboard{
             node_tx: node_tx,
+            indices: indices,
+            writes: Arc::new(WriteGate::new()),
+            write_consistency: WriteConsistency::One,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
         }
     }
 }
\ No newline at end of file
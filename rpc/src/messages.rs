@@ -4,12 +4,87 @@ use serde_json;
 use uuid::Uuid;
 use std::sync::mpsc;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// Second argument of an ERROR reply when the request itself was at fault
+pub const ERROR_INVALID: &'static str = "invalid";
+/// Second argument of an ERROR reply when the request clashes with something that already exists
+pub const ERROR_CONFLICT: &'static str = "conflict";
+/// Second argument of an ERROR reply when the Cluster can't carry out the request for now, such
+/// as while there is no metadata leader
+pub const ERROR_UNAVAILABLE: &'static str = "unavailable";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum MessageType {
     HEARTBEAT,
     REGISTER,
+    /// Tells the metadata server that the Node named by the only argument is leaving the
+    /// Cluster. The reply lists the IDs of the Primary Shards that were placed on it.
+    DEREGISTER,
     LIST_NODES,
     SHUTDOWN,
+    /// Creates an Index. Arguments are the name, number of Primary Shards and number of Replicas.
+    /// The reply lists the numbers of the Shards placed on the Node that created it.
+    CREATE_INDEX,
+    /// Lists every Index. The reply has the definition of each as JSON.
+    LIST_INDICES,
+    /// Describes the Index named by the only argument. This and the other requests about a single
+    /// Index are answered with its definition as JSON, or with no arguments if there is no such
+    /// Index.
+    GET_INDEX,
+    /// Opens the Index named by the only argument. The reply's definition is followed by the
+    /// numbers of the Shards placed on the Node that opened it.
+    OPEN_INDEX,
+    /// Closes the Index named by the only argument
+    CLOSE_INDEX,
+    /// Deletes the Index named by the only argument
+    DELETE_INDEX,
+    /// Tells a Node which copies of Shards it should be running. Each argument is an
+    /// `IndexAssignment` as JSON. Every Index in the Cluster is listed, and an Index that has just
+    /// been deleted is listed once more, marked as deleted, so the Node removes its data.
+    ASSIGN_SHARDS,
+    /// Applies a write to a Replica. Arguments are the name of the Index, the number of the Shard,
+    /// the `Operation` as JSON and the term of the Primary that sent it. The reply has no
+    /// arguments once the write is applied.
+    REPLICATE,
+    /// Asks a Node how far its copy of a Shard has got. Arguments are the name of the Index and
+    /// the number of the Shard. The reply's only argument is the sequence number of the last
+    /// `Operation` the copy has.
+    LAST_SEQUENCE,
+    /// Asks the Node holding a Primary Shard for a snapshot of it to start a new Replica from.
+    /// Arguments are the name of the Index and the number of the Shard. The reply's arguments are
+    /// the snapshot's ID, the sequence number of the last `Operation` in it, and the names of its
+    /// files.
+    RECOVER,
+    /// Reads part of a file in a snapshot. Arguments are the snapshot's ID, the name of the file
+    /// and the offset to read from. The reply's only argument is up to `RECOVERY_CHUNK_SIZE` bytes
+    /// of the file, base64 encoded, and is empty once the end of the file is reached.
+    RECOVER_CHUNK,
+    /// Tells the Node a snapshot was made on that it is no longer needed. The only argument is
+    /// the snapshot's ID. Snapshots nobody says this about are removed after `SNAPSHOT_EXPIRY`.
+    RECOVER_DONE,
+    /// Asks the Node holding a Primary Shard for the Operations a Replica is missing. Arguments
+    /// are the name of the Index, the number of the Shard and the sequence number of the last
+    /// `Operation` the Replica has. The reply has up to `RECOVERY_BATCH_SIZE` of the ones after
+    /// it, each as JSON, stopping before they add up to more than `RECOVERY_BATCH_BYTES`, and
+    /// none once the Replica has caught up.
+    RECOVER_OPERATIONS,
+    /// Asks another metadata Node for its vote. Arguments are the candidate's term, its name, and
+    /// the index and term of the last entry in its Raft log. The reply's arguments are the term
+    /// of the Node asked and whether it voted for the candidate.
+    RAFT_REQUEST_VOTE,
+    /// Sends entries of the Raft log to another metadata Node, or none as a heartbeat. Arguments
+    /// are the leader's term, its name, the index and term of the entry before the ones sent, the
+    /// index of the last committed entry and then each `LogEntry` as JSON. The reply's arguments
+    /// are the term of the Node it was sent to, whether the entries were added, and how far its
+    /// log matches the leader's.
+    RAFT_APPEND_ENTRIES,
+    /// Asks a metadata Node which Node is leading the metadata Nodes. The reply's arguments are
+    /// the leader's name and the address of its RPC server, or there are none if no leader is
+    /// known.
+    METADATA_LEADER,
+    /// Reply to a request that failed, with what went wrong as its first argument. The second
+    /// argument, if there is one, is `ERROR_INVALID`, `ERROR_CONFLICT` or `ERROR_UNAVAILABLE`.
+    /// Without one, the Node that answered failed.
+    ERROR,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -18,7 +93,10 @@ pub struct Message {
     pub message_id: Uuid,
     pub creation_time: time::SystemTime,
     pub args: Vec<String>,
-    
+    /// ID of the Message this one answers, if it is a reply
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+
     #[serde(skip_serializing, skip_deserializing)]
     pub response_chan: Option<mpsc::Sender<Message>>
 }
@@ -30,6 +108,7 @@ impl Message {
             message_id: Uuid::new_v4(),
             creation_time: time::SystemTime::now(),
             args: vec![],
+            reply_to: None,
             response_chan: None
         }
     }
@@ -49,8 +128,28 @@ impl Message {
         self
     }
 
+    /// Marks the Message as the reply to the Message with the given ID
+    pub fn reply_to(mut self, id: Uuid) -> Message {
+        self.reply_to = Some(id);
+        self
+    }
+
+    /// Sends a reply to this Message down its response channel, marked so whoever sent this
+    /// Message can match the two up. Returns false if nobody is waiting for a reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The reply
+    pub fn respond(&self, response: Message) -> bool {
+        match self.response_chan {
+            Some(ref chan) => chan.send(response.reply_to(self.message_id)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Serializes the Message into compact JSON, ready to be framed by `codec::write_frame`
     pub fn to_vec(&self) -> Result<Vec<u8>, serde_json::Error> {
-        serde_json::to_vec_pretty(&self)
+        serde_json::to_vec(&self)
     }
 }
 
@@ -69,7 +168,20 @@ mod tests {
     fn test_message_to_vec() {
         let msg = Message::new(MessageType::HEARTBEAT);
         let serialized_msg = msg.to_vec().unwrap();
-        assert_eq!(serialized_msg.len(), 200);
+        assert!(!serialized_msg.contains(&b'\n'));
+        let deserialized: Message = serde_json::from_slice(&serialized_msg).unwrap();
+        assert_eq!(deserialized.message_id, msg.message_id);
+    }
+
+    #[test]
+    fn test_respond() {
+        let msg = Message::new(MessageType::LIST_NODES);
+        assert!(!msg.respond(Message::new(MessageType::LIST_NODES)));
+
+        let (tx, rx) = mpsc::channel();
+        let msg = msg.response_chan(tx);
+        assert!(msg.respond(Message::new(MessageType::LIST_NODES)));
+        assert_eq!(rx.recv().unwrap().reply_to, Some(msg.message_id));
     }
 
 }
\ No newline at end of file
@@ -1,23 +1,52 @@
 
 use std;
-use std::io::{Read,Write};
+use std::collections::{BTreeMap, HashMap};
 use std::net::{TcpListener, TcpStream};
 use std::{thread, fs};
+use std::path::Path;
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 use std::sync::{Arc, Mutex, mpsc};
 
+use rusqlite;
 use rusqlite::Connection;
 use serde_json;
 
-use messages::{Message, MessageType};
-use db::MetadataDB;
+use inverted_index::manager::IndexCommand;
+use inverted_index::replication::Operation;
+
+use Switchboard;
+use allocator::{Allocator, IndexAssignment};
+use client::{RpcClient, DEFAULT_RPC_TIMEOUT};
+use codec::{self, CodecError};
+use messages::{Message, MessageType, ERROR_CONFLICT, ERROR_INVALID, ERROR_UNAVAILABLE};
+use db::{IndexDefinition, IndexState, MetadataCommand, MetadataDB, NodeState, NodeStatus};
+use heartbeat::{self, HeartbeatConfiguration};
+use raft::{self, LogEntry, RaftConfiguration, RaftError, RaftHandle, RaftPeer, RaftStorage};
+use raft::{DEFAULT_PROPOSAL_TIMEOUT, RAFT_DB_FILENAME};
+use recovery::{self, last_sequence, RecoveryError};
+
+/// Name of the metadata database file within a Node's data path
+pub const METADATA_DB_FILENAME: &'static str = "metadata.db";
 
 /// Node is an individual server within a Cluster
 pub struct Node {
     pub config: NodeConfiguration,
     pub db: Connection,
-    pub metadata_connection: Option<TcpStream>
+    pub metadata_connection: Option<RpcClient>,
+    /// Where this Node's Shards are started and stopped. Set once the `Switchboard` exists.
+    pub switchboard: Option<Arc<Mutex<Switchboard>>>,
+    /// Connections to the other Nodes the metadata server sends Shard assignments to, by name
+    peers: HashMap<String, RpcClient>,
+    /// This Node's part in Raft, if it is one of several metadata Nodes
+    pub raft: Option<RaftHandle>,
+    /// Entries of the Raft log that have been committed, waiting to be applied to `db`
+    committed: Option<mpsc::Receiver<LogEntry>>,
+    /// Whether this Node had taken over as metadata leader when it last checked
+    leading: bool,
+    /// Address of the metadata leader `metadata_connection` is connected to
+    metadata_leader: Option<String>,
 }
 
 /// Contains the configuration data for creating a new Node
@@ -39,12 +68,18 @@ pub struct NodeConfiguration {
     /// The address that this node will listen on for RPC connections
     pub rpc_address: String,
     /// The port that this node will listen on for RPC connections
-    pub rpc_port: u16
+    pub rpc_port: u16,
+    /// How often heartbeats are sent and how long the metadata server waits for them
+    pub heartbeat: HeartbeatConfiguration,
+    /// The metadata Nodes that keep the Cluster's metadata in step with Raft, this one included
+    /// if it is one of them. Empty if there is a single metadata server.
+    pub metadata_nodes: Vec<RaftPeer>
 }
 
 
 impl Node {
-    /// Returns a new Node
+    /// Returns a new Node, with its metadata database opened in `data_path`. The Err says why the
+    /// database could not be opened, as a Node without it would forget the Cluster's metadata.
     /// 
     /// # Arguments
     /// * `config` - A NodeConfiguration struct
@@ -54,59 +89,63 @@ impl Node {
     /// ```
     ///  use rpc::node::{Node, NodeConfiguration};
     ///  use rpc::messages::Message;
+    ///  use rpc::heartbeat::HeartbeatConfiguration;
     ///  use std::sync::{Arc, mpsc, Mutex};
     ///  let (_my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
     ///  let new_config = NodeConfiguration {
+    ///    name: String::from("node01"),
     ///    metadata_address: String::from("localhost"),
-    ///    data_path: String::from("memory"),
+    ///    data_path: String::from("/tmp/saga_doc_node"),
     ///    metadata_port: 5000,
     ///    am_metadata_server: true,
-    ///    rx: Arc::new(Mutex::new(my_node_rx))
+    ///    rx: Arc::new(Mutex::new(my_node_rx)),
+    ///    rpc_address: String::from("localhost"),
+    ///    rpc_port: 5001,
+    ///    heartbeat: HeartbeatConfiguration::default(),
+    ///    metadata_nodes: vec![]
     ///  };
-    /// let _new_node = Node::new(new_config);
+    /// let _new_node = Node::new(new_config).unwrap();
+    /// std::fs::remove_dir_all("/tmp/saga_doc_node").unwrap();
     /// ```
-    pub fn new(config: NodeConfiguration) -> Node {
-        let result = fs::create_dir_all(&config.data_path);
-        let metadata_db: Connection;
-        if result.is_err() {
-            println!("Unable to create the directory for the metadata database. It will be created in memory.");
-            metadata_db = Connection::open_in_memory().unwrap();
-        } else {
-            metadata_db = match Connection::open(&config.data_path) {
-                Ok(conn) => {
-                    conn
-                },
-                Err(e) => {
-                    println!("There was an error opening the on-disk database: {:?}. It will be created in memory.", e);
-                   Connection::open_in_memory().unwrap()
-                }
-            };
-        }
-
-        let metadata_connection: Option<TcpStream>;
-
-        // TODO: This should be factored out into a function
-        if config.am_metadata_server {
-            metadata_connection = None;
-
-        } else {
-            match TcpStream::connect(config.metadata_address.clone() + ":" + &config.metadata_port.to_string()) {
-                Ok(conn) => {
-                    println!("Connected to metadata server at: {}:{}", config.metadata_address, &config.metadata_port);
-                    metadata_connection = Some(conn);
-                },
-                Err(e) => {
-                    println!("There was an error connecting to the metadata server: {:?}", e);
-                    metadata_connection = None;
-                },
-            };
+    pub fn new(config: NodeConfiguration) -> Result<Node, NodeError> {
+        if let Err(e) = fs::create_dir_all(&config.data_path) {
+            return Err(NodeError::new(&format!("Could not create {} for the metadata database: {}", config.data_path, e)));
         }
+        let metadata_db = Connection::open(Path::new(&config.data_path).join(METADATA_DB_FILENAME))?;
 
-        Node {
+        let am_metadata_server = config.am_metadata_server;
+        let mut node = Node {
             config: config,
             db: metadata_db,
-            metadata_connection: metadata_connection,
+            metadata_connection: None,
+            switchboard: None,
+            peers: HashMap::new(),
+            raft: None,
+            committed: None,
+            leading: false,
+            metadata_leader: None,
+        };
+        if !am_metadata_server {
+            node.metadata_client();
         }
+        Ok(node)
+    }
+
+    /// Starts taking part in Raft with the other metadata Nodes, if there are any. From then on
+    /// this Node's metadata database is changed by applying the committed entries of the Raft
+    /// log, starting after the last one it applied before it stopped.
+    pub fn start_raft(&mut self) -> Result<(), NodeError> {
+        if !self.config.am_metadata_server || self.config.metadata_nodes.is_empty() {
+            return Ok(());
+        }
+        MetadataDB::create_applied_table(&self.db);
+        let storage = RaftStorage::open(&Path::new(&self.config.data_path).join(RAFT_DB_FILENAME))?;
+        let address = format!("{}:{}", self.config.rpc_address, self.config.rpc_port);
+        let config = RaftConfiguration::new(&self.config.name, &address, &self.config.metadata_nodes);
+        let (raft, committed) = raft::start(config, storage, MetadataDB::applied_index(&self.db)?)?;
+        self.raft = Some(raft);
+        self.committed = Some(committed);
+        Ok(())
     }
 
 
@@ -119,6 +158,8 @@ impl Node {
     /// * `bind_host` - The interface to which the server should bind
     /// * `bind_port` - The port to which the server should bind
     /// * `tx` - The Channel down which the server sends decoded `Messages`
+    /// * `raft` - This Node's part in Raft, if it is a metadata Node. Raft's own messages go
+    ///   straight to it, so they are never held up behind slower requests to the Node.
     ///
     /// # Example
     ///
@@ -128,9 +169,9 @@ impl Node {
     ///  use std::sync::{Arc, Mutex, mpsc};
     ///  let (_tx, _rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
     ///  // TODO: Figure out how to actually test this
-    ///  //let rpc_server = Node::start_rpc_server(String::from("127.0.0.1"), 5001, Arc::new(Mutex::new(tx)));
+    ///  //let rpc_server = Node::start_rpc_server(String::from("127.0.0.1"), 5001, Arc::new(Mutex::new(tx)), None);
     ///```
-    pub fn start_rpc_server(bind_host: String, bind_port: u32, tx: Arc<Mutex<mpsc::Sender<Message>>>) {
+    pub fn start_rpc_server(bind_host: String, bind_port: u32, tx: Arc<Mutex<mpsc::Sender<Message>>>, raft: Option<RaftHandle>) {
         println!("Starting RPC server on {}:{}", bind_host, bind_port);
         let listener = TcpListener::bind(bind_host + ":" + &bind_port.to_string()).unwrap();
 
@@ -138,8 +179,9 @@ impl Node {
             match stream {
                 Ok(stream) => {
                     let new_tx = tx.clone();
+                    let new_raft = raft.clone();
                     thread::spawn(move || {
-                        Node::handle_client(stream, new_tx);
+                        Node::handle_client(stream, new_tx, new_raft);
                     });
                 }
                 Err(_) => {
@@ -149,46 +191,118 @@ impl Node {
         }
     }
 
-    fn handle_client(mut stream: TcpStream, tx: Arc<Mutex<mpsc::Sender<Message>>>) {
+    /// Returns the address of the metadata server's RPC server, as `host:port`
+    fn metadata_server_address(config: &NodeConfiguration) -> String {
+        config.metadata_address.clone() + ":" + &config.metadata_port.to_string()
+    }
+
+    /// Returns the addresses of the metadata Nodes, which are asked who the metadata leader is
+    fn metadata_seeds(config: &NodeConfiguration) -> Vec<String> {
+        let mut seeds = vec![Node::metadata_server_address(config)];
+        for member in &config.metadata_nodes {
+            if !seeds.contains(&member.address) {
+                seeds.push(member.address.clone());
+            }
+        }
+        seeds
+    }
+
+    /// Connects to the metadata server, returning None if it can't be reached
+    fn connect_to_metadata_server(address: &str) -> Option<RpcClient> {
+        match RpcClient::connect(address, DEFAULT_RPC_TIMEOUT) {
+            Ok(client) => {
+                println!("Connected to metadata server at: {}", address);
+                Some(client)
+            },
+            Err(e) => {
+                println!("There was an error connecting to the metadata server: {}", e);
+                None
+            },
+        }
+    }
+
+    /// Returns the connection to the metadata leader, first connecting again if the last one was
+    /// lost or another Node has been elected since. A metadata Node knows the leader from Raft,
+    /// and any other Node asks the metadata Nodes.
+    fn metadata_client(&mut self) -> Option<RpcClient> {
+        let elected = self.raft.as_ref().map(|raft| raft.leader().map(|leader| leader.address));
+        let closed = self.metadata_connection.as_ref().map_or(true, |client| client.is_closed());
+        let moved = elected.as_ref().map_or(false, |elected| *elected != self.metadata_leader);
+        if closed || moved {
+            self.metadata_leader = match elected {
+                Some(elected) => elected,
+                None => raft::find_leader(&Node::metadata_seeds(&self.config)),
+            };
+            self.metadata_connection = match self.metadata_leader {
+                Some(ref address) => Node::connect_to_metadata_server(address),
+                None => {
+                    println!("There is no metadata leader to connect to");
+                    None
+                },
+            };
+        }
+        self.metadata_connection.clone()
+    }
+
+    /// Reads framed `Messages` from another Node until it disconnects and passes each one on to
+    /// the Node's thread. Replies are written back on the same connection, marked with the ID of
+    /// the `Message` they answer, so the other Node can have many requests in flight at once.
+    fn handle_client(mut stream: TcpStream, tx: Arc<Mutex<mpsc::Sender<Message>>>, raft: Option<RaftHandle>) {
         println!("New client connecting");
+        let (reply_tx, reply_rx) = mpsc::channel::<Message>();
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(e) => {
+                println!("There was an error setting up a connection for replies: {}", e);
+                return;
+            },
+        };
+        thread::spawn(move || {
+            // Ends once the connection is closed and every request on it has been answered
+            for reply in reply_rx {
+                if let Err(e) = codec::write_message(&mut writer, &reply) {
+                    println!("There was an error writing a reply: {}", e);
+                    break;
+                }
+            }
+        });
+
         loop {
-            let mut read = [0; 1028];
-            match stream.read(&mut read) {
-                Ok(n) => {
-                    if n == 0 { 
-                        // connection was closed
+            let message = match codec::read_message(&mut stream) {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    // connection was closed
+                    break;
+                },
+                Err(e) => {
+                    // Once a frame is bad there is no telling where the next one starts
+                    println!("There was an error reading an incoming message, closing the connection: {}", e);
+                    break;
+                },
+            };
+            let message = message.response_chan(reply_tx.clone());
+            // A follower that waited behind the Node's other work would miss the leader's
+            // heartbeats and start an election
+            if let Some(ref raft) = raft {
+                match message.message_type {
+                    MessageType::RAFT_REQUEST_VOTE | MessageType::RAFT_APPEND_ENTRIES => {
+                        raft.handle(message);
+                        continue;
+                    },
+                    _ => {},
+                }
+            }
+            match tx.lock() {
+                Ok(l) => {
+                    if let Err(e) = l.send(message) {
+                        // The Node has stopped, so nothing on this connection will be answered
+                        println!("There was an error sending the deserialized message, closing the connection: {}", e);
                         break;
                     }
-                    let deserialized = serde_json::from_slice(&read[0..n]);
-                    match deserialized {
-                        Ok(message) => {
-                            match tx.lock() {
-                                Ok(l) => {
-                                    match l.send(message) {
-                                        Ok(_) => {
-                                            continue;
-                                        },
-                                        Err(e) => {
-                                            println!("There was an error sending the deserialized message: {}", e);
-                                            continue;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    println!("There was an error acquring lock on tx to send Message: {}", e);
-                                    continue;
-                                },
-                            }
-                        },
-                        Err(e) => {
-                            println!("There was an error deserializing an incoming message: {}", e);
-                            continue;
-                        },
-                    };
-                }
-                Err(err) => {
-                    panic!(err);
-                }
+                },
+                Err(e) => {
+                    println!("There was an error acquring lock on tx to send Message: {}", e);
+                },
             }
         }
     }
@@ -196,12 +310,33 @@ impl Node {
     /// Handles receiving a `Message`
     /// This is started in another thread in main, and it then loops in the background
     /// to receive messages on a channel.
+    /// The metadata leader also checks on the other Nodes every heartbeat interval. Any other
+    /// Node passes requests about the Cluster's metadata on to the leader.
     pub fn receive_message(mut self) {
         let rx_chan = self.config.rx.clone();
+        let interval = self.config.heartbeat.interval;
+        let mut last_check = Instant::now();
         loop {
+            if self.config.am_metadata_server {
+                self.apply_committed(0);
+                let leading = self.is_leader();
+                self.leading = if leading && !self.leading { self.take_leadership() } else { leading };
+                if self.leading && last_check.elapsed() >= interval {
+                    self.check_nodes();
+                    last_check = Instant::now();
+                }
+            }
             // TODO: These probably shouldn't just unwrap here
             let lock = rx_chan.lock().unwrap();
-            let msg = lock.recv().unwrap();
+            let msg = match lock.recv_timeout(interval) {
+                Ok(msg) => msg,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+            if Node::is_metadata_request(&msg.message_type) && !self.leading {
+                self.forward_to_leader(msg);
+                continue;
+            }
             match msg.message_type {
                 MessageType::HEARTBEAT => {
                     self.handle_heartbeat(&msg.args);
@@ -209,68 +344,948 @@ impl Node {
                 MessageType::REGISTER => {
                     self.handle_register(&msg.args);
                 },
+                MessageType::DEREGISTER => {
+                    let response = self.handle_deregister(&msg.args);
+                    msg.respond(response);
+                    continue;
+                },
                 MessageType::LIST_NODES => {
-                    let nodes = self.handle_list_nodes(&msg.args);
-                    let response = Message::new(MessageType::LIST_NODES).args(nodes);
-                    msg.response_chan.unwrap().send(response);
+                    let response = self.handle_list_nodes();
+                    msg.respond(response);
+                    continue;
+                },
+                MessageType::CREATE_INDEX
+                | MessageType::LIST_INDICES
+                | MessageType::GET_INDEX
+                | MessageType::OPEN_INDEX
+                | MessageType::CLOSE_INDEX
+                | MessageType::DELETE_INDEX => {
+                    let response = self.handle_index_request(&msg.message_type, &msg.args);
+                    msg.respond(response);
+                    continue;
+                },
+                MessageType::ASSIGN_SHARDS => {
+                    let response = self.handle_assign_shards(&msg.args);
+                    msg.respond(response);
+                    continue;
+                },
+                MessageType::REPLICATE => {
+                    self.handle_replicate(msg);
+                    continue;
+                },
+                MessageType::LAST_SEQUENCE => {
+                    self.handle_last_sequence(msg);
+                    continue;
+                },
+                MessageType::RECOVER
+                | MessageType::RECOVER_CHUNK
+                | MessageType::RECOVER_DONE
+                | MessageType::RECOVER_OPERATIONS => {
+                    self.handle_recovery(msg);
+                    continue;
+                },
+                MessageType::RAFT_REQUEST_VOTE | MessageType::RAFT_APPEND_ENTRIES => {
+                    match self.raft {
+                        Some(ref raft) => raft.handle(msg),
+                        None => {
+                            msg.respond(Message::new(MessageType::ERROR).arg(String::from("This node is not running Raft")));
+                        },
+                    }
+                    continue;
+                },
+                MessageType::METADATA_LEADER => {
+                    let response = self.handle_metadata_leader();
+                    msg.respond(response);
                     continue;
                 },
+                MessageType::ERROR => {
+                    println!("Received an error: {:?}", msg.args);
+                },
                 MessageType::SHUTDOWN => {
                     println!("Shutting down...");
+                    if let Some(ref raft) = self.raft {
+                        raft.shutdown();
+                    }
+                    if !self.config.am_metadata_server {
+                        match self.leave_cluster() {
+                            Ok(()) => println!("Left the cluster"),
+                            Err(e) => println!("There was an error leaving the cluster: {}", e),
+                        }
+                    }
+                    msg.respond(Message::new(MessageType::SHUTDOWN));
                     return;
                 },
             };
         }
     }
 
-    fn handle_list_nodes(&mut self, arguments: &Vec<String>) -> Vec<String> {
-        MetadataDB::list_nodes(&self.db)
+    /// Returns true for requests only the metadata leader can answer
+    fn is_metadata_request(message_type: &MessageType) -> bool {
+        matches!(
+            *message_type,
+            MessageType::HEARTBEAT
+                | MessageType::REGISTER
+                | MessageType::DEREGISTER
+                | MessageType::LIST_NODES
+                | MessageType::CREATE_INDEX
+                | MessageType::LIST_INDICES
+                | MessageType::GET_INDEX
+                | MessageType::OPEN_INDEX
+                | MessageType::CLOSE_INDEX
+                | MessageType::DELETE_INDEX
+        )
     }
 
-    fn handle_heartbeat(&mut self, arguments: &Vec<String>) {
+    /// Returns true if this Node looks after the Cluster's metadata, either as the only metadata
+    /// server or because the metadata Nodes elected it leader
+    fn is_leader(&self) -> bool {
+        self.config.am_metadata_server && self.raft.as_ref().map_or(true, |raft| raft.is_leader())
+    }
+
+    /// Takes over looking after the Cluster's metadata. Everything earlier leaders committed is
+    /// applied first, and every Node gets a full timeout to find this Node before it can be
+    /// declared dead. Returns false if this Node could not take over.
+    fn take_leadership(&mut self) -> bool {
+        if let Some(raft) = self.raft.clone() {
+            // Once an entry from this term is committed, so is everything before it
+            match raft.propose(String::new(), DEFAULT_PROPOSAL_TIMEOUT) {
+                Ok(index) => {
+                    self.apply_committed(index);
+                },
+                Err(e) => {
+                    println!("Could not take over as metadata leader: {}", e);
+                    return false;
+                },
+            }
+            println!("Took over as metadata leader");
+        }
+        if let Err(e) = MetadataDB::reset_heartbeats(&self.db, heartbeat::now_millis()) {
+            println!("There was an error resetting heartbeats: {}", e);
+        }
+        self.peers.clear();
+        self.register_self();
+        true
+    }
 
+    /// Makes a change to the Cluster's metadata. With Raft the change is only made once most of
+    /// the metadata Nodes have it, and only the leader can make one.
+    fn commit(&mut self, command: MetadataCommand) -> Result<(), NodeError> {
+        let raft = match self.raft {
+            Some(ref raft) => raft.clone(),
+            None => return MetadataDB::apply(&mut self.db, &command).map_err(NodeError::from),
+        };
+        let index = raft.propose(serde_json::to_string(&command)?, DEFAULT_PROPOSAL_TIMEOUT)?;
+        match self.apply_committed(index) {
+            Some(applied) => applied,
+            None => Err(NodeError::new(&format!("Entry {} of the Raft log was committed but never arrived", index))),
+        }
+    }
+
+    /// Applies the entries of the Raft log committed since last time to the metadata database.
+    /// Returns how applying the entry at `index` went, if it was one of them.
+    fn apply_committed(&mut self, index: u64) -> Option<Result<(), NodeError>> {
+        let mut result = None;
+        while let Some(Ok(entry)) = self.committed.as_ref().map(|committed| committed.try_recv()) {
+            let applied = self.apply_entry(&entry);
+            if let Err(ref e) = applied {
+                println!("Could not apply entry {} of the Raft log: {}", entry.index, e);
+            }
+            if entry.index == index {
+                result = Some(applied);
+            }
+        }
+        result
+    }
+
+    fn apply_entry(&mut self, entry: &LogEntry) -> Result<(), NodeError> {
+        // A new leader's first entry has nothing in it
+        if entry.command.is_empty() {
+            return MetadataDB::apply_entry(&mut self.db, entry.index, None).map_err(NodeError::from);
+        }
+        match serde_json::from_str::<MetadataCommand>(&entry.command) {
+            Ok(command) => MetadataDB::apply_entry(&mut self.db, entry.index, Some(&command)).map_err(NodeError::from),
+            Err(e) => {
+                MetadataDB::apply_entry(&mut self.db, entry.index, None)?;
+                Err(NodeError::from(e))
+            }
+        }
+    }
+
+    /// Answers who the metadata leader is, as far as this Node knows
+    fn handle_metadata_leader(&self) -> Message {
+        let leader = match self.raft {
+            Some(ref raft) => raft.leader(),
+            None if self.config.am_metadata_server => Some(RaftPeer {
+                name: self.config.name.clone(),
+                address: format!("{}:{}", self.config.rpc_address, self.config.rpc_port),
+            }),
+            None => None,
+        };
+        match leader {
+            Some(leader) => Message::new(MessageType::METADATA_LEADER).arg(leader.name).arg(leader.address),
+            None => Message::new(MessageType::METADATA_LEADER),
+        }
+    }
+
+    /// Passes a request about the Cluster's metadata on to the metadata leader. The reply is sent
+    /// back from another thread, so this Node isn't held up while the leader answers.
+    fn forward_to_leader(&mut self, msg: Message) {
+        if self.is_leader() {
+            msg.respond(Message::new(MessageType::ERROR)
+                .arg(String::from("This node is still taking over as metadata leader"))
+                .arg(String::from(ERROR_UNAVAILABLE)));
+            return;
+        }
+        let client = match self.metadata_client() {
+            Some(client) => client,
+            None => {
+                msg.respond(Message::new(MessageType::ERROR)
+                    .arg(String::from("There is no metadata leader to pass the request on to"))
+                    .arg(String::from(ERROR_UNAVAILABLE)));
+                return;
+            },
+        };
+        let request = Message::new(msg.message_type.clone()).args(msg.args.clone());
+        match msg.message_type {
+            MessageType::HEARTBEAT | MessageType::REGISTER => {
+                if let Err(e) = client.send(&request) {
+                    println!("Could not pass a {:?} on to the metadata leader: {}", msg.message_type, e);
+                }
+            },
+            _ => {
+                thread::spawn(move || {
+                    let response = match client.request(request) {
+                        Ok(reply) => Node::forwarded_reply(reply),
+                        Err(e) => Message::new(MessageType::ERROR)
+                            .arg(format!("Request to the metadata leader failed: {}", e))
+                            .arg(String::from(ERROR_UNAVAILABLE)),
+                    };
+                    msg.respond(response);
+                });
+            },
+        }
+    }
+
+    /// Drops the Shard numbers from the leader's reply to CREATE_INDEX or OPEN_INDEX, as they are
+    /// the leader's own. The Shards placed on this Node are sent to it in ASSIGN_SHARDS.
+    fn forwarded_reply(mut reply: Message) -> Message {
+        match reply.message_type {
+            MessageType::CREATE_INDEX => reply.args.clear(),
+            MessageType::OPEN_INDEX => reply.args.truncate(1),
+            _ => {},
+        }
+        reply
+    }
+
+    /// Sends a request on to the metadata server and returns its reply, or an ERROR `Message` if
+    /// there is none
+    fn ask_metadata_server(&mut self, message: Message) -> Message {
+        let result = match self.metadata_client() {
+            Some(client) => client.request(message),
+            None => {
+                return Message::new(MessageType::ERROR)
+                    .arg(String::from("Not connected to the metadata server"))
+                    .arg(String::from(ERROR_UNAVAILABLE))
+            },
+        };
+        match result {
+            Ok(reply) => reply,
+            Err(e) => Message::new(MessageType::ERROR)
+                .arg(format!("Request to the metadata server failed: {}", e))
+                .arg(String::from(ERROR_UNAVAILABLE)),
+        }
+    }
+
+    /// Lists the registered Nodes, each as JSON with its state
+    fn handle_list_nodes(&self) -> Message {
+        let nodes = match MetadataDB::list_nodes(&self.db) {
+            Ok(nodes) => nodes,
+            Err(e) => return Message::new(MessageType::ERROR).arg(format!("Could not read node metadata: {}", e)),
+        };
+        let mut response = Message::new(MessageType::LIST_NODES);
+        for node in nodes {
+            match serde_json::to_string(&node) {
+                Ok(serialized) => response = response.arg(serialized),
+                Err(e) => return Message::new(MessageType::ERROR).arg(e.to_string()),
+            }
+        }
+        response
+    }
+
+    /// Marks Nodes that have stopped sending heartbeats as suspect or dead, and fails over the
+    /// Shards of those that died. The metadata leader counts itself as heard from every time it
+    /// checks.
+    fn check_nodes(&mut self) {
+        let config = self.config.heartbeat;
+        if let Err(e) = MetadataDB::record_heartbeat(&self.db, &self.config.name, heartbeat::now_millis()) {
+            println!("There was an error recording our own heartbeat: {}", e);
+        }
+        let changed = match MetadataDB::node_state_changes(&self.db, &config, heartbeat::now_millis()) {
+            Ok(changed) => changed,
+            Err(e) => {
+                println!("There was an error checking on nodes: {}", e);
+                return;
+            },
+        };
+        let mut dead = vec![];
+        for node in changed {
+            let command = MetadataCommand::SetNodeState { name: node.name.clone(), state: node.state };
+            if let Err(e) = self.commit(command) {
+                println!("Could not record that node {} is {}: {}", node.name, node.state.as_str(), e);
+                continue;
+            }
+            println!("Node {} has not been heard from and is now {}", node.name, node.state.as_str());
+            if node.state == NodeState::Dead {
+                dead.push(node.name);
+            }
+        }
+        for name in dead {
+            self.fail_over(&name);
+        }
+    }
+
+    /// Takes over for a Node that has been declared dead. Each of its Primary Shards is handed to
+    /// whichever alive Replica has the most of its writes, and the Replicas it held are placed
+    /// elsewhere. A Shard with no Replica to promote stays where it is until the Node returns.
+    fn fail_over(&mut self, name: &str) {
+        self.peers.remove(name);
+        let mut changed = match self.promote_replicas(name) {
+            Ok(changed) => changed,
+            Err(e) => {
+                println!("There was an error promoting the replicas of node {}: {}", name, e);
+                vec![]
+            },
+        };
+        // Every promoted Replica left a place to fill, as did each Replica the Node held
+        changed.extend(self.place_unassigned_replicas());
+        self.push_assignments(&changed);
+    }
+
+    /// Promotes a Replica of each Primary Shard on a dead Node and releases the Replicas it held.
+    /// Each promoted Shard starts a new term, so writes that still arrive from the old Primary
+    /// are refused. Returns the names of the Nodes whose Shards changed.
+    fn promote_replicas(&mut self, name: &str) -> Result<Vec<String>, NodeError> {
+        // The Primaries of the Replicas it held have to stop sending it their writes
+        let mut changed = MetadataDB::primaries_replicated_to(&self.db, name)?;
+        self.commit(MetadataCommand::ReleaseReplicas { node: name.to_owned() })?;
+        for (shard_id, index, number) in MetadataDB::primaries_on_node(&self.db, name)? {
+            // The Replica that has applied the most Operations loses the fewest writes
+            let mut best: Option<(u64, String)> = None;
+            for target in MetadataDB::replica_targets(&self.db, &shard_id)? {
+                let node = match MetadataDB::get_node(&self.db, &target.node)? {
+                    Some(node) => node,
+                    None => continue,
+                };
+                if node.state != NodeState::Alive {
+                    continue;
+                }
+                match self.replica_sequence(&index, number, &node) {
+                    Ok(seq) => {
+                        if best.as_ref().map_or(true, |&(best_seq, _)| seq > best_seq) {
+                            best = Some((seq, node.name));
+                        }
+                    },
+                    Err(e) => {
+                        println!("Could not ask node {} how far its replica of shard {} has got: {}", node.name, shard_id, e);
+                    },
+                }
+            }
+            match best {
+                Some((seq, node)) => {
+                    self.commit(MetadataCommand::PromoteReplica { shard_id: shard_id.clone(), node: node.clone() })?;
+                    let term = MetadataDB::shard_term(&self.db, &shard_id)?;
+                    println!(
+                        "Promoted the replica of shard {} on node {} to primary in term {}, as of operation {}",
+                        shard_id, node, term, seq
+                    );
+                    changed.extend(MetadataDB::shard_holders(&self.db, &shard_id)?);
+                },
+                None => {
+                    println!("Shard {} was on node {} and has no replica to promote", shard_id, name);
+                },
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Asks the Node holding a Replica of a Shard for the sequence number of the last Operation
+    /// the Replica has
+    fn replica_sequence(&mut self, index: &str, shard: u32, node: &NodeStatus) -> Result<u64, NodeError> {
+        if node.name == self.config.name {
+            let shard_tx = self.local_shard(index, shard)?;
+            return last_sequence(&shard_tx).ok_or_else(|| NodeError::new("Shard stopped before it could answer"));
+        }
+        let client = self.peer_client(&node.name, &format!("{}:{}", node.host, node.port))?;
+        let message = Message::new(MessageType::LAST_SEQUENCE).arg(index.to_owned()).arg(shard.to_string());
+        let reply = client.request(message).map_err(|e| NodeError::new(&e.to_string()))?;
+        match reply.message_type {
+            MessageType::LAST_SEQUENCE => reply.args
+                .get(0)
+                .and_then(|seq| seq.parse().ok())
+                .ok_or_else(|| NodeError::new("Expected a sequence number")),
+            _ => Err(NodeError::new(&reply.args.join(", "))),
+        }
+    }
+
+    /// Answers a request about the Cluster's Indices, which only the metadata leader keeps track
+    /// of
+    fn handle_index_request(&mut self, message_type: &MessageType, arguments: &Vec<String>) -> Message {
+        match *message_type {
+            MessageType::CREATE_INDEX => self.handle_create_index(arguments),
+            MessageType::LIST_INDICES => match MetadataDB::list_indices(&self.db) {
+                Ok(indices) => {
+                    let mut response = Message::new(MessageType::LIST_INDICES);
+                    for index in indices {
+                        match serde_json::to_string(&index) {
+                            Ok(serialized) => response = response.arg(serialized),
+                            Err(e) => return Message::new(MessageType::ERROR).arg(e.to_string()),
+                        }
+                    }
+                    response
+                },
+                Err(e) => Message::new(MessageType::ERROR).arg(format!("Could not read index metadata: {}", e)),
+            },
+            _ => match arguments.get(0) {
+                Some(name) => match self.change_index(message_type, name) {
+                    Ok(response) => response,
+                    Err(e) => Message::new(MessageType::ERROR).arg(format!("Could not update index {}: {}", name, e)),
+                },
+                None => Message::new(MessageType::ERROR)
+                    .arg(String::from("Expected the name of an index"))
+                    .arg(String::from(ERROR_INVALID)),
+            },
+        }
+    }
+
+    /// Describes, opens, closes or deletes an Index and replies with its definition. The Nodes
+    /// holding its Shards are told to start or stop them.
+    fn change_index(&mut self, message_type: &MessageType, name: &str) -> Result<Message, NodeError> {
+        let mut index = match MetadataDB::get_index(&self.db, name)? {
+            Some(index) => index,
+            None => return Ok(Message::new(message_type.clone())),
+        };
+        let holders = MetadataDB::nodes_with_index(&self.db, name)?;
+        let mut shards = vec![];
+        match *message_type {
+            MessageType::OPEN_INDEX => {
+                self.commit(MetadataCommand::SetIndexState { name: name.to_owned(), state: IndexState::Open })?;
+                index.state = IndexState::Open;
+                shards = MetadataDB::shards_on_node(&self.db, name, &self.config.name)?;
+            },
+            MessageType::CLOSE_INDEX => {
+                self.commit(MetadataCommand::SetIndexState { name: name.to_owned(), state: IndexState::Closed })?;
+                index.state = IndexState::Closed;
+            },
+            MessageType::DELETE_INDEX => {
+                self.commit(MetadataCommand::DeleteIndex { name: name.to_owned() })?;
+            },
+            _ => {},
+        }
+        match *message_type {
+            MessageType::GET_INDEX => {},
+            MessageType::DELETE_INDEX => self.push_deletion(&holders, &index),
+            _ => self.push_assignments(&holders),
+        }
+        Ok(Message::new(message_type.clone())
+            .arg(serde_json::to_string(&index)?)
+            .args(shards.iter().map(|shard| shard.to_string()).collect()))
+    }
+
+    /// Records a new Index in the metadata database and places its Shards across the Cluster
+    fn handle_create_index(&mut self, arguments: &Vec<String>) -> Message {
+        let index = match (arguments.get(0), arguments.get(1), arguments.get(2)) {
+            (Some(name), Some(primary), Some(replica)) => match (primary.parse(), replica.parse()) {
+                (Ok(primary), Ok(replica)) => IndexDefinition {
+                    name: name.clone(),
+                    primary_shards: primary,
+                    replica_shards: replica,
+                    state: IndexState::Open,
+                },
+                _ => {
+                    return Message::new(MessageType::ERROR)
+                        .arg(String::from("Shard counts must be integers"))
+                        .arg(String::from(ERROR_INVALID))
+                },
+            },
+            _ => {
+                return Message::new(MessageType::ERROR)
+                    .arg(String::from("Expected a name and two shard counts"))
+                    .arg(String::from(ERROR_INVALID))
+            },
+        };
+
+        match MetadataDB::index_exists(&self.db, &index.name) {
+            Ok(false) => {},
+            Ok(true) => {
+                return Message::new(MessageType::ERROR)
+                    .arg(format!("Index {} already exists", index.name))
+                    .arg(String::from(ERROR_CONFLICT));
+            },
+            Err(e) => {
+                return Message::new(MessageType::ERROR).arg(format!("Could not read index metadata: {}", e));
+            },
+        }
+        let placements = match self.allocator() {
+            Ok(mut allocator) => allocator.place_index(&index),
+            Err(e) => return Message::new(MessageType::ERROR).arg(format!("Could not read node metadata: {}", e)),
+        };
+        let placements = match placements {
+            Some(placements) => placements,
+            None => {
+                return Message::new(MessageType::ERROR)
+                    .arg(String::from("There are no alive nodes to place shards on"))
+                    .arg(String::from(ERROR_UNAVAILABLE))
+            },
+        };
+        let command = MetadataCommand::CreateIndex {
+            index: index.clone(),
+            placements: placements.clone(),
+        };
+        if let Err(e) = self.commit(command) {
+            return Message::new(MessageType::ERROR).arg(format!("Could not record index {}: {}", index.name, e));
+        }
+        let mut holders = vec![];
+        for placement in placements {
+            holders.push(placement.primary);
+            holders.extend(placement.replicas.into_iter().flatten());
+        }
+        self.push_assignments(&holders);
+
+        match MetadataDB::shards_on_node(&self.db, &index.name, &self.config.name) {
+            Ok(shards) => Message::new(MessageType::CREATE_INDEX).args(
+                shards.iter().map(|shard| shard.to_string()).collect()
+            ),
+            Err(e) => Message::new(MessageType::ERROR).arg(format!("Could not read index metadata: {}", e)),
+        }
+    }
+
+    /// Records a heartbeat from the Node named by the first argument. The other arguments are
+    /// the address of its RPC server, which a Node that isn't registered yet is registered at.
+    fn handle_heartbeat(&mut self, arguments: &Vec<String>) {
+        let name = match arguments.get(0) {
+            Some(name) => name,
+            None => {
+                println!("Received a heartbeat without a node name");
+                return;
+            },
+        };
+        match MetadataDB::record_heartbeat(&self.db, name, heartbeat::now_millis()) {
+            Ok(Some(NodeState::Alive)) => {},
+            Ok(Some(NodeState::Left)) => {
+                println!("Ignoring a heartbeat from node {}, which has left the cluster", name);
+            },
+            Ok(Some(state)) => {
+                let command = MetadataCommand::SetNodeState { name: name.clone(), state: NodeState::Alive };
+                if let Err(e) = self.commit(command) {
+                    println!("Could not record that node {} is alive again: {}", name, e);
+                    return;
+                }
+                println!("Node {} was {} and is alive again", name, state.as_str());
+                // Its Shards may have been moved while it was dead, so it has to stop running them
+                if state == NodeState::Dead {
+                    self.peers.remove(name);
+                    self.push_assignments(&[name.clone()]);
+                }
+            },
+            Ok(None) if arguments.len() >= 3 => {
+                // It registered with an earlier leader, or its registration was lost
+                println!("Registering node {} from its heartbeat", name);
+                self.handle_register(arguments);
+            },
+            Ok(None) => {
+                println!("Received a heartbeat from unregistered node: {}", name);
+            },
+            Err(e) => {
+                println!("There was an error recording a heartbeat: {}", e);
+            },
+        }
     }
 
     fn handle_register(&mut self, arguments: &Vec<String>) {
         println!("Received a registration request from: {:?}", arguments);
-        match MetadataDB::register_node(&self.db, &arguments[0], &arguments[1], arguments[2].parse::<u16>().unwrap()) {
-            true => {
+        let port = arguments.get(2).and_then(|port| port.parse::<u16>().ok());
+        let (name, host, port) = match (arguments.get(0), arguments.get(1), port) {
+            (Some(name), Some(host), Some(port)) => (name, host, port),
+            _ => {
+                println!("Invalid registration request, expected a name, host and port");
+                return;
+            },
+        };
+        let command = MetadataCommand::RegisterNode {
+            name: name.clone(),
+            host: host.clone(),
+            port: port,
+            now: heartbeat::now_millis(),
+        };
+        match self.commit(command) {
+            Ok(()) => {
                 println!("Node registered!");
             },
-            false => {
-                println!("Node failed to register!");
+            Err(e) => {
+                println!("Node failed to register: {}", e);
+                return;
             }
         }
+        // The Node may be on a new address, and it may be able to take Replicas nobody else could
+        self.peers.remove(name);
+        let mut changed = self.place_unassigned_replicas();
+        changed.push(name.clone());
+        self.push_assignments(&changed);
+    }
+
+    /// Registers the metadata leader in its own list of Nodes, so Shards can be placed on it too
+    pub fn register_self(&mut self) {
+        let command = MetadataCommand::RegisterNode {
+            name: self.config.name.clone(),
+            host: self.config.rpc_address.clone(),
+            port: self.config.rpc_port,
+            now: heartbeat::now_millis(),
+        };
+        if let Err(e) = self.commit(command) {
+            println!("Could not register the metadata leader as a node: {}", e);
+            return;
+        }
+        let changed = self.place_unassigned_replicas();
+        self.push_assignments(&changed);
+    }
+
+    /// Returns an Allocator that knows every registered Node and how many Shard copies each holds
+    fn allocator(&self) -> Result<Allocator, rusqlite::Error> {
+        Ok(Allocator::new(MetadataDB::list_nodes(&self.db)?, MetadataDB::shard_load(&self.db)?))
+    }
+
+    /// Places as many unassigned Replicas as the alive Nodes allow. Returns the names of the Nodes
+    /// that were given one, along with those already holding a copy of the same Shard.
+    fn place_unassigned_replicas(&mut self) -> Vec<String> {
+        let mut changed = vec![];
+        let placed: Result<(), NodeError> = (|| {
+            let mut allocator = self.allocator()?;
+            for (replica_id, shard_id) in MetadataDB::unassigned_replicas(&self.db)? {
+                let holders = MetadataDB::shard_holders(&self.db, &shard_id)?;
+                if let Some(node) = allocator.place_replica(&holders) {
+                    self.commit(MetadataCommand::AssignReplica { replica_id: replica_id.clone(), node: node.clone() })?;
+                    println!("Placed replica {} on node {}", replica_id, node);
+                    changed.push(node);
+                    // The Primary has a new Replica to send its writes to
+                    changed.extend(holders);
+                }
+            }
+            Ok(())
+        })();
+        if let Err(e) = placed {
+            println!("There was an error placing replicas: {}", e);
+        }
+        changed
+    }
+
+    /// Sends each Node the full list of Shard copies it should be running. The metadata server
+    /// applies its own directly. Nodes that are not alive are skipped, as they are sent theirs
+    /// when they register again.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - Names of the Nodes whose Shards changed. Repeats are sent once.
+    fn push_assignments(&mut self, nodes: &[String]) {
+        self.push_assignments_with(nodes, None);
+    }
+
+    /// Sends each Node its Shard copies as `push_assignments` does, along with an Index that has
+    /// just been deleted, marked as such so the Nodes that held it remove its data. It is the
+    /// only way a Node learns to remove an Index's data.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - Names of the Nodes that held the Index's Shards
+    /// * `deleted` - Definition of the deleted Index
+    fn push_deletion(&mut self, nodes: &[String], deleted: &IndexDefinition) {
+        self.push_assignments_with(nodes, Some(deleted));
+    }
+
+    fn push_assignments_with(&mut self, nodes: &[String], deleted: Option<&IndexDefinition>) {
+        let mut sent: Vec<&String> = vec![];
+        for name in nodes {
+            if sent.contains(&name) {
+                continue;
+            }
+            sent.push(name);
+            if let Err(e) = self.push_assignment(name, deleted) {
+                println!("Could not send shard assignments to node {}: {}", name, e);
+            }
+        }
+    }
+
+    fn push_assignment(&mut self, name: &str, deleted: Option<&IndexDefinition>) -> Result<(), NodeError> {
+        let mut assignments = MetadataDB::assignments_for_node(&self.db, name)?;
+        if let Some(index) = deleted {
+            assignments.push(IndexAssignment {
+                index: index.clone(),
+                primaries: vec![],
+                replicas: vec![],
+                replica_targets: BTreeMap::new(),
+                terms: BTreeMap::new(),
+                primary_addresses: BTreeMap::new(),
+                deleted: true,
+            });
+        }
+        if name == self.config.name {
+            return self.apply_assignments(&assignments);
+        }
+        let node = match MetadataDB::get_node(&self.db, name)? {
+            Some(ref node) if node.state == NodeState::Alive => node.clone(),
+            _ => return Ok(()),
+        };
+        let mut message = Message::new(MessageType::ASSIGN_SHARDS);
+        for assignment in &assignments {
+            message = message.arg(serde_json::to_string(assignment)?);
+        }
+        let client = self.peer_client(name, &format!("{}:{}", node.host, node.port))?;
+        let reply = client.request(message).map_err(|e| NodeError::new(&e.to_string()))?;
+        match reply.message_type {
+            MessageType::ASSIGN_SHARDS => Ok(()),
+            _ => Err(NodeError::new(&reply.args.join(", "))),
+        }
+    }
+
+    /// Returns the connection to another Node, connecting first if there is none or it was lost
+    fn peer_client(&mut self, name: &str, address: &str) -> Result<RpcClient, NodeError> {
+        if let Some(client) = self.peers.get(name) {
+            if !client.is_closed() {
+                return Ok(client.clone());
+            }
+        }
+        let client = RpcClient::connect(address, DEFAULT_RPC_TIMEOUT).map_err(|e| NodeError::new(&e.to_string()))?;
+        self.peers.insert(name.to_owned(), client.clone());
+        Ok(client)
+    }
+
+    /// Starts and stops this Node's Shards to match what the metadata server placed on it
+    fn handle_assign_shards(&mut self, arguments: &Vec<String>) -> Message {
+        let mut assignments = vec![];
+        for argument in arguments {
+            match serde_json::from_str::<IndexAssignment>(argument) {
+                Ok(assignment) => assignments.push(assignment),
+                Err(e) => return Message::new(MessageType::ERROR).arg(format!("Invalid shard assignment: {}", e)),
+            }
+        }
+        match self.apply_assignments(&assignments) {
+            Ok(()) => Message::new(MessageType::ASSIGN_SHARDS),
+            Err(e) => Message::new(MessageType::ERROR).arg(e.to_string()),
+        }
+    }
+
+    /// Hands a write from a Primary to the Replica on this Node it is meant for. The reply is sent
+    /// once the Replica has applied it, without holding up other Messages in the meantime.
+    fn handle_replicate(&self, msg: Message) {
+        let (index, shard, shard_tx, operation) = match self.replica_for(&msg.args) {
+            Ok(found) => found,
+            Err(e) => {
+                msg.respond(Message::new(MessageType::ERROR).arg(e.to_string()));
+                return;
+            }
+        };
+        let (response_tx, response_rx) = mpsc::channel();
+        let command = IndexCommand::ApplyOperation {
+            operation: operation,
+            response_channel: response_tx,
+        };
+        if shard_tx.send(command).is_err() {
+            msg.respond(Message::new(MessageType::ERROR).arg(String::from("Replica is no longer running")));
+            return;
+        }
+        let switchboard = self.switchboard.clone();
+        thread::spawn(move || {
+            let response = match response_rx.recv() {
+                Ok(Ok(())) => Message::new(MessageType::REPLICATE),
+                Ok(Err(e)) => {
+                    // The Replica has missed writes, so it catches up from its Primary
+                    if let Some(Ok(switchboard)) = switchboard.as_ref().map(|switchboard| switchboard.lock()) {
+                        switchboard.indices.recover(&index, shard);
+                    }
+                    Message::new(MessageType::ERROR).arg(e.to_string())
+                },
+                Err(_) => Message::new(MessageType::ERROR).arg(String::from("Replica stopped before applying the write")),
+            };
+            msg.respond(response);
+        });
+    }
+
+    /// Finds the Replica a REPLICATE request is for and reads the write it carries. Writes from a
+    /// Primary in an older term than the Replica's are refused, as that Primary has been replaced.
+    fn replica_for(&self, arguments: &[String]) -> Result<(String, u32, mpsc::Sender<IndexCommand>, Operation), NodeError> {
+        let shard = arguments.get(1).and_then(|shard| shard.parse::<u32>().ok());
+        let term = arguments.get(3).and_then(|term| term.parse::<u64>().ok());
+        let (index, shard, operation, term) = match (arguments.get(0), shard, arguments.get(2), term) {
+            (Some(index), Some(shard), Some(operation), Some(term)) => (index, shard, operation, term),
+            _ => return Err(NodeError::new("Expected an index, a shard number, an operation and a term")),
+        };
+        let operation = Operation::from_json(operation).map_err(|e| NodeError::new(&e.to_string()))?;
+        let switchboard = match self.switchboard {
+            Some(ref switchboard) => switchboard,
+            None => return Err(NodeError::new("Shards can't be reached before the switchboard is set up")),
+        };
+        let switchboard = switchboard.lock().map_err(|_| NodeError::new("Switchboard lock is poisoned"))?;
+        let local = match switchboard.indices.get(index) {
+            Some(local) => local,
+            None => return Err(NodeError::new(&format!("There is no replica of shard {} of index {} on this node", shard, index))),
+        };
+        let shard_tx = match local.replica(shard) {
+            Some(shard_tx) => shard_tx,
+            None => return Err(NodeError::new(&format!("There is no replica of shard {} of index {} on this node", shard, index))),
+        };
+        let current = local.term(shard).unwrap_or(0);
+        if term < current {
+            return Err(NodeError::new(&format!(
+                "Operation {} is from term {} of shard {} of index {}, which is now in term {}",
+                operation.seq, term, shard, index, current
+            )));
+        }
+        Ok((index.clone(), shard, shard_tx, operation))
+    }
+
+    /// Answers how far the copy of a Shard on this Node has got. The reply is sent once its
+    /// `Manager` has answered, without holding up other Messages in the meantime.
+    fn handle_last_sequence(&self, msg: Message) {
+        let shard = msg.args.get(1).and_then(|shard| shard.parse::<u32>().ok());
+        let shard_tx = match (msg.args.get(0), shard) {
+            (Some(index), Some(shard)) => self.local_shard(index, shard),
+            _ => Err(NodeError::new("Expected an index and a shard number")),
+        };
+        let shard_tx = match shard_tx {
+            Ok(shard_tx) => shard_tx,
+            Err(e) => {
+                msg.respond(Message::new(MessageType::ERROR).arg(e.to_string()));
+                return;
+            }
+        };
+        thread::spawn(move || {
+            let response = match last_sequence(&shard_tx) {
+                Some(seq) => Message::new(MessageType::LAST_SEQUENCE).arg(seq.to_string()),
+                None => Message::new(MessageType::ERROR).arg(String::from("Shard stopped before it could answer")),
+            };
+            msg.respond(response);
+        });
+    }
+
+    /// Answers a request from a Replica recovering from a copy of a Shard on this Node. The reply
+    /// is sent from another thread, as making a snapshot takes a while.
+    fn handle_recovery(&self, msg: Message) {
+        let switchboard = match self.switchboard {
+            Some(ref switchboard) => switchboard.clone(),
+            None => {
+                msg.respond(Message::new(MessageType::ERROR).arg(String::from("Shards can't be reached before the switchboard is set up")));
+                return;
+            }
+        };
+        thread::spawn(move || {
+            let recovery_directory = match switchboard.lock() {
+                Ok(switchboard) => switchboard.indices.recovery_directory(),
+                Err(_) => {
+                    msg.respond(Message::new(MessageType::ERROR).arg(String::from("Switchboard lock is poisoned")));
+                    return;
+                }
+            };
+            let response = recovery::answer(&msg, &recovery_directory, |index, shard| {
+                let switchboard = switchboard.lock().map_err(|_| RecoveryError::new("Switchboard lock is poisoned"))?;
+                match switchboard.indices.get(index).and_then(|local| local.shard(shard)) {
+                    Some(shard_tx) => Ok(shard_tx),
+                    None => Err(RecoveryError::new(&format!("There is no copy of shard {} of index {} on this node", shard, index))),
+                }
+            });
+            msg.respond(response);
+        });
+    }
+
+    /// Returns the channel to the `Manager` of the copy of a Shard on this Node
+    fn local_shard(&self, index: &str, shard: u32) -> Result<mpsc::Sender<IndexCommand>, NodeError> {
+        let switchboard = match self.switchboard {
+            Some(ref switchboard) => switchboard,
+            None => return Err(NodeError::new("Shards can't be reached before the switchboard is set up")),
+        };
+        let switchboard = switchboard.lock().map_err(|_| NodeError::new("Switchboard lock is poisoned"))?;
+        match switchboard.indices.get(index).and_then(|local| local.shard(shard)) {
+            Some(shard_tx) => Ok(shard_tx),
+            None => Err(NodeError::new(&format!("There is no copy of shard {} of index {} on this node", shard, index))),
+        }
+    }
+
+    fn apply_assignments(&self, assignments: &[IndexAssignment]) -> Result<(), NodeError> {
+        let switchboard = match self.switchboard {
+            Some(ref switchboard) => switchboard,
+            None => return Err(NodeError::new("Shards can't be started before the switchboard is set up")),
+        };
+        let mut switchboard = switchboard.lock().map_err(|_| NodeError::new("Switchboard lock is poisoned"))?;
+        switchboard.indices.apply(assignments).map_err(|e| NodeError::new(&format!("Could not start shards: {}", e)))
+    }
+
+    /// Records that the Node named by the only argument has left the Cluster
+    fn handle_deregister(&mut self, arguments: &Vec<String>) -> Message {
+        let name = match arguments.get(0) {
+            Some(name) => name,
+            None => return Message::new(MessageType::ERROR).arg(String::from("Expected the name of a node")),
+        };
+        // The Primaries of the Replicas it holds have to stop sending it their writes
+        let primaries = match MetadataDB::primaries_replicated_to(&self.db, name) {
+            Ok(primaries) => primaries,
+            Err(e) => return Message::new(MessageType::ERROR).arg(format!("Could not read shard metadata: {}", e)),
+        };
+        let shards = match MetadataDB::get_node(&self.db, name) {
+            Ok(Some(_)) => match MetadataDB::primaries_on_node(&self.db, name) {
+                Ok(shards) => shards.into_iter().map(|(shard_id, _, _)| shard_id).collect::<Vec<String>>(),
+                Err(e) => return Message::new(MessageType::ERROR).arg(format!("Could not read shard metadata: {}", e)),
+            },
+            Ok(None) => return Message::new(MessageType::ERROR).arg(format!("Node {} is not registered", name)),
+            Err(e) => return Message::new(MessageType::ERROR).arg(format!("Could not deregister node {}: {}", name, e)),
+        };
+        if let Err(e) = self.commit(MetadataCommand::DeregisterNode { name: name.clone() }) {
+            return Message::new(MessageType::ERROR).arg(format!("Could not deregister node {}: {}", name, e));
+        }
+        println!("Node {} left the cluster", name);
+        if !shards.is_empty() {
+            println!("Shards {:?} were on node {} and have no node to run on until it returns", shards, name);
+        }
+        self.peers.remove(name);
+        // The Replicas it held were released, so place them on the Nodes that are left
+        let mut changed = self.place_unassigned_replicas();
+        changed.extend(primaries);
+        self.push_assignments(&changed);
+        Message::new(MessageType::DEREGISTER).args(shards)
+    }
+
+    /// Tells the metadata leader this Node is leaving the Cluster and waits until it has been
+    /// recorded. Writes should already have been drained and the local Shards stopped.
+    pub fn leave_cluster(&mut self) -> Result<(), NodeError> {
+        let message = Message::new(MessageType::DEREGISTER).arg(self.config.name.clone());
+        let reply = self.ask_metadata_server(message);
+        match reply.message_type {
+            MessageType::DEREGISTER => Ok(()),
+            _ => Err(NodeError::new(&reply.args.join(", "))),
+        }
+    }
+
+    /// Starts sending heartbeats to the metadata leader from a background thread, so it knows
+    /// this Node is still running
+    pub fn start_heartbeats(&self) {
+        let name = self.config.name.clone();
+        let host = self.config.rpc_address.clone();
+        let port = self.config.rpc_port;
+        let seeds = Node::metadata_seeds(&self.config);
+        let interval = self.config.heartbeat.interval;
+        thread::spawn(move || {
+            heartbeat::send_heartbeats(name, host, port, seeds, interval);
+        });
     }
 
     /// Creates a Registration `Message` and sends it to the metadata server so we can join the cluster
     pub fn register_with_metadata_server(&mut self) -> Result<(), NodeError> {
-        let serialized_message = Message::new(MessageType::REGISTER).args(
+        let message = Message::new(MessageType::REGISTER).args(
                 vec![self.config.name.clone(), self.config.rpc_address.clone(), self.config.rpc_port.to_string()]
-            ).to_vec()?;
+            );
 
-        match self.metadata_connection {
-            Some(ref mut conn) => {
-                match conn.write(&serialized_message) {
-                    Ok(_) => {
-                        Ok(())
-                    },
-                    Err(e) => {
-                        Err(NodeError::new(&format!("Error writing to metadata conn: {}", e)))
-                    },
-                }
+        match self.metadata_client() {
+            Some(client) => {
+                client.send(&message).map_err(|e| NodeError::new(&format!("Error writing to metadata conn: {}", e)))
             },
             None => {
-                match TcpStream::connect(self.config.metadata_address.clone() + ":" + &self.config.metadata_port.to_string()) {
-                    Ok(conn) => {
-                        println!("Connected to metadata server at: {}:{}", self.config.metadata_address, self.config.metadata_port);
-                        self.metadata_connection = Some(conn);
-                    },
-                    Err(e) => {
-                        println!("There was an error connecting to the metadata server: {:?}", e);
-                        self.metadata_connection = None;
-                    },
-                };
                 Err(NodeError::new("Error with connection to metadata server. Attempting reconnect..."))
             }
         }
@@ -318,48 +1333,380 @@ impl From<serde_json::Error> for NodeError {
     }
 }
 
+impl From<CodecError> for NodeError {
+    fn from(err: CodecError) -> NodeError {
+        NodeError::new(&err.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for NodeError {
+    fn from(err: rusqlite::Error) -> NodeError {
+        NodeError::new(&err.to_string())
+    }
+}
+
+impl From<RaftError> for NodeError {
+    fn from(err: RaftError) -> NodeError {
+        NodeError::new(&err.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use node::{Node, NodeConfiguration};
     use messages::Message;
     use std::sync::{Arc, mpsc, Mutex};
+    use uuid::Uuid;
+    use std::path::PathBuf;
+    use indices::IndexRegistry;
+    use std::time::Duration;
+
+    /// A directory under /tmp for a test's data, removed when the test finishes however it ends
+    struct TestDirectory {
+        path: String,
+    }
+
+    impl TestDirectory {
+        fn new() -> TestDirectory {
+            TestDirectory { path: format!("/tmp/saga_test_{}", Uuid::new_v4()) }
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
 
     #[test]
     fn test_handle_heartbeat() {
         let (_my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let directory = TestDirectory::new();
 
         let new_config = NodeConfiguration {
             name: String::from("test01"),
             metadata_address: String::from("localhost"),
-            data_path: String::from("memory"),
+            data_path: directory.path.clone(),
             metadata_port: 5000,
             am_metadata_server: true,
             rx: Arc::new(Mutex::new(my_node_rx)),
             rpc_address: String::from("localhost"),
-            rpc_port: 5001
+            rpc_port: 5001,
+            heartbeat: HeartbeatConfiguration::default(),
+            metadata_nodes: vec![]
         };
 
-        let mut new_node = Node::new(new_config);
+        let mut new_node = Node::new(new_config).unwrap();
         new_node.handle_heartbeat(&vec![]);
+
+        let mut db = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&db);
+        MetadataDB::register_node(&db, "node01", "127.0.0.1", 3001, 0);
+        MetadataDB::update_node_states(&mut db, &HeartbeatConfiguration::default(), heartbeat::now_millis()).unwrap();
+        new_node.db = db;
+        new_node.handle_heartbeat(&vec![String::from("node01")]);
+        let nodes = MetadataDB::list_nodes(&new_node.db).unwrap();
+        assert_eq!(nodes[0].state, NodeState::Alive);
+        assert!(nodes[0].last_heard > 0);
     }
 
     #[test]
     fn test_handle_register() {
         let (_my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let directory = TestDirectory::new();
 
         let new_config = NodeConfiguration {
             name: String::from("test02"),
             metadata_address: String::from("localhost"),
-            data_path: String::from("memory"),
+            data_path: directory.path.clone(),
             metadata_port: 5000,
             am_metadata_server: true,
             rx: Arc::new(Mutex::new(my_node_rx)),
             rpc_address: String::from("localhost"),
-            rpc_port: 5001
+            rpc_port: 5001,
+            heartbeat: HeartbeatConfiguration::default(),
+            metadata_nodes: vec![]
         };
 
-        let mut new_node = Node::new(new_config);
+        let mut new_node = Node::new(new_config).unwrap();
         new_node.handle_register(&vec![]);
+
+        new_node.db = Connection::open_in_memory().unwrap();
+        MetadataDB::create_node_table(&new_node.db);
+        new_node.handle_register(&vec![String::from("node01"), String::from("127.0.0.1"), String::from("3001")]);
+        let nodes = MetadataDB::list_nodes(&new_node.db).unwrap();
+        assert_eq!((nodes[0].name.as_str(), nodes[0].port), ("node01", 3001));
+
+        MetadataDB::create_index_tables(&new_node.db);
+        let response = new_node.handle_deregister(&vec![String::from("node01")]);
+        assert_eq!(response.message_type, MessageType::DEREGISTER);
+        let response = new_node.handle_deregister(&vec![String::from("node02")]);
+        assert_eq!(response.message_type, MessageType::ERROR);
+        assert_eq!(MetadataDB::list_nodes(&new_node.db).unwrap()[0].state, NodeState::Left);
+
+        // The Node comes back on a different port
+        new_node.handle_register(&vec![String::from("node01"), String::from("127.0.0.1"), String::from("3002")]);
+        let nodes = MetadataDB::list_nodes(&new_node.db).unwrap();
+        assert_eq!((nodes[0].port, nodes[0].state), (3002, NodeState::Alive));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_handle_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Node::handle_client(stream, Arc::new(Mutex::new(tx)), None);
+        });
+
+        // Both Messages are written before either is read, so they arrive joined together
+        let large = "x".repeat(64 * 1024);
+        let mut stream = TcpStream::connect(address).unwrap();
+        codec::write_message(&mut stream, &Message::new(MessageType::REGISTER).arg(large.clone())).unwrap();
+        codec::write_message(&mut stream, &Message::new(MessageType::HEARTBEAT)).unwrap();
+        assert_eq!(rx.recv().unwrap().args, vec![large]);
+        let heartbeat = rx.recv().unwrap();
+        assert_eq!(heartbeat.message_type, MessageType::HEARTBEAT);
+
+        // Replies go back on the same connection, marked with the ID of the request
+        assert!(heartbeat.respond(Message::new(MessageType::HEARTBEAT)));
+        let reply = codec::read_message(&mut stream).unwrap().unwrap();
+        assert_eq!(reply.reply_to, Some(heartbeat.message_id));
+    }
+
+    #[test]
+    fn test_handle_index_requests() {
+        let (my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let directory = TestDirectory::new();
+
+        let new_config = NodeConfiguration {
+            name: String::from("test03"),
+            metadata_address: String::from("localhost"),
+            data_path: directory.path.clone(),
+            metadata_port: 5000,
+            am_metadata_server: true,
+            rx: Arc::new(Mutex::new(my_node_rx)),
+            rpc_address: String::from("localhost"),
+            rpc_port: 5001,
+            heartbeat: HeartbeatConfiguration::default(),
+            metadata_nodes: vec![]
+        };
+
+        let data_path = new_config.data_path.clone();
+        let mut new_node = Node::new(new_config).unwrap();
+        let switchboard = Arc::new(Mutex::new(
+            Switchboard::new(Arc::new(Mutex::new(my_node_tx)), IndexRegistry::new(PathBuf::from(&data_path)))
+        ));
+        new_node.switchboard = Some(switchboard.clone());
+        MetadataDB::create_node_table(&new_node.db);
+        MetadataDB::create_index_tables(&new_node.db);
+        let arguments = vec![String::from("logs"), String::from("2"), String::from("1")];
+        let response = new_node.handle_index_request(&MessageType::CREATE_INDEX, &arguments);
+        assert_eq!(response.message_type, MessageType::ERROR);
+
+        new_node.register_self();
+        let response = new_node.handle_index_request(&MessageType::CREATE_INDEX, &arguments);
+        assert_eq!(response.message_type, MessageType::CREATE_INDEX);
+        assert_eq!(response.args, vec!["0", "1"]);
+        // Both Primaries were started here, and there is no other host for the Replicas
+        assert_eq!(switchboard.lock().unwrap().indices.get("logs").unwrap().shards().len(), 2);
+        assert_eq!(MetadataDB::unassigned_replicas(&new_node.db).unwrap().len(), 2);
+        let response = new_node.handle_index_request(&MessageType::CREATE_INDEX, &arguments);
+        assert_eq!(response.message_type, MessageType::ERROR);
+
+        let name = vec![String::from("logs")];
+        let response = new_node.handle_index_request(&MessageType::CLOSE_INDEX, &name);
+        let index: IndexDefinition = serde_json::from_str(&response.args[0]).unwrap();
+        assert_eq!(index.state, IndexState::Closed);
+        assert!(switchboard.lock().unwrap().indices.get("logs").is_none());
+        let response = new_node.handle_index_request(&MessageType::OPEN_INDEX, &name);
+        assert_eq!(&response.args[1..], &["0", "1"]);
+        let response = new_node.handle_index_request(&MessageType::LIST_INDICES, &vec![]);
+        assert_eq!(response.args.len(), 1);
+
+        let response = new_node.handle_index_request(&MessageType::DELETE_INDEX, &name);
+        assert_eq!(response.args.len(), 1);
+        assert!(!PathBuf::from(&data_path).join("indices").join("logs").exists());
+        let response = new_node.handle_index_request(&MessageType::GET_INDEX, &name);
+        assert_eq!(response.message_type, MessageType::GET_INDEX);
+        assert!(response.args.is_empty());
+    }
+    #[test]
+    fn test_fail_over() {
+        use allocator::ShardPlacement;
+        use inverted_index::replication::OperationKind;
+
+        let (my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let directory = TestDirectory::new();
+
+        let new_config = NodeConfiguration {
+            name: String::from("test04"),
+            metadata_address: String::from("localhost"),
+            data_path: directory.path.clone(),
+            metadata_port: 5000,
+            am_metadata_server: true,
+            rx: Arc::new(Mutex::new(my_node_rx)),
+            rpc_address: String::from("localhost"),
+            rpc_port: 5001,
+            heartbeat: HeartbeatConfiguration::default(),
+            metadata_nodes: vec![]
+        };
+
+        let data_path = new_config.data_path.clone();
+        let mut new_node = Node::new(new_config).unwrap();
+        let switchboard = Arc::new(Mutex::new(
+            Switchboard::new(Arc::new(Mutex::new(my_node_tx)), IndexRegistry::new(PathBuf::from(data_path)))
+        ));
+        new_node.switchboard = Some(switchboard.clone());
+        MetadataDB::create_node_table(&new_node.db);
+        MetadataDB::create_index_tables(&new_node.db);
+        new_node.register_self();
+        MetadataDB::register_node(&new_node.db, "node01", "10.0.0.9", 3001, 0);
+
+        // node01 holds the Primary and this Node its only Replica
+        let index = IndexDefinition {
+            name: String::from("logs"),
+            primary_shards: 1,
+            replica_shards: 1,
+            state: IndexState::Open,
+        };
+        let placements = vec![ShardPlacement {
+            number: 0,
+            primary: String::from("node01"),
+            replicas: vec![Some(String::from("test04"))],
+        }];
+        MetadataDB::create_index(&new_node.db, &index, &placements).unwrap();
+        new_node.push_assignments(&[String::from("test04")]);
+
+        let operation = Operation { seq: 1, term: 1, prev_term: 0, kind: OperationKind::Delete { id: 1 } }.to_json();
+        let replicate = |term: &str| vec![String::from("logs"), String::from("0"), operation.clone(), term.to_owned()];
+        assert!(new_node.replica_for(&replicate("1")).is_ok());
+        assert!(new_node.replica_for(&replicate("0")).is_err());
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let request = Message::new(MessageType::LAST_SEQUENCE)
+            .args(vec![String::from("logs"), String::from("0")])
+            .response_chan(reply_tx);
+        new_node.handle_last_sequence(request);
+        assert_eq!(reply_rx.recv().unwrap().args, vec!["0"]);
+
+        // node01 goes quiet for long enough to be declared dead
+        MetadataDB::update_node_states(&mut new_node.db, &HeartbeatConfiguration::default(), heartbeat::now_millis()).unwrap();
+        new_node.fail_over("node01");
+        assert_eq!(MetadataDB::shards_on_node(&new_node.db, "logs", "test04").unwrap(), vec![0]);
+        assert_eq!(MetadataDB::shard_term(&new_node.db, "logs_0").unwrap(), 2);
+        // There is no other host to put a new Replica on yet
+        assert_eq!(MetadataDB::unassigned_replicas(&new_node.db).unwrap().len(), 1);
+        let switchboard = switchboard.lock().unwrap();
+        let local = switchboard.indices.get("logs").unwrap();
+        assert!(!local.is_replica(0));
+        assert_eq!(local.term(0), Some(2));
+    }
+
+    /// Starts a metadata Node running Raft the way main does, with its own RPC server, Shards and
+    /// heartbeats
+    fn start_metadata_node(member: &RaftPeer, members: &[RaftPeer], directory: &TestDirectory) {
+        let (my_node_tx, my_node_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
+        let port = member.address.rsplit(':').next().unwrap().parse::<u16>().unwrap();
+        let new_config = NodeConfiguration {
+            name: member.name.clone(),
+            metadata_address: String::from("127.0.0.1"),
+            data_path: directory.path.clone(),
+            metadata_port: port,
+            am_metadata_server: true,
+            rx: Arc::new(Mutex::new(my_node_rx)),
+            rpc_address: String::from("127.0.0.1"),
+            rpc_port: port,
+            heartbeat: HeartbeatConfiguration {
+                interval: Duration::from_millis(200),
+                suspect_timeout: Duration::from_secs(2),
+                dead_timeout: Duration::from_secs(5),
+            },
+            metadata_nodes: members.to_vec()
+        };
+
+        let data_path = new_config.data_path.clone();
+        let mut new_node = Node::new(new_config).unwrap();
+        MetadataDB::create_node_table(&new_node.db);
+        MetadataDB::create_index_tables(&new_node.db);
+        new_node.start_raft().unwrap();
+        let raft = new_node.raft.clone();
+        let my_node_tx = Arc::new(Mutex::new(my_node_tx));
+        let rpc_tx = my_node_tx.clone();
+        thread::spawn(move || {
+            Node::start_rpc_server(String::from("127.0.0.1"), u32::from(port), rpc_tx, raft);
+        });
+        new_node.switchboard = Some(Arc::new(Mutex::new(
+            Switchboard::new(my_node_tx, IndexRegistry::new(PathBuf::from(data_path).join("indices")))
+        )));
+        new_node.start_heartbeats();
+        thread::spawn(move || {
+            new_node.receive_message();
+        });
+    }
+
+    /// Sends a request to a Node until it is answered with a reply of the expected type. Replies
+    /// can be errors while the metadata Nodes are electing a leader.
+    fn request_until(address: &str, message_type: MessageType, args: Vec<String>) -> Message {
+        for _ in 0..100 {
+            let reply = RpcClient::connect(address, Duration::from_secs(2))
+                .and_then(|client| client.request(Message::new(message_type.clone()).args(args.clone())));
+            if let Ok(reply) = reply {
+                if reply.message_type == message_type {
+                    return reply;
+                }
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        panic!("{} never answered {:?}", address, message_type);
+    }
+
+    /// Asks each of the Nodes who the metadata leader is until one names a leader other than
+    /// `old_leader`, and returns its address
+    fn wait_for_leader(addresses: &[String], old_leader: Option<&str>) -> String {
+        for _ in 0..100 {
+            for address in addresses {
+                let reply = request_until(address, MessageType::METADATA_LEADER, vec![]);
+                if let Some(leader) = reply.args.get(1) {
+                    if Some(leader.as_str()) != old_leader {
+                        return leader.clone();
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        panic!("No metadata leader was elected");
+    }
+
+    #[test]
+    fn test_metadata_leader_failure() {
+        let members: Vec<RaftPeer> = (0..3)
+            .map(|number| {
+                let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+                RaftPeer { name: format!("meta0{}", number), address: format!("127.0.0.1:{}", port) }
+            })
+            .collect();
+        let directories: Vec<TestDirectory> = members.iter().map(|_| TestDirectory::new()).collect();
+        for (member, directory) in members.iter().zip(directories.iter()) {
+            start_metadata_node(member, &members, directory);
+        }
+        let addresses: Vec<String> = members.iter().map(|member| member.address.clone()).collect();
+
+        // A follower passes the request on to the leader
+        let leader = wait_for_leader(&addresses, None);
+        let follower = addresses.iter().find(|&address| *address != leader).unwrap();
+        let arguments = vec![String::from("logs"), String::from("1"), String::from("1")];
+        request_until(follower, MessageType::CREATE_INDEX, arguments);
+
+        // The others elect a new leader, which still knows about the Index
+        request_until(&leader, MessageType::SHUTDOWN, vec![]);
+        let survivors: Vec<String> = addresses.iter().filter(|&address| *address != leader).cloned().collect();
+        let new_leader = wait_for_leader(&survivors, Some(&leader));
+        assert!(survivors.contains(&new_leader));
+        let response = request_until(&survivors[0], MessageType::LIST_INDICES, vec![]);
+        let indices: Vec<IndexDefinition> = response.args.iter().map(|arg| serde_json::from_str(arg).unwrap()).collect();
+        assert_eq!(indices.iter().map(|index| index.name.as_str()).collect::<Vec<&str>>(), vec!["logs"]);
+    }
+}
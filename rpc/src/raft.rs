@@ -0,0 +1,1034 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::slice;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusqlite;
+use rusqlite::Connection;
+use serde_json;
+use uuid::Uuid;
+
+use client::{RpcClient, DEFAULT_RPC_TIMEOUT};
+use messages::{Message, MessageType};
+
+/// Name of the file a metadata Node keeps its Raft log in, within its data path
+pub const RAFT_DB_FILENAME: &'static str = "raft.db";
+
+/// How long a follower waits to hear from a leader by default before it stands for election.
+/// Each wait is picked at random between this and twice this, so elections rarely collide.
+pub const DEFAULT_ELECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a leader sends AppendEntries to each member by default when it has nothing new
+pub const DEFAULT_RAFT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long a proposal waits by default to be committed
+pub const DEFAULT_PROPOSAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most log entries sent in a single AppendEntries request
+pub const RAFT_BATCH_SIZE: usize = 100;
+
+/// Query to create the table holding the current term and who was voted for in it
+pub const QUERY_CREATE_RAFT_STATE_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS raft_state (
+        key             TEXT PRIMARY KEY NOT NULL,
+        value           TEXT NOT NULL
+    )";
+
+/// Query to create the table holding the log
+pub const QUERY_CREATE_RAFT_LOG_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS raft_log (
+        idx             INTEGER PRIMARY KEY NOT NULL,
+        term            INTEGER NOT NULL,
+        command         TEXT NOT NULL
+    )";
+
+pub const QUERY_GET_RAFT_STATE: &'static str = "SELECT value FROM raft_state WHERE key = ?1";
+
+pub const QUERY_SET_RAFT_STATE: &'static str = "INSERT OR REPLACE INTO raft_state (key, value) VALUES (?1, ?2)";
+
+pub const QUERY_GET_RAFT_LOG: &'static str = "SELECT idx, term, command FROM raft_log ORDER BY idx";
+
+pub const QUERY_APPEND_RAFT_LOG: &'static str = "INSERT OR REPLACE INTO raft_log (idx, term, command) VALUES (?1, ?2, ?3)";
+
+/// Query to remove the entries from an index onwards, once they turn out to conflict with the
+/// leader's
+pub const QUERY_TRUNCATE_RAFT_LOG: &'static str = "DELETE FROM raft_log WHERE idx >= ?1";
+
+/// A metadata Node taking part in Raft
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RaftPeer {
+    /// Name of the Node
+    pub name: String,
+    /// Address of the Node's RPC server, as `host:port`
+    pub address: String,
+}
+
+impl FromStr for RaftPeer {
+    type Err = RaftError;
+
+    /// Reads a member written as `name@host:port`
+    fn from_str(s: &str) -> Result<RaftPeer, RaftError> {
+        let mut parts = s.trim().splitn(2, '@');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(address)) if !name.is_empty() && address.contains(':') => Ok(RaftPeer {
+                name: name.to_owned(),
+                address: address.to_owned(),
+            }),
+            _ => Err(RaftError::new(&format!("Expected a metadata node as name@host:port, got {}", s))),
+        }
+    }
+}
+
+/// An entry in the Raft log. The command is whatever was proposed, and is empty for the entry a
+/// new leader appends to commit what earlier leaders left behind.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub index: u64,
+    pub term: u64,
+    pub command: String,
+}
+
+/// What part a member is playing in the current term
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Where a member has got to, as last seen by its Raft thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaftStatus {
+    pub term: u64,
+    pub role: Role,
+    /// The member leading the current term, if it is known
+    pub leader: Option<RaftPeer>,
+    /// Index of the last entry known to be committed
+    pub commit_index: u64,
+}
+
+/// How a member of the metadata Nodes takes part in Raft
+#[derive(Debug, Clone)]
+pub struct RaftConfiguration {
+    /// Name of this Node
+    pub name: String,
+    /// Address of this Node's RPC server, as `host:port`
+    pub address: String,
+    /// The other members
+    pub peers: Vec<RaftPeer>,
+    /// Shortest time a follower waits to hear from a leader before it stands for election
+    pub election_timeout: Duration,
+    /// How often a leader sends AppendEntries to each member when it has nothing new
+    pub heartbeat_interval: Duration,
+}
+
+impl RaftConfiguration {
+    /// Returns the configuration for a member with the default timeouts
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of this Node
+    /// * `address` - Address of this Node's RPC server, as `host:port`
+    /// * `members` - Every member, which may include this Node
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::raft::{RaftConfiguration, RaftPeer};
+    /// let members: Vec<RaftPeer> = vec!["meta01@127.0.0.1:3001".parse().unwrap(), "meta02@127.0.0.1:3002".parse().unwrap()];
+    /// let config = RaftConfiguration::new("meta01", "127.0.0.1:3001", &members);
+    /// assert_eq!(config.peers.len(), 1);
+    /// ```
+    pub fn new(name: &str, address: &str, members: &[RaftPeer]) -> RaftConfiguration {
+        RaftConfiguration {
+            name: name.to_owned(),
+            address: address.to_owned(),
+            peers: members.iter().filter(|member| member.name != name).cloned().collect(),
+            election_timeout: DEFAULT_ELECTION_TIMEOUT,
+            heartbeat_interval: DEFAULT_RAFT_HEARTBEAT_INTERVAL,
+        }
+    }
+}
+
+/// Keeps a member's term, vote and log on disk, so it keeps its promises across restarts
+pub struct RaftStorage {
+    conn: Connection,
+}
+
+impl RaftStorage {
+    /// Opens the Raft database at a path, creating it if needed
+    pub fn open(path: &Path) -> Result<RaftStorage, RaftError> {
+        RaftStorage::with_connection(Connection::open(path)?)
+    }
+
+    /// Opens a Raft database that only lives as long as the RaftStorage
+    pub fn open_in_memory() -> Result<RaftStorage, RaftError> {
+        RaftStorage::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<RaftStorage, RaftError> {
+        conn.execute(QUERY_CREATE_RAFT_STATE_TABLE, &[])?;
+        conn.execute(QUERY_CREATE_RAFT_LOG_TABLE, &[])?;
+        Ok(RaftStorage { conn: conn })
+    }
+
+    /// Returns the current term, who was voted for in it and the whole log
+    pub fn load(&self) -> Result<(u64, Option<String>, Vec<LogEntry>), RaftError> {
+        let term = self.state("term")?.and_then(|term| term.parse().ok()).unwrap_or(0);
+        let voted_for = self.state("voted_for")?.and_then(|name| if name.is_empty() { None } else { Some(name) });
+        let mut stmt = self.conn.prepare(QUERY_GET_RAFT_LOG)?;
+        let rows = stmt.query_map(&[], |row| {
+            let index: i64 = row.get(0);
+            let term: i64 = row.get(1);
+            LogEntry {
+                index: index as u64,
+                term: term as u64,
+                command: row.get(2),
+            }
+        })?;
+        let mut log = vec![];
+        for entry in rows {
+            log.push(entry?);
+        }
+        Ok((term, voted_for, log))
+    }
+
+    fn state(&self, key: &str) -> Result<Option<String>, RaftError> {
+        match self.conn.query_row(QUERY_GET_RAFT_STATE, &[&key], |row| row.get(0)) {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(RaftError::from(e)),
+        }
+    }
+
+    /// Records the current term and who was voted for in it
+    pub fn save_vote(&mut self, term: u64, voted_for: Option<&str>) -> Result<(), RaftError> {
+        let tx = self.conn.transaction()?;
+        tx.execute(QUERY_SET_RAFT_STATE, &[&"term", &term.to_string()])?;
+        tx.execute(QUERY_SET_RAFT_STATE, &[&"voted_for", &voted_for.unwrap_or("")])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Adds entries to the end of the log
+    pub fn append(&mut self, entries: &[LogEntry]) -> Result<(), RaftError> {
+        let tx = self.conn.transaction()?;
+        for entry in entries {
+            tx.execute(QUERY_APPEND_RAFT_LOG, &[&(entry.index as i64), &(entry.term as i64), &entry.command])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes every entry from `index` onwards
+    pub fn truncate(&mut self, index: u64) -> Result<(), RaftError> {
+        self.conn.execute(QUERY_TRUNCATE_RAFT_LOG, &[&(index as i64)])?;
+        Ok(())
+    }
+}
+
+/// What the Raft thread is told about
+enum Event {
+    /// A RAFT_REQUEST_VOTE or RAFT_APPEND_ENTRIES request from another member
+    Request(Message),
+    VoteReply { peer: String, sent_term: u64, term: u64, granted: bool },
+    AppendReply { peer: String, sent_term: u64, term: u64, success: bool, match_index: u64 },
+    /// A request to a member got no reply
+    Failed { peer: String },
+    Propose { command: String, response: mpsc::Sender<Result<u64, RaftError>> },
+    Shutdown,
+}
+
+/// Talks to a member's Raft thread. Clones talk to the same thread.
+#[derive(Clone)]
+pub struct RaftHandle {
+    events: mpsc::Sender<Event>,
+    status: Arc<Mutex<RaftStatus>>,
+}
+
+impl RaftHandle {
+    /// Appends a command to the log and waits until it is committed and has been handed to the
+    /// apply channel. Returns the index of its entry. Only the leader accepts proposals.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command. An empty one changes nothing, but once it is committed so is
+    ///   everything before it.
+    /// * `timeout` - How long to wait for the command to be committed
+    pub fn propose(&self, command: String, timeout: Duration) -> Result<u64, RaftError> {
+        let (response_tx, response_rx) = mpsc::channel();
+        let event = Event::Propose {
+            command: command,
+            response: response_tx,
+        };
+        if self.events.send(event).is_err() {
+            return Err(RaftError::new("Raft has shut down"));
+        }
+        match response_rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(RaftError::new(&format!("Proposal was not committed within {:?}", timeout))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(RaftError::new("Raft shut down before the proposal was committed")),
+        }
+    }
+
+    /// Returns where this member has got to
+    pub fn status(&self) -> RaftStatus {
+        match self.status.lock() {
+            Ok(status) => status.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Returns true if this member is the leader
+    pub fn is_leader(&self) -> bool {
+        self.status().role == Role::Leader
+    }
+
+    /// Returns the leader of the current term, if it is known
+    pub fn leader(&self) -> Option<RaftPeer> {
+        self.status().leader
+    }
+
+    /// Passes a request from another member to the Raft thread, which replies to it
+    pub fn handle(&self, msg: Message) {
+        if let Err(mpsc::SendError(Event::Request(msg))) = self.events.send(Event::Request(msg)) {
+            msg.respond(Message::new(MessageType::ERROR).arg(String::from("Raft has shut down")));
+        }
+    }
+
+    /// Stops the Raft thread. Proposals still waiting fail.
+    pub fn shutdown(&self) {
+        let _ = self.events.send(Event::Shutdown);
+    }
+}
+
+/// Starts taking part in Raft in a background thread. Committed entries are sent down the
+/// returned channel in order, apart from those already applied.
+///
+/// # Arguments
+///
+/// * `config` - Who the members are and how long to wait for them
+/// * `storage` - Where the term, vote and log are kept
+/// * `applied` - Index of the last entry applied before this member last stopped
+pub fn start(config: RaftConfiguration, storage: RaftStorage, applied: u64) -> Result<(RaftHandle, mpsc::Receiver<LogEntry>), RaftError> {
+    let (term, voted_for, log) = storage.load()?;
+    let (events_tx, events_rx) = mpsc::channel();
+    let (applied_tx, applied_rx) = mpsc::channel();
+    let status = Arc::new(Mutex::new(RaftStatus {
+        term: term,
+        role: Role::Follower,
+        leader: None,
+        commit_index: 0,
+    }));
+    let mut core = Core {
+        storage: storage,
+        term: term,
+        voted_for: voted_for,
+        // Entries applied before can't be beyond the log, unless the log was lost
+        delivered: applied.min(log.len() as u64),
+        log: log,
+        commit_index: 0,
+        role: Role::Follower,
+        leader: None,
+        election_deadline: Instant::now(),
+        votes: HashSet::new(),
+        next_index: HashMap::new(),
+        match_index: HashMap::new(),
+        in_flight: HashSet::new(),
+        last_sent: HashMap::new(),
+        last_heard: HashMap::new(),
+        clients: Arc::new(Mutex::new(HashMap::new())),
+        proposals: vec![],
+        applied_tx: applied_tx,
+        events_tx: events_tx.clone(),
+        status: status.clone(),
+        config: config,
+    };
+    core.reset_election_deadline();
+    thread::spawn(move || {
+        core.run(events_rx);
+    });
+    Ok((RaftHandle { events: events_tx, status: status }, applied_rx))
+}
+
+/// Asks each seed in turn who the metadata leader is, and returns the first answer
+///
+/// # Arguments
+///
+/// * `seeds` - Addresses of metadata Nodes, as `host:port`
+pub fn find_leader(seeds: &[String]) -> Option<String> {
+    for seed in seeds {
+        let client = match RpcClient::connect(seed, DEFAULT_RPC_TIMEOUT) {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        if let Ok(reply) = client.request(Message::new(MessageType::METADATA_LEADER)) {
+            if reply.message_type == MessageType::METADATA_LEADER {
+                if let Some(address) = reply.args.get(1) {
+                    return Some(address.clone());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// A proposal waiting for its entry to be committed
+struct Proposal {
+    index: u64,
+    term: u64,
+    response: mpsc::Sender<Result<u64, RaftError>>,
+}
+
+/// State of a member, owned by its Raft thread
+struct Core {
+    config: RaftConfiguration,
+    storage: RaftStorage,
+    term: u64,
+    voted_for: Option<String>,
+    /// Entry `n` is at `log[n - 1]`
+    log: Vec<LogEntry>,
+    commit_index: u64,
+    /// Index of the last entry sent down the apply channel
+    delivered: u64,
+    role: Role,
+    leader: Option<RaftPeer>,
+    election_deadline: Instant,
+    votes: HashSet<String>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    /// Members with an AppendEntries request waiting for a reply, which get no other until it
+    /// arrives
+    in_flight: HashSet<String>,
+    last_sent: HashMap<String, Instant>,
+    /// When each member last answered an AppendEntries request from this member as leader
+    last_heard: HashMap<String, Instant>,
+    clients: Arc<Mutex<HashMap<String, RpcClient>>>,
+    /// Proposals waiting for their entries to be committed
+    proposals: Vec<Proposal>,
+    applied_tx: mpsc::Sender<LogEntry>,
+    events_tx: mpsc::Sender<Event>,
+    status: Arc<Mutex<RaftStatus>>,
+}
+
+impl Core {
+    fn run(&mut self, events: mpsc::Receiver<Event>) {
+        let tick = self.config.heartbeat_interval / 4;
+        loop {
+            match events.recv_timeout(tick) {
+                Ok(Event::Shutdown) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Ok(event) => {
+                    if let Err(e) = self.handle_event(event) {
+                        println!("There was an error in Raft: {}", e);
+                    }
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+            }
+            if let Err(e) = self.tick() {
+                println!("There was an error in Raft: {}", e);
+            }
+            self.publish_status();
+        }
+        self.role = Role::Follower;
+        self.leader = None;
+        self.publish_status();
+        println!("Raft has shut down");
+    }
+
+    fn handle_event(&mut self, event: Event) -> Result<(), RaftError> {
+        match event {
+            Event::Request(msg) => {
+                let reply = match msg.message_type {
+                    MessageType::RAFT_REQUEST_VOTE => self.handle_request_vote(&msg.args),
+                    MessageType::RAFT_APPEND_ENTRIES => self.handle_append_entries(&msg.args),
+                    _ => Err(RaftError::new(&format!("Raft can't answer {:?}", msg.message_type))),
+                };
+                match reply {
+                    Ok(reply) => msg.respond(reply),
+                    Err(e) => msg.respond(Message::new(MessageType::ERROR).arg(e.to_string())),
+                };
+            },
+            Event::VoteReply { peer, sent_term, term, granted } => {
+                if term > self.term {
+                    self.step_down(term)?;
+                } else if self.role == Role::Candidate && sent_term == self.term && granted {
+                    self.votes.insert(peer);
+                    if self.has_majority(self.votes.len()) {
+                        self.become_leader()?;
+                    }
+                }
+            },
+            Event::AppendReply { peer, sent_term, term, success, match_index } => {
+                self.in_flight.remove(&peer);
+                if term > self.term {
+                    self.step_down(term)?;
+                } else if self.role == Role::Leader && sent_term == self.term {
+                    self.last_heard.insert(peer.clone(), Instant::now());
+                    if success {
+                        let matched = self.match_index.entry(peer.clone()).or_insert(0);
+                        *matched = (*matched).max(match_index);
+                        self.next_index.insert(peer, match_index + 1);
+                        self.advance_commit();
+                    } else {
+                        // The member's log doesn't go as far back as was hoped, or differs there
+                        let next = self.next_index.get(&peer).cloned().unwrap_or(1);
+                        self.next_index.insert(peer, (next - 1).min(match_index + 1).max(1));
+                    }
+                }
+            },
+            Event::Failed { peer } => {
+                self.in_flight.remove(&peer);
+            },
+            Event::Propose { command, response } => {
+                if self.role != Role::Leader {
+                    let _ = response.send(Err(RaftError::new("This node is not the metadata leader")));
+                } else {
+                    let index = self.append_as_leader(command)?;
+                    self.proposals.push(Proposal {
+                        index: index,
+                        term: self.term,
+                        response: response,
+                    });
+                    self.advance_commit();
+                }
+            },
+            Event::Shutdown => {},
+        }
+        Ok(())
+    }
+
+    /// Stands for election if no leader has been heard from in time, or sends each member what
+    /// it is missing if this member leads. A leader that most members have not answered for an
+    /// election timeout steps down, as they may well have elected another leader without it.
+    fn tick(&mut self) -> Result<(), RaftError> {
+        if self.role == Role::Leader {
+            let timeout = self.config.election_timeout;
+            let heard = 1 + self.config.peers.iter()
+                .filter(|peer| self.last_heard.get(&peer.name).map_or(false, |heard| heard.elapsed() < timeout))
+                .count();
+            if !self.has_majority(heard) {
+                println!("Most metadata Nodes have not answered for {:?}", timeout);
+                let term = self.term;
+                return self.step_down(term);
+            }
+            for peer in self.config.peers.clone() {
+                if self.in_flight.contains(&peer.name) {
+                    continue;
+                }
+                let next = self.next_index.get(&peer.name).cloned().unwrap_or(1);
+                let idle = self.last_sent.get(&peer.name).map_or(true, |sent| sent.elapsed() >= self.config.heartbeat_interval);
+                if next <= self.last_index() || idle {
+                    self.send_append_entries(&peer, next);
+                }
+            }
+        } else if Instant::now() >= self.election_deadline {
+            self.start_election()?;
+        }
+        Ok(())
+    }
+
+    fn handle_request_vote(&mut self, arguments: &[String]) -> Result<Message, RaftError> {
+        let numbers = parse_numbers(arguments, &[0, 2, 3])?;
+        let (term, last_log_index, last_log_term) = (numbers[0], numbers[1], numbers[2]);
+        let candidate = match arguments.get(1) {
+            Some(candidate) => candidate,
+            None => return Err(RaftError::new("Expected a term, a candidate and its last log index and term")),
+        };
+        if term > self.term {
+            self.step_down(term)?;
+        }
+        let up_to_date = last_log_term > self.last_term() || (last_log_term == self.last_term() && last_log_index >= self.last_index());
+        let free = self.voted_for.as_ref().map_or(true, |voted_for| voted_for == candidate);
+        let granted = term == self.term && up_to_date && free;
+        if granted {
+            self.voted_for = Some(candidate.clone());
+            self.storage.save_vote(self.term, Some(candidate))?;
+            self.reset_election_deadline();
+        }
+        Ok(Message::new(MessageType::RAFT_REQUEST_VOTE).arg(self.term.to_string()).arg(granted.to_string()))
+    }
+
+    fn handle_append_entries(&mut self, arguments: &[String]) -> Result<Message, RaftError> {
+        let numbers = parse_numbers(arguments, &[0, 2, 3, 4])?;
+        let (term, prev_index, prev_term, leader_commit) = (numbers[0], numbers[1], numbers[2], numbers[3]);
+        let leader = match arguments.get(1) {
+            Some(leader) => leader,
+            None => return Err(RaftError::new("Expected a term, a leader, the previous entry and the commit index")),
+        };
+        let reply = |term: u64, success: bool, match_index: u64| {
+            Message::new(MessageType::RAFT_APPEND_ENTRIES)
+                .arg(term.to_string())
+                .arg(success.to_string())
+                .arg(match_index.to_string())
+        };
+        if term < self.term {
+            return Ok(reply(self.term, false, 0));
+        }
+        if term > self.term || self.role != Role::Follower {
+            self.step_down(term)?;
+        }
+        self.leader = self.config.peers.iter().find(|peer| &peer.name == leader).cloned();
+        self.reset_election_deadline();
+
+        if prev_index > self.last_index() || self.term_at(prev_index) != prev_term {
+            // The leader backs up to the end of this log, or one before where they differ
+            return Ok(reply(self.term, false, self.last_index().min(prev_index.saturating_sub(1))));
+        }
+        let mut entries = vec![];
+        for entry in &arguments[5..] {
+            entries.push(serde_json::from_str::<LogEntry>(entry)?);
+        }
+        let mut new_entries = vec![];
+        for entry in entries {
+            if entry.index <= self.last_index() {
+                if self.term_at(entry.index) == entry.term {
+                    continue;
+                }
+                // Anything from a conflicting entry on was never committed, so it goes
+                self.storage.truncate(entry.index)?;
+                self.log.truncate(entry.index as usize - 1);
+            }
+            new_entries.push(entry);
+        }
+        self.storage.append(&new_entries)?;
+        self.log.extend(new_entries);
+        let matched = prev_index + (arguments.len() - 5) as u64;
+        if leader_commit.min(matched) > self.commit_index {
+            self.commit_index = leader_commit.min(matched);
+            self.deliver();
+        }
+        Ok(reply(self.term, true, matched))
+    }
+
+    fn start_election(&mut self) -> Result<(), RaftError> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.voted_for = Some(self.config.name.clone());
+        self.storage.save_vote(self.term, Some(&self.config.name))?;
+        self.votes.clear();
+        self.votes.insert(self.config.name.clone());
+        self.reset_election_deadline();
+        println!("Standing for election as metadata leader in term {}", self.term);
+        if self.has_majority(self.votes.len()) {
+            return self.become_leader();
+        }
+        let request = vec![
+            self.term.to_string(),
+            self.config.name.clone(),
+            self.last_index().to_string(),
+            self.last_term().to_string(),
+        ];
+        for peer in self.config.peers.clone() {
+            let message = Message::new(MessageType::RAFT_REQUEST_VOTE).args(request.clone());
+            let sent_term = self.term;
+            self.send(&peer, message, move |peer, reply| {
+                let term = reply.args.get(0).and_then(|term| term.parse().ok())?;
+                Some(Event::VoteReply {
+                    peer: peer,
+                    sent_term: sent_term,
+                    term: term,
+                    granted: reply.args.get(1).map_or(false, |granted| granted == "true"),
+                })
+            });
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<(), RaftError> {
+        println!("Elected metadata leader in term {}", self.term);
+        self.role = Role::Leader;
+        self.leader = Some(RaftPeer {
+            name: self.config.name.clone(),
+            address: self.config.address.clone(),
+        });
+        let next = self.last_index() + 1;
+        // Every member gets an election timeout to answer the new leader
+        let now = Instant::now();
+        for peer in &self.config.peers {
+            self.next_index.insert(peer.name.clone(), next);
+            self.match_index.insert(peer.name.clone(), 0);
+            self.last_sent.remove(&peer.name);
+            self.last_heard.insert(peer.name.clone(), now);
+        }
+        // Entries from earlier terms can only be committed along with one from this term
+        self.append_as_leader(String::new())?;
+        self.advance_commit();
+        Ok(())
+    }
+
+    fn append_as_leader(&mut self, command: String) -> Result<u64, RaftError> {
+        let entry = LogEntry {
+            index: self.last_index() + 1,
+            term: self.term,
+            command: command,
+        };
+        self.storage.append(slice::from_ref(&entry))?;
+        self.log.push(entry);
+        Ok(self.last_index())
+    }
+
+    /// Goes back to following, in a later term if one has been seen. Proposals that were waiting
+    /// on this member as leader fail, as their entries may never be committed.
+    fn step_down(&mut self, term: u64) -> Result<(), RaftError> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.storage.save_vote(term, None)?;
+            self.leader = None;
+        }
+        if self.role == Role::Leader {
+            println!("No longer the metadata leader, now in term {}", self.term);
+            self.leader = None;
+            for proposal in self.proposals.drain(..) {
+                let _ = proposal.response.send(Err(RaftError::new("This node stopped being the metadata leader")));
+            }
+        }
+        self.role = Role::Follower;
+        self.reset_election_deadline();
+        Ok(())
+    }
+
+    fn send_append_entries(&mut self, peer: &RaftPeer, next: u64) {
+        let prev_index = next - 1;
+        let mut args = vec![
+            self.term.to_string(),
+            self.config.name.clone(),
+            prev_index.to_string(),
+            self.term_at(prev_index).to_string(),
+            self.commit_index.to_string(),
+        ];
+        for entry in self.log.iter().skip(prev_index as usize).take(RAFT_BATCH_SIZE) {
+            match serde_json::to_string(entry) {
+                Ok(entry) => args.push(entry),
+                Err(e) => {
+                    println!("Could not serialize Raft log entry {}: {}", entry.index, e);
+                    return;
+                },
+            }
+        }
+        self.in_flight.insert(peer.name.clone());
+        self.last_sent.insert(peer.name.clone(), Instant::now());
+        let sent_term = self.term;
+        self.send(peer, Message::new(MessageType::RAFT_APPEND_ENTRIES).args(args), move |peer, reply| {
+            let term = reply.args.get(0).and_then(|term| term.parse().ok())?;
+            let match_index = reply.args.get(2).and_then(|index| index.parse().ok())?;
+            Some(Event::AppendReply {
+                peer: peer,
+                sent_term: sent_term,
+                term: term,
+                success: reply.args.get(1).map_or(false, |success| success == "true"),
+                match_index: match_index,
+            })
+        });
+    }
+
+    /// Sends a request to another member from a background thread, and tells the Raft thread
+    /// what the reply was. Connections are kept for the next request.
+    fn send<F>(&self, peer: &RaftPeer, message: Message, read_reply: F)
+    where
+        F: FnOnce(String, Message) -> Option<Event> + Send + 'static,
+    {
+        let peer = peer.clone();
+        let clients = self.clients.clone();
+        let events = self.events_tx.clone();
+        let timeout = self.config.election_timeout;
+        thread::spawn(move || {
+            let cached = clients.lock().ok().and_then(|clients| clients.get(&peer.name).cloned());
+            let client = match cached {
+                Some(ref client) if !client.is_closed() => client.clone(),
+                _ => match RpcClient::connect(&peer.address, timeout) {
+                    Ok(client) => {
+                        if let Ok(mut clients) = clients.lock() {
+                            clients.insert(peer.name.clone(), client.clone());
+                        }
+                        client
+                    },
+                    Err(_) => {
+                        let _ = events.send(Event::Failed { peer: peer.name });
+                        return;
+                    },
+                },
+            };
+            let event = match client.request(message) {
+                Ok(reply) => read_reply(peer.name.clone(), reply),
+                Err(_) => None,
+            };
+            let _ = events.send(event.unwrap_or(Event::Failed { peer: peer.name }));
+        });
+    }
+
+    /// Commits the latest entry from this term that most members have
+    fn advance_commit(&mut self) {
+        let mut index = self.last_index();
+        while index > self.commit_index && self.term_at(index) == self.term {
+            let copies = 1 + self.match_index.values().filter(|&&matched| matched >= index).count();
+            if self.has_majority(copies) {
+                self.commit_index = index;
+                self.deliver();
+                return;
+            }
+            index -= 1;
+        }
+    }
+
+    /// Sends newly committed entries down the apply channel, then answers the proposals they
+    /// settle
+    fn deliver(&mut self) {
+        while self.delivered < self.commit_index {
+            let entry = self.log[self.delivered as usize].clone();
+            self.delivered += 1;
+            let _ = self.applied_tx.send(entry);
+        }
+        // Whoever a proposal is answered to sees the commit in the status too
+        self.publish_status();
+        let delivered = self.delivered;
+        let mut waiting = vec![];
+        for proposal in self.proposals.drain(..) {
+            if proposal.index > delivered {
+                waiting.push(proposal);
+            } else if self.log.get(proposal.index as usize - 1).map(|entry| entry.term) == Some(proposal.term) {
+                let _ = proposal.response.send(Ok(proposal.index));
+            } else {
+                let _ = proposal.response.send(Err(RaftError::new("The proposal was replaced by a new leader's entries")));
+            }
+        }
+        self.proposals = waiting;
+    }
+
+    fn has_majority(&self, count: usize) -> bool {
+        count * 2 > self.config.peers.len() + 1
+    }
+
+    fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log.last().map_or(0, |entry| entry.term)
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        if index == 0 {
+            return 0;
+        }
+        self.log.get(index as usize - 1).map_or(0, |entry| entry.term)
+    }
+
+    /// Picks a new time to stand for election by, at random between one and two election
+    /// timeouts from now. A member on its own stands straight away.
+    fn reset_election_deadline(&mut self) {
+        if self.config.peers.is_empty() {
+            self.election_deadline = Instant::now();
+            return;
+        }
+        let timeout = self.config.election_timeout;
+        let bytes = Uuid::new_v4();
+        let random = bytes.as_bytes().iter().take(8).fold(0u64, |acc, &byte| (acc << 8) | u64::from(byte));
+        let spread = (timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis())).max(1);
+        self.election_deadline = Instant::now() + timeout + Duration::from_millis(random % spread);
+    }
+
+    fn publish_status(&self) {
+        if let Ok(mut status) = self.status.lock() {
+            *status = RaftStatus {
+                term: self.term,
+                role: self.role,
+                leader: self.leader.clone(),
+                commit_index: self.commit_index,
+            };
+        }
+    }
+}
+
+/// Reads the arguments at the given positions as numbers
+fn parse_numbers(arguments: &[String], positions: &[usize]) -> Result<Vec<u64>, RaftError> {
+    let mut numbers = vec![];
+    for &position in positions {
+        match arguments.get(position).and_then(|argument| argument.parse().ok()) {
+            Some(number) => numbers.push(number),
+            None => return Err(RaftError::new(&format!("Expected a number as argument {}", position))),
+        }
+    }
+    Ok(numbers)
+}
+
+#[derive(Debug)]
+pub struct RaftError {
+    details: String,
+}
+
+impl RaftError {
+    /// Creates and returns a new RaftError
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The error message we want to include in the RaftError
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::raft::RaftError;
+    /// let _raft_error = RaftError::new("No leader!");
+    /// ```
+    pub fn new(msg: &str) -> RaftError {
+        RaftError { details: msg.to_string() }
+    }
+}
+
+impl fmt::Display for RaftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for RaftError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<rusqlite::Error> for RaftError {
+    fn from(err: rusqlite::Error) -> RaftError {
+        RaftError::new(&err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for RaftError {
+    fn from(err: serde_json::Error) -> RaftError {
+        RaftError::new(&err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use node::Node;
+
+    /// Returns a port nothing is listening on
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    /// Starts a member with its own RPC server, the way a metadata Node runs one
+    fn start_member(name: &str, members: &[RaftPeer]) -> (RaftHandle, mpsc::Receiver<LogEntry>) {
+        let address = members.iter().find(|member| member.name == name).unwrap().address.clone();
+        let mut config = RaftConfiguration::new(name, &address, members);
+        config.election_timeout = Duration::from_millis(300);
+        config.heartbeat_interval = Duration::from_millis(50);
+        let (raft, committed) = start(config, RaftStorage::open_in_memory().unwrap(), 0).unwrap();
+
+        // Raft's messages go straight to it, so nothing is sent down the channel
+        let (tx, _) = mpsc::channel();
+        let port = address.rsplit(':').next().unwrap().parse::<u32>().unwrap();
+        let requests = raft.clone();
+        thread::spawn(move || {
+            Node::start_rpc_server(String::from("127.0.0.1"), port, Arc::new(Mutex::new(tx)), Some(requests));
+        });
+        (raft, committed)
+    }
+
+    /// Waits for one of the members to be elected, and returns its position
+    fn wait_for_leader(members: &[&RaftHandle]) -> usize {
+        for _ in 0..200 {
+            if let Some(position) = members.iter().position(|member| member.is_leader()) {
+                return position;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("No leader was elected");
+    }
+
+    /// Returns the next command committed, skipping the empty entries leaders start with
+    fn next_command(committed: &mpsc::Receiver<LogEntry>) -> String {
+        loop {
+            let entry = committed.recv_timeout(Duration::from_secs(5)).unwrap();
+            if !entry.command.is_empty() {
+                return entry.command;
+            }
+        }
+    }
+
+    #[test]
+    fn test_peer_from_str() {
+        let peer: RaftPeer = "meta01@10.0.0.1:3001".parse().unwrap();
+        assert_eq!(peer, RaftPeer { name: String::from("meta01"), address: String::from("10.0.0.1:3001") });
+        assert!("10.0.0.1:3001".parse::<RaftPeer>().is_err());
+        assert!("@10.0.0.1:3001".parse::<RaftPeer>().is_err());
+    }
+
+    #[test]
+    fn test_storage() {
+        let mut storage = RaftStorage::open_in_memory().unwrap();
+        assert_eq!(storage.load().unwrap(), (0, None, vec![]));
+        storage.save_vote(3, Some("meta02")).unwrap();
+        let entries: Vec<LogEntry> = (1..4)
+            .map(|index| LogEntry { index: index, term: 2, command: index.to_string() })
+            .collect();
+        storage.append(&entries).unwrap();
+        storage.truncate(3).unwrap();
+        assert_eq!(storage.load().unwrap(), (3, Some(String::from("meta02")), entries[..2].to_vec()));
+        storage.save_vote(4, None).unwrap();
+        assert_eq!(storage.load().unwrap().1, None);
+    }
+
+    #[test]
+    fn test_single_member() {
+        let members = vec![RaftPeer { name: String::from("meta01"), address: format!("127.0.0.1:{}", free_port()) }];
+        let config = RaftConfiguration::new("meta01", &members[0].address, &members);
+        let (raft, committed) = start(config, RaftStorage::open_in_memory().unwrap(), 0).unwrap();
+        wait_for_leader(&[&raft]);
+        // The first entry is the one the leader starts its term with
+        assert_eq!(raft.propose(String::from("a"), DEFAULT_PROPOSAL_TIMEOUT).unwrap(), 2);
+        assert_eq!(next_command(&committed), "a");
+        assert_eq!(raft.status().commit_index, 2);
+        raft.shutdown();
+        assert!(raft.propose(String::from("b"), DEFAULT_PROPOSAL_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_replication_and_election() {
+        let members: Vec<RaftPeer> = (0..3)
+            .map(|number| RaftPeer { name: format!("meta0{}", number), address: format!("127.0.0.1:{}", free_port()) })
+            .collect();
+        let started: Vec<(RaftHandle, mpsc::Receiver<LogEntry>)> =
+            members.iter().map(|member| start_member(&member.name, &members)).collect();
+        let handles: Vec<&RaftHandle> = started.iter().map(|&(ref raft, _)| raft).collect();
+
+        let leader = wait_for_leader(&handles);
+        let follower = (leader + 1) % 3;
+        assert!(handles[follower].propose(String::from("a"), DEFAULT_PROPOSAL_TIMEOUT).is_err());
+        handles[leader].propose(String::from("a"), DEFAULT_PROPOSAL_TIMEOUT).unwrap();
+        for &(_, ref committed) in &started {
+            assert_eq!(next_command(committed), "a");
+        }
+
+        // The others elect a new leader, which has everything that was committed
+        handles[leader].shutdown();
+        let survivors: Vec<&RaftHandle> = (0..3).filter(|&number| number != leader).map(|number| handles[number]).collect();
+        let new_leader = survivors[wait_for_leader(&survivors)];
+        assert_ne!(new_leader.leader().unwrap().name, members[leader].name);
+        new_leader.propose(String::from("b"), DEFAULT_PROPOSAL_TIMEOUT).unwrap();
+        for number in (0..3).filter(|&number| number != leader) {
+            assert_eq!(next_command(&started[number].1), "b");
+            assert_eq!(handles[number].leader(), new_leader.leader());
+        }
+
+        // A leader that can no longer reach most members stops leading
+        let last = survivors.iter().find(|survivor| !survivor.is_leader()).unwrap();
+        last.shutdown();
+        for _ in 0..40 {
+            if !new_leader.is_leader() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(!new_leader.is_leader());
+        assert_eq!(new_leader.leader(), None);
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use base64;
+use uuid::Uuid;
+
+use inverted_index::index::InvertedIndexError;
+use inverted_index::manager::IndexCommand;
+use inverted_index::replication::{Operation, Snapshot};
+
+use client::{RpcClient, RpcError};
+use messages::{Message, MessageType};
+
+/// Most bytes of a snapshot file sent in a single RECOVER_CHUNK reply
+pub const RECOVERY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Most Operations sent in a single RECOVER_OPERATIONS reply
+pub const RECOVERY_BATCH_SIZE: usize = 500;
+
+/// Most bytes of Operation JSON sent in a single RECOVER_OPERATIONS reply. Each Operation carries
+/// its whole Document and is escaped again within the reply, so this is kept well under
+/// `codec::MAX_FRAME_SIZE`. A single Operation is still sent when it alone is larger.
+pub const RECOVERY_BATCH_BYTES: usize = 8 * 1024 * 1024;
+
+/// How long a recovering Replica waits for each reply from its Primary. Making a snapshot of a
+/// large Shard takes a while, so this is much longer than `DEFAULT_RPC_TIMEOUT`.
+pub const RECOVERY_RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a snapshot made for another Node is kept after it was last read from. A Replica
+/// that is still copying asks for a chunk well within `RECOVERY_RPC_TIMEOUT`, so a snapshot left
+/// this long belongs to one that failed or went away without sending RECOVER_DONE.
+pub const SNAPSHOT_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+/// Directory within the recovery directory that snapshots made for other Nodes are kept in
+const OUTGOING_DIRECTORY: &'static str = "outgoing";
+
+/// File within a snapshot made for another Node that is rewritten every time the snapshot is
+/// read from, so that its modification time tells when the snapshot was last used
+const LAST_USED_FILE: &'static str = "last_used";
+
+/// Directory within the recovery directory that snapshots copied from other Nodes are kept in
+/// until they are restored
+const INCOMING_DIRECTORY: &'static str = "incoming";
+
+/// Asks a Shard's `Manager` for the sequence number of the last Operation it has. Returns None if
+/// the Manager has stopped.
+pub fn last_sequence(shard_tx: &mpsc::Sender<IndexCommand>) -> Option<u64> {
+    let (response_tx, response_rx) = mpsc::channel();
+    shard_tx.send(IndexCommand::LastSequence { response_channel: response_tx }).ok()?;
+    response_rx.recv().ok()
+}
+
+/// Starts bringing a Replica on this Node up to date with its Primary in a background thread.
+/// A Replica with nothing in it, or whose last Operation the Primary does not have, first copies
+/// a snapshot of the Primary's segments, and then any Operations it is missing are replayed from
+/// the Primary's OpLog. The Primary keeps taking writes the whole time. Returns false if the
+/// Replica is already recovering.
+///
+/// # Arguments
+///
+/// * `index` - Name of the Index
+/// * `shard` - Number of the Shard
+/// * `primary_address` - Address of the Node holding the Primary, as `host:port`
+/// * `shard_tx` - Channel to the Replica's `Manager`
+/// * `recovery_directory` - Directory snapshots are copied into before they are restored
+/// * `recovering` - Numbers of the Index's Shards that are recovering, shared by every recovery
+pub fn start_recovery(
+    index: &str,
+    shard: u32,
+    primary_address: &str,
+    shard_tx: mpsc::Sender<IndexCommand>,
+    recovery_directory: PathBuf,
+    recovering: Arc<Mutex<BTreeSet<u32>>>,
+) -> bool {
+    match recovering.lock() {
+        Ok(mut recovering) => {
+            if !recovering.insert(shard) {
+                return false;
+            }
+        }
+        Err(_) => return false,
+    }
+    let index = index.to_owned();
+    let primary_address = primary_address.to_owned();
+    thread::spawn(move || {
+        let _guard = RecoveryGuard {
+            shard: shard,
+            recovering: recovering,
+        };
+        println!("Recovering the replica of shard {} of index {} from {}", shard, index, primary_address);
+        match recover(&index, shard, &primary_address, &shard_tx, &recovery_directory) {
+            Ok(seq) => println!("Replica of shard {} of index {} has caught up to operation {}", shard, index, seq),
+            Err(e) => println!("Replica of shard {} of index {} could not recover: {}", shard, index, e),
+        }
+    });
+    true
+}
+
+/// Clears a Shard's recovering flag once its recovery thread ends, however it ends
+struct RecoveryGuard {
+    shard: u32,
+    recovering: Arc<Mutex<BTreeSet<u32>>>,
+}
+
+impl Drop for RecoveryGuard {
+    fn drop(&mut self) {
+        if let Ok(mut recovering) = self.recovering.lock() {
+            recovering.remove(&self.shard);
+        }
+    }
+}
+
+/// Brings a Replica up to date with its Primary. Returns the sequence number of the last
+/// Operation the Replica then has.
+fn recover(
+    index: &str,
+    shard: u32,
+    primary_address: &str,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    recovery_directory: &Path,
+) -> Result<u64, RecoveryError> {
+    let client = RpcClient::connect(primary_address, RECOVERY_RPC_TIMEOUT)?;
+    let mut seq = last_sequence(shard_tx).ok_or_else(|| RecoveryError::new("Replica stopped before it could answer"))?;
+    if seq == 0 {
+        let message = Message::new(MessageType::LAST_SEQUENCE).arg(index.to_owned()).arg(shard.to_string());
+        let reply = request(&client, message, MessageType::LAST_SEQUENCE)?;
+        let primary_seq: u64 = parse_arg(&reply, 0, "a sequence number")?;
+        if primary_seq > 0 {
+            seq = copy_snapshot(&client, index, shard, shard_tx, recovery_directory)?;
+        }
+    } else if !matches_primary(&client, index, shard, shard_tx, seq)? {
+        // The Replica took writes from a Primary that was replaced, and they can't be undone
+        // in its segments, so it starts over from its current Primary
+        println!(
+            "Replica of shard {} of index {} has operations its primary does not, restoring it from a snapshot",
+            shard, index
+        );
+        seq = copy_snapshot(&client, index, shard, shard_tx, recovery_directory)?;
+    }
+    replay(&client, index, shard, shard_tx, seq)
+}
+
+/// Returns true if the Primary's OpLog has the Replica's last Operation, in the same term. As
+/// each Operation is only taken if it follows on from the one before it, the rest of the
+/// Replica's OpLog then matches the Primary's too.
+fn matches_primary(
+    client: &RpcClient,
+    index: &str,
+    shard: u32,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    seq: u64,
+) -> Result<bool, RecoveryError> {
+    let term = match operations_since(shard_tx, seq - 1)?.first() {
+        Some(operation) if operation.seq == seq => operation.term,
+        _ => return Err(RecoveryError::new(&format!("Replica has no operation {}", seq))),
+    };
+    let message = Message::new(MessageType::RECOVER_OPERATIONS)
+        .arg(index.to_owned())
+        .arg(shard.to_string())
+        .arg((seq - 1).to_string());
+    let reply = request(client, message, MessageType::RECOVER_OPERATIONS)?;
+    match reply.args.get(0) {
+        Some(operation) => {
+            let operation = Operation::from_json(operation)?;
+            Ok(operation.seq == seq && operation.term == term)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Copies a snapshot of the Primary into the recovery directory and restores the Replica from
+/// it. Returns the sequence number of the last Operation in the snapshot.
+fn copy_snapshot(
+    client: &RpcClient,
+    index: &str,
+    shard: u32,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    recovery_directory: &Path,
+) -> Result<u64, RecoveryError> {
+    let message = Message::new(MessageType::RECOVER).arg(index.to_owned()).arg(shard.to_string());
+    let reply = request(client, message, MessageType::RECOVER)?;
+    let id = match reply.args.get(0) {
+        Some(id) => id.clone(),
+        None => return Err(RecoveryError::new("Expected the ID of a snapshot")),
+    };
+    let staging = recovery_directory.join(INCOMING_DIRECTORY).join(Uuid::new_v4().to_string());
+    let files = reply.args.get(2..).unwrap_or(&[]);
+    let result = copy_files(client, &id, files, &staging).and_then(|_| restore(shard_tx, &staging));
+    // The Primary can let go of its snapshot whether or not the copy worked
+    let _ = client.request(Message::new(MessageType::RECOVER_DONE).arg(id));
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+/// Copies each file of a snapshot into a directory, a chunk at a time
+fn copy_files(client: &RpcClient, id: &str, files: &[String], directory: &Path) -> Result<(), RecoveryError> {
+    fs::create_dir_all(directory)?;
+    for name in files {
+        check_file_name(name)?;
+        let mut file = File::create(directory.join(name))?;
+        let mut offset = 0u64;
+        loop {
+            let message = Message::new(MessageType::RECOVER_CHUNK)
+                .arg(id.to_owned())
+                .arg(name.clone())
+                .arg(offset.to_string());
+            let reply = request(client, message, MessageType::RECOVER_CHUNK)?;
+            let chunk = match reply.args.get(0) {
+                Some(chunk) => base64::decode(chunk)
+                    .map_err(|e| RecoveryError::new(&format!("Could not decode part of a file: {}", e)))?,
+                None => return Err(RecoveryError::new("Expected part of a file")),
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            file.write_all(&chunk)?;
+            offset += chunk.len() as u64;
+        }
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Replaces everything in a Replica with a snapshot that has been copied to a directory
+fn restore(shard_tx: &mpsc::Sender<IndexCommand>, directory: &Path) -> Result<u64, RecoveryError> {
+    let (response_tx, response_rx) = mpsc::channel();
+    shard_tx
+        .send(IndexCommand::Restore {
+            directory: directory.to_path_buf(),
+            response_channel: response_tx,
+        })
+        .map_err(|_| RecoveryError::new("Replica is no longer running"))?;
+    match response_rx.recv() {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(RecoveryError::new("Replica stopped before it was restored")),
+    }
+}
+
+/// Applies the Operations the Replica is missing from the Primary's OpLog, a batch at a time,
+/// until the Primary has none left to send. Writes the Primary takes after that reach the
+/// Replica the usual way.
+fn replay(
+    client: &RpcClient,
+    index: &str,
+    shard: u32,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    mut seq: u64,
+) -> Result<u64, RecoveryError> {
+    loop {
+        let message = Message::new(MessageType::RECOVER_OPERATIONS)
+            .arg(index.to_owned())
+            .arg(shard.to_string())
+            .arg(seq.to_string());
+        let reply = request(client, message, MessageType::RECOVER_OPERATIONS)?;
+        if reply.args.is_empty() {
+            return Ok(seq);
+        }
+        for operation in &reply.args {
+            let operation = Operation::from_json(operation)?;
+            let operation_seq = operation.seq;
+            let (response_tx, response_rx) = mpsc::channel();
+            shard_tx
+                .send(IndexCommand::ApplyOperation {
+                    operation: operation,
+                    response_channel: response_tx,
+                })
+                .map_err(|_| RecoveryError::new("Replica is no longer running"))?;
+            match response_rx.recv() {
+                Ok(result) => result?,
+                Err(_) => return Err(RecoveryError::new("Replica stopped before applying an operation")),
+            }
+            seq = operation_seq;
+        }
+    }
+}
+
+/// Sends a request to the Primary and checks the reply is of the type expected
+fn request(client: &RpcClient, message: Message, expected: MessageType) -> Result<Message, RecoveryError> {
+    let reply = client.request(message)?;
+    if reply.message_type != expected {
+        return Err(RecoveryError::new(&reply.args.join(", ")));
+    }
+    Ok(reply)
+}
+
+/// Answers one of the requests a Replica sends while it recovers from a copy of a Shard on this
+/// Node. Making a snapshot waits for every segment to be copied, so this should be called off
+/// the thread that handles Messages. Snapshots that have not been read from for
+/// `SNAPSHOT_EXPIRY` are removed first.
+///
+/// # Arguments
+///
+/// * `message` - A RECOVER, RECOVER_CHUNK, RECOVER_DONE or RECOVER_OPERATIONS request
+/// * `recovery_directory` - Directory snapshots for other Nodes are made in
+/// * `local_shard` - Returns the channel to the `Manager` of the copy of a Shard on this Node,
+///   given the name of the Index and the number of the Shard
+pub fn answer<F>(message: &Message, recovery_directory: &Path, local_shard: F) -> Message
+where
+    F: Fn(&str, u32) -> Result<mpsc::Sender<IndexCommand>, RecoveryError>,
+{
+    expire_snapshots(recovery_directory, SNAPSHOT_EXPIRY);
+    let args = &message.args;
+    let reply = match message.message_type {
+        MessageType::RECOVER => parse_shard(message)
+            .and_then(|(index, shard)| local_shard(index, shard))
+            .and_then(|shard_tx| take_snapshot(&shard_tx, recovery_directory))
+            .map(|(id, snapshot)| {
+                Message::new(MessageType::RECOVER)
+                    .arg(id)
+                    .arg(snapshot.seq.to_string())
+                    .args(snapshot.files)
+            }),
+        MessageType::RECOVER_CHUNK => match (args.get(0), args.get(1)) {
+            (Some(id), Some(name)) => parse_arg(message, 2, "an offset")
+                .and_then(|offset| read_chunk(recovery_directory, id, name, offset))
+                .map(|chunk| Message::new(MessageType::RECOVER_CHUNK).arg(base64::encode(&chunk))),
+            _ => Err(RecoveryError::new("Expected a snapshot ID, a file name and an offset")),
+        },
+        MessageType::RECOVER_DONE => match args.get(0) {
+            Some(id) => remove_snapshot(recovery_directory, id).map(|_| Message::new(MessageType::RECOVER_DONE)),
+            None => Err(RecoveryError::new("Expected a snapshot ID")),
+        },
+        MessageType::RECOVER_OPERATIONS => parse_shard(message)
+            .and_then(|(index, shard)| {
+                let seq = parse_arg(message, 2, "a sequence number")?;
+                let shard_tx = local_shard(index, shard)?;
+                operations_since(&shard_tx, seq)
+            })
+            .map(|operations| {
+                Message::new(MessageType::RECOVER_OPERATIONS)
+                    .args(operations.iter().map(|operation| operation.to_json()).collect())
+            }),
+        _ => Err(RecoveryError::new(&format!("{:?} is not a recovery request", message.message_type))),
+    };
+    match reply {
+        Ok(reply) => reply,
+        Err(e) => Message::new(MessageType::ERROR).arg(e.to_string()),
+    }
+}
+
+/// Makes a snapshot of a Shard in a new directory of its own. Returns the snapshot's ID, which
+/// names that directory.
+fn take_snapshot(shard_tx: &mpsc::Sender<IndexCommand>, recovery_directory: &Path) -> Result<(String, Snapshot), RecoveryError> {
+    let id = Uuid::new_v4().to_string();
+    let directory = recovery_directory.join(OUTGOING_DIRECTORY).join(&id);
+    let (response_tx, response_rx) = mpsc::channel();
+    shard_tx
+        .send(IndexCommand::Snapshot {
+            directory: directory.clone(),
+            response_channel: response_tx,
+        })
+        .map_err(|_| RecoveryError::new("Shard is no longer running"))?;
+    let result = match response_rx.recv() {
+        Ok(result) => result.map_err(RecoveryError::from),
+        Err(_) => Err(RecoveryError::new("Shard stopped before the snapshot was made")),
+    };
+    match result.and_then(|snapshot| touch_snapshot(&directory).map(|_| snapshot)) {
+        Ok(snapshot) => Ok((id, snapshot)),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&directory);
+            Err(e)
+        }
+    }
+}
+
+/// Reads up to `RECOVERY_CHUNK_SIZE` bytes of a file in a snapshot, starting at `offset`
+fn read_chunk(recovery_directory: &Path, id: &str, name: &str, offset: u64) -> Result<Vec<u8>, RecoveryError> {
+    check_file_name(name)?;
+    let directory = snapshot_directory(recovery_directory, id)?;
+    let mut file = File::open(directory.join(name))?;
+    touch_snapshot(&directory)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut chunk = vec![];
+    file.take(RECOVERY_CHUNK_SIZE as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Removes a snapshot once the Replica it was made for has finished with it
+fn remove_snapshot(recovery_directory: &Path, id: &str) -> Result<(), RecoveryError> {
+    let directory = snapshot_directory(recovery_directory, id)?;
+    if directory.exists() {
+        fs::remove_dir_all(directory)?;
+    }
+    Ok(())
+}
+
+/// Marks a snapshot made for another Node as just used
+fn touch_snapshot(directory: &Path) -> Result<(), RecoveryError> {
+    File::create(directory.join(LAST_USED_FILE))?;
+    Ok(())
+}
+
+/// Removes the snapshots made for other Nodes that have not been read from for `expiry`
+fn expire_snapshots(recovery_directory: &Path, expiry: Duration) {
+    let entries = match fs::read_dir(recovery_directory.join(OUTGOING_DIRECTORY)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let directory = entry.path();
+        let last_used = fs::metadata(directory.join(LAST_USED_FILE))
+            .or_else(|_| fs::metadata(&directory))
+            .and_then(|metadata| metadata.modified());
+        let idle = match last_used {
+            Ok(last_used) => SystemTime::now().duration_since(last_used).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        if idle >= expiry {
+            println!("Removing snapshot {} that has not been used for {:?}", directory.display(), idle);
+            if let Err(e) = fs::remove_dir_all(&directory) {
+                println!("There was an error removing snapshot {}: {}", directory.display(), e);
+            }
+        }
+    }
+}
+
+/// Fetches up to `RECOVERY_BATCH_SIZE` of the Operations in a Shard's OpLog after `seq`, and
+/// fewer if they add up to more than `RECOVERY_BATCH_BYTES`
+fn operations_since(shard_tx: &mpsc::Sender<IndexCommand>, seq: u64) -> Result<Vec<Operation>, RecoveryError> {
+    let (response_tx, response_rx) = mpsc::channel();
+    shard_tx
+        .send(IndexCommand::OperationsSince {
+            seq: seq,
+            limit: RECOVERY_BATCH_SIZE,
+            max_bytes: RECOVERY_BATCH_BYTES,
+            response_channel: response_tx,
+        })
+        .map_err(|_| RecoveryError::new("Shard is no longer running"))?;
+    match response_rx.recv() {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(RecoveryError::new("Shard stopped before it could answer")),
+    }
+}
+
+/// Returns the directory a snapshot was made in. The ID comes from another Node, so it must be
+/// one this Node could have handed out rather than a path.
+fn snapshot_directory(recovery_directory: &Path, id: &str) -> Result<PathBuf, RecoveryError> {
+    if Uuid::parse_str(id).is_err() {
+        return Err(RecoveryError::new(&format!("{} is not a snapshot ID", id)));
+    }
+    Ok(recovery_directory.join(OUTGOING_DIRECTORY).join(id))
+}
+
+/// Checks that a file name from another Node names a file directly within a snapshot
+fn check_file_name(name: &str) -> Result<(), RecoveryError> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return Err(RecoveryError::new(&format!("{} is not the name of a snapshot file", name)));
+    }
+    Ok(())
+}
+
+/// Reads the name of the Index and the number of the Shard from the first two arguments
+fn parse_shard(message: &Message) -> Result<(&str, u32), RecoveryError> {
+    match message.args.get(0) {
+        Some(index) => Ok((index.as_str(), parse_arg(message, 1, "a shard number")?)),
+        None => Err(RecoveryError::new("Expected an index and a shard number")),
+    }
+}
+
+/// Parses the argument at `position`, described by `expected` if it is missing or invalid
+fn parse_arg<T: ::std::str::FromStr>(message: &Message, position: usize, expected: &str) -> Result<T, RecoveryError> {
+    match message.args.get(position).and_then(|arg| arg.parse().ok()) {
+        Some(value) => Ok(value),
+        None => Err(RecoveryError::new(&format!("Expected {}", expected))),
+    }
+}
+
+/// Custom error type returned when a Replica can't be recovered
+#[derive(Debug)]
+pub struct RecoveryError {
+    details: String,
+}
+
+impl RecoveryError {
+    /// Creates and returns a new RecoveryError
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The error message we want to include in the RecoveryError
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rpc::recovery::RecoveryError;
+    /// let _recovery_error = RecoveryError::new("Primary went away!");
+    /// ```
+    pub fn new(msg: &str) -> RecoveryError {
+        RecoveryError { details: msg.to_string() }
+    }
+}
+
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for RecoveryError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+impl From<io::Error> for RecoveryError {
+    fn from(err: io::Error) -> RecoveryError {
+        RecoveryError::new(&err.to_string())
+    }
+}
+
+impl From<InvertedIndexError> for RecoveryError {
+    fn from(err: InvertedIndexError) -> RecoveryError {
+        RecoveryError::new(&err.to_string())
+    }
+}
+
+impl From<RpcError> for RecoveryError {
+    fn from(err: RpcError) -> RecoveryError {
+        RecoveryError::new(&err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use inverted_index::document::Document;
+    use inverted_index::manager::{Manager, ManagerConfiguration};
+    use inverted_index::replication::OperationKind;
+    use codec;
+
+    /// Starts a Shard's `Manager` with its data in a directory of its own
+    fn start_shard(data_directory: &Path) -> mpsc::Sender<IndexCommand> {
+        let (tx, rx) = mpsc::channel();
+        Manager::new(ManagerConfiguration::new("logs", data_directory.to_path_buf()), rx).unwrap();
+        tx
+    }
+
+    /// Starts a Node that answers recovery requests for a single Shard, as a Primary's would
+    fn primary(shard_tx: mpsc::Sender<IndexCommand>, recovery_directory: PathBuf) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                while let Ok(Some(request)) = codec::read_message(&mut stream) {
+                    let reply = match request.message_type {
+                        MessageType::LAST_SEQUENCE => {
+                            Message::new(MessageType::LAST_SEQUENCE).arg(last_sequence(&shard_tx).unwrap().to_string())
+                        }
+                        _ => answer(&request, &recovery_directory, |_, _| Ok(shard_tx.clone())),
+                    };
+                    codec::write_message(&mut stream, &reply.reply_to(request.message_id)).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    fn index(shard_tx: &mpsc::Sender<IndexCommand>, id: u64, raw: &str) {
+        let (response_tx, response_rx) = mpsc::channel();
+        shard_tx.send(IndexCommand::IndexDocument {
+            document: Document::new(id, raw),
+            response_channel: Some(response_tx),
+        }).unwrap();
+        response_rx.recv().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        let directory = PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4()));
+        let primary_tx = start_shard(&directory.join("primary"));
+        let replica_tx = start_shard(&directory.join("replica"));
+        for id in 0..3 {
+            index(&primary_tx, id, "A molar bear");
+        }
+        let address = primary(primary_tx.clone(), directory.join("primary").join("recovery"));
+
+        let replica_recovery = directory.join("replica").join("recovery");
+        assert_eq!(recover("logs", 0, &address, &replica_tx, &replica_recovery).unwrap(), 3);
+        // Already recovered, so only new Operations are replayed
+        index(&primary_tx, 3, "A polar bear");
+        assert_eq!(recover("logs", 0, &address, &replica_tx, &replica_recovery).unwrap(), 4);
+
+        // The Replica took a write from a Primary that has since been replaced
+        let (apply_tx, apply_rx) = mpsc::channel();
+        replica_tx.send(IndexCommand::ApplyOperation {
+            operation: Operation {
+                seq: 5,
+                term: 1,
+                prev_term: 1,
+                kind: OperationKind::Delete { id: 0 },
+            },
+            response_channel: apply_tx,
+        }).unwrap();
+        apply_rx.recv().unwrap().unwrap();
+        assert_eq!(recover("logs", 0, &address, &replica_tx, &replica_recovery).unwrap(), 4);
+
+        for id in 0..4 {
+            let (get_tx, get_rx) = mpsc::channel();
+            replica_tx.send(IndexCommand::GetDocument {
+                id: id,
+                response_channel: get_tx,
+            }).unwrap();
+            assert!(get_rx.recv().unwrap().unwrap().is_some());
+        }
+        // Both sides have cleaned up after the snapshot
+        assert_eq!(fs::read_dir(directory.join("primary").join("recovery").join(OUTGOING_DIRECTORY)).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(replica_recovery.join(INCOMING_DIRECTORY)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_recovery_guard() {
+        let recovering = Arc::new(Mutex::new(BTreeSet::new()));
+        let (shard_tx, _shard_rx) = mpsc::channel();
+        recovering.lock().unwrap().insert(0);
+        assert!(!start_recovery("logs", 0, "127.0.0.1:1", shard_tx, PathBuf::from("/tmp"), recovering.clone()));
+        {
+            let _guard = RecoveryGuard {
+                shard: 0,
+                recovering: recovering.clone(),
+            };
+        }
+        assert!(recovering.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_answer_checks_paths() {
+        let recovery_directory = PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4()));
+        let id = Uuid::new_v4().to_string();
+        let request = |id: &str, name: &str| {
+            let message = Message::new(MessageType::RECOVER_CHUNK)
+                .arg(id.to_owned())
+                .arg(name.to_owned())
+                .arg(String::from("0"));
+            answer(&message, &recovery_directory, |_, _| Err(RecoveryError::new("No shards")))
+        };
+        assert_eq!(request("..", "metadata.db").message_type, MessageType::ERROR);
+        assert_eq!(request(&id, "../../metadata.db").message_type, MessageType::ERROR);
+        assert_eq!(request(&id, "..").message_type, MessageType::ERROR);
+
+        fs::create_dir_all(recovery_directory.join(OUTGOING_DIRECTORY).join(&id)).unwrap();
+        let mut file = File::create(recovery_directory.join(OUTGOING_DIRECTORY).join(&id).join("0.db")).unwrap();
+        file.write_all(&[0, 15, 255]).unwrap();
+        let reply = request(&id, "0.db");
+        assert_eq!(reply.args, vec!["AA//"]);
+        assert_eq!(base64::decode(&reply.args[0]).unwrap(), vec![0, 15, 255]);
+    }
+
+    #[test]
+    fn test_expire_snapshots() {
+        let recovery_directory = PathBuf::from(format!("/tmp/saga_test_{}", Uuid::new_v4()));
+        let snapshot = recovery_directory.join(OUTGOING_DIRECTORY).join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&snapshot).unwrap();
+        touch_snapshot(&snapshot).unwrap();
+        expire_snapshots(&recovery_directory, SNAPSHOT_EXPIRY);
+        assert!(snapshot.exists());
+        expire_snapshots(&recovery_directory, Duration::from_secs(0));
+        assert!(!snapshot.exists());
+        fs::remove_dir_all(&recovery_directory).unwrap();
+    }
+}
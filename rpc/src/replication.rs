@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use inverted_index::index::InvertedIndexError;
+use inverted_index::replication::{Replicate, ReplicationReport, WriteOutcome};
+
+use client::{RpcClient, DEFAULT_RPC_TIMEOUT};
+use messages::{Message, MessageType};
+
+/// The Node a Replica of a Shard is placed on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicaTarget {
+    /// Name of the Node
+    pub node: String,
+    /// Address of the Node's RPC server, as `host:port`
+    pub address: String,
+}
+
+/// The Replicas a Primary Shard sends its writes to. It is shared between the `IndexRegistry`,
+/// which updates it when the metadata server moves Replicas, and the Shard's replicator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicaSet {
+    /// Number of copies of the Shard the Index is configured with, Primary included. Replicas
+    /// that have not been placed yet still count, so they can't make a write look safer than it is.
+    pub copies: usize,
+    /// Where the Replicas that have been placed are
+    pub targets: Vec<ReplicaTarget>,
+    /// The Shard's term, which is sent with every write so Replicas can refuse those from a
+    /// Primary that has been replaced
+    pub term: u64,
+}
+
+impl Default for ReplicaSet {
+    fn default() -> ReplicaSet {
+        ReplicaSet {
+            copies: 1,
+            targets: vec![],
+            term: 1,
+        }
+    }
+}
+
+/// Starts the thread that sends a Primary Shard's writes to its Replicas, in the order they were
+/// logged, and returns the channel its `Manager` hands them off on. The thread stops once the
+/// Manager does.
+///
+/// Each write goes to one Replica after another, and the client is answered as soon as enough
+/// copies have it for the write's `WriteConsistency`. A Replica that can't be reached or refuses
+/// the write doesn't count, and is left behind until it is recovered.
+///
+/// # Arguments
+///
+/// * `index` - Name of the Index
+/// * `shard` - Number of the Shard
+/// * `replicas` - Where the Shard's Replicas are
+pub fn start_replicator(index: &str, shard: u32, replicas: Arc<Mutex<ReplicaSet>>) -> mpsc::Sender<Replicate> {
+    let (tx, rx): (mpsc::Sender<Replicate>, mpsc::Receiver<Replicate>) = mpsc::channel();
+    let index = index.to_owned();
+    thread::spawn(move || {
+        // Connections to the Nodes holding Replicas, by address
+        let mut clients: HashMap<String, RpcClient> = HashMap::new();
+        for replicate in rx.iter() {
+            let replica_set = match replicas.lock() {
+                Ok(replica_set) => replica_set.clone(),
+                Err(_) => ReplicaSet::default(),
+            };
+            let mut report = ReplicationReport {
+                consistency: replicate.consistency,
+                copies: replica_set.copies,
+                successful: 1,
+            };
+            let seq = replicate.operation.seq;
+            let mut response_channel = replicate.response_channel;
+            answer_when_met(&mut response_channel, seq, report);
+
+            let operation = replicate.operation.to_json();
+            for target in &replica_set.targets {
+                let message = Message::new(MessageType::REPLICATE)
+                    .arg(index.clone())
+                    .arg(shard.to_string())
+                    .arg(operation.clone())
+                    .arg(replica_set.term.to_string());
+                match send_to_replica(&mut clients, target, message) {
+                    Ok(()) => {
+                        report.successful += 1;
+                        answer_when_met(&mut response_channel, seq, report);
+                    }
+                    Err(e) => {
+                        println!(
+                            "Could not replicate operation {} of shard {} of index {} to node {}: {}",
+                            seq, shard, index, target.node, e
+                        );
+                    }
+                }
+            }
+            // Not enough copies have the write, so the client is told how many do
+            if let Some(response_channel) = response_channel {
+                let _ = response_channel.send(Ok(outcome(seq, report)));
+            }
+        }
+    });
+    tx
+}
+
+/// Answers the client if enough copies have the write, unless it has already been answered
+fn answer_when_met(
+    response_channel: &mut Option<mpsc::Sender<Result<WriteOutcome, InvertedIndexError>>>,
+    seq: u64,
+    report: ReplicationReport,
+) {
+    if report.is_met() {
+        if let Some(response_channel) = response_channel.take() {
+            let _ = response_channel.send(Ok(outcome(seq, report)));
+        }
+    }
+}
+
+fn outcome(seq: u64, report: ReplicationReport) -> WriteOutcome {
+    WriteOutcome {
+        seq: seq,
+        found: true,
+        replication: report,
+    }
+}
+
+/// Sends a write to a Replica and waits for it to be applied, connecting to its Node first if
+/// there is no connection or it was lost
+fn send_to_replica(clients: &mut HashMap<String, RpcClient>, target: &ReplicaTarget, message: Message) -> Result<(), String> {
+    let connected = clients.get(&target.address).map_or(false, |client| !client.is_closed());
+    if !connected {
+        let client = RpcClient::connect(&target.address, DEFAULT_RPC_TIMEOUT).map_err(|e| e.to_string())?;
+        clients.insert(target.address.clone(), client);
+    }
+    let reply = match clients.get(&target.address) {
+        Some(client) => client.request(message).map_err(|e| e.to_string())?,
+        None => return Err(String::from("No connection")),
+    };
+    match reply.message_type {
+        MessageType::REPLICATE => Ok(()),
+        _ => Err(reply.args.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use inverted_index::replication::{Operation, OperationKind, WriteConsistency};
+    use codec;
+
+    /// Starts a Replica that applies every write it is sent
+    fn replica() -> ReplicaTarget {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Ok(Some(request)) = codec::read_message(&mut stream) {
+                let reply = Message::new(MessageType::REPLICATE).reply_to(request.message_id);
+                codec::write_message(&mut stream, &reply).unwrap();
+            }
+        });
+        ReplicaTarget {
+            node: String::from("replica"),
+            address: address,
+        }
+    }
+
+    /// Address nothing is listening on
+    fn unreachable() -> ReplicaTarget {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        ReplicaTarget {
+            node: String::from("gone"),
+            address: address,
+        }
+    }
+
+    fn write(tx: &mpsc::Sender<Replicate>, seq: u64, consistency: WriteConsistency) -> WriteOutcome {
+        let (response_tx, response_rx) = mpsc::channel();
+        tx.send(Replicate {
+            operation: Operation {
+                seq: seq,
+                term: 1,
+                prev_term: 1,
+                kind: OperationKind::Delete { id: 1 },
+            },
+            consistency: consistency,
+            response_channel: Some(response_tx),
+        }).unwrap();
+        response_rx.recv().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_replicate() {
+        let replicas = Arc::new(Mutex::new(ReplicaSet {
+            copies: 3,
+            targets: vec![unreachable(), replica()],
+            term: 1,
+        }));
+        let tx = start_replicator("logs", 0, replicas.clone());
+
+        let outcome = write(&tx, 1, WriteConsistency::Quorum);
+        assert_eq!(outcome.seq, 1);
+        assert_eq!(outcome.replication.successful, 2);
+        assert!(outcome.replication.is_met());
+
+        let outcome = write(&tx, 2, WriteConsistency::All);
+        assert_eq!(outcome.replication.successful, 2);
+        assert!(!outcome.replication.is_met());
+
+        // Only the Primary is needed, so the client is answered before any Replica is tried
+        let outcome = write(&tx, 3, WriteConsistency::One);
+        assert_eq!(outcome.replication.successful, 1);
+        assert!(outcome.replication.is_met());
+    }
+}
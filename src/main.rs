@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate clap;
+extern crate ctrlc;
 extern crate uuid;
 extern crate rusqlite;
 extern crate serde;
@@ -11,26 +12,32 @@ extern crate inverted_index;
 extern crate rpc;
 
 use std::path::PathBuf;
-use std::time;
+use std::process;
+use std::time::{self, Duration};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 
 use clap::App;
 use hyper::server::Http;
 
-use inverted_index::manager::Manager;
-use inverted_index::manager;
-use inverted_index::shard;
-use inverted_index::manager::StorageEngine;
-
 use rpc::node::{Node, NodeConfiguration};
-use rpc::messages::Message;
+use rpc::client::DEFAULT_RPC_TIMEOUT;
+use rpc::messages::{Message, MessageType};
 use rpc::db::MetadataDB;
+use rpc::drain;
+use rpc::heartbeat::HeartbeatConfiguration;
+use rpc::indices::IndexRegistry;
+use rpc::raft::RaftPeer;
+use inverted_index::replication::WriteConsistency;
 
 use web::router;
 use web::{Saga, ServiceConfiguration};
+use web::workers::{self, WorkerPool};
 use web::handlers::health;
 use web::handlers::cluster;
+use web::handlers::search;
+use web::handlers::documents;
+use web::handlers::indices;
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
@@ -38,10 +45,7 @@ fn main() {
     let server_matches = matches.subcommand_matches("server");
     let server_matches = server_matches.unwrap();
 
-    let data_path = server_matches.value_of("data_path").unwrap_or("/tmp/saga/");
-
-    let (_index_manager_tx, index_manager_rx): (mpsc::Sender<manager::IndexCommand>, mpsc::Receiver<manager::IndexCommand>) = mpsc::channel();
-    let _index_manager = Manager::new("test_idx", PathBuf::from(data_path), index_manager_rx, StorageEngine::SQLite, shard::ShardType::Primary);
+    let index_data_path = server_matches.value_of("data_path").unwrap_or("/tmp/saga/");
 
     let metadata_address: &str;
     let metadata_port: &str;
@@ -71,6 +75,37 @@ fn main() {
 
     let data_path: &str = server_matches.value_of("data_dir").unwrap_or("/tmp");
 
+    let max_body_size = match server_matches.value_of("max_body_size") {
+        Some(size) => size.parse::<usize>().expect("max_body_size must be a number of bytes"),
+        None => web::DEFAULT_MAX_BODY_SIZE,
+    };
+    let web_workers = match server_matches.value_of("web_workers") {
+        Some(threads) => threads.parse::<usize>().expect("web_workers must be a number of threads"),
+        None => workers::DEFAULT_WORKER_THREADS,
+    };
+    let request_timeout = millis_arg(server_matches.value_of("request_timeout"), "request_timeout", workers::DEFAULT_REQUEST_TIMEOUT);
+
+    let default_heartbeat = HeartbeatConfiguration::default();
+    let heartbeat = HeartbeatConfiguration {
+        interval: millis_arg(server_matches.value_of("heartbeat_interval"), "heartbeat_interval", default_heartbeat.interval),
+        suspect_timeout: millis_arg(server_matches.value_of("suspect_timeout"), "suspect_timeout", default_heartbeat.suspect_timeout),
+        dead_timeout: millis_arg(server_matches.value_of("dead_timeout"), "dead_timeout", default_heartbeat.dead_timeout),
+    };
+
+    let drain_timeout = millis_arg(server_matches.value_of("drain_timeout"), "drain_timeout", drain::DEFAULT_DRAIN_TIMEOUT);
+    let write_consistency = match server_matches.value_of("write_consistency") {
+        Some(consistency) => consistency.parse::<WriteConsistency>().expect("write_consistency must be one, quorum or all"),
+        None => WriteConsistency::One,
+    };
+
+    let metadata_nodes: Vec<RaftPeer> = match server_matches.value_of("metadata_nodes") {
+        Some(members) => members
+            .split(',')
+            .map(|member| member.parse::<RaftPeer>().expect("metadata_nodes must be a list of name@host:port"))
+            .collect(),
+        None => vec![],
+    };
+
     let addr = (web_address.to_owned() + ":" + web_port).parse().unwrap();
 
     // Set up the Node struct for this server
@@ -84,12 +119,34 @@ fn main() {
         am_metadata_server: am_metadata_server,
         rx: Arc::new(Mutex::new(my_node_rx)),
         rpc_address: rpc_address.to_owned(),
-        rpc_port: rpc_port.parse::<u16>().unwrap()
+        rpc_port: rpc_port.parse::<u16>().unwrap(),
+        heartbeat: heartbeat,
+        metadata_nodes: metadata_nodes
     };
 
-    let mut my_node = Node::new(my_node_config);
+    let mut my_node = Node::new(my_node_config).expect("Could not open the metadata database");
     MetadataDB::create_cluster_table(&mut my_node.db);
     MetadataDB::create_node_table(&mut my_node.db);
+    MetadataDB::create_index_tables(&my_node.db);
+    // Metadata Nodes keep their metadata databases in step by replaying the Raft log
+    my_node.start_raft().expect("Could not start Raft");
+
+    // A metadata Node starts the Shards of every open Index that were placed on it before it
+    // last stopped. Any other Node has no Index metadata of its own, and is sent its Shards by
+    // the metadata leader once it registers.
+    let mut index_registry = IndexRegistry::new(PathBuf::from(index_data_path));
+    if am_metadata_server {
+        match MetadataDB::assignments_for_node(&my_node.db, node_name) {
+            Ok(assignments) => {
+                if let Err(e) = index_registry.apply(&assignments) {
+                    println!("There was an error starting the shards placed on this node: {}", e);
+                }
+            },
+            Err(e) => {
+                println!("There was an error finding the shards placed on this node: {}", e);
+            }
+        }
+    }
 
     // Set up the RPC server and start it
     // TODO: There may be a cleaner way to handle this without so many clones
@@ -97,12 +154,21 @@ fn main() {
     let cloned_rpc_port = rpc_port.parse::<u32>().unwrap().clone();
     let my_node_tx = Arc::new(Mutex::new(my_node_tx));
     let cloned_my_node_tx = my_node_tx.clone();
+    let cloned_raft = my_node.raft.clone();
     thread::spawn(move || {
-        Node::start_rpc_server(cloned_rpc_address, cloned_rpc_port, cloned_my_node_tx.clone());
+        Node::start_rpc_server(cloned_rpc_address, cloned_rpc_port, cloned_my_node_tx.clone(), cloned_raft);
     });
 
+    // The Node starts and stops Shards through the switchboard when the metadata server moves them
+    let mut switchboard = rpc::Switchboard::new(my_node_tx.clone(), index_registry);
+    switchboard.write_consistency = write_consistency;
+    switchboard.request_timeout = request_timeout;
+    let swb = Arc::new(Mutex::new(switchboard));
+    my_node.switchboard = Some(swb.clone());
+
     // If we aren't the metadata server, we need to establish a connection and register with the
-    // metadata server
+    // metadata leader. The metadata leader registers itself once it takes over, and the other
+    // metadata Nodes are registered from their heartbeats.
     if !am_metadata_server {
         loop {
             if my_node.register_with_metadata_server().is_err() {
@@ -112,6 +178,9 @@ fn main() {
                 break;
             }
         }
+        my_node.start_heartbeats();
+    } else if my_node.raft.is_some() {
+        my_node.start_heartbeats();
     }
 
     // Starts the RPC listening loop in a background thread
@@ -120,19 +189,21 @@ fn main() {
     });
     // END
 
-    let swb =
-        Arc::new(
-            Mutex::new(
-                rpc::Switchboard::new(my_node_tx.clone())
-            )
-        );
+    // Leave the cluster cleanly on Ctrl-C or SIGTERM
+    let shutdown_swb = swb.clone();
+    ctrlc::set_handler(move || {
+        shutdown(&shutdown_swb, drain_timeout);
+        process::exit(0);
+    }).expect("Could not set up the shutdown handler");
 
     // Configure and start up the web server
 
     let cloned_data_path = data_path.to_owned();
+    let worker_pool = WorkerPool::new(web_workers, request_timeout);
     let server = Http::new().bind(&addr, move || {
         let mut router = router::Router::new();
-        let service_config = ServiceConfiguration::new(cloned_data_path.to_owned(), swb.clone());
+        let service_config = ServiceConfiguration::new(cloned_data_path.to_owned(), swb.clone(), worker_pool.clone())
+            .with_max_body_size(max_body_size);
 
         let health_route = router::Route::new("/healthz", hyper::Method::Get, health::health_check).unwrap();
         router.add_route(health_route);
@@ -140,6 +211,48 @@ fn main() {
         let node_list_route = router::Route::new("/nodes", hyper::Method::Get, cluster::list_nodes).unwrap();
         router.add_route(node_list_route);
 
+        // Cluster-wide endpoints live under /_cluster
+        let mut cluster_router = router::Router::with_prefix("/_cluster");
+        let cluster_health_route = router::Route::new("/health", hyper::Method::Get, health::health_check).unwrap();
+        cluster_router.add_route(cluster_health_route);
+        let cluster_nodes_route = router::Route::new("/nodes", hyper::Method::Get, cluster::list_nodes).unwrap();
+        cluster_router.add_route(cluster_nodes_route);
+
+        let list_indices_route = router::Route::new("/_indices/?", hyper::Method::Get, indices::list_indices).unwrap();
+        router.add_route(list_indices_route);
+
+        let create_index_route = router::Route::new("/:index/?", hyper::Method::Put, indices::create_index).unwrap();
+        router.add_route(create_index_route);
+
+        let get_index_route = router::Route::new("/:index/?", hyper::Method::Get, indices::get_index).unwrap();
+        router.add_route(get_index_route);
+
+        let delete_index_route = router::Route::new("/:index/?", hyper::Method::Delete, indices::delete_index).unwrap();
+        router.add_route(delete_index_route);
+
+        let open_index_route = router::Route::new("/:index/_open", hyper::Method::Post, indices::open_index).unwrap();
+        router.add_route(open_index_route);
+
+        let close_index_route = router::Route::new("/:index/_close", hyper::Method::Post, indices::close_index).unwrap();
+        router.add_route(close_index_route);
+
+        let search_route = router::Route::new("/:index/_search", hyper::Method::Post, search::search).unwrap();
+        router.add_route(search_route);
+
+        let create_document_route = router::Route::new("/:index/document/?", hyper::Method::Post, documents::create_document).unwrap();
+        router.add_route(create_document_route);
+
+        let put_document_route = router::Route::new("/:index/document/(?P<id>[0-9]+)", hyper::Method::Put, documents::put_document).unwrap();
+        router.add_route(put_document_route);
+
+        let get_document_route = router::Route::new("/:index/document/(?P<id>[0-9]+)", hyper::Method::Get, documents::get_document).unwrap();
+        router.add_route(get_document_route);
+
+        let delete_document_route = router::Route::new("/:index/document/(?P<id>[0-9]+)", hyper::Method::Delete, documents::delete_document).unwrap();
+        router.add_route(delete_document_route);
+
+        let router = router.add_child(cluster_router);
+
         let saga = Saga{
             router: router,
             config: service_config
@@ -149,3 +262,42 @@ fn main() {
     println!("Starting web server on {}:{}", web_address, web_port);
     server.run().unwrap();
 }
+
+/// Shuts this Node down gracefully. New writes are refused, the ones in progress are waited for,
+/// every Shard on this Node is stopped, and then the Node tells the metadata server it is leaving.
+fn shutdown(swb: &Arc<Mutex<rpc::Switchboard>>, drain_timeout: Duration) {
+    println!("Shutting down, waiting up to {:?} for writes in progress", drain_timeout);
+    let (writes, node_tx) = match swb.lock() {
+        Ok(l) => (l.writes.clone(), l.node_tx.clone()),
+        Err(_) => {
+            println!("Could not reach the switchboard, shutting down without draining writes");
+            return;
+        }
+    };
+    if !writes.drain(drain_timeout) {
+        println!("Writes were still in progress when the drain timeout ran out");
+    }
+    if let Ok(mut l) = swb.lock() {
+        l.indices.close_all();
+    }
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let sent = match node_tx.lock() {
+        Ok(l) => l.send(Message::new(MessageType::SHUTDOWN).response_chan(done_tx)).is_ok(),
+        Err(_) => false,
+    };
+    // Leaving the cluster is one request to the metadata server, which may have to reconnect first
+    if !sent || done_rx.recv_timeout(DEFAULT_RPC_TIMEOUT * 2).is_err() {
+        println!("The node did not confirm it left the cluster");
+    }
+}
+
+/// Reads a command line argument given in milliseconds, or returns `default` if it wasn't given
+fn millis_arg(value: Option<&str>, name: &str, default: Duration) -> Duration {
+    match value {
+        Some(millis) => Duration::from_millis(
+            millis.parse::<u64>().unwrap_or_else(|_| panic!("{} must be a number of milliseconds", name)),
+        ),
+        None => default,
+    }
+}
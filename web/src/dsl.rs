@@ -0,0 +1,388 @@
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{self, Map, Value};
+
+use inverted_index::query::{Operator, Query};
+
+/// Number of hits returned when a search does not ask for a size
+pub const DEFAULT_SIZE: usize = 10;
+/// Furthest into the results a search can page, `from` plus `size`
+pub const MAX_RESULT_WINDOW: usize = 10000;
+
+/// A parsed search request
+#[derive(Debug, PartialEq)]
+pub struct SearchRequest {
+    /// What to search for
+    pub query: Query,
+    /// Number of hits to skip
+    pub from: usize,
+    /// Number of hits to return
+    pub size: usize,
+}
+
+/// Parses the body of a search request. An empty body matches every Document.
+///
+/// # Arguments
+///
+/// * `body` - The JSON body of the request
+///
+/// # Example
+///
+/// ```
+/// extern crate web;
+/// let search = web::dsl::parse_search(br#"{"query": {"match": {"message": "molar bear"}}, "size": 5}"#).unwrap();
+/// assert_eq!(search.size, 5);
+/// ```
+pub fn parse_search(body: &[u8]) -> Result<SearchRequest, QueryParseError> {
+    let mut search = SearchRequest {
+        query: Query::MatchAll,
+        from: 0,
+        size: DEFAULT_SIZE,
+    };
+    if body.iter().all(|b| (*b as char).is_whitespace()) {
+        return Ok(search);
+    }
+    let body: Value = serde_json::from_slice(body)
+        .map_err(|e| QueryParseError::new(&format!("Search body is not valid JSON: {}", e)))?;
+    let options = match body {
+        Value::Object(options) => options,
+        _ => return Err(QueryParseError::new("Search body must be a JSON object")),
+    };
+    for (key, value) in &options {
+        match key.as_str() {
+            "query" => search.query = parse_query(value)?,
+            "from" => search.from = parse_count("from", value)?,
+            "size" => search.size = parse_count("size", value)?,
+            _ => return Err(QueryParseError::new(&format!("Unknown search option '{}'", key))),
+        }
+    }
+    match search.from.checked_add(search.size) {
+        Some(window) if window <= MAX_RESULT_WINDOW => Ok(search),
+        _ => Err(QueryParseError::new(&format!(
+            "from + size can't be more than {}",
+            MAX_RESULT_WINDOW
+        ))),
+    }
+}
+
+/// Parses a single query clause, such as `{"match": {"message": "molar bear"}}`
+///
+/// # Arguments
+///
+/// * `value` - The JSON clause
+pub fn parse_query(value: &Value) -> Result<Query, QueryParseError> {
+    let (query_type, body) = single_entry(value, "A query")?;
+    match query_type.as_str() {
+        "match_all" => Ok(Query::MatchAll),
+        "match" => {
+            let (field, spec) = single_entry(body, "A match query")?;
+            match *spec {
+                Value::Object(ref options) => {
+                    check_options("match", options, &["query", "operator"])?;
+                    let text = match options.get("query") {
+                        Some(text) => scalar_text("match", text)?,
+                        None => return Err(QueryParseError::new("A match query needs a 'query'")),
+                    };
+                    let operator = match options.get("operator").and_then(|o| o.as_str()) {
+                        None | Some("or") => Operator::Or,
+                        Some("and") => Operator::And,
+                        Some(other) => {
+                            return Err(QueryParseError::new(&format!(
+                                "Unknown operator '{}', expected 'and' or 'or'",
+                                other
+                            )))
+                        }
+                    };
+                    Ok(Query::Match {
+                        field: field,
+                        text: text,
+                        operator: operator,
+                    })
+                }
+                ref text => Ok(Query::matching(field, scalar_text("match", text)?)),
+            }
+        }
+        "phrase" | "match_phrase" => {
+            let (field, spec) = single_entry(body, "A phrase query")?;
+            let text = match *spec {
+                Value::Object(ref options) => {
+                    check_options("phrase", options, &["query"])?;
+                    match options.get("query") {
+                        Some(text) => scalar_text("phrase", text)?,
+                        None => return Err(QueryParseError::new("A phrase query needs a 'query'")),
+                    }
+                }
+                ref text => scalar_text("phrase", text)?,
+            };
+            Ok(Query::phrase(field, text))
+        }
+        "term" => {
+            let (field, spec) = single_entry(body, "A term query")?;
+            let value = match *spec {
+                Value::Object(ref options) => {
+                    check_options("term", options, &["value"])?;
+                    match options.get("value") {
+                        Some(value) => value,
+                        None => return Err(QueryParseError::new("A term query needs a 'value'")),
+                    }
+                }
+                ref value => value,
+            };
+            Ok(Query::term(field, scalar("term", value)?.clone()))
+        }
+        "terms" => {
+            let (field, spec) = single_entry(body, "A terms query")?;
+            let values = match *spec {
+                Value::Array(ref values) => values,
+                _ => return Err(QueryParseError::new("A terms query needs an array of values")),
+            };
+            let mut scalars = vec![];
+            for value in values {
+                scalars.push(scalar("terms", value)?.clone());
+            }
+            Ok(Query::Terms {
+                field: field,
+                values: scalars,
+            })
+        }
+        "range" => {
+            let (field, spec) = single_entry(body, "A range query")?;
+            let options = match *spec {
+                Value::Object(ref options) => options,
+                _ => return Err(QueryParseError::new("A range query needs an object of bounds")),
+            };
+            check_options("range", options, &["gt", "gte", "lt", "lte"])?;
+            let bound = |name: &str| -> Result<Option<Value>, QueryParseError> {
+                match options.get(name) {
+                    Some(value) => Ok(Some(scalar("range", value)?.clone())),
+                    None => Ok(None),
+                }
+            };
+            Ok(Query::Range {
+                field: field,
+                gt: bound("gt")?,
+                gte: bound("gte")?,
+                lt: bound("lt")?,
+                lte: bound("lte")?,
+            })
+        }
+        "bool" => {
+            let options = match *body {
+                Value::Object(ref options) => options,
+                _ => return Err(QueryParseError::new("A bool query needs an object of clauses")),
+            };
+            check_options("bool", options, &["must", "should", "must_not"])?;
+            Ok(Query::Bool {
+                must: parse_clauses(options.get("must"))?,
+                should: parse_clauses(options.get("should"))?,
+                must_not: parse_clauses(options.get("must_not"))?,
+            })
+        }
+        other => Err(QueryParseError::new(&format!("Unknown query type '{}'", other))),
+    }
+}
+
+/// Parses the clauses of a bool query, which may be a single query or an array of them
+fn parse_clauses(value: Option<&Value>) -> Result<Vec<Query>, QueryParseError> {
+    match value {
+        None => Ok(vec![]),
+        Some(&Value::Array(ref clauses)) => clauses.iter().map(parse_query).collect(),
+        Some(clause) => Ok(vec![parse_query(clause)?]),
+    }
+}
+
+/// Splits an object that must have exactly one key, like `{"match": {...}}`, into its key and value
+fn single_entry<'a>(value: &'a Value, what: &str) -> Result<(String, &'a Value), QueryParseError> {
+    match *value {
+        Value::Object(ref map) if map.len() == 1 => {
+            let (key, value) = map.iter().next().unwrap();
+            Ok((key.clone(), value))
+        }
+        _ => Err(QueryParseError::new(&format!("{} must be an object with exactly one key", what))),
+    }
+}
+
+/// Rejects options a query does not know about, which are usually typos
+fn check_options(query_type: &str, options: &Map<String, Value>, known: &[&str]) -> Result<(), QueryParseError> {
+    for key in options.keys() {
+        if !known.contains(&key.as_str()) {
+            return Err(QueryParseError::new(&format!(
+                "Unknown option '{}' in {} query",
+                key, query_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a value is a string, number or boolean
+fn scalar<'a>(query_type: &str, value: &'a Value) -> Result<&'a Value, QueryParseError> {
+    match *value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => Ok(value),
+        _ => Err(QueryParseError::new(&format!(
+            "A {} query needs a string, number or boolean, not {}",
+            query_type, value
+        ))),
+    }
+}
+
+/// Returns the text of a scalar value, without quotes if it is a string
+fn scalar_text(query_type: &str, value: &Value) -> Result<String, QueryParseError> {
+    match *scalar(query_type, value)? {
+        Value::String(ref s) => Ok(s.clone()),
+        ref other => Ok(other.to_string()),
+    }
+}
+
+/// Reads `from` or `size`, which must be non-negative integers
+fn parse_count(name: &str, value: &Value) -> Result<usize, QueryParseError> {
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| QueryParseError::new(&format!("'{}' must be a non-negative integer", name)))
+}
+
+#[derive(Debug)]
+/// Custom error type returned when a search request can't be parsed
+pub struct QueryParseError {
+    details: String,
+}
+
+impl QueryParseError {
+    /// Creates and returns a new QueryParseError
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The error message we want to include in the QueryParseError
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use web::dsl::QueryParseError;
+    /// let error = QueryParseError::new("Unknown query type 'fuzzy'");
+    /// ```
+    pub fn new(msg: &str) -> QueryParseError {
+        QueryParseError { details: msg.to_string() }
+    }
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for QueryParseError {
+    fn description(&self) -> &str {
+        &self.details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> Result<Query, QueryParseError> {
+        parse_search(body.as_bytes()).map(|search| search.query)
+    }
+
+    #[test]
+    fn test_empty_body_matches_all() {
+        let search = parse_search(b"").unwrap();
+        assert_eq!(search.query, Query::MatchAll);
+        assert_eq!(search.from, 0);
+        assert_eq!(search.size, DEFAULT_SIZE);
+    }
+
+    #[test]
+    fn test_parse_match() {
+        assert_eq!(
+            parse(r#"{"query": {"match": {"message": "molar bear"}}}"#).unwrap(),
+            Query::matching("message", "molar bear")
+        );
+        assert_eq!(
+            parse(r#"{"query": {"match": {"message": {"query": "molar bear", "operator": "and"}}}}"#).unwrap(),
+            Query::Match {
+                field: "message".into(),
+                text: "molar bear".into(),
+                operator: Operator::And,
+            }
+        );
+        assert!(parse(r#"{"query": {"match": {"message": {"query": "bear", "operator": "xor"}}}}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_term_phrase_and_range() {
+        assert_eq!(
+            parse(r#"{"query": {"term": {"status": 404}}}"#).unwrap(),
+            Query::term("status", 404)
+        );
+        assert_eq!(
+            parse(r#"{"query": {"term": {"host": {"value": "web01"}}}}"#).unwrap(),
+            Query::term("host", "web01")
+        );
+        assert_eq!(
+            parse(r#"{"query": {"phrase": {"message": "molar bear"}}}"#).unwrap(),
+            Query::phrase("message", "molar bear")
+        );
+        assert_eq!(
+            parse(r#"{"query": {"range": {"status": {"gte": 400, "lt": 500}}}}"#).unwrap(),
+            Query::Range {
+                field: "status".into(),
+                gt: None,
+                gte: Some(Value::from(400)),
+                lt: Some(Value::from(500)),
+                lte: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        let query = parse(
+            r#"{"query": {"bool": {
+                "must": {"match": {"message": "bear"}},
+                "must_not": [{"term": {"host": "web02"}}, {"terms": {"status": [500, 503]}}]
+            }}}"#,
+        ).unwrap();
+        assert_eq!(
+            query,
+            Query::Bool {
+                must: vec![Query::matching("message", "bear")],
+                should: vec![],
+                must_not: vec![
+                    Query::term("host", "web02"),
+                    Query::Terms {
+                        field: "status".into(),
+                        values: vec![Value::from(500), Value::from(503)],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_paging() {
+        let search = parse_search(br#"{"from": 20, "size": 5}"#).unwrap();
+        assert_eq!(search.from, 20);
+        assert_eq!(search.size, 5);
+        assert!(parse_search(br#"{"size": -1}"#).is_err());
+        assert!(parse_search(br#"{"from": 9999, "size": 10}"#).is_err());
+        assert!(parse_search(br#"{"from": 18446744073709551615}"#).is_err());
+        assert!(parse_search(br#"{"from": 1, "size": 18446744073709551615}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("not json").is_err());
+        assert!(parse(r#"[1, 2]"#).is_err());
+        assert!(parse(r#"{"query": {"fuzzy": {"message": "bear"}}}"#).is_err());
+        assert!(parse(r#"{"query": {"match": {"message": "bear", "host": "web01"}}}"#).is_err());
+        assert!(parse(r#"{"query": {"term": {"tags": ["a"]}}}"#).is_err());
+        assert!(parse(r#"{"query": {"bool": {"must": {"match": {}}}}}"#).is_err());
+        assert!(parse(r#"{"qeury": {"match_all": {}}}"#).is_err());
+        let error = parse(r#"{"query": {"range": {"status": {"gte": 1, "between": 2}}}}"#).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown option 'between' in range query");
+    }
+}
@@ -1,54 +1,38 @@
-use std::sync::{Arc, Mutex, mpsc};
+use std::sync::{Arc, Mutex};
 
 use hyper::server::{Request, Response};
 use hyper::StatusCode;
+
 use serde_json;
 
 use rpc::Switchboard;
-use rpc::messages::{MessageType, Message};
-
-/// Handles a request to list all nodes in the cluster
-pub fn list_nodes(_req: Request, swb: Arc<Mutex<Switchboard>>) -> Response {
-    let (resp_tx, resp_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) = mpsc::channel();
-    let query = Message::new(MessageType::LIST_NODES).response_chan(resp_tx);
-    let node_tx = match swb.lock() {
-        Ok(l) => {
-            l.node_tx.clone()
-        },
-        Err(e) => {
-            return Response::new().with_status(StatusCode::InternalServerError);
-        },
-    };
+use rpc::db::NodeStatus;
+use rpc::messages::MessageType;
 
-    match node_tx.lock() {
-        Ok(l) => {
-            l.send(query);
-        },
-        Err(e) => {
-            return Response::new().with_status(StatusCode::InternalServerError);
-        },
-    };
+use handlers::{ask_node, error_response, json_response};
+use router::Params;
 
-    match resp_rx.recv() {
-        Ok(response) => {
-                match serde_json::to_string(&response.args) {
-                    Ok(serialized) => {
-                        return Response::new().with_body(serialized);
-                    },
-                    Err(e) => {
-                        return Response::new().with_status(StatusCode::InternalServerError);            
-                    },
-                };
-        },
-        Err(e) => {
-            return Response::new().with_status(StatusCode::InternalServerError);
-        }
+/// Handles a request to list all nodes in the cluster, along with whether the metadata server
+/// has heard from each recently
+pub fn list_nodes(_req: Request, _params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let reply = match ask_node(&swb, MessageType::LIST_NODES, vec![]) {
+        Ok(reply) => reply,
+        Err(response) => return response,
     };
+    if reply.message_type != MessageType::LIST_NODES {
+        let reason = reply.args.get(0).cloned().unwrap_or_else(|| String::from("Could not list nodes"));
+        return error_response(StatusCode::BadGateway, &reason);
+    }
+    let nodes: Result<Vec<NodeStatus>, _> = reply.args.iter().map(|node| serde_json::from_str(node)).collect();
+    match nodes {
+        Ok(nodes) => json_response(StatusCode::Ok, &nodes),
+        Err(e) => error_response(StatusCode::InternalServerError, &format!("Could not read node list: {}", e)),
+    }
 }
 
 /// Handles a request from a data node to register
 /// Only used if we are the metadata node
-pub fn register(req: Request, swb: Arc<Mutex<Switchboard>>) -> Response {
+pub fn register(req: Request, _params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
     Response::new()
 }
 
@@ -0,0 +1,351 @@
+use std::str;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Instant;
+
+use hyper::header::ContentType;
+use hyper::mime;
+use hyper::server::{Request, Response};
+use hyper::StatusCode;
+use serde_json::{self, Value};
+use uuid::Uuid;
+
+use inverted_index::index::InvertedIndexError;
+use inverted_index::manager::IndexCommand;
+use inverted_index::replication::{OperationKind, WriteConsistency, WriteOutcome};
+use rpc::Switchboard;
+
+use handlers::{begin_write, error_response, index_error_response, index_param, json_response, local_index, query_param, request_deadline, source_value, wait_for};
+use read_body;
+use router::Params;
+
+/// Body of the Response to a write of a Document
+#[derive(Serialize, Debug)]
+struct WriteResponse {
+    id: u64,
+    result: &'static str,
+    replication: ReplicationResponse,
+}
+
+/// How many copies of the Document's Shard had the write when the client was answered
+#[derive(Serialize, Debug)]
+struct ReplicationResponse {
+    consistency: &'static str,
+    copies: usize,
+    successful: usize,
+}
+
+impl WriteResponse {
+    fn new(id: u64, result: &'static str, outcome: &WriteOutcome) -> WriteResponse {
+        WriteResponse {
+            id: id,
+            result: result,
+            replication: ReplicationResponse {
+                consistency: outcome.replication.consistency.as_str(),
+                copies: outcome.replication.copies,
+                successful: outcome.replication.successful,
+            },
+        }
+    }
+}
+
+/// Body of the Response to a fetch of a Document
+#[derive(Serialize, Debug)]
+struct DocumentResponse {
+    id: u64,
+    source: Value,
+}
+
+/// How the body of a Document write should be read
+enum BodyFormat {
+    /// `application/json`, which must be a JSON object
+    Json,
+    /// `text/plain`, which all goes in the `message` field
+    PlainText,
+    /// No Content-Type was given, so JSON objects are read as JSON and anything else as text
+    Detect,
+}
+
+/// Handles indexing a Document with an ID picked by the server, `POST /<index>/document`
+pub fn create_document(req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let id = generate_id();
+    match write_document(req, &params, swb, id) {
+        Ok(outcome) => json_response(StatusCode::Created, &WriteResponse::new(id, "created", &outcome)),
+        Err(response) => response,
+    }
+}
+
+/// Handles indexing a Document with an ID picked by the client, `PUT /<index>/document/<id>`.
+/// A Document that already has the ID is replaced.
+pub fn put_document(req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let id = match id_param(&params) {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match write_document(req, &params, swb, id) {
+        Ok(outcome) => json_response(StatusCode::Ok, &WriteResponse::new(id, "indexed", &outcome)),
+        Err(response) => response,
+    }
+}
+
+/// Handles fetching a Document, `GET /<index>/document/<id>`
+pub fn get_document(_req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let deadline = match request_deadline(&swb) {
+        Ok(deadline) => deadline,
+        Err(response) => return response,
+    };
+    let (index, shard_tx, id) = match index_and_id(&params, &swb) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let (resp_tx, resp_rx) = mpsc::channel();
+    let command = IndexCommand::GetDocument {
+        id: id,
+        response_channel: resp_tx,
+    };
+    match ask(&index, &shard_tx, command, resp_rx, deadline) {
+        Ok(Some(document)) => json_response(
+            StatusCode::Ok,
+            &DocumentResponse {
+                id: id,
+                source: source_value(document.content(), document.is_plain_text()),
+            },
+        ),
+        Ok(None) => error_response(StatusCode::NotFound, &format!("Document {} not found", id)),
+        Err(response) => response,
+    }
+}
+
+/// Handles deleting a Document, `DELETE /<index>/document/<id>`
+pub fn delete_document(req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let _write = match begin_write(&swb) {
+        Ok(write) => write,
+        Err(response) => return response,
+    };
+    let consistency = match write_consistency(&req, &swb) {
+        Ok(consistency) => consistency,
+        Err(response) => return response,
+    };
+    let deadline = match request_deadline(&swb) {
+        Ok(deadline) => deadline,
+        Err(response) => return response,
+    };
+    let (index, shard_tx, id) = match index_and_id(&params, &swb) {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    match replicate(&index, &shard_tx, OperationKind::Delete { id: id }, consistency, deadline) {
+        Ok(ref outcome) if outcome.found => json_response(StatusCode::Ok, &WriteResponse::new(id, "deleted", outcome)),
+        Ok(_) => error_response(StatusCode::NotFound, &format!("Document {} not found", id)),
+        Err(response) => response,
+    }
+}
+
+/// Finds the Index and Document ID a Request is about, along with the Shard the Document belongs in
+fn index_and_id(
+    params: &Params,
+    swb: &Arc<Mutex<Switchboard>>,
+) -> Result<(String, mpsc::Sender<IndexCommand>, u64), Response> {
+    let index = index_param(params)?;
+    let id = id_param(params)?;
+    let shard_tx = shard_channel(swb, &index, id)?;
+    Ok((index, shard_tx, id))
+}
+
+/// Finds the channel to the `Manager` of the Shard a Document belongs in
+fn shard_channel(swb: &Arc<Mutex<Switchboard>>, index: &str, id: u64) -> Result<mpsc::Sender<IndexCommand>, Response> {
+    match local_index(swb, index)?.shard_for(id) {
+        (_, Some(shard_tx)) => Ok(shard_tx),
+        (shard, None) => Err(error_response(
+            StatusCode::MisdirectedRequest,
+            &format!("Shard {} of index {} is not on this node", shard, index),
+        )),
+    }
+}
+
+/// Reads the Document in the body of a Request and sends it to its Index's `Manager`, waiting
+/// until it has been written to as many copies of its Shard as the Request asks for
+fn write_document(req: Request, params: &Params, swb: Arc<Mutex<Switchboard>>, id: u64) -> Result<WriteOutcome, Response> {
+    let _write = begin_write(&swb)?;
+    let consistency = write_consistency(&req, &swb)?;
+    let deadline = request_deadline(&swb)?;
+    let index = index_param(params)?;
+    let shard_tx = shard_channel(&swb, &index, id)?;
+    let format = match req.headers().get::<ContentType>() {
+        None => BodyFormat::Detect,
+        Some(ct) if ct.type_() == mime::APPLICATION && ct.subtype() == mime::JSON => BodyFormat::Json,
+        Some(ct) if ct.type_() == mime::TEXT && ct.subtype() == mime::PLAIN => BodyFormat::PlainText,
+        Some(ct) => {
+            return Err(error_response(
+                StatusCode::UnsupportedMediaType,
+                &format!("Unsupported Content-Type {}, expected application/json or text/plain", ct),
+            ));
+        }
+    };
+
+    let body = read_body(req)
+        .map_err(|e| error_response(StatusCode::BadRequest, &format!("Could not read body: {}", e)))?;
+    let body = str::from_utf8(&body).map_err(|_| error_response(StatusCode::BadRequest, "Body must be UTF-8"))?;
+    if body.trim().is_empty() {
+        return Err(error_response(StatusCode::BadRequest, "Document is empty"));
+    }
+    let plain_text = match format {
+        BodyFormat::Json => match serde_json::from_str::<Value>(body) {
+            Ok(Value::Object(_)) => false,
+            _ => return Err(error_response(StatusCode::BadRequest, "Body is not a JSON object")),
+        },
+        BodyFormat::PlainText => true,
+        BodyFormat::Detect => false,
+    };
+    let operation = OperationKind::Index {
+        id: id,
+        content: body.to_owned(),
+        plain_text: plain_text,
+    };
+    replicate(&index, &shard_tx, operation, consistency, deadline)
+}
+
+/// Writes to the Primary of a Shard and waits until as many of its copies have the write as
+/// `consistency` asks for. If too few do, the Err says how many did; the write is not undone.
+fn replicate(
+    index: &str,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    operation: OperationKind,
+    consistency: WriteConsistency,
+    deadline: Instant,
+) -> Result<WriteOutcome, Response> {
+    let id = operation.id();
+    let (resp_tx, resp_rx) = mpsc::channel();
+    let command = IndexCommand::Write {
+        operation: operation,
+        consistency: consistency,
+        response_channel: resp_tx,
+    };
+    let outcome = ask(index, shard_tx, command, resp_rx, deadline)?;
+    if !outcome.replication.is_met() {
+        let report = outcome.replication;
+        return Err(error_response(
+            StatusCode::ServiceUnavailable,
+            &format!(
+                "Document {} was written to {} of {} copies, but {} consistency needs {}",
+                id,
+                report.successful,
+                report.copies,
+                report.consistency,
+                report.consistency.required(report.copies)
+            ),
+        ));
+    }
+    Ok(outcome)
+}
+
+/// Returns the `WriteConsistency` a Request asks for with `?consistency=`, or this Node's
+/// default if it doesn't ask
+fn write_consistency(req: &Request, swb: &Arc<Mutex<Switchboard>>) -> Result<WriteConsistency, Response> {
+    match query_param(req, "consistency") {
+        Some(consistency) => consistency
+            .parse::<WriteConsistency>()
+            .map_err(|e| error_response(StatusCode::BadRequest, &e.to_string())),
+        None => match swb.lock() {
+            Ok(l) => Ok(l.write_consistency),
+            Err(_) => Err(Response::new().with_status(StatusCode::InternalServerError)),
+        },
+    }
+}
+
+/// Sends a command to the `Manager` of one of an Index's Shards and waits for its answer until
+/// `deadline`. Errors the Index reports are about the Document, such as a field that does not
+/// fit its mapping, so they are the client's to fix, unless the Index itself failed.
+fn ask<T>(
+    index: &str,
+    shard_tx: &mpsc::Sender<IndexCommand>,
+    command: IndexCommand,
+    resp_rx: mpsc::Receiver<Result<T, InvertedIndexError>>,
+    deadline: Instant,
+) -> Result<T, Response> {
+    if shard_tx.send(command).is_err() {
+        return Err(error_response(
+            StatusCode::InternalServerError,
+            &format!("Index {} is not running", index),
+        ));
+    }
+    match wait_for(&resp_rx, deadline, &format!("Index {}", index))? {
+        Ok(answer) => Ok(answer),
+        Err(e) => Err(index_error_response(&e)),
+    }
+}
+
+/// Returns the Document ID captured from the path as `id`
+fn id_param(params: &Params) -> Result<u64, Response> {
+    params
+        .get("id")
+        .and_then(|id| id.parse::<u64>().ok())
+        .ok_or_else(|| error_response(StatusCode::BadRequest, "Document ID must be a non-negative integer"))
+}
+
+/// Picks an ID for a Document that was sent without one. IDs are random so that Nodes do not
+/// have to agree on a counter, and are kept below 2^63 so they fit a signed 64 bit integer too.
+fn generate_id() -> u64 {
+    let uuid = Uuid::new_v4();
+    let id = uuid.as_bytes()[..8]
+        .iter()
+        .fold(0u64, |id, byte| (id << 8) | u64::from(*byte));
+    id >> 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let params = Params::new().with("index", "logs").with("id", "42");
+        assert_eq!(index_param(&params).ok(), Some("logs".to_owned()));
+        assert!(index_param(&Params::new()).is_err());
+        assert_eq!(id_param(&params).ok(), Some(42));
+        assert!(id_param(&Params::new().with("id", "-1")).is_err());
+        assert!(id_param(&Params::new()).is_err());
+    }
+
+    #[test]
+    fn test_write_consistency() {
+        use std::path::PathBuf;
+        use hyper::Method;
+        use rpc::indices::IndexRegistry;
+
+        let (node_tx, _node_rx) = mpsc::channel();
+        let mut switchboard = Switchboard::new(Arc::new(Mutex::new(node_tx)), IndexRegistry::new(PathBuf::from("/tmp/saga")));
+        switchboard.write_consistency = WriteConsistency::Quorum;
+        let swb = Arc::new(Mutex::new(switchboard));
+
+        let req = Request::new(Method::Delete, "/logs/document/42".parse().unwrap());
+        assert_eq!(write_consistency(&req, &swb).ok(), Some(WriteConsistency::Quorum));
+        let req = Request::new(Method::Delete, "/logs/document/42?pretty&consistency=all".parse().unwrap());
+        assert_eq!(write_consistency(&req, &swb).ok(), Some(WriteConsistency::All));
+        let req = Request::new(Method::Delete, "/logs/document/42?consistency=most".parse().unwrap());
+        assert_eq!(write_consistency(&req, &swb).unwrap_err().status(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_writes_refused_while_leaving() {
+        use std::path::PathBuf;
+        use std::time::Duration;
+        use hyper::Method;
+        use rpc::indices::IndexRegistry;
+
+        let (node_tx, _node_rx) = mpsc::channel();
+        let switchboard = Switchboard::new(Arc::new(Mutex::new(node_tx)), IndexRegistry::new(PathBuf::from("/tmp/saga")));
+        assert!(switchboard.writes.drain(Duration::from_secs(1)));
+        let swb = Arc::new(Mutex::new(switchboard));
+
+        let params = Params::new().with("index", "logs").with("id", "42");
+        let req = Request::new(Method::Delete, "/logs/document/42".parse().unwrap());
+        assert_eq!(delete_document(req, params, swb).status(), StatusCode::ServiceUnavailable);
+    }
+
+    #[test]
+    fn test_generate_id() {
+        let first = generate_id();
+        assert!(first < 1 << 63);
+        assert_ne!(first, generate_id());
+    }
+}
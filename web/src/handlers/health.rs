@@ -5,7 +5,9 @@ use hyper::StatusCode;
 
 use rpc::Switchboard;
 
+use router::Params;
+
 /// A basic health-check function. Returns 200 OK if a request can reach it
-pub fn health_check(req: Request, swb: Arc<Mutex<Switchboard>>) -> Response {
+pub fn health_check(req: Request, _params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
     Response::new().with_status(StatusCode::Ok)
 }
\ No newline at end of file
@@ -1,27 +1,325 @@
 use std::sync::{Arc, Mutex};
-use hyper::{Request, Response, Body, Chunk};
+
+use hyper::server::{Request, Response};
+use hyper::StatusCode;
 use serde_json;
-use futures::Stream;
-use futures::Future;
-use futures;
 
 use rpc::Switchboard;
+use rpc::db::{IndexDefinition, IndexState};
+use rpc::messages::{Message, MessageType, ERROR_CONFLICT, ERROR_INVALID, ERROR_UNAVAILABLE};
+
+use handlers::{ask_node, error_response, index_param, json_response};
+use read_body;
+use router::Params;
+
+/// Longest an Index name can be, in bytes
+pub const MAX_INDEX_NAME_LENGTH: usize = 255;
+/// Most Primary Shards an Index can have. Every Shard runs its own Manager and segment workers.
+pub const MAX_PRIMARY_SHARDS: u32 = 64;
+/// Most Replicas each Primary Shard can have
+pub const MAX_REPLICA_SHARDS: u32 = 16;
+
+/// Handles the request to create a new index, `PUT /<index>`, with the number of Shards in the
+/// body. An empty body creates an Index with one Primary Shard and no Replicas.
+pub fn create_index(req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let name = match index_param(&params) {
+        Ok(name) => name,
+        Err(response) => return response,
+    };
+    if let Err(e) = validate_index_name(&name) {
+        return error_response(StatusCode::BadRequest, &e);
+    }
+    let body = match read_body(req) {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BadRequest, &format!("Could not read body: {}", e)),
+    };
+    let index = match parse_create_index(&name, &body) {
+        Ok(index) => index,
+        Err(e) => return error_response(StatusCode::BadRequest, &e),
+    };
+
+    let exists = match swb.lock() {
+        Ok(l) => l.indices.get(&index.name).is_some(),
+        Err(_) => return Response::new().with_status(StatusCode::InternalServerError),
+    };
+    if exists {
+        return error_response(StatusCode::Conflict, &format!("Index {} already exists", index.name));
+    }
 
-/// Handles the request to create a new index
-pub fn create_index(req: Request, swb: Arc<Mutex<Switchboard>>) -> Response {
-    //let mut body = req.body()
+    let args = vec![
+        index.name.clone(),
+        index.primary_shards.to_string(),
+        index.replica_shards.to_string(),
+    ];
+    let reply = match ask_node(&swb, MessageType::CREATE_INDEX, args) {
+        Ok(reply) => reply,
+        Err(response) => return response,
+    };
+    if reply.message_type != MessageType::CREATE_INDEX {
+        return reply_error(&reply);
+    }
+    let shards: Vec<u32> = reply.args.iter().filter_map(|shard| shard.parse().ok()).collect();
 
-    Response::new()
+    // The Index is recorded at this point, so Shards that fail to start will be retried when the
+    // Node restarts
+    let opened = match swb.lock() {
+        Ok(mut l) => l.indices.open(&index.name, index.primary_shards, &shards),
+        Err(_) => return Response::new().with_status(StatusCode::InternalServerError),
+    };
+    match opened {
+        Ok(()) => json_response(StatusCode::Created, &index),
+        Err(e) => error_response(
+            StatusCode::InternalServerError,
+            &format!("Index {} was created but its Shards could not be started: {}", index.name, e),
+        ),
+    }
+}
+
+/// Body of the Response listing every Index
+#[derive(Serialize, Debug)]
+struct IndicesResponse {
+    indices: Vec<IndexDefinition>,
+}
+
+/// Body of the Response to deleting an Index
+#[derive(Serialize, Debug)]
+struct DeleteResponse {
+    name: String,
+    result: &'static str,
+}
+
+/// Handles listing every Index in the Cluster, `GET /_indices`
+pub fn list_indices(_req: Request, _params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let reply = match ask_node(&swb, MessageType::LIST_INDICES, vec![]) {
+        Ok(reply) => reply,
+        Err(response) => return response,
+    };
+    if reply.message_type != MessageType::LIST_INDICES {
+        return reply_error(&reply);
+    }
+    let mut indices = vec![];
+    for serialized in &reply.args {
+        match serde_json::from_str(serialized) {
+            Ok(index) => indices.push(index),
+            Err(e) => {
+                return error_response(
+                    StatusCode::InternalServerError,
+                    &format!("Could not read index definition: {}", e),
+                )
+            }
+        }
+    }
+    json_response(StatusCode::Ok, &IndicesResponse { indices: indices })
+}
+
+/// Handles describing an Index, `GET /<index>`
+pub fn get_index(_req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    match change_index(&params, &swb, MessageType::GET_INDEX) {
+        Ok((index, _)) => json_response(StatusCode::Ok, &index),
+        Err(response) => response,
+    }
+}
+
+/// Handles opening a closed Index, `POST /<index>/_open`, which starts its Shards on this Node
+pub fn open_index(_req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let (index, shards) = match change_index(&params, &swb, MessageType::OPEN_INDEX) {
+        Ok(changed) => changed,
+        Err(response) => return response,
+    };
+    let opened = match swb.lock() {
+        Ok(mut l) => l.indices.open(&index.name, index.primary_shards, &shards),
+        Err(_) => return Response::new().with_status(StatusCode::InternalServerError),
+    };
+    match opened {
+        Ok(()) => json_response(StatusCode::Ok, &index),
+        Err(e) => error_response(
+            StatusCode::InternalServerError,
+            &format!("Index {} was opened but its Shards could not be started: {}", index.name, e),
+        ),
+    }
+}
+
+/// Handles closing an Index, `POST /<index>/_close`. Its Shards are stopped but their data is
+/// kept until it is opened again.
+pub fn close_index(_req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let (index, _) = match change_index(&params, &swb, MessageType::CLOSE_INDEX) {
+        Ok(changed) => changed,
+        Err(response) => return response,
+    };
+    match swb.lock() {
+        Ok(mut l) => l.indices.close(&index.name),
+        Err(_) => return Response::new().with_status(StatusCode::InternalServerError),
+    };
+    json_response(StatusCode::Ok, &index)
+}
+
+/// Handles deleting an Index along with all of its Documents, `DELETE /<index>`
+pub fn delete_index(_req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let (index, _) = match change_index(&params, &swb, MessageType::DELETE_INDEX) {
+        Ok(changed) => changed,
+        Err(response) => return response,
+    };
+    let deleted = match swb.lock() {
+        Ok(mut l) => l.indices.delete(&index.name),
+        Err(_) => return Response::new().with_status(StatusCode::InternalServerError),
+    };
+    match deleted {
+        Ok(()) => json_response(
+            StatusCode::Ok,
+            &DeleteResponse {
+                name: index.name,
+                result: "deleted",
+            },
+        ),
+        Err(e) => error_response(
+            StatusCode::InternalServerError,
+            &format!("Index {} was deleted but its data could not be removed: {}", index.name, e),
+        ),
+    }
+}
+
+/// Asks the metadata server to describe or change the Index a Request is about. Returns the
+/// Index's definition and the numbers of any of its Shards the reply lists.
+fn change_index(
+    params: &Params,
+    swb: &Arc<Mutex<Switchboard>>,
+    message_type: MessageType,
+) -> Result<(IndexDefinition, Vec<u32>), Response> {
+    let name = index_param(params)?;
+    let reply = ask_node(swb, message_type.clone(), vec![name.clone()])?;
+    if reply.message_type != message_type {
+        return Err(reply_error(&reply));
+    }
+    let index = match reply.args.get(0) {
+        Some(serialized) => serde_json::from_str(serialized).map_err(|e| {
+            error_response(
+                StatusCode::InternalServerError,
+                &format!("Could not read index definition: {}", e),
+            )
+        })?,
+        None => return Err(error_response(StatusCode::NotFound, &format!("Index {} not found", name))),
+    };
+    let shards = reply.args[1..].iter().filter_map(|shard| shard.parse().ok()).collect();
+    Ok((index, shards))
+}
+
+/// Turns a reply from the Node that reports an error into a Response. Unless the Node says the
+/// request was at fault or the Cluster is unavailable, the Node failed, such as to read or write
+/// its metadata.
+fn reply_error(reply: &Message) -> Response {
+    let reason = reply.args.get(0).cloned().unwrap_or_else(|| String::from("Request to the Node failed"));
+    let status = match reply.args.get(1).map(|kind| kind.as_str()) {
+        Some(ERROR_INVALID) => StatusCode::BadRequest,
+        Some(ERROR_CONFLICT) => StatusCode::Conflict,
+        Some(ERROR_UNAVAILABLE) => StatusCode::ServiceUnavailable,
+        _ => StatusCode::InternalServerError,
+    };
+    error_response(status, &reason)
 }
 
 // Represents a request to create a new index. JSON should be
 // de-serialized into one of these structs
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 struct CreateIndex {
-    // Name of the index
-    name: String,
+    // Name of the index. Optional, as it is already in the path.
+    #[serde(default)]
+    name: Option<String>,
     // Number of primary shards
-    primary: i32,
+    #[serde(default = "default_primary")]
+    primary: u32,
     // Number of replica shards
-    replica: i32
-}
\ No newline at end of file
+    #[serde(default)]
+    replica: u32
+}
+
+fn default_primary() -> u32 {
+    1
+}
+
+/// Reads the body of a request to create an Index named `name`
+fn parse_create_index(name: &str, body: &[u8]) -> Result<IndexDefinition, String> {
+    let create: CreateIndex = if body.iter().all(|b| b.is_ascii_whitespace()) {
+        CreateIndex {
+            name: None,
+            primary: default_primary(),
+            replica: 0,
+        }
+    } else {
+        serde_json::from_slice(body).map_err(|e| format!("Invalid index definition: {}", e))?
+    };
+    if let Some(ref body_name) = create.name {
+        if body_name != name {
+            return Err(format!("Index name {} in the body does not match {} in the path", body_name, name));
+        }
+    }
+    if create.primary == 0 || create.primary > MAX_PRIMARY_SHARDS {
+        return Err(format!("primary must be between 1 and {}", MAX_PRIMARY_SHARDS));
+    }
+    if create.replica > MAX_REPLICA_SHARDS {
+        return Err(format!("replica must be at most {}", MAX_REPLICA_SHARDS));
+    }
+    Ok(IndexDefinition {
+        name: name.to_owned(),
+        primary_shards: create.primary,
+        replica_shards: create.replica,
+        state: IndexState::Open,
+    })
+}
+
+/// Checks that a name can be used for an Index. Names become part of URLs and directory names,
+/// so they are limited to lowercase letters, digits, `_` and `-`. Names starting with `_` are
+/// kept for endpoints such as `/_search`.
+pub fn validate_index_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(String::from("Index name must not be empty"));
+    }
+    if name.len() > MAX_INDEX_NAME_LENGTH {
+        return Err(format!("Index name must be at most {} bytes", MAX_INDEX_NAME_LENGTH));
+    }
+    if name.starts_with('_') || name.starts_with('-') {
+        return Err(format!("Index name {} must not start with _ or -", name));
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-') {
+        return Err(format!(
+            "Index name {} may only contain lowercase letters, digits, _ and -",
+            name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_index_name() {
+        assert!(validate_index_name("logs-2018_01").is_ok());
+        assert!(validate_index_name("").is_err());
+        assert!(validate_index_name("_search").is_err());
+        assert!(validate_index_name("Logs").is_err());
+        assert!(validate_index_name("logs/2018").is_err());
+        assert!(validate_index_name(&"a".repeat(MAX_INDEX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        let index = parse_create_index("logs", b"").unwrap();
+        assert_eq!((index.primary_shards, index.replica_shards), (1, 0));
+        let index = parse_create_index("logs", br#"{"name": "logs", "primary": 3, "replica": 1}"#).unwrap();
+        assert_eq!((index.primary_shards, index.replica_shards), (3, 1));
+        assert!(parse_create_index("logs", br#"{"name": "metrics"}"#).is_err());
+        assert!(parse_create_index("logs", br#"{"primary": 0}"#).is_err());
+        assert!(parse_create_index("logs", br#"{"primary": -1}"#).is_err());
+        assert!(parse_create_index("logs", br#"{"shards": 2}"#).is_err());
+    }
+
+    #[test]
+    fn test_reply_error() {
+        let error = |args: Vec<&str>| Message::new(MessageType::ERROR).args(args.into_iter().map(String::from).collect());
+        assert_eq!(reply_error(&error(vec!["Index logs already exists", ERROR_CONFLICT])).status(), StatusCode::Conflict);
+        assert_eq!(reply_error(&error(vec!["Expected the name of an index", ERROR_INVALID])).status(), StatusCode::BadRequest);
+        assert_eq!(reply_error(&error(vec!["disk I/O error"])).status(), StatusCode::InternalServerError);
+    }
+}
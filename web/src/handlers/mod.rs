@@ -1,3 +1,217 @@
 pub mod indices;
 pub mod health;
-pub mod cluster;
\ No newline at end of file
+pub mod cluster;
+pub mod search;
+pub mod documents;
+
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::{Request, Response};
+use hyper::StatusCode;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use inverted_index::index::InvertedIndexError;
+use rpc::Switchboard;
+use rpc::drain::{WriteGate, WriteGuard};
+use rpc::indices::LocalIndex;
+use rpc::messages::{Message, MessageType};
+
+use router::Params;
+
+/// Body of every error Response, so clients can always find out what went wrong
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Serializes a value into a JSON Response with the given status
+///
+/// # Arguments
+///
+/// * `status` - Status of the Response
+/// * `body` - Anything that can be serialized to JSON
+pub fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response {
+    match serde_json::to_string(body) {
+        Ok(serialized) => Response::new()
+            .with_status(status)
+            .with_header(ContentType::json())
+            .with_header(ContentLength(serialized.len() as u64))
+            .with_body(serialized),
+        Err(_) => Response::new().with_status(StatusCode::InternalServerError),
+    }
+}
+
+/// Builds a JSON Response of the form `{"error": "..."}`
+///
+/// # Arguments
+///
+/// * `status` - Status of the Response
+/// * `message` - What went wrong
+pub fn error_response(status: StatusCode, message: &str) -> Response {
+    json_response(status, &ErrorBody { error: message.to_owned() })
+}
+
+/// Finds the Shards of an Index that run on this Node. If the Index has none here, the Err holds
+/// the Response to send back instead.
+///
+/// # Arguments
+///
+/// * `swb` - The `Switchboard` passed to the Handler
+/// * `index` - Name of the Index
+pub fn local_index(swb: &Arc<Mutex<Switchboard>>, index: &str) -> Result<LocalIndex, Response> {
+    let local = match swb.lock() {
+        Ok(l) => l.indices.get(index).cloned(),
+        Err(_) => {
+            return Err(Response::new().with_status(StatusCode::InternalServerError));
+        }
+    };
+    local.ok_or_else(|| error_response(StatusCode::NotFound, &format!("Index {} not found", index)))
+}
+
+/// Starts a write on this Node. The write is waited for if the Node leaves the Cluster, until the
+/// returned WriteGuard is dropped. If the Node is already leaving, the Err holds the Response to
+/// send back instead.
+///
+/// # Arguments
+///
+/// * `swb` - The `Switchboard` passed to the Handler
+pub fn begin_write(swb: &Arc<Mutex<Switchboard>>) -> Result<WriteGuard, Response> {
+    let writes = match swb.lock() {
+        Ok(l) => l.writes.clone(),
+        Err(_) => {
+            return Err(Response::new().with_status(StatusCode::InternalServerError));
+        }
+    };
+    WriteGate::begin(&writes)
+        .ok_or_else(|| error_response(StatusCode::ServiceUnavailable, "Node is leaving the cluster"))
+}
+
+/// Returns the name of the Index a Request is about, captured from its path as `index`
+pub fn index_param(params: &Params) -> Result<String, Response> {
+    match params.get("index") {
+        Some(index) if !index.is_empty() => Ok(index.to_owned()),
+        _ => Err(error_response(StatusCode::BadRequest, "No index given")),
+    }
+}
+
+/// Returns the value of a parameter in the query string of a Request, if it was given
+///
+/// # Arguments
+///
+/// * `req` - The Request
+/// * `name` - Name of the parameter
+pub fn query_param(req: &Request, name: &str) -> Option<String> {
+    req.query()?
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => Some(value.to_owned()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Returns when a Handler has to stop waiting on the Node and the Shards, which is when the
+/// client stops waiting on the Handler
+///
+/// # Arguments
+///
+/// * `swb` - The `Switchboard` passed to the Handler
+pub fn request_deadline(swb: &Arc<Mutex<Switchboard>>) -> Result<Instant, Response> {
+    match swb.lock() {
+        Ok(l) => Ok(Instant::now() + l.request_timeout),
+        Err(_) => Err(Response::new().with_status(StatusCode::InternalServerError)),
+    }
+}
+
+/// Waits for an answer on a channel until the deadline. If none comes in time the Err is a 504,
+/// and if the other end is gone it is a 500.
+///
+/// # Arguments
+///
+/// * `resp_rx` - Channel the answer comes in on
+/// * `deadline` - When to stop waiting, from `request_deadline`
+/// * `from` - What is being waited on, for the error message
+pub fn wait_for<T>(resp_rx: &mpsc::Receiver<T>, deadline: Instant, from: &str) -> Result<T, Response> {
+    let now = Instant::now();
+    let remaining = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+    resp_rx.recv_timeout(remaining).map_err(|e| match e {
+        mpsc::RecvTimeoutError::Timeout => {
+            error_response(StatusCode::GatewayTimeout, &format!("{} did not answer in time", from))
+        }
+        mpsc::RecvTimeoutError::Disconnected => {
+            error_response(StatusCode::InternalServerError, &format!("{} stopped before answering", from))
+        }
+    })
+}
+
+/// Sends a `Message` to this Node's RPC thread and waits for the reply, for as long as the
+/// request may take
+///
+/// # Arguments
+///
+/// * `swb` - The `Switchboard` passed to the Handler
+/// * `message_type` - Type of the `Message`
+/// * `args` - Arguments of the `Message`
+pub fn ask_node(swb: &Arc<Mutex<Switchboard>>, message_type: MessageType, args: Vec<String>) -> Result<Message, Response> {
+    let deadline = request_deadline(swb)?;
+    let (resp_tx, resp_rx) = mpsc::channel();
+    let query = Message::new(message_type).args(args).response_chan(resp_tx);
+    let node_tx = match swb.lock() {
+        Ok(l) => l.node_tx.clone(),
+        Err(_) => {
+            return Err(Response::new().with_status(StatusCode::InternalServerError));
+        }
+    };
+    let sent = match node_tx.lock() {
+        Ok(l) => l.send(query).is_ok(),
+        Err(_) => false,
+    };
+    if !sent {
+        return Err(error_response(StatusCode::InternalServerError, "Node is not running"));
+    }
+    wait_for(&resp_rx, deadline, "Node")
+}
+
+/// Answers with the error an Index gave. Failures of the Index itself, such as its storage or a
+/// stopped worker, are the server's fault and anything else is the request's.
+pub fn index_error_response(error: &InvertedIndexError) -> Response {
+    let status = if error.is_internal() {
+        StatusCode::InternalServerError
+    } else {
+        StatusCode::BadRequest
+    };
+    error_response(status, &error.to_string())
+}
+
+/// Turns the raw content of a Document into what is sent back to clients. JSON Documents are
+/// returned as JSON and anything else, including plain text that happens to be valid JSON, as a
+/// string.
+pub fn source_value(raw: &str, plain_text: bool) -> Value {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(json @ Value::Object(_)) if !plain_text => json,
+        _ => Value::String(raw.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_for() {
+        let (resp_tx, resp_rx) = mpsc::channel();
+        resp_tx.send(1).unwrap();
+        assert_eq!(wait_for(&resp_rx, Instant::now(), "Node").ok(), Some(1));
+        let response = wait_for(&resp_rx, Instant::now() + Duration::from_millis(50), "Node").unwrap_err();
+        assert_eq!(response.status(), StatusCode::GatewayTimeout);
+        drop(resp_tx);
+        let response = wait_for(&resp_rx, Instant::now() + Duration::from_millis(50), "Node").unwrap_err();
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+    }
+}
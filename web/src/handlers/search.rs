@@ -0,0 +1,123 @@
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Instant;
+
+use hyper::server::{Request, Response};
+use hyper::StatusCode;
+use serde_json::Value;
+
+use inverted_index::manager::IndexCommand;
+use inverted_index::query::Hit;
+use inverted_index::scoring::rank;
+use rpc::Switchboard;
+
+use dsl;
+use handlers::{error_response, index_error_response, index_param, json_response, local_index, request_deadline, source_value, wait_for};
+use read_body;
+use router::Params;
+
+/// Body of a successful search Response
+#[derive(Serialize, Debug)]
+struct SearchResponse {
+    /// Milliseconds the search took
+    took: u64,
+    /// Number of Documents that matched, including those not returned
+    total: usize,
+    /// The Documents returned, most relevant first
+    hits: Vec<HitResponse>,
+}
+
+/// A single Document in a search Response
+#[derive(Serialize, Debug)]
+struct HitResponse {
+    id: u64,
+    score: f64,
+    /// The Document as it was indexed
+    source: Value,
+}
+
+impl From<Hit> for HitResponse {
+    fn from(hit: Hit) -> HitResponse {
+        HitResponse {
+            id: hit.id,
+            score: hit.score,
+            source: source_value(&hit.source, hit.plain_text),
+        }
+    }
+}
+
+/// Handles a search of an Index, `POST /<index>/_search`, with a query in the body. Every Shard
+/// is searched and scores its matches with its own statistics, then the best of all of them are
+/// returned.
+pub fn search(req: Request, params: Params, swb: Arc<Mutex<Switchboard>>) -> Response {
+    let started = Instant::now();
+    let deadline = match request_deadline(&swb) {
+        Ok(deadline) => deadline,
+        Err(response) => return response,
+    };
+    let index = match index_param(&params) {
+        Ok(index) => index,
+        Err(response) => return response,
+    };
+    let local = match local_index(&swb, &index) {
+        Ok(local) => local,
+        Err(response) => return response,
+    };
+
+    let body = match read_body(req) {
+        Ok(body) => body,
+        Err(e) => return error_response(StatusCode::BadRequest, &format!("Could not read body: {}", e)),
+    };
+    let search = match dsl::parse_search(&body) {
+        Ok(search) => search,
+        Err(e) => return error_response(StatusCode::BadRequest, &e.to_string()),
+    };
+
+    // Any page of the results can only hold Hits that are within the first `from + size` of
+    // some Shard, so that is all each Shard needs to send back
+    let mut responses = vec![];
+    for shard_tx in local.shards() {
+        let (resp_tx, resp_rx) = mpsc::channel();
+        let command = IndexCommand::Search {
+            query: search.query.clone(),
+            from: 0,
+            size: search.from.saturating_add(search.size),
+            response_channel: resp_tx,
+        };
+        if shard_tx.send(command).is_err() {
+            return error_response(
+                StatusCode::InternalServerError,
+                &format!("Index {} is not running", index),
+            );
+        }
+        responses.push(resp_rx);
+    }
+
+    let mut total = 0;
+    let mut hits = vec![];
+    for resp_rx in responses {
+        match wait_for(&resp_rx, deadline, &format!("Index {}", index)) {
+            Ok(Ok(results)) => {
+                total += results.total;
+                hits.extend(results.hits);
+            }
+            // The query parsed but could not be run against this Index, such as a term that is
+            // not valid for the type its field is mapped as, or the Index failed
+            Ok(Err(e)) => return index_error_response(&e),
+            Err(response) => return response,
+        }
+    }
+    rank(&mut hits);
+
+    let elapsed = started.elapsed();
+    let response = SearchResponse {
+        took: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos()) / 1_000_000,
+        total: total,
+        hits: hits
+            .into_iter()
+            .skip(search.from)
+            .take(search.size)
+            .map(HitResponse::from)
+            .collect(),
+    };
+    json_response(StatusCode::Ok, &response)
+}
@@ -2,21 +2,39 @@
 extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
+extern crate flate2;
 extern crate futures;
+extern crate futures_cpupool;
 extern crate hyper;
 extern crate regex;
 extern crate rusqlite;
+extern crate tokio_timer;
+extern crate uuid;
 extern crate rpc; 
+extern crate inverted_index;
 
+pub mod dsl;
 pub mod handlers;
+pub mod middleware;
 pub mod router;
+pub mod workers;
 
 // Imports
 use std::sync::{Arc,Mutex};
-use futures::future::Future;
+use futures::future::{self, Either, Future};
+use futures::Stream;
 
+use hyper::header::{Allow, Connection, ContentLength};
 use hyper::server::{Request, Response, Service};
-use hyper::StatusCode;
+use hyper::{Body, Chunk, StatusCode};
+
+use handlers::error_response;
+use middleware::Chain;
+use router::RouteMatch;
+use workers::WorkerPool;
+
+/// Largest request body accepted by default, in bytes
+pub const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
 
 /// Saga is a struct that will be used to implement the Hyper Service Trait
 pub struct Saga {
@@ -35,36 +53,175 @@ impl Service for Saga {
 
     /// This called for every web request the server receives
     fn call(&self, req: Request) -> Self::Future {
-        match self.router.route(req.method(), req.path()) {
-            Some(h) => {
-                Box::new(futures::future::ok(
-                    h(req, self.config.switchboard.clone())
-                ))
-            },
-            None => {
-                // If no matching Handler is found, return NotFound
-                Box::new(futures::future::ok(
-                    Response::new().with_status(StatusCode::NotFound)
-                ))
+        let route = self.router.route(req.method(), req.path());
+        let switchboard = self.config.switchboard.clone();
+        let middleware = self.config.middleware.clone();
+        let workers = self.config.workers.clone();
+
+        // Handlers run synchronously on the worker pool, so the body is read in full on the
+        // event loop before they get the Request.
+        let (method, uri, version, headers, body) = req.deconstruct();
+        let max_body_size = self.config.max_body_size;
+        // A body declared too large is not read at all, so the connection is closed after the
+        // response rather than left with the unread body in it.
+        let declared_too_large = headers
+            .get::<ContentLength>()
+            .map_or(false, |length| length.0 > max_body_size as u64);
+        let body = if declared_too_large {
+            Either::A(future::ok(None))
+        } else {
+            Either::B(read_limited(body, max_body_size))
+        };
+
+        Box::new(body.and_then(move |chunk| workers.run(move || {
+            let mut req = Request::new(method, uri);
+            req.set_version(version);
+            *req.headers_mut() = headers;
+            let too_large = chunk.is_none();
+            if let Some(chunk) = chunk {
+                req.set_body(chunk);
             }
-        }
+
+            let response = middleware.run(req, &|req| {
+                if too_large {
+                    return error_response(
+                        StatusCode::PayloadTooLarge,
+                        &format!("Request body must be at most {} bytes", max_body_size),
+                    );
+                }
+                match route {
+                    RouteMatch::Found(h, ref params) => h(req, params.clone(), switchboard.clone()),
+                    RouteMatch::MethodNotAllowed(ref allowed) => {
+                        // The path is known but not with this method, so tell the client which ones work
+                        Response::new().with_status(StatusCode::MethodNotAllowed).with_header(Allow(allowed.clone()))
+                    },
+                    // If no matching Handler is found, return NotFound
+                    RouteMatch::NotFound => Response::new().with_status(StatusCode::NotFound),
+                }
+            });
+            if declared_too_large {
+                response.with_header(Connection::close())
+            } else {
+                response
+            }
+        })))
     }
 }
 
+/// Reads a whole body, unless it is longer than `limit` bytes, in which case None is returned.
+/// The rest of a body that is too long is still read, so the connection can be reused, but it is
+/// not kept.
+///
+/// # Arguments
+///
+/// * `body` - Body of a Request
+/// * `limit` - Largest body to keep, in bytes
+pub fn read_limited(body: Body, limit: usize) -> Box<Future<Item = Option<Chunk>, Error = hyper::Error>> {
+    Box::new(
+        body.fold(Some(Vec::new()), move |buffer, chunk| {
+            let buffer = buffer.and_then(|mut buffer| {
+                if buffer.len() + chunk.len() > limit {
+                    None
+                } else {
+                    buffer.extend_from_slice(&chunk);
+                    Some(buffer)
+                }
+            });
+            Ok::<_, hyper::Error>(buffer)
+        }).map(|buffer| buffer.map(Chunk::from)),
+    )
+}
+
+/// Reads the body of a Request. `Saga` buffers bodies before handing Requests to Handlers, so
+/// this does not block.
+///
+/// # Arguments
+///
+/// * `req` - A Request passed to a Handler
+pub fn read_body(req: Request) -> Result<Chunk, hyper::Error> {
+    req.body().concat2().wait()
+}
+
 /// Convenience type to hold configuration variables used with the Hyper Service
 pub struct ServiceConfiguration {
     /// Location of the root data path
     pub data_path: String,
     /// A `Switchboard` struct so we can easily send messages to other parts of the application
     pub switchboard: Arc<Mutex<rpc::Switchboard>>,
+    /// Largest request body accepted, in bytes. Larger ones get a 413.
+    pub max_body_size: usize,
+    /// `Middleware` every request is run through on its way to a Handler
+    pub middleware: Arc<Chain>,
+    /// Threads the `Middleware` and Handlers run on
+    pub workers: WorkerPool,
 }
 
 impl ServiceConfiguration {
-    /// Creates and returns a ServiceConfiguration
-    pub fn new(data_path: String, switchboard: Arc<Mutex<rpc::Switchboard>>) -> ServiceConfiguration {
+    /// Creates and returns a ServiceConfiguration with the default body size limit and
+    /// `Middleware`. A ServiceConfiguration is made for every connection, so the `WorkerPool` is
+    /// passed in to be shared by all of them.
+    pub fn new(data_path: String, switchboard: Arc<Mutex<rpc::Switchboard>>, workers: WorkerPool) -> ServiceConfiguration {
         ServiceConfiguration {
             data_path: data_path,
-            switchboard: switchboard
+            switchboard: switchboard,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            middleware: Arc::new(Chain::default()),
+            workers: workers,
         }
     }
+
+    /// Sets the largest request body accepted, in bytes
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> ServiceConfiguration {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Replaces the `Middleware` every request is run through
+    pub fn with_middleware(mut self, middleware: Chain) -> ServiceConfiguration {
+        self.middleware = Arc::new(middleware);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_limited() {
+        let body = Body::from(vec![1u8; 16]);
+        assert_eq!(read_limited(body, 16).wait().unwrap().map(|chunk| chunk.len()), Some(16));
+        let body = Body::from(vec![1u8; 17]);
+        assert!(read_limited(body, 16).wait().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_declared_too_large_closes_connection() {
+        use std::path::PathBuf;
+        use std::sync::mpsc;
+        use std::time::Duration;
+        use hyper::Method;
+        use rpc::indices::IndexRegistry;
+
+        let (node_tx, _node_rx) = mpsc::channel();
+        let switchboard = rpc::Switchboard::new(Arc::new(Mutex::new(node_tx)), IndexRegistry::new(PathBuf::from("/tmp/saga")));
+        let config = ServiceConfiguration::new(
+            "/tmp/saga".to_owned(),
+            Arc::new(Mutex::new(switchboard)),
+            WorkerPool::new(1, Duration::from_secs(5)),
+        ).with_max_body_size(16);
+        let saga = Saga { router: router::Router::new(), config: config };
+
+        let mut req = Request::new(Method::Post, "/logs/document".parse().unwrap());
+        req.headers_mut().set(ContentLength(17));
+        let response = saga.call(req).wait().unwrap();
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+        assert_eq!(response.headers().get::<Connection>(), Some(&Connection::close()));
+
+        let mut req = Request::new(Method::Post, "/logs/document".parse().unwrap());
+        req.set_body(vec![1u8; 17]);
+        let response = saga.call(req).wait().unwrap();
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+        assert!(response.headers().get::<Connection>().is_none());
+    }
 }
\ No newline at end of file
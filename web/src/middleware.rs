@@ -0,0 +1,282 @@
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::str;
+use std::time::Instant;
+
+use flate2::Compression as GzipLevel;
+use flate2::write::GzEncoder;
+use futures::{Future, Stream};
+use hyper::StatusCode;
+use hyper::header::{q, AcceptEncoding, ContentEncoding, ContentLength, Encoding};
+use hyper::server::{Request, Response};
+use uuid::Uuid;
+
+use handlers::error_response;
+
+/// Header that carries the ID of a Request, on both the Request and its Response
+pub const REQUEST_ID_HEADER: &'static str = "X-Request-Id";
+/// Longest Request ID taken from a client. Longer ones are replaced with a generated ID.
+pub const MAX_REQUEST_ID_LENGTH: usize = 200;
+/// Smallest Response body that `Compression` compresses by default, in bytes
+pub const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+
+/// Middleware wraps the Handler of every Request. It can change the Request before passing it
+/// on, answer it without passing it on, or change the Response on its way back.
+pub trait Middleware: Send + Sync {
+    /// Handles a Request
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The Request
+    /// * `next` - Passes the Request on to the rest of the Chain and then the Handler
+    fn handle(&self, req: Request, next: &Fn(Request) -> Response) -> Response;
+}
+
+/// An ordered list of Middleware. The first Middleware added is the outermost, so it sees the
+/// Request first and the Response last.
+pub struct Chain {
+    middleware: Vec<Box<Middleware>>,
+}
+
+impl Chain {
+    /// Creates and returns an empty Chain
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use web::middleware::{AccessLog, Chain, RequestId};
+    /// let chain = Chain::new().with(RequestId).with(AccessLog);
+    /// ```
+    pub fn new() -> Chain {
+        Chain { middleware: vec![] }
+    }
+
+    /// Adds a Middleware inside the ones already in the Chain
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Runs a Request through every Middleware and then the Handler
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The Request
+    /// * `handler` - Produces the Response once every Middleware has passed the Request on
+    pub fn run(&self, req: Request, handler: &Fn(Request) -> Response) -> Response {
+        self.run_from(0, req, handler)
+    }
+
+    fn run_from(&self, position: usize, req: Request, handler: &Fn(Request) -> Response) -> Response {
+        match self.middleware.get(position) {
+            Some(middleware) => middleware.handle(req, &|req| self.run_from(position + 1, req, handler)),
+            None => handler(req),
+        }
+    }
+}
+
+impl Default for Chain {
+    /// The Chain used by `ServiceConfiguration::new`: request IDs, access logging, compression
+    /// and panic catching, in that order
+    fn default() -> Chain {
+        Chain::new()
+            .with(RequestId)
+            .with(AccessLog)
+            .with(Compression::new(DEFAULT_COMPRESSION_MIN_SIZE))
+            .with(CatchPanic)
+    }
+}
+
+/// Gives every Request an ID in the `X-Request-Id` header and copies it onto the Response. An ID
+/// sent by the client is kept, so Requests can be followed across services.
+pub struct RequestId;
+
+impl Middleware for RequestId {
+    fn handle(&self, mut req: Request, next: &Fn(Request) -> Response) -> Response {
+        let id = request_id(&req).unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.headers_mut().set_raw(REQUEST_ID_HEADER, id.clone());
+        let mut res = next(req);
+        res.headers_mut().set_raw(REQUEST_ID_HEADER, id);
+        res
+    }
+}
+
+/// Returns the ID of a Request, if it has a usable one
+pub fn request_id(req: &Request) -> Option<String> {
+    req.headers()
+        .get_raw(REQUEST_ID_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|id| str::from_utf8(id).ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .filter(|id| id.chars().all(|c| c.is_ascii_graphic()))
+        .map(|id| id.to_owned())
+}
+
+/// Prints a line for every Request with its method, path, status and how long it took
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn handle(&self, req: Request, next: &Fn(Request) -> Response) -> Response {
+        let started = Instant::now();
+        let id = request_id(&req).unwrap_or_else(|| String::from("-"));
+        let method = req.method().clone();
+        let path = req.path().to_owned();
+        let res = next(req);
+        let elapsed = started.elapsed();
+        let millis = elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0;
+        println!("{} {} {} {} {:.3}ms", id, method, path, res.status().as_u16(), millis);
+        res
+    }
+}
+
+/// Turns a panicking Handler into a 500 Response, instead of dropping the connection
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn handle(&self, req: Request, next: &Fn(Request) -> Response) -> Response {
+        let path = req.path().to_owned();
+        match panic::catch_unwind(AssertUnwindSafe(|| next(req))) {
+            Ok(res) => res,
+            Err(_) => {
+                println!("Handler for {} panicked", path);
+                error_response(StatusCode::InternalServerError, "Internal server error")
+            }
+        }
+    }
+}
+
+/// Gzips Response bodies for clients that accept it
+pub struct Compression {
+    /// Smallest body that is compressed, in bytes. Smaller bodies are not worth the overhead.
+    min_size: usize,
+}
+
+impl Compression {
+    /// Creates and returns a Compression Middleware
+    ///
+    /// # Arguments
+    ///
+    /// * `min_size` - Smallest Response body that is compressed, in bytes
+    pub fn new(min_size: usize) -> Compression {
+        Compression { min_size: min_size }
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: Request, next: &Fn(Request) -> Response) -> Response {
+        let accepts_gzip = req.headers().get::<AcceptEncoding>().map_or(false, |accepted| {
+            accepted.iter().any(|encoding| encoding.item == Encoding::Gzip && encoding.quality > q(0))
+        });
+        let res = next(req);
+        if !accepts_gzip || res.headers().has::<ContentEncoding>() {
+            return res;
+        }
+
+        let status = res.status();
+        let mut headers = res.headers().clone();
+        // Handlers build their bodies in memory, so this does not block
+        let body = match res.body().concat2().wait() {
+            Ok(body) => body,
+            Err(_) => return error_response(StatusCode::InternalServerError, "Could not read response body"),
+        };
+        let body = if body.len() < self.min_size {
+            body.to_vec()
+        } else {
+            let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+            match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+                Ok(compressed) => {
+                    headers.set(ContentEncoding(vec![Encoding::Gzip]));
+                    headers.set(ContentLength(compressed.len() as u64));
+                    compressed
+                }
+                Err(_) => body.to_vec(),
+            }
+        };
+        headers.set_raw("Vary", "Accept-Encoding");
+
+        let mut res = Response::new().with_status(status).with_body(body);
+        *res.headers_mut() = headers;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use flate2::read::GzDecoder;
+    use hyper::Method;
+    use hyper::header::qitem;
+
+    fn request() -> Request {
+        Request::new(Method::Get, "/logs".parse().unwrap())
+    }
+
+    fn body(res: Response) -> Vec<u8> {
+        res.body().concat2().wait().unwrap().to_vec()
+    }
+
+    struct Record(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Middleware for Record {
+        fn handle(&self, req: Request, next: &Fn(Request) -> Response) -> Response {
+            self.1.lock().unwrap().push(self.0);
+            next(req)
+        }
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let chain = Chain::new().with(Record("outer", seen.clone())).with(Record("inner", seen.clone()));
+        let res = chain.run(request(), &|_| Response::new().with_status(StatusCode::Accepted));
+        assert_eq!(res.status(), StatusCode::Accepted);
+        assert_eq!(*seen.lock().unwrap(), vec!["outer", "inner"]);
+    }
+
+    #[test]
+    fn test_request_id() {
+        let chain = Chain::new().with(RequestId);
+        let res = chain.run(request(), &|req| {
+            assert!(request_id(&req).is_some());
+            Response::new()
+        });
+        assert!(res.headers().get_raw(REQUEST_ID_HEADER).is_some());
+
+        let mut req = request();
+        req.headers_mut().set_raw(REQUEST_ID_HEADER, "abc-123");
+        let res = chain.run(req, &|_| Response::new());
+        assert_eq!(res.headers().get_raw(REQUEST_ID_HEADER).and_then(|raw| raw.one()), Some(&b"abc-123"[..]));
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let chain = Chain::new().with(CatchPanic);
+        let res = chain.run(request(), &|_| panic!("Handler failed"));
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn test_compression() {
+        let chain = Chain::new().with(Compression::new(16));
+        let text = "saga ".repeat(100);
+
+        let res = chain.run(request(), &|_| Response::new().with_body(text.clone()));
+        assert!(!res.headers().has::<ContentEncoding>());
+        assert_eq!(body(res), text.as_bytes());
+
+        let mut req = request();
+        req.headers_mut().set(AcceptEncoding(vec![qitem(Encoding::Gzip)]));
+        let res = chain.run(req, &|_| Response::new().with_body(text.clone()));
+        assert_eq!(res.headers().get::<ContentEncoding>(), Some(&ContentEncoding(vec![Encoding::Gzip])));
+        let mut decompressed = String::new();
+        GzDecoder::new(&body(res)[..]).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, text);
+
+        let mut req = request();
+        req.headers_mut().set(AcceptEncoding(vec![qitem(Encoding::Gzip)]));
+        let res = chain.run(req, &|_| Response::new().with_body("short"));
+        assert!(!res.headers().has::<ContentEncoding>());
+    }
+}
@@ -1,4 +1,6 @@
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::sync::{Arc, Mutex};
@@ -9,26 +11,72 @@ use regex::Regex;
 
 use rpc::Switchboard;
 
-/// Convenience type alias for `Response`
-type ResponseFuture = Response;
+/// Convenience type alias for Handlers, so we don't have to keep writing out the long type.
+/// Handlers may block, so they are run on a `WorkerPool` rather than hyper's event loop.
+pub type Handler = fn(Request, Params, Arc<Mutex<Switchboard>>) -> Response;
 
-/// Convenience type alias for Handlers, so we don't have to keep writing out the long type
-type Handler = fn(Request, Arc<Mutex<Switchboard>>) -> ResponseFuture;
+/// The values a Route captured from the path of a request, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    values: HashMap<String, String>,
+}
+
+impl Params {
+    /// Creates and returns an empty Params
+    pub fn new() -> Params {
+        Params { values: HashMap::new() }
+    }
+
+    /// Returns the value captured for a name, if there is one
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use web::router::Params;
+    /// let params = Params::new().with("index", "logs");
+    /// assert_eq!(params.get("index"), Some("logs"));
+    /// ```
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|value| value.as_str())
+    }
+
+    /// Adds a value to the Params, replacing any the name already had
+    pub fn with<S: Into<String>>(mut self, name: S, value: S) -> Params {
+        self.values.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// What a Router found for a request
+pub enum RouteMatch {
+    /// A Route matched both the path and the method
+    Found(Handler, Params),
+    /// At least one Route matched the path, but none of them accept the method. Holds the methods
+    /// accepted by the most specific pattern that matched.
+    MethodNotAllowed(Vec<Method>),
+    /// No Route matched the path
+    NotFound,
+}
 
 /// Router accepts components of an HTTP request and tries to find the appropriate function to handle it
 pub struct Router {
-    /// Holds child routers, not currently used
+    /// Path the Router is mounted under. Empty for a Router mounted at the root.
+    prefix: String,
+    /// Holds child routers, which are searched along with this Router's own routes
     child_routers: Vec<Router>,
     /// Holds the registered routes
     routes: Vec<Route>,
 }
 
 /// Route is a combination of a regex, a HTTP Method, and a Handler function. When a request with a matching
-/// regex and method come in, the Handler is executed.
+/// regex and method come in, the Handler is executed. The regex must match the whole path, and
+/// the values of its named captures are handed to the Handler as `Params`.
 pub struct Route {
     regex: Regex,
     verb: Method,
-    handler: Handler
+    handler: Handler,
+    /// Rank of each segment of the pattern, used to pick between Routes that match the same path
+    specificity: Vec<u8>,
 }
 
 impl Route {
@@ -36,48 +84,55 @@ impl Route {
     /// 
     /// # Arguments
     /// 
-    /// * `re` - A &str that will be compiled into a Regex
+    /// * `re` - A &str that will be compiled into a Regex. A segment of the form `:name` matches
+    ///   any single segment of the path and captures it as `name`. The Regex is anchored at both
+    ///   ends, so it always has to match the whole path.
     /// * `verb` - The HTTP Method that should be matched
     /// 
     /// # Example
     /// 
     /// ```
     /// extern crate hyper;
+    /// extern crate rpc;
     /// extern crate web;
-    /// fn test(req: hyper::server::Request) -> hyper::server::Response {
+    /// use std::sync::{Arc, Mutex};
+    /// fn test(_req: hyper::server::Request, _params: web::router::Params, _swb: Arc<Mutex<rpc::Switchboard>>) -> hyper::server::Response {
     ///     hyper::server::Response::new()
     /// };
-    /// let new_route = web::router::Route::new("/test", hyper::Method::Get, test);
+    /// let new_route = web::router::Route::new("/:index/document/(?P<id>[0-9]+)", hyper::Method::Get, test);
     /// ```
     pub fn new(re: &str, verb: Method, handler: Handler) -> Result<Route, RouterError> {
-        let re = Regex::new(re);
-        if re.is_err() {
+        let compiled = Regex::new(&compile_pattern(re)?);
+        if compiled.is_err() {
             return Err(RouterError::new("Invalid route"));
         }
 
         Ok(
             Route {
-                regex: re.unwrap(),
+                regex: compiled.unwrap(),
                 verb: verb,
-                handler: handler
+                handler: handler,
+                specificity: specificity(re),
             }
         )
     }
 
-    /// Goes through all the registered routes and returns a Handler if one is found that matches
-    fn is_match(&self, verb: &Method, path: &str) -> Option<Handler> {
-        if self.verb != *verb {
-            return None;
-        }
-
-        if !self.regex.is_match(path) {
-            return None;
+    /// Returns the values captured from the path if the Route matches it, whatever the method
+    fn is_match(&self, path: &str) -> Option<Params> {
+        let captures = match self.regex.captures(path) {
+            Some(captures) => captures,
+            None => return None,
+        };
+        let mut params = Params::new();
+        for name in self.regex.capture_names().filter_map(|name| name) {
+            if let Some(value) = captures.name(name) {
+                params = params.with(name, value.as_str());
+            }
         }
 
-        return Some(self.handler);
+        Some(params)
     }
 
-
 }
 
 impl Router {
@@ -93,11 +148,35 @@ impl Router {
     /// ```
     pub fn new() -> Router {
         Router {
+            prefix: String::new(),
             child_routers: vec![],
             routes: vec![],
         }
     }
 
+    /// Creates and returns a new Router to be mounted under a prefix. Its routes are matched
+    /// against what is left of the path once the prefix is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Path the Router is mounted under, such as `/_cluster`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate web;
+    /// let cluster_router = web::router::Router::with_prefix("/_cluster");
+    /// let router = web::router::Router::new().add_child(cluster_router);
+    /// ```
+    pub fn with_prefix(prefix: &str) -> Router {
+        let mut router = Router::new();
+        let prefix = prefix.trim_matches('/');
+        if !prefix.is_empty() {
+            router.prefix = format!("/{}", prefix);
+        }
+        router
+    }
+
     /// Adds a Route to the Router
     /// 
     /// # Arguments
@@ -109,7 +188,9 @@ impl Router {
     /// extern crate web;
     /// extern crate hyper;
     /// let mut new_router = web::router::Router::new();
-    /// fn test(req: hyper::server::Request) -> hyper::server::Response {
+    /// extern crate rpc;
+    /// use std::sync::{Arc, Mutex};
+    /// fn test(_req: hyper::server::Request, _params: web::router::Params, _swb: Arc<Mutex<rpc::Switchboard>>) -> hyper::server::Response {
     ///     hyper::server::Response::new()
     /// };
     /// let new_route = web::router::Route::new("/healthz", hyper::Method::Get, test).unwrap();
@@ -119,7 +200,10 @@ impl Router {
         self.routes.push(route);
     }
 
-    /// Goes through each Route of a Router and tries to find one that matches
+    /// Goes through each Route of a Router and its children and tries to find one that matches.
+    /// When several Routes match, the most specific one wins: literal segments beat `:name`
+    /// segments, which beat any other regex. Routes that are just as specific are tried in the
+    /// order they were added, with a Router's own Routes before those of its children.
     /// 
     /// # Arguments
     /// 
@@ -131,29 +215,86 @@ impl Router {
     /// extern crate web;
     /// extern crate hyper;
     /// let mut new_router = web::router::Router::new();
-    /// fn test(req: hyper::server::Request) -> hyper::server::Response {
+    /// extern crate rpc;
+    /// use std::sync::{Arc, Mutex};
+    /// fn test(_req: hyper::server::Request, _params: web::router::Params, _swb: Arc<Mutex<rpc::Switchboard>>) -> hyper::server::Response {
     ///     hyper::server::Response::new()
     /// };  
     /// let new_route = web::router::Route::new("/healthz", hyper::Method::Get, test).unwrap();
     /// new_router.add_route(new_route);
     /// let _result = new_router.route(&hyper::Method::Get, "/healthz");
     /// ```
-    pub fn route(&self, verb: &Method, path: &str) -> Option<Handler> {
+    pub fn route(&self, verb: &Method, path: &str) -> RouteMatch {
+        let mut found = None;
+        let mut allowed = None;
+        self.search(verb, path, &[], &mut found, &mut allowed);
+        match (found, allowed) {
+            (Some((_, handler, params)), _) => RouteMatch::Found(handler, params),
+            (None, Some((_, allowed))) => RouteMatch::MethodNotAllowed(allowed),
+            (None, None) => RouteMatch::NotFound,
+        }
+    }
+
+    /// Looks for the most specific Route matching a request in this Router and its children,
+    /// keeping the best one seen so far in `found`. `allowed` keeps the methods of the most
+    /// specific pattern that matched the path, whatever its method.
+    fn search(
+        &self,
+        verb: &Method,
+        path: &str,
+        rank: &[u8],
+        found: &mut Option<(Vec<u8>, Handler, Params)>,
+        allowed: &mut Option<(Vec<u8>, Vec<Method>)>,
+    ) {
+        let path = match strip_prefix(&self.prefix, path) {
+            Some(path) => path,
+            None => return,
+        };
+        let mut rank = rank.to_vec();
+        rank.extend(specificity(&self.prefix));
+
         for route in &self.routes {
-            match route.is_match(verb, path) {
-                Some(h) => {
-                    return Some(h)
-                },
-                None => {}
+            let params = match route.is_match(path) {
+                Some(params) => params,
+                None => continue,
+            };
+            let mut route_rank = rank.clone();
+            route_rank.extend(route.specificity.iter().cloned());
+            let best_path = match *allowed {
+                Some((ref best, _)) => route_rank.cmp(best),
+                None => Ordering::Greater,
             };
+            match best_path {
+                Ordering::Greater => *allowed = Some((route_rank.clone(), vec![route.verb.clone()])),
+                Ordering::Equal => {
+                    if let Some((_, ref mut verbs)) = *allowed {
+                        if !verbs.contains(&route.verb) {
+                            verbs.push(route.verb.clone());
+                        }
+                    }
+                }
+                Ordering::Less => {}
+            }
+            if route.verb != *verb {
+                continue;
+            }
+            let better = match *found {
+                Some((ref best, _, _)) => route_rank > *best,
+                None => true,
+            };
+            if better {
+                *found = Some((route_rank, route.handler, params));
+            }
         }
 
-        None
+        for child in &self.child_routers {
+            child.search(verb, path, &rank, found, allowed);
+        }
     }
 
 
-    /// Adds a child Router. If a Router has children, and none of its
-    /// routes match, searching will continue down into the children.
+    /// Adds a child Router. If a Router has children, searching will continue down into the
+    /// children, and a child with a prefix only sees requests whose path starts with it.
     /// 
     /// # Arguments
     /// 
@@ -173,6 +314,66 @@ impl Router {
     }
 }
 
+/// Turns a route pattern into the Regex it is matched with. Segments of the form `:name` become
+/// named captures of a single path segment, everything else is kept as it is, and the result is
+/// grouped and anchored at both ends, so that a `|` in the pattern cannot escape the anchors.
+fn compile_pattern(pattern: &str) -> Result<String, RouterError> {
+    let mut segments = vec![];
+    for segment in pattern.trim_left_matches('^').trim_right_matches('$').split('/') {
+        if segment.starts_with(':') {
+            let name = &segment[1..];
+            let valid = name.chars().next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(RouterError::new(&format!("Invalid parameter name in route {}", pattern)));
+            }
+            segments.push(format!("(?P<{}>[^/]+)", name));
+        } else {
+            segments.push(segment.to_owned());
+        }
+    }
+    Ok(format!("^(?:{})$", segments.join("/")))
+}
+
+/// Ranks each segment of a route pattern by how specific it is: 2 for a literal segment, 1 for a
+/// `:name` segment and 0 for any other regex. An optional trailing `/` is not ranked.
+fn specificity(pattern: &str) -> Vec<u8> {
+    pattern
+        .trim_left_matches('^')
+        .trim_right_matches('$')
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != "?")
+        .map(|segment| {
+            if segment.starts_with(':') {
+                1
+            } else if segment.chars().any(|c| "\\.+*?()|[]{}^$".contains(c)) {
+                0
+            } else {
+                2
+            }
+        })
+        .collect()
+}
+
+/// Removes the prefix a Router is mounted under from a path. Returns None if the path is not
+/// under the prefix.
+fn strip_prefix<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        return Some(path);
+    }
+    if !path.starts_with(prefix) {
+        return None;
+    }
+    let rest = &path[prefix.len()..];
+    if rest.is_empty() {
+        Some("/")
+    } else if rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
 impl fmt::Display for RouterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.details)
@@ -214,6 +415,10 @@ impl RouterError {
 mod tests {
     use super::*;
 
+    fn test(_req: Request, _params: Params, _swb: Arc<Mutex<Switchboard>>) -> Response {
+        Response::new()
+    }
+
     #[test]
     fn test_create_router() {
         let router = Router::new();
@@ -222,22 +427,125 @@ mod tests {
 
     #[test]
     fn test_create_route() {
-        fn test(req: Request) -> Response {
-            Response::new()
-        };
         let route = Route::new("/healthz", Method::Get, test).unwrap();
         assert_eq!(route.verb, Method::Get);
+        assert!(Route::new("/:1index", Method::Get, test).is_err());
+    }
+
+    fn is_found(result: RouteMatch) -> bool {
+        match result {
+            RouteMatch::Found(_, _) => true,
+            _ => false,
+        }
+    }
+
+    fn params(result: RouteMatch) -> Params {
+        match result {
+            RouteMatch::Found(_, params) => params,
+            _ => panic!("No route was found"),
+        }
     }
 
     #[test]
     fn test_route_matches() {
-        fn test(req: Request) -> Response {
-            Response::new()
-        };
         let mut router = Router::new();
         let route = Route::new("/hea.*", Method::Get, test).unwrap();
         router.add_route(route);
-        let result = router.route(&Method::Get, "/healthz");
-        assert!(result.is_some());
+        assert!(is_found(router.route(&Method::Get, "/healthz")));
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/:index", Method::Get, test).unwrap());
+        router.add_route(Route::new("/:index", Method::Put, test).unwrap());
+        match router.route(&Method::Post, "/logs") {
+            RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::Get, Method::Put]),
+            _ => panic!("Expected the method not to be allowed"),
+        }
+        match router.route(&Method::Post, "/logs/_search") {
+            RouteMatch::NotFound => {}
+            _ => panic!("Expected no route to be found"),
+        }
+    }
+
+    #[test]
+    fn test_method_not_allowed_uses_best_route() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/:index", Method::Get, test).unwrap());
+        router.add_route(Route::new("/:index", Method::Delete, test).unwrap());
+        router.add_route(Route::new("/_indices", Method::Post, test).unwrap());
+        match router.route(&Method::Put, "/_indices") {
+            RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::Post]),
+            _ => panic!("Expected the method not to be allowed"),
+        }
+        match router.route(&Method::Put, "/logs") {
+            RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::Get, Method::Delete]),
+            _ => panic!("Expected the method not to be allowed"),
+        }
+    }
+
+    #[test]
+    fn test_route_is_anchored() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/nodes", Method::Get, test).unwrap());
+        assert!(is_found(router.route(&Method::Get, "/nodes")));
+        assert!(!is_found(router.route(&Method::Get, "/nodes/foo/bar")));
+        assert!(!is_found(router.route(&Method::Get, "/cluster/nodes")));
+    }
+
+    #[test]
+    fn test_alternation_is_anchored() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/nodes|/health", Method::Get, test).unwrap());
+        assert!(is_found(router.route(&Method::Get, "/nodes")));
+        assert!(is_found(router.route(&Method::Get, "/health")));
+        assert!(!is_found(router.route(&Method::Get, "/nodes/extra")));
+        assert!(!is_found(router.route(&Method::Get, "/extra/health")));
+    }
+
+    #[test]
+    fn test_route_params() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/:index/document/(?P<id>[0-9]+)", Method::Get, test).unwrap());
+        match router.route(&Method::Get, "/logs/document/42") {
+            RouteMatch::Found(_, params) => assert_eq!(params, Params::new().with("index", "logs").with("id", "42")),
+            _ => panic!("No route was found"),
+        }
+        assert!(!is_found(router.route(&Method::Get, "/logs/document/abc")));
+        assert!(!is_found(router.route(&Method::Get, "/logs/extra/document/42")));
+    }
+
+    #[test]
+    fn test_route_precedence() {
+        let mut router = Router::new();
+        router.add_route(Route::new("/(?P<path>.*)", Method::Get, test).unwrap());
+        router.add_route(Route::new("/:index", Method::Get, test).unwrap());
+        router.add_route(Route::new("/_indices", Method::Get, test).unwrap());
+        assert_eq!(params(router.route(&Method::Get, "/_indices")), Params::new());
+        assert_eq!(params(router.route(&Method::Get, "/logs")), Params::new().with("index", "logs"));
+        assert_eq!(params(router.route(&Method::Get, "/logs/_search")), Params::new().with("path", "logs/_search"));
+    }
+
+    #[test]
+    fn test_child_routers() {
+        let mut cluster_router = Router::with_prefix("/_cluster/");
+        cluster_router.add_route(Route::new("/nodes", Method::Get, test).unwrap());
+        cluster_router.add_route(Route::new("/?", Method::Get, test).unwrap());
+        let mut router = Router::new();
+        router.add_route(Route::new("/:index/:action", Method::Get, test).unwrap());
+        let router = router.add_child(cluster_router);
+
+        assert_eq!(params(router.route(&Method::Get, "/_cluster/nodes")), Params::new());
+        assert_eq!(params(router.route(&Method::Get, "/_cluster")), Params::new());
+        assert_eq!(
+            params(router.route(&Method::Get, "/logs/nodes")),
+            Params::new().with("index", "logs").with("action", "nodes")
+        );
+        assert!(!is_found(router.route(&Method::Get, "/_clusternodes")));
+        match router.route(&Method::Delete, "/_cluster/nodes") {
+            RouteMatch::MethodNotAllowed(allowed) => assert_eq!(allowed, vec![Method::Get]),
+            _ => panic!("Expected the method not to be allowed"),
+        }
     }
 }
@@ -0,0 +1,118 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
+
+use futures::future::{self, Either, Future};
+use futures_cpupool::{Builder, CpuPool};
+use hyper;
+use hyper::StatusCode;
+use hyper::server::Response;
+use tokio_timer::Timer;
+
+use handlers::error_response;
+
+/// Number of threads Handlers run on by default
+pub const DEFAULT_WORKER_THREADS: usize = 16;
+/// How long a Handler has to answer by default before the client gets a 504
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = ::rpc::DEFAULT_REQUEST_TIMEOUT;
+
+/// Runs Handlers on a pool of threads. Handlers block while they wait on the Node and the index
+/// Managers, and running them on hyper's event loop would hold up every other request on it.
+#[derive(Clone)]
+pub struct WorkerPool {
+    pool: CpuPool,
+    timer: Timer,
+    timeout: Duration,
+}
+
+impl WorkerPool {
+    /// Creates and returns a WorkerPool. The threads are shared by every clone of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - Number of threads Handlers run on
+    /// * `timeout` - How long a Handler has to answer before the client gets a 504
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use web::workers::WorkerPool;
+    /// let workers = WorkerPool::new(4, Duration::from_secs(10));
+    /// ```
+    pub fn new(threads: usize, timeout: Duration) -> WorkerPool {
+        WorkerPool {
+            pool: Builder::new().pool_size(threads).name_prefix("saga-web-").create(),
+            timer: Timer::default(),
+            timeout: timeout,
+        }
+    }
+
+    /// Runs a function that produces a Response on one of the threads. If it does not finish in
+    /// time the client gets a 504 right away, though the thread keeps going until the function
+    /// returns. A function that panics gets a 500.
+    ///
+    /// # Arguments
+    ///
+    /// * `work` - Function that produces the Response
+    pub fn run<F>(&self, work: F) -> Box<Future<Item = Response, Error = hyper::Error>>
+    where
+        F: FnOnce() -> Response + Send + 'static,
+    {
+        let work = self.pool
+            .spawn_fn(move || panic::catch_unwind(AssertUnwindSafe(work)).map_err(|_| ()))
+            .then(|result| match result {
+                Ok(res) => Ok::<_, hyper::Error>(res),
+                Err(_) => Ok(error_response(StatusCode::InternalServerError, "Internal server error")),
+            });
+        let timeout = self.timeout;
+        Box::new(work.select2(self.timer.sleep(timeout)).then(move |result| -> Box<Future<Item = Response, Error = hyper::Error>> {
+            match result {
+                Ok(Either::A((res, _))) => Box::new(future::ok(res)),
+                Ok(Either::B(_)) => Box::new(future::ok(timed_out(timeout))),
+                // The timer could not track the timeout, so wait for the Handler without one
+                Err(Either::B((_, work))) => Box::new(work),
+                Err(Either::A((e, _))) => Box::new(future::err(e)),
+            }
+        }))
+    }
+}
+
+/// Builds the Response sent when a Handler takes too long
+fn timed_out(timeout: Duration) -> Response {
+    error_response(
+        StatusCode::GatewayTimeout,
+        &format!("Request did not finish within {}ms", timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos() / 1_000_000)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_run() {
+        let workers = WorkerPool::new(2, Duration::from_secs(5));
+        let res = workers.run(|| Response::new().with_status(StatusCode::Accepted)).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::Accepted);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let workers = WorkerPool::new(2, Duration::from_millis(200));
+        let res = workers.run(|| {
+            thread::sleep(Duration::from_secs(2));
+            Response::new()
+        }).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::GatewayTimeout);
+    }
+
+    #[test]
+    fn test_panic() {
+        let workers = WorkerPool::new(1, Duration::from_secs(5));
+        let res = workers.run(|| panic!("Handler failed")).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::InternalServerError);
+        let res = workers.run(|| Response::new()).wait().unwrap();
+        assert_eq!(res.status(), StatusCode::Ok);
+    }
+}